@@ -0,0 +1,40 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Arbitrary configuration files must be rejected with a `ParseError` or
+//! loaded, and never make the parser, the validation or the assertions
+//! panic
+
+#![no_main]
+
+use autopulsed::config::Config;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|content: &str| {
+    if let Ok(raw) = serde_yaml::from_str::<serde_yaml::Value>(content) {
+        Config::unknown_keys(&raw);
+    }
+
+    let config = match Config::from_yaml(content) {
+        Ok(config) => config,
+        Err(error) => {
+            error.to_string();
+            return;
+        }
+    };
+    config.validate().to_string();
+    config.run_assertions();
+});
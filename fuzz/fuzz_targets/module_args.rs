@@ -0,0 +1,152 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Module arguments of remaps must split into exactly the arguments they
+//! were built from, the way PulseAudio reads them, whatever the names and
+//! properties contain
+
+#![no_main]
+
+use std::collections::{BTreeMap, HashMap};
+
+use arbitrary::Arbitrary;
+use autopulsed::config::{MasterRef, RemapConfig};
+use autopulsed::modargs::{self, REMAP_TAG_PROPERTY, is_valid_property_key};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    source: bool,
+    master_name: String,
+    config_name: String,
+    device_name: Option<String>,
+    device_properties: HashMap<String, String>,
+    format: Option<String>,
+    channel_map: Option<Vec<String>>,
+    resample_method: Option<String>,
+}
+
+/// Key-value pairs as pa_modargs_new and pa_proplist_from_string split
+/// them, or None if they would reject the string
+fn split(text: &str) -> Option<BTreeMap<String, String>> {
+    let mut pairs = BTreeMap::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(pairs);
+        }
+
+        let mut key = String::new();
+        loop {
+            match chars.next()? {
+                '=' => break,
+                c if c.is_whitespace() => return None,
+                c => key.push(c),
+            }
+        }
+
+        let quote = chars.next_if(|&c| c == '"' || c == '\'');
+        let mut value = String::new();
+        loop {
+            match (chars.next(), quote) {
+                (None, None) => break,
+                (None, Some(_)) => return None,
+                (Some('\\'), _) => value.push(chars.next()?),
+                (Some(c), Some(quote)) if c == quote => break,
+                (Some(c), None) if c.is_whitespace() => break,
+                (Some(c), _) => value.push(c),
+            }
+        }
+        pairs.insert(key, value);
+    }
+}
+
+fn without_nul(value: &str) -> String {
+    value.replace('\0', "")
+}
+
+fuzz_target!(|input: Input| {
+    let kind = if input.source { "source" } else { "sink" };
+    let config = RemapConfig {
+        master: MasterRef::Name(input.master_name.clone()),
+        device_name: input.device_name.clone(),
+        device_properties: Some(input.device_properties.clone()),
+        format: input.format.clone(),
+        rate: None,
+        channels: None,
+        channel_map: input.channel_map.clone(),
+        master_channel_map: None,
+        resample_method: input.resample_method.clone(),
+        remix: None,
+        verify_playback: false,
+        on_name_collision: Default::default(),
+        on_external_unload: Default::default(),
+    };
+    let args = modargs::remap(
+        &config,
+        kind,
+        modargs::RemapNames {
+            master_name: &input.master_name,
+            config_name: &input.config_name,
+        },
+    );
+    assert!(!args.contains('\0'), "NUL in {args:?}");
+
+    let mut expected = BTreeMap::new();
+    expected.insert("master".to_string(), without_nul(&input.master_name));
+    if let Some(device_name) = &input.device_name {
+        expected.insert(format!("{kind}_name"), without_nul(device_name));
+    }
+    let properties_key = format!("{kind}_properties");
+    expected.insert(properties_key.clone(), String::new());
+    if let Some(format) = &input.format {
+        expected.insert("format".to_string(), without_nul(format));
+    }
+    if let Some(channel_map) = &input.channel_map {
+        expected.insert(
+            "channel_map".to_string(),
+            without_nul(&channel_map.join(",")),
+        );
+    }
+    if let Some(resample_method) = &input.resample_method {
+        expected.insert(
+            "resample_method".to_string(),
+            without_nul(resample_method),
+        );
+    }
+
+    let mut actual = split(&args).expect("arguments do not parse");
+    let properties = actual.insert(properties_key, String::new());
+    assert_eq!(actual, expected, "{args}");
+
+    let mut expected_properties: BTreeMap<_, _> = input
+        .device_properties
+        .iter()
+        .filter(|(key, _)| is_valid_property_key(key))
+        .map(|(key, value)| (key.clone(), without_nul(value)))
+        .collect();
+    expected_properties.insert(
+        REMAP_TAG_PROPERTY.to_string(),
+        without_nul(&input.config_name),
+    );
+    let properties = properties.expect("no properties");
+    assert_eq!(
+        split(&properties).expect("properties do not parse"),
+        expected_properties,
+        "{args}"
+    );
+});
@@ -0,0 +1,309 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Choosing the default ALSA card on systems without a sound server
+//!
+//! Cards are listed from /proc/asound/cards and matched against the sink
+//! configs by the same detect rules, with the properties PulseAudio gives
+//! its ALSA devices. The card of the winning config is written to a file
+//! in the syntax of asound.conf, which ALSA applications pick up when they
+//! open the default device.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command as Process, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use autopulsed::config::{AlsaFallbackConfig, Config};
+
+use crate::container;
+
+const CARDS: &str = "/proc/asound/cards";
+
+/// Socket of a server running in system mode
+const SYSTEM_SOCKET: &str = "/run/pulse/native";
+
+/// Prints an event per line whenever a sound device is added or removed
+const MONITOR: &[&str] =
+    &["udevadm", "monitor", "--udev", "--subsystem-match=sound"];
+
+/// How often to look for a server and for cards without udev events
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Card {
+    pub index: u32,
+    pub id: String,
+    pub name: String,
+    pub long_name: String,
+}
+
+/// Card chosen as the default, and the sink config it was chosen for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Selection<'a> {
+    card: &'a Card,
+    config_name: &'a str,
+}
+
+impl Card {
+    /// Properties as PulseAudio sets them on the devices of the card
+    fn properties(&self) -> BTreeMap<String, String> {
+        let properties = BTreeMap::from([
+            ("alsa.card".to_string(), self.index.to_string()),
+            ("alsa.card_name".to_string(), self.name.clone()),
+            ("alsa.long_card_name".to_string(), self.long_name.clone()),
+            ("device.string".to_string(), format!("hw:{}", self.id)),
+        ]);
+        #[cfg(feature = "udev")]
+        let properties = crate::udev::enrich(properties);
+        properties
+    }
+}
+
+/// Whether the server would be looked for at a local socket that does not
+/// exist
+pub fn lacks_server(server: Option<&str>) -> bool {
+    let socket = container::socket_path(server, |name| std::env::var_os(name));
+    socket.is_some_and(|socket| !socket.exists())
+        && (server.is_some() || !Path::new(SYSTEM_SOCKET).exists())
+}
+
+/// Keep the default card chosen until a server appears
+pub fn run(
+    config: &Config,
+    fallback: &AlsaFallbackConfig,
+    server: Option<&str>,
+) -> io::Result<()> {
+    info!(
+        "No sound server is running, choosing the default ALSA card in {}",
+        fallback.defaults_file.display()
+    );
+    let changes = watch_udev();
+    let mut present = BTreeSet::new();
+    let mut chosen = None;
+    while lacks_server(server) {
+        let cards = parse_cards(&std::fs::read_to_string(CARDS)?);
+        let ids = cards.iter().map(|card| card.id.clone()).collect();
+        for id in present.difference(&ids) {
+            info!("ALSA card '{id}' has disappeared");
+        }
+        for card in cards.iter().filter(|card| !present.contains(&card.id)) {
+            info!("ALSA card '{}' ({}) has appeared", card.id, card.name);
+        }
+        present = ids;
+
+        let selected = select(config, &cards);
+        if selected.map(|selection| &selection.card.id) != chosen.as_ref() {
+            match selected {
+                Some(selection) => {
+                    selection.write(&fallback.defaults_file)?;
+                    info!(
+                        "Set the default ALSA card to '{}' ('{}')",
+                        selection.card.id, selection.config_name
+                    );
+                }
+                None => info!("No ALSA card matches any sink config"),
+            }
+            chosen = selected.map(|selection| selection.card.id.clone());
+        }
+
+        match changes.as_ref().map(|r| r.recv_timeout(POLL_INTERVAL)) {
+            Some(Ok(())) | Some(Err(RecvTimeoutError::Timeout)) => {}
+            Some(Err(RecvTimeoutError::Disconnected)) | None => {
+                thread::sleep(POLL_INTERVAL)
+            }
+        }
+    }
+    info!("A sound server has appeared, leaving ALSA alone");
+    Ok(())
+}
+
+/// Cards as listed in /proc/asound/cards, two lines each:
+///
+/// ```text
+///  1 [Headset        ]: USB-Audio - USB Headset
+///                       Vendor USB Headset at usb-0000:00:14.0-2, full speed
+/// ```
+fn parse_cards(text: &str) -> Vec<Card> {
+    let mut cards = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let Some((index, rest)) = line.trim_start().split_once(" [") else {
+            continue;
+        };
+        let Ok(index) = index.parse() else {
+            continue;
+        };
+        let Some((id, rest)) = rest.split_once(']') else {
+            continue;
+        };
+        let name = rest
+            .split_once(" - ")
+            .map_or("", |(_, name)| name)
+            .trim()
+            .to_string();
+        cards.push(Card {
+            index,
+            id: id.trim().to_string(),
+            name,
+            long_name: lines.next().unwrap_or_default().trim().to_string(),
+        });
+    }
+    cards
+}
+
+/// The card of the sink config with the best priority that one of the
+/// cards matches
+fn select<'a>(config: &'a Config, cards: &'a [Card]) -> Option<Selection<'a>> {
+    let mut candidates = config
+        .sinks
+        .iter()
+        .filter_map(|(name, device)| {
+            let priority = config.resolve_priority("sinks", name)?;
+            let card = cards.iter().find(|card| {
+                device
+                    .matches_properties(&card.id, &card.properties())
+                    .unwrap_or(false)
+            })?;
+            Some((priority, name.as_str(), card))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|&(priority, name, _)| (priority, name));
+    candidates
+        .first()
+        .map(|&(_, config_name, card)| Selection { card, config_name })
+}
+
+impl Selection<'_> {
+    /// Write the card as the default to the file, in asound.conf syntax
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let content = format!(
+            "# Written by autopulsed for '{}' while no sound server is \
+             running\ndefaults.pcm.card {id}\ndefaults.ctl.card {id}\n",
+            self.config_name,
+            id = self.card.id
+        );
+        let mut temporary = PathBuf::from(path).into_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, content)?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+/// Signal every sound device added or removed as udev reports it, or
+/// nothing if udev cannot be watched
+fn watch_udev() -> Option<Receiver<()>> {
+    let spawned = Process::new(MONITOR[0])
+        .args(&MONITOR[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut monitor = match spawned {
+        Ok(monitor) => monitor,
+        Err(e) => {
+            warn!("Not watching udev, looking for cards periodically: {e}");
+            return None;
+        }
+    };
+    let stdout = monitor.stdout.take()?;
+    let (sender, receiver) = mpsc::channel();
+    thread::Builder::new()
+        .name("udev".to_string())
+        .spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                debug!("udev: {line}");
+                if sender.send(()).is_err() {
+                    break;
+                }
+            }
+            let _ = monitor.kill();
+            let _ = monitor.wait();
+        })
+        .ok()?;
+    Some(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARDS_TEXT: &str = " 0 [PCH            ]: HDA-Intel - HDA Intel PCH
+                      HDA Intel PCH at 0xf7f10000 irq 32
+ 1 [Headset        ]: USB-Audio - USB Headset
+                      Vendor USB Headset at usb-0000:00:14.0-2, full speed
+";
+
+    #[test]
+    fn test_parse_cards() {
+        let cards = parse_cards(CARDS_TEXT);
+        assert_eq!(
+            cards,
+            vec![
+                Card {
+                    index: 0,
+                    id: "PCH".to_string(),
+                    name: "HDA Intel PCH".to_string(),
+                    long_name: "HDA Intel PCH at 0xf7f10000 irq 32"
+                        .to_string(),
+                },
+                Card {
+                    index: 1,
+                    id: "Headset".to_string(),
+                    name: "USB Headset".to_string(),
+                    long_name: "Vendor USB Headset at usb-0000:00:14.0-2, \
+                                full speed"
+                        .to_string(),
+                },
+            ]
+        );
+        assert_eq!(parse_cards("--- no soundcards ---\n"), vec![]);
+    }
+
+    #[test]
+    fn test_select() {
+        let config = Config::from_yaml(
+            "sinks:
+  speakers:
+    priority: 2
+    detect:
+      alsa.card_name: HDA Intel PCH
+  headset:
+    priority: 1
+    detect:
+      alsa.card_name: USB Headset
+",
+        )
+        .unwrap();
+        let cards = parse_cards(CARDS_TEXT);
+        let selected = select(&config, &cards).unwrap();
+        assert_eq!(
+            (selected.card.id.as_str(), selected.config_name),
+            ("Headset", "headset")
+        );
+        let selected = select(&config, &cards[..1]).unwrap();
+        assert_eq!(
+            (selected.card.id.as_str(), selected.config_name),
+            ("PCH", "speakers")
+        );
+        assert_eq!(select(&config, &[]), None);
+    }
+}
@@ -14,13 +14,66 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::control::Command;
+use crate::server::ServerFlavor;
+
+mod assertion;
+mod detect;
+mod error;
+mod example;
+mod migration;
+mod secrets;
+
+pub use assertion::Assertion;
+pub use detect::DetectRule;
+pub use error::ParseError;
+pub use example::example_yaml;
+pub use migration::{CURRENT_VERSION, Change, Migration, Migrator, migrate};
+
+/// Reference to the master device of a remap
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MasterRef {
+    /// Device config in the same group as the remap
+    Name(String),
+    /// Sink config, optionally resolved to the monitor source of the sink
+    Sink {
+        sink: String,
+        #[serde(default)]
+        monitor: bool,
+    },
+    /// Source config, for explicitly namespaced references
+    Source { source: String },
+}
+
+impl std::fmt::Display for MasterRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MasterRef::Name(name) => write!(f, "{name}"),
+            MasterRef::Sink {
+                sink,
+                monitor: false,
+            } => write!(f, "sink {sink}"),
+            MasterRef::Sink {
+                sink,
+                monitor: true,
+            } => write!(f, "monitor of sink {sink}"),
+            MasterRef::Source { source } => write!(f, "source {source}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct RemapConfig {
     // Required parameter
-    pub master: String,
+    pub master: MasterRef,
 
     // Device configuration (common for sink/source)
     pub device_name: Option<String>,
@@ -35,46 +88,1912 @@ pub struct RemapConfig {
     pub channel_map: Option<Vec<String>>,
     pub master_channel_map: Option<Vec<String>>,
 
-    // Other options
-    pub resample_method: Option<String>,
-    pub remix: Option<bool>,
-}
+    // Other options
+    pub resample_method: Option<String>,
+    pub remix: Option<bool>,
+
+    /// Only make the device default once a short test stream has
+    /// succeeded on it
+    #[serde(default)]
+    pub verify_playback: bool,
+
+    /// What to do when another device already uses `device_name`
+    #[serde(default)]
+    pub on_name_collision: NameCollisionPolicy,
+
+    /// What to do when someone else unloads the module
+    #[serde(default)]
+    pub on_external_unload: ExternalUnloadPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceMatchConfig {
+    Detect(HashMap<String, DetectRule>),
+    Remap(RemapConfig),
+    Jack(JackConfig),
+    Script(Script),
+}
+
+/// Without rules, which every device satisfies
+impl Default for DeviceMatchConfig {
+    fn default() -> Self {
+        Self::Detect(HashMap::new())
+    }
+}
+
+/// Expression over the properties of a device deciding whether it matches,
+/// compiled when the configuration is loaded (requires the `script`
+/// feature)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Script {
+    source: String,
+    #[cfg(feature = "script")]
+    #[serde(skip)]
+    program: std::sync::Arc<crate::script::Program>,
+}
+
+impl Script {
+    /// Whether the device matches, a script failing at runtime counting as
+    /// no match
+    pub fn matches(
+        &self,
+        device_name: &str,
+        properties: &BTreeMap<String, String>,
+    ) -> bool {
+        #[cfg(feature = "script")]
+        match self.program.evaluate(properties) {
+            Ok(result) => result,
+            Err(e) => {
+                log::warn!("Script failed for {device_name}: {e}");
+                false
+            }
+        }
+        // Scripts are rejected on load without the feature
+        #[cfg(not(feature = "script"))]
+        {
+            let _ = (device_name, properties);
+            false
+        }
+    }
+}
+
+impl TryFrom<String> for Script {
+    type Error = String;
+
+    #[cfg(feature = "script")]
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let program = crate::script::compile(&source)
+            .map_err(|e| format!("invalid script: {e}"))?;
+        Ok(Script {
+            source,
+            program: std::sync::Arc::new(program),
+        })
+    }
+
+    #[cfg(not(feature = "script"))]
+    fn try_from(_source: String) -> Result<Self, Self::Error> {
+        Err("scripts need autopulsed built with the script feature"
+            .to_string())
+    }
+}
+
+impl From<Script> for String {
+    fn from(script: Script) -> Self {
+        script.source
+    }
+}
+
+impl JsonSchema for Script {
+    fn schema_name() -> String {
+        "Script".to_string()
+    }
+
+    fn json_schema(
+        generator: &mut schemars::r#gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        String::json_schema(generator)
+    }
+}
+
+/// Shortcut for matching the bridge devices of module-jack-sink/source
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct JackConfig {
+    /// Match only the bridge with this JACK client name
+    pub client_name: Option<String>,
+    /// Load the bridge module ourselves while a JACK server is running
+    #[serde(default)]
+    pub load: bool,
+    /// Whether the bridge connects to the physical JACK ports
+    pub connect: Option<bool>,
+    /// What to do when someone else unloads the bridge module
+    #[serde(default)]
+    pub on_external_unload: ExternalUnloadPolicy,
+}
+
+/// Encoded format a sink can pass through to a receiver
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PassthroughFormat {
+    Ac3,
+    Eac3,
+    Mpeg,
+    Dts,
+    Mpeg2Aac,
+}
+
+/// Priority of a device config, lower numbers winning
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "PriorityRepr", into = "PriorityRepr")]
+pub enum Priority {
+    Fixed(u32),
+    /// Priority of the remap master plus the offset, written as
+    /// `inherit`, `inherit+N` or `inherit-N`
+    Inherit(i64),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum PriorityRepr {
+    Fixed(u32),
+    Expression(String),
+}
+
+impl JsonSchema for Priority {
+    fn schema_name() -> String {
+        "Priority".to_string()
+    }
+
+    fn json_schema(
+        generator: &mut schemars::r#gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        PriorityRepr::json_schema(generator)
+    }
+}
+
+impl TryFrom<PriorityRepr> for Priority {
+    type Error = String;
+
+    fn try_from(repr: PriorityRepr) -> Result<Self, Self::Error> {
+        let expression = match repr {
+            PriorityRepr::Fixed(priority) => {
+                return Ok(Priority::Fixed(priority));
+            }
+            PriorityRepr::Expression(expression) => expression,
+        };
+        let invalid = || {
+            format!(
+                "invalid priority '{expression}', expected a number, \
+                 'inherit', 'inherit+N' or 'inherit-N'"
+            )
+        };
+        let offset = expression
+            .trim()
+            .strip_prefix("inherit")
+            .ok_or_else(invalid)?
+            .trim();
+        if offset.is_empty() {
+            return Ok(Priority::Inherit(0));
+        }
+        let (sign, digits) = match offset.split_at(1) {
+            ("+", digits) => (1, digits),
+            ("-", digits) => (-1, digits),
+            _ => return Err(invalid()),
+        };
+        let magnitude: u32 = digits.trim().parse().map_err(|_| invalid())?;
+        Ok(Priority::Inherit(sign * i64::from(magnitude)))
+    }
+}
+
+impl From<Priority> for PriorityRepr {
+    fn from(priority: Priority) -> Self {
+        match priority {
+            Priority::Fixed(priority) => PriorityRepr::Fixed(priority),
+            Priority::Inherit(0) => {
+                PriorityRepr::Expression("inherit".to_string())
+            }
+            Priority::Inherit(offset) => {
+                PriorityRepr::Expression(format!("inherit{offset:+}"))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DeviceConfig {
+    pub priority: Option<Priority>,
+    #[serde(flatten)]
+    pub match_config: DeviceMatchConfig,
+    /// Formats the sink accepts besides PCM, set when it is recognized
+    pub passthrough: Option<Vec<PassthroughFormat>>,
+    /// Actions run when a device is recognized as this config
+    #[serde(default)]
+    pub on_appear: Vec<Command>,
+    /// Actions run when a device recognized as this config disappears
+    #[serde(default)]
+    pub on_disappear: Vec<Command>,
+    /// Recognize a matching device only as this config, even if it
+    /// matches others too
+    #[serde(default)]
+    pub exclusive: bool,
+    /// Recognize devices by the weights of the detect rules they satisfy
+    /// instead of requiring every rule
+    pub scoring: Option<ScoringConfig>,
+    /// Capabilities a device must have to match, whatever its properties
+    pub requires: Option<CapabilityRequirements>,
+    /// Runtime state a device must be in to be recognized as the config,
+    /// checked again whenever the device changes
+    pub when: Option<RuntimeCondition>,
+    /// Physical port the device must be plugged into, as in the udev
+    /// `ID_PATH` of the device, such as "usb-0:2"
+    pub port_path: Option<String>,
+    /// Compare the values of every detect rule regardless of letter case,
+    /// like `ignore_case` on each of them
+    #[serde(default)]
+    pub case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RuntimeCondition {
+    pub state: Option<DeviceRunState>,
+    pub muted: Option<bool>,
+}
+
+/// Whether streams play on or record from a device, as the server reports
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceRunState {
+    Running,
+    Idle,
+    Suspended,
+}
+
+/// Checked against the sample spec and flags of a device
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct CapabilityRequirements {
+    /// Fewest channels the device must have
+    pub min_channels: Option<u8>,
+    /// Sample rates of which the device must run at one
+    #[serde(default)]
+    pub sample_rates: Vec<u32>,
+    /// Whether the device must have a hardware volume control, or must not
+    pub hardware_volume: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ScoringConfig {
+    /// Score a device needs to be recognized as the config
+    pub threshold: u32,
+    /// Weight of the detect rule for each property, 1 when not given
+    #[serde(default)]
+    pub weights: HashMap<String, u32>,
+}
+
+impl ScoringConfig {
+    fn weight(&self, key: &str) -> u32 {
+        self.weights.get(key).copied().unwrap_or(1)
+    }
+}
+
+impl DeviceConfig {
+    /// Whether devices recognized as this config must pass a playback
+    /// check before becoming default
+    pub fn requires_playback_check(&self) -> bool {
+        matches!(
+            &self.match_config,
+            DeviceMatchConfig::Remap(remap) if remap.verify_playback
+        )
+    }
+
+    /// Whether a device with the name and properties is recognized as this
+    /// config as far as they tell, not for remaps, which are recognized by
+    /// the module that created them
+    pub fn matches_properties(
+        &self,
+        device_name: &str,
+        properties: &BTreeMap<String, String>,
+    ) -> Option<bool> {
+        if let Some(port_path) = &self.port_path
+            && !matches!(self.match_config, DeviceMatchConfig::Remap(_))
+            && !detect::port_path_matches(port_path, properties)
+        {
+            return Some(false);
+        }
+        match &self.match_config {
+            DeviceMatchConfig::Detect(rules) => Some(match &self.scoring {
+                Some(scoring) => self
+                    .match_score(properties)
+                    .is_some_and(|score| score >= scoring.threshold),
+                None => rules.iter().all(|(key, rule)| {
+                    rule.matches_with(
+                        properties.get(key).map(String::as_str),
+                        self.case_insensitive,
+                    )
+                }),
+            }),
+            DeviceMatchConfig::Remap(_) => None,
+            DeviceMatchConfig::Jack(jack) => {
+                Some(crate::jack::matches(jack, properties))
+            }
+            DeviceMatchConfig::Script(script) => {
+                Some(script.matches(device_name, properties))
+            }
+        }
+    }
+
+    /// Sum of the weights of the detect rules that the properties satisfy,
+    /// for configs in scoring mode
+    pub fn match_score(
+        &self,
+        properties: &BTreeMap<String, String>,
+    ) -> Option<u32> {
+        let (DeviceMatchConfig::Detect(rules), Some(scoring)) =
+            (&self.match_config, &self.scoring)
+        else {
+            return None;
+        };
+        Some(
+            rules
+                .iter()
+                .filter(|&(key, rule)| {
+                    rule.matches_with(
+                        properties.get(key).map(String::as_str),
+                        self.case_insensitive,
+                    )
+                })
+                .map(|(key, _)| scoring.weight(key))
+                .sum(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Config {
+    /// Version of the configuration format, that of files written before
+    /// versioning when unset; older configurations are migrated on load
+    pub config_version: Option<u64>,
+    #[serde(default)]
+    pub sinks: HashMap<String, DeviceConfig>,
+    #[serde(default)]
+    pub sources: HashMap<String, DeviceConfig>,
+    /// Reject remap masters referring to undefined config names
+    #[serde(default)]
+    pub strict: bool,
+    /// Manage sinks, or leave them to another tool (default: true)
+    pub manage_sinks: Option<bool>,
+    /// Manage sources, or leave them to another tool (default: true)
+    pub manage_sources: Option<bool>,
+    #[serde(default)]
+    pub server_quirks: ServerQuirksConfig,
+    /// What to do while a session manager such as WirePlumber is running
+    #[serde(default)]
+    pub session_manager_policy: SessionManagerPolicy,
+    /// Play a sound on the default sink after switching it
+    #[serde(default)]
+    pub play_test_sound: bool,
+    /// WAV file to play instead of the built-in chime
+    pub test_sound: Option<PathBuf>,
+    /// Follow the peak level of the default devices for status reports
+    #[serde(default)]
+    pub peak_monitor: bool,
+    /// Switch away from a default sink that stays silent while playing
+    pub silence_failover: Option<SilenceFailoverConfig>,
+    /// Sink and source configs made default together
+    #[serde(default)]
+    pub bundles: HashMap<String, BundleConfig>,
+    /// Switch Bluetooth headsets to a profile with a microphone while
+    /// something records
+    pub headset_profile_switching: Option<HeadsetProfileConfig>,
+    /// Play notification sounds on a fixed sink
+    pub notifications: Option<NotificationsConfig>,
+    /// Shell command run when recording from managed sources starts and
+    /// stops, with `AUTOPULSED_RECORDING` set to 1 or 0
+    pub recording_hook: Option<String>,
+    /// Group devices by a property and restrict defaults to one group
+    pub default_scope: Option<DefaultScopeConfig>,
+    /// What to do when a device matches several configs
+    #[serde(default)]
+    pub multi_match: MultiMatchPolicy,
+    /// Seconds between log summaries of devices matching no config
+    pub unmanaged_summary_interval: Option<u64>,
+    /// Seconds between listing devices and modules again to repair what
+    /// missed events left behind (default: 300, 0 disables it)
+    pub reconcile_interval: Option<u64>,
+    /// Bounds of what is kept in memory and in the journal, for long-running
+    /// instances on small devices
+    pub limits: Option<LimitsConfig>,
+    /// Set the defaults found at startup again when exiting
+    #[serde(default)]
+    pub restore_defaults_on_exit: bool,
+    /// Write a summary of the run as JSON to this file when exiting; it
+    /// is removed at startup, so that a crash leaves none
+    pub summary_file: Option<PathBuf>,
+    /// File keeping the defaults chosen, pausing and privacy mode set by
+    /// commands across restarts, so that the configuration itself can stay
+    /// read-only
+    pub overrides_file: Option<PathBuf>,
+    /// Count default switches and how long each config was the default in
+    /// a local file, for `autopulsed stats`; nothing is sent anywhere
+    pub statistics: Option<StatisticsConfig>,
+    /// Log the candidates of every default and remap decision
+    #[serde(default)]
+    pub trace_decisions: bool,
+    /// Whether sink and source entries that cannot be parsed fail the
+    /// whole configuration or are left out
+    #[serde(default)]
+    pub on_invalid_entry: InvalidEntryPolicy,
+    /// Entries left out by `on_invalid_entry: skip`, with the reason
+    #[serde(skip)]
+    pub skipped_entries: BTreeMap<String, String>,
+    /// Changes made to bring the configuration to the current version
+    #[serde(skip)]
+    pub migrations: Vec<String>,
+    /// Control by OSC messages (requires the `osc` feature)
+    #[cfg(feature = "osc")]
+    pub osc: Option<OscConfig>,
+    /// REST API for home-automation systems
+    pub http: Option<HttpConfig>,
+    /// Home Assistant integration (requires the `mqtt` feature)
+    #[cfg(feature = "mqtt")]
+    pub mqtt: Option<MqttConfig>,
+    /// Choose the default ALSA card while no sound server is running
+    /// (requires the `alsa` feature)
+    #[cfg(feature = "alsa")]
+    pub alsa_fallback: Option<AlsaFallbackConfig>,
+    /// HTTP endpoints the events are posted to as JSON
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    /// Example devices with the configs they must be recognized as, checked
+    /// by `check --run-assertions`
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+}
+
+#[cfg(feature = "osc")]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OscConfig {
+    /// UDP address to listen on, e.g. "127.0.0.1:9000"
+    pub listen: String,
+    /// Actions by OSC address
+    #[serde(default)]
+    pub mappings: HashMap<String, Command>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct HttpConfig {
+    /// TCP address to listen on, which must be a loopback address
+    pub listen: String,
+    /// Expected in the `Authorization: Bearer` header of every request
+    #[serde(default)]
+    pub token: String,
+    /// File to read the token from instead, relative to the systemd
+    /// credentials if any
+    pub token_file: Option<PathBuf>,
+}
+
+#[cfg(feature = "mqtt")]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MqttConfig {
+    /// Broker address, e.g. "localhost:1883"
+    pub broker: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// File to read the password from instead, relative to the systemd
+    /// credentials if any
+    pub password_file: Option<PathBuf>,
+    /// Defaults to "autopulsed"
+    pub client_id: Option<String>,
+    /// Defaults to "homeassistant"
+    pub discovery_prefix: Option<String>,
+    /// Prefix of state and command topics, defaults to "autopulsed"
+    pub topic_prefix: Option<String>,
+}
+
+/// Names of the events, as in the `event` field of their JSON
+pub const EVENT_NAMES: &[&str] = &[
+    "recognized",
+    "unrecognized",
+    "default_changed",
+    "module_loaded",
+    "module_unloaded",
+    "module_failed",
+    "recording_started",
+    "recording_stopped",
+    "silence_detected",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    /// Endpoint, e.g. "http://localhost:8123/api/webhook/audio"; only
+    /// plain HTTP is supported
+    pub url: String,
+    /// Events to post, by name (default: all)
+    pub events: Option<Vec<String>>,
+    /// Key signing the body by HMAC-SHA256 in the X-Autopulsed-Signature
+    /// header
+    pub secret: Option<String>,
+    /// File to read the secret from instead, relative to the systemd
+    /// credentials if any
+    pub secret_file: Option<PathBuf>,
+    /// Further attempts after a failed delivery, waiting twice as long
+    /// before each (default: 3)
+    pub retries: Option<u32>,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: &str) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.iter().any(|name| name == event))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
+}
+
+#[cfg(feature = "alsa")]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AlsaFallbackConfig {
+    /// File to write the default card to in the syntax of asound.conf,
+    /// for inclusion from /etc/asound.conf or ~/.asoundrc
+    pub defaults_file: PathBuf,
+}
+
+/// Sink and source configs of one device, such as a USB headset
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BundleConfig {
+    pub sink: String,
+    pub source: String,
+    /// Among bundles whose devices are all present, the lowest wins
+    pub priority: Option<u32>,
+    /// What to do while only one of the devices is present
+    #[serde(default)]
+    pub partial: PartialBundlePolicy,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PartialBundlePolicy {
+    /// Let the present device compete by its own priority
+    #[default]
+    Individual,
+    /// Make neither device default until both are present
+    Ignore,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct NotificationsConfig {
+    /// Sink config to route notification streams to
+    pub sink: String,
+    /// Values of `media.role` to route, defaults to "event"
+    pub roles: Option<Vec<String>>,
+    /// Only route streams of clients run by these users, defaults to those
+    /// of any user
+    pub owner_uid: Option<Vec<u32>>,
+}
+
+impl NotificationsConfig {
+    pub fn routes_role(&self, role: &str) -> bool {
+        match &self.roles {
+            Some(roles) => roles.iter().any(|r| r == role),
+            None => role == "event",
+        }
+    }
+
+    /// Whether streams of a client run by the user are routed, given the
+    /// uid if it is known
+    pub fn routes_owner(&self, uid: Option<u32>) -> bool {
+        match &self.owner_uid {
+            Some(uids) => uid.is_some_and(|uid| uids.contains(&uid)),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct HeadsetProfileConfig {
+    /// Card profile to switch to, by default the available headset or
+    /// handsfree profile with the highest priority
+    pub headset_profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DefaultScopeConfig {
+    /// Property whose value groups the devices, defaults to "device.bus"
+    pub group_by: Option<String>,
+    /// Only devices of this group become default by priority; all groups
+    /// compete when unset
+    pub group: Option<String>,
+}
+
+impl DefaultScopeConfig {
+    pub fn group_by(&self) -> &str {
+        self.group_by.as_deref().unwrap_or("device.bus")
+    }
+
+    /// Group of a device with the given properties, if it has the property
+    pub fn group_of<'a>(
+        &self,
+        properties: &'a BTreeMap<String, String>,
+    ) -> Option<&'a str> {
+        properties.get(self.group_by()).map(String::as_str)
+    }
+
+    /// Whether a device with the given properties may become default by
+    /// priority
+    pub fn includes(&self, properties: &BTreeMap<String, String>) -> bool {
+        match &self.group {
+            Some(group) => self.group_of(properties) == Some(group.as_str()),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SilenceFailoverConfig {
+    /// Peak level (0.0 to 1.0) up to which the sink counts as silent,
+    /// defaults to 0.001
+    pub threshold: Option<f32>,
+    /// Seconds of silence before failing over, defaults to 10
+    pub duration: Option<u64>,
+}
+
+impl SilenceFailoverConfig {
+    pub fn threshold(&self) -> f32 {
+        self.threshold.unwrap_or(0.001)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration.unwrap_or(10))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct LimitsConfig {
+    /// Actions kept in the journal file (default: 1000)
+    pub journal_entries: Option<usize>,
+    /// Events queued for each HTTP or MQTT subscriber that has fallen
+    /// behind, beyond which it misses events (default: 1000)
+    pub event_backlog: Option<usize>,
+    /// Bytes of properties kept for each device; beyond, the longest values
+    /// of properties no config refers to are dropped (default: 16384)
+    pub device_properties: Option<usize>,
+}
+
+impl LimitsConfig {
+    pub fn device_properties(&self) -> usize {
+        self.device_properties.unwrap_or(16384)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct StatisticsConfig {
+    /// Defaults to statistics.json in the state directory
+    pub file: Option<PathBuf>,
+    /// Days after which counting starts anew, the previous counts being
+    /// kept as file.1, file.2 and so on (default: 30)
+    pub rotate_days: Option<u64>,
+    /// Previous files kept (default: 3)
+    pub keep: Option<usize>,
+}
+
+impl StatisticsConfig {
+    pub fn rotate_days(&self) -> u64 {
+        self.rotate_days.unwrap_or(30)
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(3)
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MultiMatchPolicy {
+    /// Recognize the device as all of the configs
+    #[default]
+    Allow,
+    /// Recognize the device as all of the configs, with a warning
+    Warn,
+    /// Recognize the device as none of the configs
+    Error,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionManagerPolicy {
+    /// Leave everything to the session manager
+    Defer,
+    /// Only manage remap devices, leaving defaults to the session manager
+    Cooperate,
+    /// Manage everything regardless of the session manager
+    #[default]
+    Override,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidEntryPolicy {
+    /// Reject the configuration
+    #[default]
+    Fail,
+    /// Load the configuration without the entry, with a warning
+    Skip,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NameCollisionPolicy {
+    /// Leave the remap unloaded, with a warning
+    #[default]
+    Error,
+    /// Load the remap under the name with a counter appended
+    Suffix,
+    /// Recognize the existing device as the remap instead of loading it
+    Adopt,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalUnloadPolicy {
+    /// Load the module again
+    #[default]
+    Reload,
+    /// Leave the module unloaded until the configuration is reloaded
+    Accept,
+}
+
+/// Overrides for the behavior adjustments chosen by the detected server
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ServerQuirksConfig {
+    /// Use the quirks of this server instead of the detected one
+    pub preset: Option<ServerFlavor>,
+    pub match_remaps_by_name: Option<bool>,
+    pub verify_default: Option<bool>,
+}
+
+impl HttpConfig {
+    fn validate(&self) -> Result<(), String> {
+        let address: SocketAddr = self.listen.parse().map_err(|e| {
+            format!("Invalid HTTP listen address '{}': {e}", self.listen)
+        })?;
+        if !address.ip().is_loopback() {
+            return Err(format!(
+                "HTTP listen address '{}' is not a loopback address",
+                self.listen
+            ));
+        }
+        if self.token.is_empty() {
+            return Err("HTTP token must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttConfig {
+    fn validate(&self) -> Result<(), String> {
+        // MQTT 3.1.1 only allows a password along with a user name
+        if self.username.is_none()
+            && (self.password.is_some() || self.password_file.is_some())
+        {
+            return Err("MQTT password needs a username".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Name of the sink or source config matching the devices that no other
+/// config matches
+pub const CATCH_ALL_CONFIG: &str = "_default";
+
+/// Keys known at the top level of the configuration
+const CONFIG_KEYS: &[&str] = &[
+    "config_version",
+    "sinks",
+    "sources",
+    "strict",
+    "manage_sinks",
+    "manage_sources",
+    "server_quirks",
+    "session_manager_policy",
+    "play_test_sound",
+    "test_sound",
+    "peak_monitor",
+    "silence_failover",
+    "bundles",
+    "headset_profile_switching",
+    "notifications",
+    "recording_hook",
+    "default_scope",
+    "multi_match",
+    "unmanaged_summary_interval",
+    "reconcile_interval",
+    "limits",
+    "restore_defaults_on_exit",
+    "summary_file",
+    "overrides_file",
+    "statistics",
+    "trace_decisions",
+    "on_invalid_entry",
+    "osc",
+    "http",
+    "mqtt",
+    "alsa_fallback",
+    "webhooks",
+    "assertions",
+];
+
+/// Top-level keys of sections that need a feature, with the feature and
+/// whether this build has it
+const FEATURE_KEYS: &[(&str, &str, bool)] = &[
+    ("osc", "osc", cfg!(feature = "osc")),
+    ("mqtt", "mqtt", cfg!(feature = "mqtt")),
+    ("alsa_fallback", "alsa", cfg!(feature = "alsa")),
+];
+
+/// Top-level keys that the configuration of this build has
+#[cfg(test)]
+fn built_config_keys() -> Vec<&'static str> {
+    let mut keys: Vec<_> = CONFIG_KEYS
+        .iter()
+        .copied()
+        .filter(|key| {
+            FEATURE_KEYS.iter().all(|&(feature_key, _, enabled)| {
+                enabled || feature_key != *key
+            })
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Keys known in a sink or source config
+const DEVICE_KEYS: &[&str] = &[
+    "priority",
+    "detect",
+    "remap",
+    "jack",
+    "script",
+    "passthrough",
+    "on_appear",
+    "on_disappear",
+    "exclusive",
+    "scoring",
+    "requires",
+    "when",
+    "port_path",
+    "case_insensitive",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The configuration cannot be used
+    Error,
+    /// The configuration is accepted but probably not what was meant
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Everything found wrong with a configuration, in the order of checking
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn error(&mut self, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            message,
+        });
+    }
+
+    pub fn warning(&mut self, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &str> {
+        self.messages(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &str> {
+        self.messages(Severity::Warning)
+    }
+
+    fn messages(&self, severity: Severity) -> impl Iterator<Item = &str> {
+        self.issues
+            .iter()
+            .filter(move |issue| issue.severity == severity)
+            .map(|issue| issue.message.as_str())
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}: {}", issue.severity, issue.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Parse the configuration file, pointing at the offending line on
+    /// failure
+    ///
+    /// Configurations of older versions are migrated first, recording the
+    /// changes in `migrations`. With `on_invalid_entry: skip`, sink and
+    /// source entries that cannot be parsed are left out and recorded in
+    /// `skipped_entries`.
+    pub fn from_yaml(content: &str) -> Result<Self, ParseError> {
+        let Ok(mut raw) = serde_yaml::from_str::<serde_yaml::Value>(content)
+        else {
+            return Self::parse_entries(content);
+        };
+        let migrations = migrate(&mut raw).map_err(|message| {
+            ParseError::at_key(content, "config_version", message)
+        })?;
+        if migrations.is_empty() {
+            return Self::parse_entries(content);
+        }
+        let migrated = serde_yaml::to_string(&raw)
+            .map_err(|error| ParseError::detached(&error))?;
+        let mut config = Self::parse_entries(&migrated)?;
+        config.migrations = migrations;
+        Ok(config)
+    }
+
+    fn parse_entries(content: &str) -> Result<Self, ParseError> {
+        let error = match serde_yaml::from_str(content) {
+            Ok(config) => return Ok(config),
+            Err(error) => ParseError::new(content, &error),
+        };
+        let Ok(mut raw) = serde_yaml::from_str::<serde_yaml::Value>(content)
+        else {
+            return Err(error);
+        };
+        let policy = raw
+            .get("on_invalid_entry")
+            .and_then(|value| serde_yaml::from_value(value.clone()).ok());
+        if policy != Some(InvalidEntryPolicy::Skip) {
+            return Err(error);
+        }
+
+        let skipped = Self::remove_invalid_entries(&mut raw);
+        if skipped.is_empty() {
+            return Err(error);
+        }
+        // Whatever is still wrong is outside of the entries
+        let mut config: Config = serde_yaml::from_value(raw)
+            .map_err(|error| ParseError::detached(&error))?;
+        config.skipped_entries = skipped;
+        Ok(config)
+    }
+
+    /// Remove the sink and source entries that are not valid device
+    /// configs, returning their paths and errors
+    fn remove_invalid_entries(
+        raw: &mut serde_yaml::Value,
+    ) -> BTreeMap<String, String> {
+        let mut skipped = BTreeMap::new();
+        for group in ["sinks", "sources"] {
+            let Some(entries) =
+                raw.get_mut(group).and_then(|v| v.as_mapping_mut())
+            else {
+                continue;
+            };
+            entries.retain(|name, value| {
+                let error = match serde_yaml::from_value::<DeviceConfig>(
+                    value.clone(),
+                ) {
+                    Ok(_) => return true,
+                    Err(error) => error,
+                };
+                let name = name.as_str().unwrap_or("?");
+                skipped.insert(format!("{group}.{name}"), error.to_string());
+                false
+            });
+        }
+        skipped
+    }
+
+    /// Properties that detect rules match on or that group the devices
+    pub fn referenced_properties(&self) -> HashSet<&str> {
+        let detected = self
+            .sinks
+            .values()
+            .chain(self.sources.values())
+            .filter_map(|config| match &config.match_config {
+                DeviceMatchConfig::Detect(rules) => Some(rules.keys()),
+                _ => None,
+            })
+            .flatten()
+            .map(String::as_str);
+        let port_paths = if self
+            .sinks
+            .values()
+            .chain(self.sources.values())
+            .any(|config| config.port_path.is_some())
+        {
+            detect::PORT_PATH_PROPERTIES
+        } else {
+            &[]
+        };
+        detected
+            .chain(port_paths.iter().copied())
+            .chain(self.default_scope.as_ref().map(|scope| scope.group_by()))
+            .collect()
+    }
+
+    /// Groups turned off by `manage_sinks` or `manage_sources`
+    pub fn unmanaged_groups(&self) -> Vec<&'static str> {
+        [
+            ("sinks", self.manage_sinks),
+            ("sources", self.manage_sources),
+        ]
+        .into_iter()
+        .filter(|(_, manage)| *manage == Some(false))
+        .map(|(group, _)| group)
+        .collect()
+    }
+
+    /// Leave out the configs of the groups that are not managed, so that
+    /// nothing acts upon their devices
+    pub fn drop_unmanaged_groups(&mut self) {
+        if self.manage_sinks == Some(false) {
+            self.sinks.clear();
+        }
+        if self.manage_sources == Some(false) {
+            self.sources.clear();
+        }
+    }
+
+    /// Read the secrets that are given as files
+    pub fn resolve_secrets(&mut self) -> Result<(), String> {
+        if let Some(http) = &mut self.http {
+            let mut token =
+                (!http.token.is_empty()).then(|| http.token.clone());
+            secrets::resolve_field(
+                &mut token,
+                http.token_file.as_deref(),
+                "token",
+            )?;
+            http.token = token.unwrap_or_default();
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &mut self.mqtt {
+            secrets::resolve_field(
+                &mut mqtt.password,
+                mqtt.password_file.as_deref(),
+                "password",
+            )?;
+        }
+        for webhook in &mut self.webhooks {
+            secrets::resolve_field(
+                &mut webhook.secret,
+                webhook.secret_file.as_deref(),
+                "secret",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// JSON Schema of the configuration file, derived from the definitions
+    /// above so that it cannot drift from them
+    pub fn json_schema() -> schemars::schema::RootSchema {
+        schemars::schema_for!(Config)
+    }
+
+    /// Effective priority of a config in "sinks" or "sources", following
+    /// inherited priorities through remap masters
+    ///
+    /// Returns `None` if the config has no priority, or inherits it from a
+    /// master that is undefined, has none or is part of a cycle.
+    pub fn resolve_priority(
+        &self,
+        device_type: &str,
+        name: &str,
+    ) -> Option<u32> {
+        let mut offset = 0i64;
+        let mut current = (device_type, name);
+        // Each config can be visited once, anything longer is a cycle
+        for _ in 0..=self.sinks.len() + self.sources.len() {
+            let (group, name) = current;
+            let devices = match group {
+                "sinks" => &self.sinks,
+                _ => &self.sources,
+            };
+            let config = devices.get(name)?;
+            match config.priority? {
+                Priority::Fixed(priority) => {
+                    let priority = i64::from(priority) + offset;
+                    return Some(priority.clamp(0, u32::MAX.into()) as u32);
+                }
+                Priority::Inherit(step) => {
+                    let DeviceMatchConfig::Remap(remap) = &config.match_config
+                    else {
+                        return None;
+                    };
+                    offset += step;
+                    current = Self::master_target(&remap.master, group);
+                }
+            }
+        }
+        None
+    }
+
+    /// Group and name of the master config of a remap config in "sinks" or
+    /// "sources"
+    pub fn remap_master<'a>(
+        &'a self,
+        device_type: &'a str,
+        name: &str,
+    ) -> Option<(&'a str, &'a str)> {
+        let devices = match device_type {
+            "sinks" => &self.sinks,
+            _ => &self.sources,
+        };
+        match &devices.get(name)?.match_config {
+            DeviceMatchConfig::Remap(remap) => {
+                Some(Self::master_target(&remap.master, device_type))
+            }
+            _ => None,
+        }
+    }
+
+    /// Check the configuration as a whole, collecting every error and
+    /// warning instead of stopping at the first one
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        for (path, reason) in &self.skipped_entries {
+            report.warning(format!(
+                "Invalid entry '{path}' is skipped: {reason}"
+            ));
+        }
+        for change in &self.migrations {
+            report.warning(format!(
+                "Outdated configuration: {change}; run `autopulsed \
+                 migrate-config` to update the file"
+            ));
+        }
+        Self::validate_master_kinds(&self.sinks, "sinks", &mut report);
+        Self::validate_master_kinds(&self.sources, "sources", &mut report);
+        Self::validate_property_keys(&self.sinks, "sinks", &mut report);
+        Self::validate_property_keys(&self.sources, "sources", &mut report);
+        Self::validate_remap_references(&self.sinks, "sinks", &mut report);
+        Self::validate_remap_references(&self.sources, "sources", &mut report);
+        self.validate_master_existence(&mut report);
+        self.validate_duplicate_names(&mut report);
+        let mut passthrough_sources: Vec<_> = self
+            .sources
+            .iter()
+            .filter(|(_, config)| config.passthrough.is_some())
+            .map(|(name, _)| name)
+            .collect();
+        passthrough_sources.sort();
+        for name in passthrough_sources {
+            report.error(format!(
+                "Source '{name}' has passthrough formats, which only sinks \
+                 support"
+            ));
+        }
+        for (device_type, devices) in
+            [("sinks", &self.sinks), ("sources", &self.sources)]
+        {
+            if devices.get(CATCH_ALL_CONFIG).is_some_and(|config| {
+                !matches!(config.match_config, DeviceMatchConfig::Detect(_))
+            }) {
+                report.error(format!(
+                    "'{CATCH_ALL_CONFIG}' in {device_type} must use detect \
+                     rules"
+                ));
+            }
+        }
+        Self::validate_actions(&self.sinks, &mut report);
+        Self::validate_actions(&self.sources, &mut report);
+        Self::validate_detect_rules(&self.sinks, &mut report);
+        Self::validate_detect_rules(&self.sources, &mut report);
+        Self::validate_scoring(&self.sinks, &mut report);
+        Self::validate_scoring(&self.sources, &mut report);
+        Self::validate_port_paths(&self.sinks, &mut report);
+        Self::validate_port_paths(&self.sources, &mut report);
+        self.validate_bundles(&mut report);
+        self.validate_webhooks(&mut report);
+        if let Some(notifications) = &self.notifications
+            && !self.sinks.contains_key(&notifications.sink)
+        {
+            report.error(format!(
+                "Notifications refer to undefined sink '{}'",
+                notifications.sink
+            ));
+        }
+        if let Some(http) = &self.http
+            && let Err(e) = http.validate()
+        {
+            report.error(e);
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &self.mqtt
+            && let Err(e) = mqtt.validate()
+        {
+            report.error(e);
+        }
+        self.validate_priorities("sinks", &mut report);
+        self.validate_priorities("sources", &mut report);
+        self.validate_reachability(&mut report);
+        self.validate_detect_overlaps("sinks", &mut report);
+        self.validate_detect_overlaps("sources", &mut report);
+        report
+    }
+
+    /// Paths of keys in the raw configuration that are not known and thus
+    /// silently ignored when parsing, such as misspelled options
+    ///
+    /// Keys that were renamed or retired are left to the migration.
+    pub fn unknown_keys(raw: &serde_yaml::Value) -> Vec<String> {
+        let mut unknown = Vec::new();
+        let mut raw = raw.clone();
+        if migrate(&mut raw).is_err() {
+            return unknown;
+        }
+        let Some(root) = raw.as_mapping() else {
+            return unknown;
+        };
+        for (key, value) in root {
+            let Some(key) = key.as_str() else { continue };
+            if !CONFIG_KEYS.contains(&key) {
+                unknown.push(key.to_string());
+                continue;
+            }
+            if key != "sinks" && key != "sources" {
+                continue;
+            }
+            let Some(devices) = value.as_mapping() else {
+                continue;
+            };
+            for (name, device) in devices {
+                let (Some(name), Some(device)) =
+                    (name.as_str(), device.as_mapping())
+                else {
+                    continue;
+                };
+                unknown.extend(
+                    device
+                        .keys()
+                        .filter_map(|field| field.as_str())
+                        .filter(|field| !DEVICE_KEYS.contains(field))
+                        .map(|field| format!("{key}.{name}.{field}")),
+                );
+            }
+        }
+        unknown.sort();
+        unknown
+    }
+
+    /// Keys in the raw configuration of sections that this build leaves
+    /// out, with the feature they need
+    pub fn disabled_keys(
+        raw: &serde_yaml::Value,
+    ) -> Vec<(&'static str, &'static str)> {
+        FEATURE_KEYS
+            .iter()
+            .filter(|&&(key, _, enabled)| !enabled && raw.get(key).is_some())
+            .map(|&(key, feature, _)| (key, feature))
+            .collect()
+    }
+
+    fn validate_actions(
+        devices: &HashMap<String, DeviceConfig>,
+        report: &mut ValidationReport,
+    ) {
+        for (name, config) in sorted(devices) {
+            if config
+                .on_appear
+                .iter()
+                .chain(&config.on_disappear)
+                .any(|action| *action == Command::Reload)
+            {
+                report.error(format!(
+                    "Device '{name}' cannot reload the configuration as an \
+                     action"
+                ));
+            }
+        }
+    }
+
+    fn validate_detect_rules(
+        devices: &HashMap<String, DeviceConfig>,
+        report: &mut ValidationReport,
+    ) {
+        for (name, config) in sorted(devices) {
+            let DeviceMatchConfig::Detect(rules) = &config.match_config else {
+                continue;
+            };
+            for (key, rule) in sorted(rules) {
+                if matches!(rule, DetectRule::Presence(presence) if presence.is_empty())
+                {
+                    report.error(format!(
+                        "Detect rule of device '{name}' for '{key}' has no \
+                         condition; use exists, absent or non_empty"
+                    ));
+                }
+                if let DetectRule::Regex(regex) = rule
+                    && let Some(error) = regex.regex.error()
+                {
+                    report.error(format!(
+                        "Detect rule of device '{name}' for '{key}' has an \
+                         invalid regex: {error}"
+                    ));
+                }
+            }
+        }
+    }
+
+    fn validate_scoring(
+        devices: &HashMap<String, DeviceConfig>,
+        report: &mut ValidationReport,
+    ) {
+        for (name, config) in sorted(devices) {
+            let Some(scoring) = &config.scoring else {
+                continue;
+            };
+            let DeviceMatchConfig::Detect(rules) = &config.match_config else {
+                report.error(format!(
+                    "Device '{name}' uses scoring, which needs detect rules"
+                ));
+                continue;
+            };
+            for (key, _) in sorted(&scoring.weights) {
+                if !rules.contains_key(key) {
+                    report.error(format!(
+                        "Device '{name}' has a weight for '{key}', which is \
+                         not one of its detect rules"
+                    ));
+                }
+            }
+            let highest: u32 =
+                rules.keys().map(|key| scoring.weight(key)).sum();
+            if scoring.threshold > highest {
+                report.error(format!(
+                    "Device '{name}' needs a score of {}, but its detect \
+                     rules add up to {highest} at most",
+                    scoring.threshold
+                ));
+            } else if scoring.threshold == 0 {
+                report.warning(format!(
+                    "Device '{name}' has a scoring threshold of 0 and \
+                     matches every device"
+                ));
+            }
+        }
+    }
+
+    fn validate_port_paths(
+        devices: &HashMap<String, DeviceConfig>,
+        report: &mut ValidationReport,
+    ) {
+        for (name, config) in sorted(devices) {
+            match (&config.port_path, &config.match_config) {
+                (Some(_), DeviceMatchConfig::Remap(_)) => {
+                    report.error(format!(
+                        "Device '{name}' has a port_path, but remaps are not \
+                         plugged into any port"
+                    ));
+                }
+                (Some(port_path), _) if port_path.is_empty() => {
+                    report.error(format!(
+                        "Device '{name}' has an empty port_path"
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn validate_webhooks(&self, report: &mut ValidationReport) {
+        for webhook in &self.webhooks {
+            if !webhook.url.starts_with("http://") {
+                report.error(format!(
+                    "Webhook URL '{}' must start with http://; post to a \
+                     local relay for HTTPS",
+                    webhook.url
+                ));
+            }
+            for event in webhook.events.iter().flatten() {
+                if !EVENT_NAMES.contains(&event.as_str()) {
+                    report.error(format!(
+                        "Webhook '{}' asks for unknown event '{event}'",
+                        webhook.url
+                    ));
+                }
+            }
+        }
+    }
+
+    fn validate_bundles(&self, report: &mut ValidationReport) {
+        for (name, bundle) in sorted(&self.bundles) {
+            if !self.sinks.contains_key(&bundle.sink) {
+                report.error(format!(
+                    "Bundle '{name}' refers to undefined sink '{}'",
+                    bundle.sink
+                ));
+            }
+            if !self.sources.contains_key(&bundle.source) {
+                report.error(format!(
+                    "Bundle '{name}' refers to undefined source '{}'",
+                    bundle.source
+                ));
+            }
+        }
+    }
+
+    fn validate_duplicate_names(&self, report: &mut ValidationReport) {
+        let mut duplicates: Vec<_> = self
+            .sinks
+            .keys()
+            .filter(|name| self.sources.contains_key(*name))
+            .collect();
+        duplicates.sort();
+
+        for name in duplicates {
+            let message = format!(
+                "Name '{name}' is used in both sinks and sources; refer to it \
+                 as {{sink: {name}}} or {{source: {name}}} to avoid ambiguity"
+            );
+            if self.strict {
+                report.error(message);
+            } else {
+                report.warning(message);
+            }
+        }
+    }
+
+    /// Report inherited priorities that cannot be resolved, and warn about
+    /// configs sharing a priority, between which the choice of the default
+    /// is arbitrary
+    fn validate_priorities(
+        &self,
+        device_type: &str,
+        report: &mut ValidationReport,
+    ) {
+        let devices = match device_type {
+            "sinks" => &self.sinks,
+            _ => &self.sources,
+        };
+        for (name, config) in sorted(devices) {
+            if config.priority.is_none()
+                || self.resolve_priority(device_type, name).is_some()
+            {
+                continue;
+            }
+            if matches!(config.match_config, DeviceMatchConfig::Remap(_)) {
+                report.warning(format!(
+                    "Config '{name}' in {device_type} inherits its priority, \
+                     but no master with a priority can be found"
+                ));
+            } else {
+                report.error(format!(
+                    "Config '{name}' in {device_type} inherits its priority, \
+                     but only remaps have a master to inherit from"
+                ));
+            }
+        }
+
+        let mut by_priority: HashMap<u32, Vec<&str>> = HashMap::new();
+        for name in devices.keys() {
+            if let Some(priority) = self.resolve_priority(device_type, name) {
+                by_priority.entry(priority).or_default().push(name);
+            }
+        }
+        let mut shared: Vec<_> = by_priority
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .collect();
+        shared.sort();
+
+        for (priority, mut names) in shared {
+            names.sort();
+            report.warning(format!(
+                "Configs {} in {device_type} share priority {priority}; \
+                 which of them becomes default is arbitrary",
+                names
+                    .iter()
+                    .map(|name| format!("'{name}'"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    }
+
+    /// Warn about detected configs without a priority that nothing else
+    /// refers to, whose devices can never be used for anything
+    fn validate_reachability(&self, report: &mut ValidationReport) {
+        for (device_type, devices) in
+            [("sinks", &self.sinks), ("sources", &self.sources)]
+        {
+            for (name, config) in sorted(devices) {
+                if config.priority.is_some()
+                    || !matches!(
+                        config.match_config,
+                        DeviceMatchConfig::Detect(_)
+                    )
+                    || config.passthrough.is_some()
+                    || !config.on_appear.is_empty()
+                    || !config.on_disappear.is_empty()
+                    || self.is_referenced(name, device_type)
+                {
+                    continue;
+                }
+                report.warning(format!(
+                    "Config '{name}' in {device_type} has no priority and \
+                     nothing refers to it, so it can never take effect"
+                ));
+            }
+        }
+    }
+
+    /// Report pairs of detected configs where every device matching one
+    /// also matches the other, which are errors in strict mode
+    fn validate_detect_overlaps(
+        &self,
+        device_type: &str,
+        report: &mut ValidationReport,
+    ) {
+        let devices = match device_type {
+            "sinks" => &self.sinks,
+            _ => &self.sources,
+        };
+        let detected: Vec<_> = sorted(devices)
+            .into_iter()
+            // Never matches together with another config
+            .filter(|(name, _)| name.as_str() != CATCH_ALL_CONFIG)
+            .filter_map(|(name, config)| match &config.match_config {
+                // Scored rules need not all match
+                DeviceMatchConfig::Detect(_) if config.scoring.is_some() => {
+                    None
+                }
+                DeviceMatchConfig::Detect(rules) => {
+                    Some((name, rules, &config.port_path))
+                }
+                _ => None,
+            })
+            .collect();
+
+        for (i, &(name, rules, port_path)) in detected.iter().enumerate() {
+            for &(other_name, other_rules, other_port_path) in
+                &detected[i + 1..]
+            {
+                // Told apart by where they are plugged in
+                if port_path != other_port_path {
+                    continue;
+                }
+                let message = if rules == other_rules {
+                    format!(
+                        "Configs '{name}' and '{other_name}' in \
+                         {device_type} have identical detect rules and \
+                         always match the same devices; add a property \
+                         such as device.serial to tell them apart"
+                    )
+                } else if let Some((general, specific, extra)) =
+                    Self::detect_subset(
+                        (name, rules),
+                        (other_name, other_rules),
+                    )
+                {
+                    format!(
+                        "Detect rules of '{general}' in {device_type} are \
+                         a subset of those of '{specific}', so every device \
+                         matching '{specific}' also matches '{general}'; \
+                         add {extra} to '{general}' with a different value \
+                         to tell them apart"
+                    )
+                } else {
+                    continue;
+                };
+                if self.strict {
+                    report.error(message);
+                } else {
+                    report.warning(message);
+                }
+            }
+        }
+    }
+
+    /// If the rules of one config are a strict subset of the other, the
+    /// more general and the more specific name, with the properties only
+    /// the specific one checks
+    fn detect_subset<'a>(
+        a: (&'a str, &HashMap<String, DetectRule>),
+        b: (&'a str, &HashMap<String, DetectRule>),
+    ) -> Option<(&'a str, &'a str, String)> {
+        let is_subset =
+            |small: &HashMap<String, DetectRule>,
+             large: &HashMap<String, DetectRule>| {
+                small
+                    .iter()
+                    .all(|(key, value)| large.get(key) == Some(value))
+            };
+        let ((general, general_rules), (specific, specific_rules)) =
+            if is_subset(a.1, b.1) {
+                (a, b)
+            } else if is_subset(b.1, a.1) {
+                (b, a)
+            } else {
+                return None;
+            };
+
+        let mut extra: Vec<_> = specific_rules
+            .keys()
+            .filter(|key| !general_rules.contains_key(*key))
+            .map(String::as_str)
+            .collect();
+        extra.sort();
+        let extra = match extra.as_slice() {
+            [key] => key.to_string(),
+            keys => format!("one of {}", keys.join(", ")),
+        };
+        Some((general, specific, extra))
+    }
+
+    /// Whether a remap, bundle or notification setting refers to the config
+    fn is_referenced(&self, name: &str, device_type: &str) -> bool {
+        let as_master = [("sinks", &self.sinks), ("sources", &self.sources)]
+            .into_iter()
+            .flat_map(|(group, devices)| {
+                devices.values().map(move |config| (group, config))
+            })
+            .any(|(group, config)| match &config.match_config {
+                DeviceMatchConfig::Remap(remap) => {
+                    Self::master_target(&remap.master, group)
+                        == (device_type, name)
+                }
+                _ => false,
+            });
+        let in_bundle =
+            self.bundles.values().any(|bundle| match device_type {
+                "sinks" => bundle.sink == name,
+                _ => bundle.source == name,
+            });
+        let for_notifications = device_type == "sinks"
+            && self
+                .notifications
+                .as_ref()
+                .is_some_and(|notifications| notifications.sink == name);
+        as_master || in_bundle || for_notifications
+    }
+
+    /// Group and config name a remap master in the given group refers to
+    fn master_target<'a>(
+        master: &'a MasterRef,
+        device_type: &'a str,
+    ) -> (&'a str, &'a str) {
+        match master {
+            MasterRef::Name(master) => (device_type, master),
+            MasterRef::Sink { sink, .. } => ("sinks", sink),
+            MasterRef::Source { source } => ("sources", source),
+        }
+    }
+
+    /// Report remap masters referring to undefined configs, which are
+    /// errors in strict mode
+    fn validate_master_existence(&self, report: &mut ValidationReport) {
+        for (device_type, devices) in
+            [("sinks", &self.sinks), ("sources", &self.sources)]
+        {
+            for (name, config) in sorted(devices) {
+                let DeviceMatchConfig::Remap(remap) = &config.match_config
+                else {
+                    continue;
+                };
+
+                let (master_type, master) =
+                    Self::master_target(&remap.master, device_type);
+                let masters = match master_type {
+                    "sinks" => &self.sinks,
+                    _ => &self.sources,
+                };
+
+                if !masters.contains_key(master) {
+                    let message = format!(
+                        "Remap '{name}' in {device_type} refers to undefined \
+                         master '{master}' in {master_type}"
+                    );
+                    if self.strict {
+                        report.error(message);
+                    } else {
+                        report.warning(message);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Config name a remap master refers to within the group of the remap,
+    /// or `None` if the master lives in another group
+    fn local_master<'a>(
+        master: &'a MasterRef,
+        device_type: &str,
+    ) -> Option<&'a str> {
+        match master {
+            MasterRef::Name(name) => Some(name),
+            MasterRef::Sink { sink, .. } => {
+                (device_type == "sinks").then_some(sink.as_str())
+            }
+            MasterRef::Source { source } => {
+                (device_type == "sources").then_some(source.as_str())
+            }
+        }
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum DeviceMatchConfig {
-    Detect(HashMap<String, String>),
-    Remap(RemapConfig),
-}
+    /// Device properties of remaps must have keys PulseAudio can take in
+    /// the module arguments, and leave the ownership tag alone
+    fn validate_property_keys(
+        devices: &HashMap<String, DeviceConfig>,
+        device_type: &str,
+        report: &mut ValidationReport,
+    ) {
+        for (name, config) in sorted(devices) {
+            let DeviceMatchConfig::Remap(remap) = &config.match_config else {
+                continue;
+            };
+            let mut keys: Vec<_> = remap
+                .device_properties
+                .iter()
+                .flatten()
+                .map(|(k, _)| k)
+                .collect();
+            keys.sort();
+            for key in keys {
+                if !crate::modargs::is_valid_property_key(key) {
+                    report.error(format!(
+                        "Remap '{name}' in {device_type} has property \
+                         '{key}', which is not a valid property key"
+                    ));
+                } else if key == crate::modargs::REMAP_TAG_PROPERTY {
+                    report.error(format!(
+                        "Remap '{name}' in {device_type} cannot set property \
+                         '{key}', which autopulsed sets itself"
+                    ));
+                }
+            }
+        }
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DeviceConfig {
-    pub priority: Option<u32>,
-    #[serde(flatten)]
-    pub match_config: DeviceMatchConfig,
-}
+    fn validate_master_kinds(
+        devices: &HashMap<String, DeviceConfig>,
+        device_type: &str,
+        report: &mut ValidationReport,
+    ) {
+        for (name, config) in sorted(devices) {
+            let DeviceMatchConfig::Remap(remap) = &config.match_config else {
+                continue;
+            };
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-pub struct Config {
-    #[serde(default)]
-    pub sinks: HashMap<String, DeviceConfig>,
-    #[serde(default)]
-    pub sources: HashMap<String, DeviceConfig>,
-}
+            if matches!(remap.master, MasterRef::Source { .. })
+                && device_type == "sinks"
+            {
+                report.error(format!(
+                    "Remap '{name}' in sinks cannot use a source as master"
+                ));
+            }
 
-impl Config {
-    /// Validate the configuration for circular references in remap chains
-    pub fn validate(&self) -> Result<(), String> {
-        Self::validate_remap_references(&self.sinks, "sinks")?;
-        Self::validate_remap_references(&self.sources, "sources")?;
-        Ok(())
+            if let MasterRef::Sink { monitor, .. } = &remap.master {
+                match (device_type, monitor) {
+                    ("sinks", true) => {
+                        report.error(format!(
+                            "Remap '{name}' in sinks cannot use a monitor \
+                             source as master"
+                        ));
+                    }
+                    ("sources", false) => {
+                        report.error(format!(
+                            "Remap '{name}' in sources must set \
+                             'monitor: true' to use a sink as master"
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
     }
 
     fn validate_remap_references(
         devices: &HashMap<String, DeviceConfig>,
         device_type: &str,
-    ) -> Result<(), String> {
-        for (name, config) in devices {
+        report: &mut ValidationReport,
+    ) {
+        // Each cycle is reported once, whichever member it is found from
+        let mut reported = HashSet::new();
+        for (name, config) in sorted(devices) {
             if let DeviceMatchConfig::Remap(_) = &config.match_config {
                 // Use iterative approach to detect cycles
                 let mut visited = HashSet::new();
@@ -92,11 +2011,15 @@ impl Config {
                             .map(|s| s.to_string())
                             .collect();
 
-                        return Err(format!(
-                            "Circular reference detected in {}: {}",
-                            device_type,
-                            cycle_path.join(" -> ")
-                        ));
+                        if !reported.contains(current) {
+                            reported.extend(cycle_path.clone());
+                            report.error(format!(
+                                "Circular reference detected in {}: {}",
+                                device_type,
+                                cycle_path.join(" -> ")
+                            ));
+                        }
+                        break;
                     }
 
                     // Get the device configuration
@@ -108,21 +2031,47 @@ impl Config {
                     // Check if this device has a remap master
                     match &device.match_config {
                         DeviceMatchConfig::Remap(remap) => {
-                            current = &remap.master;
-                            path.push(current);
+                            match Self::local_master(
+                                &remap.master,
+                                device_type,
+                            ) {
+                                Some(master) => {
+                                    current = master;
+                                    path.push(current);
+                                }
+                                None => break, // Master in another group
+                            }
                         }
-                        DeviceMatchConfig::Detect(_) => break, // End of chain
+                        DeviceMatchConfig::Detect(_)
+                        | DeviceMatchConfig::Jack(_)
+                        | DeviceMatchConfig::Script(_) => break, // End of chain
                     }
                 }
             }
         }
-        Ok(())
     }
 }
 
+/// Entries of a config map in name order, for a stable report
+fn sorted<T>(map: &HashMap<String, T>) -> Vec<(&String, &T)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::control::DeviceRef;
+
+    /// Errors of the report joined, or its warnings if there are none
+    fn check(config: &Config) -> Result<Vec<String>, String> {
+        let report = config.validate();
+        if report.has_errors() {
+            return Err(report.errors().collect::<Vec<_>>().join("\n"));
+        }
+        Ok(report.warnings().map(str::to_string).collect())
+    }
 
     #[test]
     fn test_circular_reference_detection() {
@@ -132,9 +2081,9 @@ mod tests {
         config.sinks.insert(
             "a".to_string(),
             DeviceConfig {
-                priority: Some(1),
+                priority: Some(Priority::Fixed(1)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "b".to_string(),
+                    master: MasterRef::Name("b".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -144,16 +2093,20 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
         config.sinks.insert(
             "b".to_string(),
             DeviceConfig {
-                priority: Some(2),
+                priority: Some(Priority::Fixed(2)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "c".to_string(),
+                    master: MasterRef::Name("c".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -163,16 +2116,20 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
         config.sinks.insert(
             "c".to_string(),
             DeviceConfig {
-                priority: Some(3),
+                priority: Some(Priority::Fixed(3)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "a".to_string(),
+                    master: MasterRef::Name("a".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -182,11 +2139,15 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
-        let result = config.validate();
+        let result = check(&config);
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(
@@ -211,9 +2172,9 @@ mod tests {
         config.sinks.insert(
             "a".to_string(),
             DeviceConfig {
-                priority: Some(1),
+                priority: Some(Priority::Fixed(1)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "b".to_string(),
+                    master: MasterRef::Name("b".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -223,16 +2184,20 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
         config.sinks.insert(
             "b".to_string(),
             DeviceConfig {
-                priority: Some(2),
+                priority: Some(Priority::Fixed(2)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "c".to_string(),
+                    master: MasterRef::Name("c".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -242,19 +2207,24 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
         config.sinks.insert(
             "c".to_string(),
             DeviceConfig {
-                priority: Some(3),
+                priority: Some(Priority::Fixed(3)),
                 match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
             },
         );
 
-        assert!(config.validate().is_ok());
+        assert!(check(&config).is_ok());
     }
 
     #[test]
@@ -265,9 +2235,9 @@ mod tests {
         config.sources.insert(
             "a".to_string(),
             DeviceConfig {
-                priority: Some(1),
+                priority: Some(Priority::Fixed(1)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "a".to_string(),
+                    master: MasterRef::Name("a".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -277,11 +2247,15 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
-        let result = config.validate();
+        let result = check(&config);
         assert!(result.is_err());
         let err = result.unwrap_err();
         assert!(err.contains("Circular reference detected"));
@@ -296,9 +2270,9 @@ mod tests {
         config.sinks.insert(
             "a".to_string(),
             DeviceConfig {
-                priority: Some(1),
+                priority: Some(Priority::Fixed(1)),
                 match_config: DeviceMatchConfig::Remap(RemapConfig {
-                    master: "nonexistent".to_string(),
+                    master: MasterRef::Name("nonexistent".to_string()),
                     device_name: None,
                     device_properties: None,
                     format: None,
@@ -308,11 +2282,858 @@ mod tests {
                     master_channel_map: None,
                     resample_method: None,
                     remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
                 }),
+                ..Default::default()
             },
         );
 
         // This should be valid - referencing a non-existent device is not a circular reference
-        assert!(config.validate().is_ok());
+        assert!(check(&config).is_ok());
+    }
+
+    #[test]
+    fn test_sink_monitor_master_parsing() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  speakers:
+    detect:
+      device.description: "Speakers"
+sources:
+  speakers_mono:
+    remap:
+      master:
+        sink: "speakers"
+        monitor: true
+      channels: 1
+"#,
+        )
+        .unwrap();
+
+        let DeviceMatchConfig::Remap(remap) =
+            &config.sources["speakers_mono"].match_config
+        else {
+            panic!("Expected remap config");
+        };
+        assert_eq!(
+            remap.master,
+            MasterRef::Sink {
+                sink: "speakers".to_string(),
+                monitor: true,
+            }
+        );
+        assert!(check(&config).is_ok());
+    }
+
+    #[test]
+    fn test_property_key_validation() {
+        let config = Config::from_yaml(
+            r#"
+sinks:
+  speakers:
+    priority: 1
+    detect:
+      device.description: Speakers
+  remapped:
+    remap:
+      master: speakers
+      device_properties:
+        "device description": Remapped
+        autopulsed.config: other
+"#,
+        )
+        .unwrap();
+        let err = check(&config).unwrap_err();
+        assert!(
+            err.contains("property 'device description', which is not"),
+            "Error message: {err}"
+        );
+        assert!(
+            err.contains("'autopulsed.config', which autopulsed sets"),
+            "Error message: {err}"
+        );
+    }
+
+    #[test]
+    fn test_master_kind_validation() {
+        let remap_of = |master: MasterRef| DeviceConfig {
+            priority: None,
+            match_config: DeviceMatchConfig::Remap(RemapConfig {
+                master,
+                device_name: None,
+                device_properties: None,
+                format: None,
+                rate: None,
+                channels: None,
+                channel_map: None,
+                master_channel_map: None,
+                resample_method: None,
+                remix: None,
+                verify_playback: false,
+                on_name_collision: NameCollisionPolicy::Error,
+                on_external_unload: ExternalUnloadPolicy::Reload,
+            }),
+            ..Default::default()
+        };
+
+        // A source cannot be backed by a sink itself, only by its monitor
+        let mut config = Config::default();
+        config.sources.insert(
+            "a".to_string(),
+            remap_of(MasterRef::Sink {
+                sink: "b".to_string(),
+                monitor: false,
+            }),
+        );
+        let err = check(&config).unwrap_err();
+        assert!(err.contains("monitor: true"), "Error message: {err}");
+
+        // A sink cannot be backed by a monitor source
+        let mut config = Config::default();
+        config.sinks.insert(
+            "a".to_string(),
+            remap_of(MasterRef::Sink {
+                sink: "b".to_string(),
+                monitor: true,
+            }),
+        );
+        let err = check(&config).unwrap_err();
+        assert!(err.contains("monitor source"), "Error message: {err}");
+
+        // Explicit sink references within sinks still take part in cycles
+        let mut config = Config::default();
+        config.sinks.insert(
+            "a".to_string(),
+            remap_of(MasterRef::Sink {
+                sink: "a".to_string(),
+                monitor: false,
+            }),
+        );
+        let err = check(&config).unwrap_err();
+        assert!(err.contains("Circular reference detected"));
+    }
+
+    #[test]
+    fn test_strict_reference_to_nonexistent_device() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+strict: true
+sources:
+  mono:
+    remap:
+      master:
+        sink: "missing"
+        monitor: true
+"#,
+        )
+        .unwrap();
+
+        let err = check(&config).unwrap_err();
+        assert!(
+            err.contains("undefined master 'missing' in sinks"),
+            "Error message: {err}"
+        );
+
+        config.sinks.insert(
+            "missing".to_string(),
+            DeviceConfig {
+                priority: None,
+                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
+            },
+        );
+        assert!(check(&config).is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_names_across_groups() {
+        let detect = || DeviceConfig {
+            priority: None,
+            match_config: DeviceMatchConfig::Detect(HashMap::new()),
+            ..Default::default()
+        };
+
+        let mut config = Config::default();
+        config.sinks.insert("headset".to_string(), detect());
+        config.sources.insert("headset".to_string(), detect());
+        config.sources.insert("mic".to_string(), detect());
+
+        let warnings: Vec<_> = check(&config)
+            .unwrap()
+            .into_iter()
+            .filter(|warning| warning.contains("both sinks and sources"))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'headset'"), "{}", warnings[0]);
+
+        config.strict = true;
+        let err = check(&config).unwrap_err();
+        assert!(
+            err.contains("both sinks and sources"),
+            "Error message: {err}"
+        );
+    }
+
+    #[test]
+    fn test_session_manager_policy_parsing() {
+        let config: Config = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(
+            config.session_manager_policy,
+            SessionManagerPolicy::Override
+        );
+
+        let yaml = "session_manager_policy: cooperate";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.session_manager_policy,
+            SessionManagerPolicy::Cooperate
+        );
+    }
+
+    #[test]
+    fn test_unmanaged_groups() {
+        let yaml = r#"
+manage_sources: false
+sinks:
+  speakers:
+    detect:
+      device.name: speakers
+sources:
+  mic:
+    detect:
+      device.name: mic
+"#;
+        let mut config = Config::from_yaml(yaml).unwrap();
+        assert_eq!(config.unmanaged_groups(), ["sources"]);
+
+        config.drop_unmanaged_groups();
+        assert!(config.sinks.contains_key("speakers"));
+        assert!(config.sources.is_empty());
+    }
+
+    #[test]
+    fn test_jack_shortcut_parsing() {
+        let yaml = r#"
+sinks:
+  any_jack:
+    jack: {}
+  managed_jack:
+    priority: 1
+    jack:
+      client_name: pulse_out
+      load: true
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+
+        let DeviceMatchConfig::Jack(jack) =
+            &config.sinks["any_jack"].match_config
+        else {
+            panic!("Expected a JACK config");
+        };
+        assert_eq!(jack.client_name, None);
+        assert!(!jack.load);
+
+        let DeviceMatchConfig::Jack(jack) =
+            &config.sinks["managed_jack"].match_config
+        else {
+            panic!("Expected a JACK config");
+        };
+        assert_eq!(jack.client_name.as_deref(), Some("pulse_out"));
+        assert!(jack.load);
+    }
+
+    #[cfg(feature = "osc")]
+    #[test]
+    fn test_osc_mappings_parsing() {
+        let yaml = r#"
+osc:
+  listen: 127.0.0.1:9000
+  mappings:
+    /autopulsed/headphones:
+      action: set_default
+      sink: headphones
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let osc = config.osc.unwrap();
+
+        assert_eq!(osc.listen, "127.0.0.1:9000");
+        assert!(matches!(
+            &osc.mappings["/autopulsed/headphones"],
+            Command::SetDefault { .. }
+        ));
+    }
+
+    #[test]
+    fn test_http_validation() {
+        let parse = |yaml: &str| {
+            let config: Config = serde_yaml::from_str(yaml).unwrap();
+            check(&config)
+        };
+
+        assert!(parse("http: {listen: \"127.0.0.1:8080\", token: t}").is_ok());
+        assert!(parse("http: {listen: \"[::1]:8080\", token: t}").is_ok());
+        assert!(
+            parse("http: {listen: \"0.0.0.0:8080\", token: t}")
+                .unwrap_err()
+                .contains("not a loopback address")
+        );
+        assert!(
+            parse("http: {listen: \"127.0.0.1:8080\", token: \"\"}")
+                .unwrap_err()
+                .contains("must not be empty")
+        );
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn test_mqtt_validation() {
+        let parse = |yaml: &str| {
+            let config: Config = serde_yaml::from_str(yaml).unwrap();
+            check(&config)
+        };
+
+        assert!(parse("mqtt: {broker: \"localhost:1883\"}").is_ok());
+        assert!(
+            parse(
+                "mqtt: {broker: \"localhost:1883\", username: u, password: p}"
+            )
+            .is_ok()
+        );
+        assert!(
+            parse("mqtt: {broker: \"localhost:1883\", password: p}")
+                .unwrap_err()
+                .contains("needs a username")
+        );
+        assert!(
+            parse("mqtt: {broker: \"localhost:1883\", password_file: p}")
+                .unwrap_err()
+                .contains("needs a username")
+        );
+    }
+
+    #[test]
+    fn test_disabled_keys() {
+        let raw: serde_yaml::Value = serde_yaml::from_str(
+            "osc: {listen: \"127.0.0.1:9000\"}\nhttp: {listen: x}",
+        )
+        .unwrap();
+        let expected: &[(&str, &str)] = if cfg!(feature = "osc") {
+            &[]
+        } else {
+            &[("osc", "osc")]
+        };
+        assert_eq!(Config::disabled_keys(&raw), expected);
+    }
+
+    #[test]
+    fn test_device_actions_parsing() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  headphones:
+    detect: {}
+    on_appear:
+      - {action: set_mute, sink: speakers, muted: true}
+      - {action: pause}
+    on_disappear:
+      - {action: set_mute, sink: speakers, muted: false}
+"#,
+        )
+        .unwrap();
+        let headphones = &config.sinks["headphones"];
+        assert_eq!(
+            headphones.on_appear,
+            vec![
+                Command::SetMute {
+                    device: DeviceRef::Sink {
+                        sink: "speakers".to_string()
+                    },
+                    muted: true,
+                },
+                Command::Pause,
+            ]
+        );
+        assert_eq!(headphones.on_disappear.len(), 1);
+        assert!(check(&config).is_ok());
+
+        let config: Config = serde_yaml::from_str(
+            "sinks: {usb: {detect: {}, on_appear: [{action: reload}]}}",
+        )
+        .unwrap();
+        assert!(check(&config).is_err());
+    }
+
+    #[test]
+    fn test_bundle_validation() {
+        let parse = |bundle: &str| {
+            let config: Config = serde_yaml::from_str(&format!(
+                "sinks: {{headset_out: {{detect: {{}}}}}}\n\
+                 sources: {{headset_mic: {{detect: {{}}}}}}\n\
+                 bundles: {{headset: {bundle}}}"
+            ))
+            .unwrap();
+            check(&config).map(|_| config)
+        };
+
+        let config =
+            parse("{sink: headset_out, source: headset_mic, partial: ignore}")
+                .unwrap();
+        assert_eq!(
+            config.bundles["headset"].partial,
+            PartialBundlePolicy::Ignore
+        );
+        assert!(
+            parse("{sink: headset_mic, source: headset_mic}")
+                .unwrap_err()
+                .contains("undefined sink 'headset_mic'")
+        );
+        assert!(
+            parse("{sink: headset_out, source: other}")
+                .unwrap_err()
+                .contains("undefined source 'other'")
+        );
+    }
+
+    #[test]
+    fn test_notifications_config() {
+        let config: Config = serde_yaml::from_str(
+            "sinks: {speakers: {detect: {}}}\n\
+             notifications: {sink: speakers}",
+        )
+        .unwrap();
+        assert!(check(&config).is_ok());
+        let notifications = config.notifications.unwrap();
+        assert!(notifications.routes_role("event"));
+        assert!(!notifications.routes_role("music"));
+
+        let config: Config = serde_yaml::from_str(
+            "notifications: {sink: speakers, roles: [event, phone]}",
+        )
+        .unwrap();
+        assert!(check(&config).unwrap_err().contains("'speakers'"));
+        assert!(config.notifications.unwrap().routes_role("phone"));
+
+        let config: Config = serde_yaml::from_str(
+            "notifications: {sink: speakers, owner_uid: [1001]}",
+        )
+        .unwrap();
+        let notifications = config.notifications.unwrap();
+        assert!(notifications.routes_owner(Some(1001)));
+        assert!(!notifications.routes_owner(Some(0)));
+        assert!(!notifications.routes_owner(None));
+    }
+
+    #[test]
+    fn test_passthrough_parsing() {
+        let config: Config = serde_yaml::from_str(
+            "sinks: {receiver: {detect: {}, passthrough: [ac3, eac3, dts]}}",
+        )
+        .unwrap();
+        assert_eq!(
+            config.sinks["receiver"].passthrough,
+            Some(vec![
+                PassthroughFormat::Ac3,
+                PassthroughFormat::Eac3,
+                PassthroughFormat::Dts,
+            ])
+        );
+        assert!(check(&config).is_ok());
+
+        let config: Config = serde_yaml::from_str(
+            "sources: {mic: {detect: {}, passthrough: [ac3]}}",
+        )
+        .unwrap();
+        assert!(check(&config).unwrap_err().contains("only sinks"));
+    }
+
+    #[test]
+    fn test_validation_report_collects_all_issues() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  speakers: {priority: 1, detect: {device.bus: pci}}
+  headphones: {priority: 1, detect: {device.bus: usb}}
+  spare: {detect: {device.bus: bluetooth}}
+  loop: {priority: 2, remap: {master: loop}}
+sources:
+  mic: {priority: 1, detect: {}, passthrough: [ac3]}
+  mono: {priority: 2, remap: {master: {sink: missing, monitor: true}}}
+notifications: {sink: missing}
+"#,
+        )
+        .unwrap();
+
+        let report = config.validate();
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 3, "{report}");
+        assert!(errors[0].contains("loop -> loop"), "{report}");
+        assert!(errors[1].contains("only sinks"), "{report}");
+        assert!(errors[2].contains("Notifications"), "{report}");
+
+        let warnings: Vec<_> = report.warnings().collect();
+        assert_eq!(warnings.len(), 3, "{report}");
+        assert!(warnings[0].contains("undefined master"), "{report}");
+        assert!(warnings[1].contains("'headphones', 'speakers'"), "{report}");
+        assert!(warnings[2].contains("'spare'"), "{report}");
+        assert!(report.to_string().starts_with("error: Circular"));
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let raw: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+peek_monitor: true
+sinks:
+  usb: {prority: 1, detect: {}, on_appear: []}
+sources:
+  mic: {priority: 1, remap: {master: usb}}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Config::unknown_keys(&raw),
+            vec!["peek_monitor".to_string(), "sinks.usb.prority".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_skip_invalid_entries() {
+        let content = r#"
+on_invalid_entry: skip
+sinks:
+  usb: {priority: 1, detect: {device.bus: usb}}
+  broken: {priority: high, detect: {}}
+sources:
+  mic: {priority: 1, remap: {master: usb, rate: fast}}
+"#;
+        let config = Config::from_yaml(content).unwrap();
+        assert_eq!(config.sinks.keys().collect::<Vec<_>>(), vec!["usb"]);
+        assert!(config.sources.is_empty());
+        assert_eq!(
+            config.skipped_entries.keys().collect::<Vec<_>>(),
+            vec!["sinks.broken", "sources.mic"]
+        );
+        let report = config.validate();
+        assert!(
+            report
+                .warnings()
+                .any(|message| message.contains("'sinks.broken'"))
+        );
+
+        let failing = content.replace("skip", "fail");
+        assert!(Config::from_yaml(&failing).is_err());
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = Config::json_schema();
+        let mut keys: Vec<_> = schema
+            .schema
+            .object
+            .as_ref()
+            .unwrap()
+            .properties
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, built_config_keys());
+        assert!(schema.definitions.contains_key("RemapConfig"));
+    }
+
+    #[test]
+    fn test_config_keys_are_complete() {
+        let serialized = serde_yaml::to_value(Config::default()).unwrap();
+        let mut keys: Vec<_> = serialized
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .map(|key| key.as_str().unwrap())
+            .collect();
+        keys.sort();
+        assert_eq!(keys, built_config_keys());
+    }
+
+    #[test]
+    fn test_default_scope() {
+        let config: Config =
+            serde_yaml::from_str("default_scope: {group: usb}").unwrap();
+        let scope = config.default_scope.unwrap();
+        let usb =
+            BTreeMap::from([("device.bus".to_string(), "usb".to_string())]);
+        let pci =
+            BTreeMap::from([("device.bus".to_string(), "pci".to_string())]);
+        assert_eq!(scope.group_of(&usb), Some("usb"));
+        assert!(scope.includes(&usb));
+        assert!(!scope.includes(&pci));
+        assert!(!scope.includes(&BTreeMap::new()));
+
+        let scope = DefaultScopeConfig {
+            group_by: Some("alsa.card_name".to_string()),
+            group: None,
+        };
+        assert_eq!(scope.group_of(&usb), None);
+        assert!(scope.includes(&pci));
+    }
+
+    #[test]
+    fn test_priority_inheritance() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  dac: {priority: 10, detect: {}}
+  eq: {priority: inherit-2, remap: {master: dac}}
+  surround: {priority: "inherit+5", remap: {master: eq}}
+sources:
+  loopback:
+    priority: inherit
+    remap: {master: {sink: surround, monitor: true}}
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.sinks["eq"].priority, Some(Priority::Inherit(-2)));
+        assert_eq!(config.resolve_priority("sinks", "dac"), Some(10));
+        assert_eq!(config.resolve_priority("sinks", "eq"), Some(8));
+        assert_eq!(config.resolve_priority("sinks", "surround"), Some(13));
+        assert_eq!(config.resolve_priority("sources", "loopback"), Some(13));
+        assert_eq!(
+            config.remap_master("sources", "loopback"),
+            Some(("sinks", "surround"))
+        );
+        assert_eq!(config.remap_master("sinks", "dac"), None);
+        assert!(!config.validate().has_errors());
+
+        let config: Config = serde_yaml::from_str(
+            "sinks: {usb: {priority: inherit, detect: {}}}",
+        )
+        .unwrap();
+        assert_eq!(config.resolve_priority("sinks", "usb"), None);
+        assert!(check(&config).unwrap_err().contains("only remaps"));
+
+        assert!(
+            serde_yaml::from_str::<Config>(
+                "sinks: {usb: {priority: inherit*2, detect: {}}}"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_presence_rules() {
+        let config: Config = serde_yaml::from_str(
+            "sinks:\n  hardware:\n    detect:\n      device.bus: {exists: \
+             true}\n      device.serial: {}\n",
+        )
+        .unwrap();
+
+        let errors = check(&config).unwrap_err();
+        assert!(
+            errors.contains("'hardware' for 'device.serial' has no condition"),
+            "{errors}"
+        );
+    }
+
+    #[test]
+    fn test_scoring() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  dac:
+    priority: 1
+    detect: {device.bus: usb, device.vendor.id: "1235", device.serial: X}
+    scoring: {threshold: 3, weights: {device.vendor.id: 2}}
+  dac2:
+    priority: 2
+    detect: {device.bus: usb, device.vendor.id: "1235"}
+    scoring: {threshold: 2}
+"#,
+        )
+        .unwrap();
+        let properties = |pairs: &[(&str, &str)]| -> BTreeMap<_, _> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        // Scored configs are not reported as overlapping
+        assert_eq!(check(&config), Ok(Vec::new()));
+        let dac = &config.sinks["dac"];
+        assert_eq!(
+            dac.match_score(&properties(&[
+                ("device.vendor.id", "1235"),
+                ("device.serial", "Y"),
+                ("device.bus", "usb"),
+            ])),
+            Some(3)
+        );
+        assert_eq!(dac.match_score(&properties(&[])), Some(0));
+
+        let invalid: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  dac:
+    detect: {device.bus: usb}
+    scoring: {threshold: 3, weights: {device.serial: 1}}
+  jack: {jack: {}, scoring: {threshold: 1}}
+"#,
+        )
+        .unwrap();
+        let errors = check(&invalid).unwrap_err();
+        assert!(errors.contains("weight for 'device.serial'"), "{errors}");
+        assert!(errors.contains("add up to 1 at most"), "{errors}");
+        assert!(errors.contains("'jack' uses scoring"), "{errors}");
+    }
+
+    #[test]
+    fn test_detect_overlaps() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  usb: {priority: 2, detect: {device.bus: usb}}
+  scarlett:
+    priority: 1
+    detect: {device.bus: usb, device.serial: "X", device.vendor.id: "1235"}
+  hdmi: {priority: 3, detect: {device.bus: pci}}
+sources:
+  mic: {priority: 1, detect: {device.bus: usb}}
+  webcam: {priority: 2, detect: {device.bus: usb}}
+"#,
+        )
+        .unwrap();
+
+        let warnings = check(&config).unwrap();
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(
+            warnings[0].contains(
+                "Detect rules of 'usb' in sinks are a subset of those of \
+                 'scarlett'"
+            ),
+            "{}",
+            warnings[0]
+        );
+        assert!(
+            warnings[0].contains("add one of device.serial, device.vendor.id"),
+            "{}",
+            warnings[0]
+        );
+        assert!(
+            warnings[1].contains("'mic' and 'webcam' in sources"),
+            "{}",
+            warnings[1]
+        );
+
+        config.strict = true;
+        assert_eq!(config.validate().errors().count(), 2);
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let config: Config = serde_yaml::from_str(
+            "sinks: {usb: {priority: 1, case_insensitive: true, detect: \
+             {device.description: \"USB Audio\", device.bus: usb}}}",
+        )
+        .unwrap();
+        let device = BTreeMap::from([
+            ("device.description".to_string(), "USB AUDIO".to_string()),
+            ("device.bus".to_string(), "USB".to_string()),
+        ]);
+        let usb = &config.sinks["usb"];
+        assert_eq!(
+            usb.matches_properties("alsa_output.usb", &device),
+            Some(true)
+        );
+
+        let mut strict = usb.clone();
+        strict.case_insensitive = false;
+        assert_eq!(
+            strict.matches_properties("alsa_output.usb", &device),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let config: Config = serde_yaml::from_str(
+            "sinks: {usb: {priority: 1, detect: \
+             {device.description: {regex: \"USB (Audio\"}}}}",
+        )
+        .unwrap();
+        let error = check(&config).unwrap_err();
+        assert!(error.contains("invalid regex"), "{error}");
+    }
+
+    #[test]
+    fn test_port_path() {
+        let config: Config = serde_yaml::from_str(
+            "sinks:\n  \
+               left: {priority: 1, detect: {device.bus: usb}, \
+             port_path: \"usb-0:1\"}\n  \
+               right: {priority: 2, detect: {device.bus: usb}, \
+             port_path: \"usb-0:2\"}\n",
+        )
+        .unwrap();
+        // Identical rules, but never the same devices
+        assert_eq!(check(&config), Ok(Vec::new()));
+        assert!(config.referenced_properties().contains("udev.ID_PATH"));
+
+        let device = BTreeMap::from([
+            ("device.bus".to_string(), "usb".to_string()),
+            (
+                "device.bus_path".to_string(),
+                "pci-0000:00:14.0-usb-0:2:1.0".to_string(),
+            ),
+        ]);
+        let matches = |name: &str| {
+            config.sinks[name].matches_properties("alsa_output.usb", &device)
+        };
+        assert_eq!(matches("left"), Some(false));
+        assert_eq!(matches("right"), Some(true));
+
+        let config: Config = serde_yaml::from_str(
+            "sinks: {remap: {priority: 1, remap: {master: usb}, \
+             port_path: \"usb-0:1\"}}",
+        )
+        .unwrap();
+        assert!(check(&config).unwrap_err().contains("not plugged into"));
+    }
+
+    #[test]
+    fn test_webhooks() {
+        let config: Config = serde_yaml::from_str(
+            "webhooks:\n\
+             - {url: \"http://localhost:8123/hook\", \
+             events: [default_changed]}\n\
+             - {url: \"https://example.com/hook\", events: [device_lost]}\n",
+        )
+        .unwrap();
+        let error = check(&config).unwrap_err();
+        assert!(error.contains("must start with http://"), "{error}");
+        assert!(error.contains("unknown event 'device_lost'"), "{error}");
+        assert!(!error.contains("localhost"), "{error}");
+
+        assert!(config.webhooks[0].wants("default_changed"));
+        assert!(!config.webhooks[0].wants("module_failed"));
+        assert_eq!(config.webhooks[0].retries(), 3);
+    }
+
+    #[test]
+    fn test_catch_all_config() {
+        let config: Config = serde_yaml::from_str(
+            "sinks: {usb: {priority: 1, detect: {device.bus: usb}},\n\
+             _default: {priority: 100, detect: {}}}\n\
+             notifications: {sink: _default}",
+        )
+        .unwrap();
+        assert_eq!(check(&config), Ok(Vec::new()));
+
+        let config: Config = serde_yaml::from_str(
+            "sinks: {_default: {priority: 100, remap: {master: usb}}}",
+        )
+        .unwrap();
+        assert!(check(&config).unwrap_err().contains("detect rules"));
     }
 }
@@ -0,0 +1,182 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Examples of devices with the configs they must be recognized as, which
+//! users keep as regression tests of their detect rules
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::Config;
+
+/// Example device and the configs it is expected to be recognized as
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Assertion {
+    /// What the example stands for, shown in the results
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub device: ExampleDevice,
+    /// Configs the device must be recognized as, and no others
+    #[serde(default)]
+    pub expect: Vec<String>,
+}
+
+/// Properties of an example sink or source, as `pactl list` shows them
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ExampleDevice {
+    Sink { sink: BTreeMap<String, String> },
+    Source { source: BTreeMap<String, String> },
+}
+
+/// Result of one assertion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssertionOutcome {
+    pub label: String,
+    pub result: Result<(), String>,
+}
+
+impl Assertion {
+    /// Label of the assertion in the results, the description or the name
+    /// of the example device
+    fn label(&self, position: usize) -> String {
+        let (group, properties) = self.group_and_properties();
+        match (&self.description, properties.get("device.name")) {
+            (Some(description), _) => description.clone(),
+            (None, Some(name)) => format!("{group} {name}"),
+            (None, None) => format!("assertion #{}", position + 1),
+        }
+    }
+
+    fn group_and_properties(
+        &self,
+    ) -> (&'static str, &BTreeMap<String, String>) {
+        match &self.device {
+            ExampleDevice::Sink { sink } => ("sink", sink),
+            ExampleDevice::Source { source } => ("source", source),
+        }
+    }
+
+    fn evaluate(&self, config: &Config) -> Result<(), String> {
+        let (group, properties) = self.group_and_properties();
+        let configs = match &self.device {
+            ExampleDevice::Sink { .. } => &config.sinks,
+            ExampleDevice::Source { .. } => &config.sources,
+        };
+        if let Some(unknown) =
+            self.expect.iter().find(|name| !configs.contains_key(*name))
+        {
+            return Err(format!("there is no {group} config '{unknown}'"));
+        }
+
+        let device_name =
+            properties.get("device.name").map_or("", |name| name);
+        let matched: BTreeSet<&str> = configs
+            .iter()
+            .filter(|(_, device_config)| {
+                device_config.matches_properties(device_name, properties)
+                    == Some(true)
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let expected: BTreeSet<&str> =
+            self.expect.iter().map(String::as_str).collect();
+        if matched == expected {
+            return Ok(());
+        }
+        Err(format!(
+            "expected {}, but it matches {}",
+            describe(&expected),
+            describe(&matched)
+        ))
+    }
+}
+
+fn describe(names: &BTreeSet<&str>) -> String {
+    if names.is_empty() {
+        return "no config".to_string();
+    }
+    names
+        .iter()
+        .map(|name| format!("'{name}'"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Config {
+    /// Evaluate the detect rules, scripts and JACK shortcuts against the
+    /// example devices; remaps are left out, as no properties tell them
+    pub fn run_assertions(&self) -> Vec<AssertionOutcome> {
+        self.assertions
+            .iter()
+            .enumerate()
+            .map(|(position, assertion)| AssertionOutcome {
+                label: assertion.label(position),
+                result: assertion.evaluate(self),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_assertions() {
+        let yaml = r#"
+sinks:
+  speakers:
+    detect:
+      device.description: Speakers
+  usb:
+    detect:
+      device.bus: usb
+assertions:
+  - sink:
+      device.name: alsa_output.pci.analog-stereo
+      device.description: Speakers
+    expect: [speakers]
+  - description: USB speakers match both
+    sink:
+      device.description: Speakers
+      device.bus: usb
+    expect: [speakers]
+  - source:
+      device.name: mic
+  - sink: {}
+    expect: [headphones]
+"#;
+        let config = Config::from_yaml(yaml).unwrap();
+        let outcomes = config.run_assertions();
+
+        assert_eq!(outcomes[0].label, "sink alsa_output.pci.analog-stereo");
+        assert_eq!(outcomes[0].result, Ok(()));
+        assert_eq!(outcomes[1].label, "USB speakers match both");
+        assert_eq!(
+            outcomes[1].result,
+            Err("expected 'speakers', but it matches 'speakers', 'usb'"
+                .to_string())
+        );
+        assert_eq!(outcomes[2].result, Ok(()));
+        assert_eq!(outcomes[3].label, "assertion #4");
+        assert_eq!(
+            outcomes[3].result,
+            Err("there is no sink config 'headphones'".to_string())
+        );
+    }
+}
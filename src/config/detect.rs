@@ -0,0 +1,443 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use regex::{Regex, RegexBuilder};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Properties holding the physical path of a device, as udev names it,
+/// the first present one counting
+pub const PORT_PATH_PROPERTIES: &[&str] = &["udev.ID_PATH", "device.bus_path"];
+
+/// Expected value of a property in the detect rules of a device config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum DetectRule {
+    /// The exact value
+    Exact(String),
+    Value(ValueRule),
+    Regex(RegexRule),
+    Presence(PresenceRule),
+}
+
+/// A value compared more loosely than byte by byte
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ValueRule {
+    pub value: String,
+    /// Compare regardless of letter case
+    #[serde(default)]
+    pub ignore_case: bool,
+    /// Compare with accents and ligatures of Latin letters folded, so that
+    /// "Écouteurs" also matches "Ecouteurs" and its decomposed form
+    #[serde(default)]
+    pub normalize: bool,
+}
+
+/// A value matching a regular expression as a whole
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegexRule {
+    pub regex: Pattern,
+}
+
+/// Regular expression compiled once, matched against the whole value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct Pattern {
+    source: String,
+    /// None if the expression is invalid, which validation reports
+    compiled: Option<Regex>,
+    compiled_ignoring_case: Option<Regex>,
+}
+
+impl Pattern {
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Why the expression cannot be used, if it cannot
+    pub fn error(&self) -> Option<String> {
+        self.compiled
+            .is_none()
+            .then(|| Self::compile(&self.source).unwrap_err().to_string())
+    }
+
+    fn compile(source: &str) -> Result<Regex, regex::Error> {
+        Regex::new(&format!("^(?:{source})$"))
+    }
+
+    fn is_match(&self, text: &str, ignore_case: bool) -> bool {
+        let compiled = if ignore_case {
+            &self.compiled_ignoring_case
+        } else {
+            &self.compiled
+        };
+        compiled
+            .as_ref()
+            .is_some_and(|compiled| compiled.is_match(text))
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(source: String) -> Self {
+        let compiled = Self::compile(&source).ok();
+        let compiled_ignoring_case =
+            RegexBuilder::new(&format!("^(?:{source})$"))
+                .case_insensitive(true)
+                .build()
+                .ok();
+        Self {
+            source,
+            compiled,
+            compiled_ignoring_case,
+        }
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.source
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Pattern {}
+
+impl JsonSchema for Pattern {
+    fn schema_name() -> String {
+        "Pattern".to_string()
+    }
+
+    fn json_schema(
+        generator: &mut schemars::r#gen::SchemaGenerator,
+    ) -> schemars::schema::Schema {
+        String::json_schema(generator)
+    }
+}
+
+/// Whether a property is there, regardless of its value; every condition
+/// given must hold
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(deny_unknown_fields)]
+pub struct PresenceRule {
+    /// Whether the device has the property
+    pub exists: Option<bool>,
+    /// Whether the device lacks the property
+    pub absent: Option<bool>,
+    /// Whether the device has the property with a value other than ""
+    pub non_empty: Option<bool>,
+}
+
+impl DetectRule {
+    /// Whether the value of the property, if the device has it, satisfies
+    /// the rule
+    pub fn matches(&self, actual: Option<&str>) -> bool {
+        self.matches_with(actual, false)
+    }
+
+    /// Like `matches`, but regardless of letter case if `ignore_case` is
+    /// set, as for the rules of a `case_insensitive` config
+    pub fn matches_with(
+        &self,
+        actual: Option<&str>,
+        ignore_case: bool,
+    ) -> bool {
+        match (self, actual) {
+            (DetectRule::Presence(rule), actual) => rule.matches(actual),
+            (_, None) => false,
+            (DetectRule::Exact(expected), Some(actual)) => {
+                if ignore_case {
+                    actual.to_lowercase() == expected.to_lowercase()
+                } else {
+                    actual == expected
+                }
+            }
+            (DetectRule::Value(rule), Some(actual)) => {
+                let ignore_case = rule.ignore_case || ignore_case;
+                rule.comparable(actual, ignore_case)
+                    == rule.comparable(&rule.value, ignore_case)
+            }
+            (DetectRule::Regex(rule), Some(actual)) => {
+                rule.regex.is_match(actual, ignore_case)
+            }
+        }
+    }
+}
+
+impl PresenceRule {
+    fn matches(&self, actual: Option<&str>) -> bool {
+        self.exists.is_none_or(|exists| actual.is_some() == exists)
+            && self.absent.is_none_or(|absent| actual.is_none() == absent)
+            && self.non_empty.is_none_or(|non_empty| {
+                actual.is_some_and(|value| !value.is_empty()) == non_empty
+            })
+    }
+
+    /// Whether the rule checks anything at all
+    pub fn is_empty(&self) -> bool {
+        *self == PresenceRule::default()
+    }
+}
+
+impl ValueRule {
+    fn comparable(&self, text: &str, ignore_case: bool) -> String {
+        let text = if self.normalize {
+            normalize(text)
+        } else {
+            text.to_string()
+        };
+        if ignore_case {
+            text.to_lowercase()
+        } else {
+            text
+        }
+    }
+}
+
+/// Whether the device sits at the port path or below it, such as behind
+/// a hub plugged into the port or at one of its USB interfaces
+///
+/// `usb-0:2` matches `pci-0000:00:14.0-usb-0:2:1.0` and
+/// `pci-0000:00:14.0-usb-0:2.4:1.0`, but not `pci-0000:00:14.0-usb-0:21:1.0`.
+pub fn port_path_matches(
+    port_path: &str,
+    properties: &BTreeMap<String, String>,
+) -> bool {
+    let Some(path) = PORT_PATH_PROPERTIES
+        .iter()
+        .find_map(|key| properties.get(*key))
+    else {
+        return false;
+    };
+    path.match_indices(port_path).any(|(start, _)| {
+        let before = path[..start].chars().next_back();
+        let after = path[start + port_path.len()..].chars().next();
+        before.is_none_or(|c| c == '-')
+            && after.is_none_or(|c| matches!(c, ':' | '.'))
+    })
+}
+
+/// Precomposed Latin letters by the letter they are folded to
+const FOLDED_LETTERS: &[(char, &str)] = &[
+    ('A', "ÀÁÂÃÄÅĀĂĄ"),
+    ('a', "àáâãäåāăą"),
+    ('C', "ÇĆĈĊČ"),
+    ('c', "çćĉċč"),
+    ('D', "ĎĐ"),
+    ('d', "ďđ"),
+    ('E', "ÈÉÊËĒĔĖĘĚ"),
+    ('e', "èéêëēĕėęě"),
+    ('G', "ĜĞĠĢ"),
+    ('g', "ĝğġģ"),
+    ('H', "ĤĦ"),
+    ('h', "ĥħ"),
+    ('I', "ÌÍÎÏĨĪĬĮİ"),
+    ('i', "ìíîïĩīĭįı"),
+    ('J', "Ĵ"),
+    ('j', "ĵ"),
+    ('K', "Ķ"),
+    ('k', "ķ"),
+    ('L', "ĹĻĽĿŁ"),
+    ('l', "ĺļľŀł"),
+    ('N', "ÑŃŅŇ"),
+    ('n', "ñńņň"),
+    ('O', "ÒÓÔÕÖØŌŎŐ"),
+    ('o', "òóôõöøōŏő"),
+    ('R', "ŔŖŘ"),
+    ('r', "ŕŗř"),
+    ('S', "ŚŜŞŠ"),
+    ('s', "śŝşš"),
+    ('T', "ŢŤŦ"),
+    ('t', "ţťŧ"),
+    ('U', "ÙÚÛÜŨŪŬŮŰŲ"),
+    ('u', "ùúûüũūŭůűų"),
+    ('W', "Ŵ"),
+    ('w', "ŵ"),
+    ('Y', "ÝŶŸ"),
+    ('y', "ýÿŷ"),
+    ('Z', "ŹŻŽ"),
+    ('z', "źżž"),
+];
+
+const LIGATURES: &[(char, &str)] = &[
+    ('Æ', "AE"),
+    ('æ', "ae"),
+    ('Œ', "OE"),
+    ('œ', "oe"),
+    ('ß', "ss"),
+    ('ﬁ', "fi"),
+    ('ﬂ', "fl"),
+];
+
+/// Combining diacritical marks, left over from decomposed letters
+fn is_combining_mark(c: char) -> bool {
+    ('\u{0300}'..='\u{036f}').contains(&c)
+}
+
+/// Fold accented Latin letters to their base letters and ligatures to
+/// their letters, dropping combining marks and making spaces plain
+fn normalize(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        if is_combining_mark(c) {
+            continue;
+        }
+        if let Some((_, letters)) =
+            LIGATURES.iter().find(|(ligature, _)| *ligature == c)
+        {
+            folded.push_str(letters);
+        } else if let Some((base, _)) = FOLDED_LETTERS
+            .iter()
+            .find(|(_, accented)| accented.contains(c))
+        {
+            folded.push(*base);
+        } else if c.is_whitespace() {
+            folded.push(' ');
+        } else {
+            folded.push(c);
+        }
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(yaml: &str) -> DetectRule {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let exact = rule("Écouteurs");
+        assert_eq!(exact, DetectRule::Exact("Écouteurs".to_string()));
+        assert!(exact.matches(Some("Écouteurs")));
+        assert!(!exact.matches(Some("écouteurs")));
+        assert!(!exact.matches(None));
+
+        let loose =
+            rule("{value: Écouteurs, ignore_case: true, normalize: true}");
+        assert!(loose.matches(Some("ecouteurs")));
+        assert!(loose.matches(Some("E\u{301}COUTEURS")));
+        assert!(!loose.matches(Some("Ecouteur")));
+
+        assert!(exact.matches_with(Some("ÉCOUTEURS"), true));
+        assert!(!exact.matches_with(Some("Ecouteurs"), true));
+        assert!(
+            rule("{value: Écouteurs, normalize: true}")
+                .matches_with(Some("ECOUTEURS"), true)
+        );
+
+        let case_only = rule("{value: Focusrite, ignore_case: true}");
+        assert!(case_only.matches(Some("FOCUSRITE")));
+        assert!(
+            !rule("{value: Écouteurs, normalize: true}")
+                .matches(Some("ecouteurs"))
+        );
+
+        assert!(
+            serde_yaml::from_str::<DetectRule>("{value: a, typo: true}")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_regex() {
+        let regex = rule("{regex: \"USB Audio.*\"}");
+        assert!(regex.matches(Some("USB Audio")));
+        assert!(regex.matches(Some("USB Audio Rev 2")));
+        assert!(!regex.matches(Some("Old USB Audio")));
+        assert!(!regex.matches(None));
+        assert_eq!(regex, rule("{regex: \"USB Audio.*\"}"));
+
+        let serial = rule("{regex: \"Scarlett 2i2 [0-9A-F]+|Scarlett Solo\"}");
+        assert!(serial.matches(Some("Scarlett 2i2 3F2A")));
+        assert!(serial.matches(Some("Scarlett Solo")));
+        assert!(!serial.matches(Some("Scarlett 2i2 USB")));
+
+        assert!(!regex.matches(Some("usb audio")));
+        assert!(regex.matches_with(Some("usb audio"), true));
+
+        let DetectRule::Regex(invalid) = rule("{regex: \"(USB\"}") else {
+            panic!("not a regex rule");
+        };
+        assert!(invalid.regex.error().is_some());
+        assert!(!DetectRule::Regex(invalid).matches(Some("(USB")));
+    }
+
+    #[test]
+    fn test_presence() {
+        let exists = rule("{exists: true}");
+        assert!(exists.matches(Some("")));
+        assert!(!exists.matches(None));
+
+        let absent = rule("{absent: true}");
+        assert!(absent.matches(None));
+        assert!(!absent.matches(Some("usb")));
+        assert!(rule("{exists: false}").matches(None));
+
+        let non_empty = rule("{non_empty: true}");
+        assert!(non_empty.matches(Some("usb")));
+        assert!(!non_empty.matches(Some("")));
+        assert!(!non_empty.matches(None));
+        assert!(rule("{non_empty: false}").matches(None));
+
+        assert!(matches!(rule("{}"), DetectRule::Presence(p) if p.is_empty()));
+        assert!(serde_yaml::from_str::<DetectRule>("{exist: true}").is_err());
+    }
+
+    #[test]
+    fn test_port_path_matches() {
+        let device = |key: &str, path: &str| {
+            BTreeMap::from([(key.to_string(), path.to_string())])
+        };
+        let direct = device("udev.ID_PATH", "pci-0000:00:14.0-usb-0:2:1.0");
+        assert!(port_path_matches("usb-0:2", &direct));
+        assert!(port_path_matches("pci-0000:00:14.0-usb-0:2", &direct));
+        assert!(port_path_matches("pci-0000:00:14.0-usb-0:2:1.0", &direct));
+        assert!(!port_path_matches("usb-0:1", &direct));
+        assert!(!port_path_matches("sb-0:2", &direct));
+
+        let behind_hub =
+            device("device.bus_path", "pci-0000:00:14.0-usb-0:2.4:1.0");
+        assert!(port_path_matches("usb-0:2", &behind_hub));
+        assert!(port_path_matches("usb-0:2.4", &behind_hub));
+        assert!(!port_path_matches("usb-0:2.1", &behind_hub));
+
+        let other_port =
+            device("udev.ID_PATH", "pci-0000:00:14.0-usb-0:21:1.0");
+        assert!(!port_path_matches("usb-0:2", &other_port));
+        assert!(!port_path_matches("usb-0:2", &BTreeMap::new()));
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("Ærøskøbing Straße"), "AEroskobing Strasse");
+        assert_eq!(normalize("Cafe\u{301}\u{a0}Noir"), "Cafe Noir");
+        assert_eq!(normalize("日本語"), "日本語");
+    }
+}
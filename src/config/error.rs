@@ -0,0 +1,255 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+use super::{JackConfig, PassthroughFormat, Priority, RemapConfig};
+use crate::control::Command;
+
+/// Lines shown before the offending one
+const CONTEXT_LINES: usize = 2;
+
+/// Why the configuration file could not be parsed, and where
+pub struct ParseError {
+    /// Keys leading to the offending value, e.g. `sinks.usb.remap.rate`
+    pub path: Option<String>,
+    pub message: String,
+    /// Line and column, both starting at 1
+    pub location: Option<(usize, usize)>,
+    snippet: Vec<(usize, String)>,
+}
+
+impl ParseError {
+    pub fn new(content: &str, error: &serde_yaml::Error) -> Self {
+        let mut location = error.location().map(|l| (l.line(), l.column()));
+        let (mut path, mut message) = split_message(error);
+
+        // Errors within device configs stop at the config, because its
+        // match keys are flattened into it
+        if let Some(device_path) = &path
+            && let Some((inner_path, inner_message)) =
+                Self::find_device_error(content, device_path)
+        {
+            let full_path = format!("{device_path}.{inner_path}");
+            let keys: Vec<_> = full_path.split('.').collect();
+            location = Self::locate_keys(content, &keys).or(location);
+            path = Some(full_path);
+            message = inner_message;
+        }
+
+        Self {
+            path,
+            message,
+            location,
+            snippet: snippet(content, location),
+        }
+    }
+
+    /// Error of the value of a key, found before deserializing
+    pub fn at_key(content: &str, key: &str, message: String) -> Self {
+        let location = Self::locate_keys(content, &[key]);
+        Self {
+            path: Some(key.to_string()),
+            message,
+            location,
+            snippet: snippet(content, location),
+        }
+    }
+
+    /// Error without a location in the file, for values parsed after
+    /// modifying them
+    pub fn detached(error: &serde_yaml::Error) -> Self {
+        let (path, message) = split_message(error);
+        Self {
+            path,
+            message,
+            location: None,
+            snippet: Vec::new(),
+        }
+    }
+
+    /// Key path within a device config, and the error of its value
+    fn find_device_error(
+        content: &str,
+        device_path: &str,
+    ) -> Option<(String, String)> {
+        let (group, name) = device_path.split_once('.')?;
+        if group != "sinks" && group != "sources" {
+            return None;
+        }
+        let raw: Value = serde_yaml::from_str(content).ok()?;
+        let device = raw.get(group)?.get(name)?.as_mapping()?;
+
+        device.iter().find_map(|(key, value)| {
+            let key = key.as_str()?;
+            let error = match key {
+                "priority" => check::<Priority>(value),
+                "detect" => check::<HashMap<String, String>>(value),
+                "remap" => check::<RemapConfig>(value),
+                "jack" => check::<JackConfig>(value),
+                "passthrough" => check::<Vec<PassthroughFormat>>(value),
+                "on_appear" | "on_disappear" => check::<Vec<Command>>(value),
+                "exclusive" => check::<bool>(value),
+                _ => None,
+            }?;
+            Some(match split_message(&error) {
+                (Some(inner), message) => (format!("{key}.{inner}"), message),
+                (None, message) => (key.to_string(), message),
+            })
+        })
+    }
+
+    /// Line and column of the last key of the path, following block
+    /// mappings by indentation
+    fn locate_keys(content: &str, keys: &[&str]) -> Option<(usize, usize)> {
+        let lines: Vec<_> = content.lines().collect();
+        let mut start = 0;
+        let mut min_indent = 0;
+        let mut found = None;
+        for key in keys {
+            let (line, indent) = (start..lines.len()).find_map(|i| {
+                let text = lines[i];
+                let indent = text.len() - text.trim_start().len();
+                let rest = text.trim_start().strip_prefix(key)?;
+                (indent >= min_indent && rest.trim_start().starts_with(':'))
+                    .then_some((i, indent))
+            })?;
+            found = Some((line + 1, indent + 1));
+            start = line + 1;
+            min_indent = indent + 1;
+        }
+        found
+    }
+}
+
+/// Numbered lines leading up to the location
+fn snippet(
+    content: &str,
+    location: Option<(usize, usize)>,
+) -> Vec<(usize, String)> {
+    let Some((line, _)) = location else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .enumerate()
+        .skip(line.saturating_sub(CONTEXT_LINES + 1))
+        .take(line.min(CONTEXT_LINES + 1))
+        .map(|(i, text)| (i + 1, text.to_string()))
+        .collect()
+}
+
+/// Path and message of the error, without the location
+fn split_message(error: &serde_yaml::Error) -> (Option<String>, String) {
+    let mut message = error.to_string();
+    if let Some(location) = error.location() {
+        let suffix = format!(
+            " at line {} column {}",
+            location.line(),
+            location.column()
+        );
+        message = message.replacen(&suffix, "", 1);
+    }
+    match message.split_once(": ") {
+        Some((path, rest)) if !path.contains(char::is_whitespace) => {
+            (Some(path.to_string()), rest.to_string())
+        }
+        _ => (None, message),
+    }
+}
+
+/// The error of deserializing the value as `T`, if any
+///
+/// The value is parsed from text again, as only then do errors tell the
+/// path within the value.
+fn check<T: DeserializeOwned>(value: &Value) -> Option<serde_yaml::Error> {
+    let text = serde_yaml::to_string(value).ok()?;
+    serde_yaml::from_str::<T>(&text).err()
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{path}: {}", self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+        let Some((line, column)) = self.location else {
+            return Ok(());
+        };
+        write!(f, "\n  --> line {line}, column {column}")?;
+        let width = line.to_string().len();
+        for (number, text) in &self.snippet {
+            write!(f, "\n {number:>width$} | {text}")?;
+        }
+        write!(f, "\n {:width$} | {:>column$}", "", "^")
+    }
+}
+
+// Shown by main when the daemon fails to start
+impl fmt::Debug for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    #[test]
+    fn test_error_within_device_config() {
+        let content = "\
+sinks:
+  headset:
+    priority: 1
+    remap:
+      master: usb
+      rate: fast
+";
+        let error = Config::from_yaml(content).unwrap_err();
+        assert_eq!(error.path.as_deref(), Some("sinks.headset.remap.rate"));
+        assert_eq!(error.location, Some((6, 7)));
+        assert_eq!(
+            error.to_string(),
+            "\
+sinks.headset.remap.rate: invalid type: string \"fast\", expected u32
+  --> line 6, column 7
+ 4 |     remap:
+ 5 |       master: usb
+ 6 |       rate: fast
+   |       ^"
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let error = Config::from_yaml("sinks:\n\tusb: {}\n").unwrap_err();
+        assert_eq!(error.path, None);
+        assert_eq!(error.location.map(|(line, _)| line), Some(2));
+    }
+
+    #[test]
+    fn test_error_at_top_level() {
+        let error = Config::from_yaml("strict: maybe\n").unwrap_err();
+        assert_eq!(error.path.as_deref(), Some("strict"));
+        assert_eq!(error.location, Some((1, 9)));
+    }
+}
@@ -0,0 +1,362 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use schemars::Map;
+use schemars::schema::{InstanceType, Schema, SchemaObject, SingleOrVec};
+use serde_json::Value;
+
+/// Width that comments are wrapped to
+const WIDTH: usize = 79;
+
+/// An option and the schemas it has in the variants of its object
+type Property<'a> = (&'a str, Vec<&'a SchemaObject>);
+
+/// Where an option is written
+#[derive(Clone, Copy)]
+struct Placement {
+    /// Nesting level, two spaces of indentation each
+    depth: usize,
+    /// Whether the option is commented out
+    commented: bool,
+}
+
+impl Placement {
+    /// Commented out inside the option, past `levels` more levels
+    fn nested(self, levels: usize) -> Self {
+        Self {
+            depth: self.depth + levels,
+            commented: true,
+        }
+    }
+}
+
+/// Commented example configuration derived from the JSON Schema, setting
+/// the options that have defaults and showing the others commented out
+///
+/// With `full`, sections such as remaps are expanded down to their last
+/// option instead of being shown as a placeholder.
+pub fn example_yaml(full: bool) -> String {
+    let root = super::Config::json_schema();
+    let mut writer = Writer {
+        definitions: &root.definitions,
+        full,
+        out: String::new(),
+    };
+    writer.comment("# ", "autopulsed configuration");
+    writer.comment("# ", "");
+    writer.comment(
+        "# ",
+        "Generated by `autopulsed gen example-config`; options that are \
+         commented out have no default.",
+    );
+    for property in writer.properties(&root.schema) {
+        writer.out.push('\n');
+        writer.property(
+            property,
+            Placement {
+                depth: 0,
+                commented: false,
+            },
+        );
+    }
+    writer.out
+}
+
+struct Writer<'a> {
+    definitions: &'a Map<String, Schema>,
+    full: bool,
+    out: String,
+}
+
+impl<'a> Writer<'a> {
+    /// Comment wrapped at word boundaries, each line starting with
+    /// `prefix`
+    fn comment(&mut self, prefix: &str, text: &str) {
+        let mut current = String::new();
+        for word in text.split_whitespace() {
+            if !current.is_empty()
+                && prefix.len() + current.len() + 1 + word.len() > WIDTH
+            {
+                self.out.push_str(&format!("{prefix}{current}\n"));
+                current.clear();
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        let line = format!("{prefix}{current}");
+        self.out.push_str(line.trim_end());
+        self.out.push('\n');
+    }
+
+    fn object(schema: &'a Schema) -> Option<&'a SchemaObject> {
+        match schema {
+            Schema::Object(object) => Some(object),
+            Schema::Bool(_) => None,
+        }
+    }
+
+    /// The schema behind references and `Option`
+    fn resolve(&self, mut schema: &'a SchemaObject) -> &'a SchemaObject {
+        loop {
+            if let Some(name) = schema
+                .reference
+                .as_deref()
+                .and_then(|reference| reference.rsplit('/').next())
+                && let Some(target) =
+                    self.definitions.get(name).and_then(Self::object)
+            {
+                schema = target;
+                continue;
+            }
+            let any_of = Self::any_of(schema);
+            let non_null: Vec<_> =
+                any_of.iter().filter(|variant| !is_null(variant)).collect();
+            match non_null.as_slice() {
+                [inner] if non_null.len() < any_of.len() => schema = inner,
+                _ => return schema,
+            }
+        }
+    }
+
+    fn any_of(schema: &'a SchemaObject) -> Vec<&'a SchemaObject> {
+        schema
+            .subschemas
+            .as_ref()
+            .and_then(|subschemas| subschemas.any_of.as_ref())
+            .into_iter()
+            .flatten()
+            .filter_map(Self::object)
+            .collect()
+    }
+
+    /// Options of an object, including those flattened into it from enums
+    fn properties(&self, schema: &'a SchemaObject) -> Vec<Property<'a>> {
+        let mut properties: Vec<Property<'a>> = Vec::new();
+        let mut add =
+            |name: &'a str, schema: &'a SchemaObject| match properties
+                .iter_mut()
+                .find(|(known, _)| *known == name)
+            {
+                Some((_, schemas)) => schemas.push(schema),
+                None => properties.push((name, vec![schema])),
+            };
+        for (name, property) in
+            schema.object.iter().flat_map(|o| &o.properties)
+        {
+            if let Some(property) = Self::object(property) {
+                add(name, property);
+            }
+        }
+        if let Some(subschemas) = &schema.subschemas {
+            let variants: Vec<_> = subschemas
+                .all_of
+                .iter()
+                .chain(&subschemas.one_of)
+                .flatten()
+                .filter_map(Self::object)
+                .map(|variant| self.resolve(variant))
+                .collect();
+            if variants.iter().all(|variant| variant.object.is_some()) {
+                for variant in variants {
+                    for (name, schemas) in self.properties(variant) {
+                        for schema in schemas {
+                            add(name, schema);
+                        }
+                    }
+                }
+            }
+        }
+        properties
+    }
+
+    /// Option with its description, commented out within sections that
+    /// are only shown for reference
+    ///
+    /// The indentation follows the `#` of commented options so that
+    /// uncommenting them leaves valid YAML.
+    fn property(
+        &mut self,
+        (name, schemas): Property<'a>,
+        placement: Placement,
+    ) {
+        let indent = "  ".repeat(placement.depth);
+        let prefix = if placement.commented {
+            format!("# {indent}")
+        } else {
+            indent.clone()
+        };
+        let schema = schemas[0];
+        let resolved = self.resolve(schema);
+        let description = [schema, resolved]
+            .into_iter()
+            .find_map(|s| s.metadata.as_ref()?.description.as_deref());
+        if let Some(description) = description {
+            self.comment(&format!("{prefix}# "), description);
+        }
+        let disabled = format!("# {indent}");
+
+        if self.full && schemas.len() == 1 {
+            let nested = self.properties(resolved);
+            if !nested.is_empty() {
+                self.out.push_str(&format!("{disabled}{name}:\n"));
+                for property in nested {
+                    self.property(property, placement.nested(1));
+                }
+                return;
+            }
+            let entry = resolved
+                .object
+                .as_ref()
+                .and_then(|object| object.additional_properties.as_deref())
+                .and_then(Self::object)
+                .map(|entry| self.properties(self.resolve(entry)))
+                .filter(|entry| !entry.is_empty());
+            if let Some(entry) = entry {
+                self.out.push_str(&format!("{disabled}{name}:\n"));
+                self.out.push_str(&format!("{disabled}  <name>:\n"));
+                for property in entry {
+                    self.property(property, placement.nested(2));
+                }
+                return;
+            }
+        }
+
+        let default = schema
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.default.as_ref());
+        let line = match default {
+            Some(value) => format!("{prefix}{name}: {}", yaml(value)),
+            None => {
+                let placeholder = self.placeholder(&schemas);
+                format!("{disabled}{name}: {placeholder}")
+            }
+        };
+        self.out.push_str(&line);
+        self.out.push('\n');
+    }
+
+    /// Stand-in for a value without default, listing the choices of enums
+    fn placeholder(&self, schemas: &[&'a SchemaObject]) -> String {
+        let mut choices = Vec::new();
+        let mut types = Vec::new();
+        for schema in schemas {
+            let schema = self.resolve(schema);
+            let variants = schema
+                .subschemas
+                .as_ref()
+                .and_then(|subschemas| subschemas.one_of.as_ref())
+                .into_iter()
+                .flatten()
+                .filter_map(Self::object);
+            for variant in std::iter::once(schema).chain(variants) {
+                choices.extend(variant.enum_values.iter().flatten().map(yaml));
+            }
+            let alternatives = Self::any_of(schema);
+            if alternatives.is_empty() {
+                types.push(type_name(schema));
+            }
+            for alternative in alternatives {
+                types.push(type_name(self.resolve(alternative)));
+            }
+        }
+        if !choices.is_empty() {
+            choices.dedup();
+            return choices.join(" | ");
+        }
+        types.dedup();
+        format!("<{}>", types.join(" | "))
+    }
+}
+
+fn is_null(schema: &SchemaObject) -> bool {
+    schema.instance_type
+        == Some(SingleOrVec::Single(Box::new(InstanceType::Null)))
+}
+
+fn type_name(schema: &SchemaObject) -> &'static str {
+    let instance_type = match &schema.instance_type {
+        Some(SingleOrVec::Single(instance_type)) => Some(&**instance_type),
+        Some(SingleOrVec::Vec(types)) => {
+            let mut non_null =
+                types.iter().filter(|t| **t != InstanceType::Null);
+            non_null.next().filter(|_| non_null.next().is_none())
+        }
+        None => None,
+    };
+    match instance_type {
+        Some(InstanceType::Boolean) => "boolean",
+        Some(InstanceType::Integer) => "integer",
+        Some(InstanceType::Number) => "number",
+        Some(InstanceType::String) => "string",
+        Some(InstanceType::Array) => "list",
+        Some(InstanceType::Object) => "map",
+        Some(InstanceType::Null) | None => "value",
+    }
+}
+
+/// Value as a YAML flow scalar or collection on one line, leaving out
+/// unset options of sections
+fn yaml(value: &Value) -> String {
+    let value = match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        _ => value.clone(),
+    };
+    serde_json::to_string(&value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, built_config_keys};
+
+    #[test]
+    fn test_example_is_valid() {
+        for full in [false, true] {
+            let example = example_yaml(full);
+            let config = Config::from_yaml(&example).unwrap();
+            assert!(config.sinks.is_empty());
+            assert!(!config.trace_decisions);
+        }
+    }
+
+    #[test]
+    fn test_example_covers_options() {
+        let example = example_yaml(false);
+        for key in built_config_keys() {
+            assert!(
+                example.lines().any(|line| {
+                    line.strip_prefix("# ")
+                        .unwrap_or(line)
+                        .starts_with(&format!("{key}:"))
+                }),
+                "{key} is missing"
+            );
+        }
+
+        let full = example_yaml(true);
+        assert!(full.contains("\n#     remap:\n"));
+        assert!(full.contains("\n#       master: <"));
+        assert!(full.contains("\nsession_manager_policy: \"override\"\n"));
+    }
+}
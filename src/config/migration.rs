@@ -0,0 +1,270 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde_yaml::{Mapping, Value};
+
+/// Version of the configuration format that this build reads
+pub const CURRENT_VERSION: u64 = 1;
+
+/// Version assumed for files without `config_version`, which predate it
+const UNVERSIONED: u64 = 1;
+
+/// Change of a key from one version to the next
+///
+/// Paths are dot-separated keys, where `*` stands for any entry name such
+/// as that of a sink config.
+#[derive(Debug)]
+pub enum Change {
+    /// The last key of the path was renamed to the given one
+    Rename(&'static str, &'static str),
+    /// The key has no effect anymore, with a hint on what to use instead
+    Retire(&'static str, &'static str),
+}
+
+impl Change {
+    fn path(&self) -> &'static str {
+        match self {
+            Change::Rename(path, _) | Change::Retire(path, _) => path,
+        }
+    }
+}
+
+/// Changes turning a configuration of the previous version into one of
+/// `version`
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u64,
+    pub changes: &'static [Change],
+}
+
+/// Migrations in the order of their versions; add one whenever a key is
+/// renamed or retired, bumping `CURRENT_VERSION`
+const MIGRATIONS: &[Migration] = &[];
+
+/// Bring the raw configuration to the current version, returning a
+/// description of every change made
+///
+/// Fails for configurations written for a newer version.
+pub fn migrate(raw: &mut Value) -> Result<Vec<String>, String> {
+    Migrator::BUILTIN.migrate(raw)
+}
+
+/// Migrations leading up to a version of the format
+#[derive(Debug)]
+pub struct Migrator {
+    /// In the order of their versions
+    pub migrations: &'static [Migration],
+    /// Version configurations are brought to
+    pub current: u64,
+}
+
+impl Migrator {
+    /// The migrations of this build
+    pub const BUILTIN: Migrator = Migrator {
+        migrations: MIGRATIONS,
+        current: CURRENT_VERSION,
+    };
+
+    /// Bring the raw configuration to `current`, as [`migrate`] does
+    pub fn migrate(&self, raw: &mut Value) -> Result<Vec<String>, String> {
+        let current = self.current;
+        let Some(root) = raw.as_mapping_mut() else {
+            return Ok(Vec::new());
+        };
+        let version = match root.get("config_version") {
+            None => UNVERSIONED,
+            Some(value) => value
+                .as_u64()
+                .filter(|version| *version >= 1)
+                .ok_or_else(|| "expected a positive integer".to_string())?,
+        };
+        if version > current {
+            return Err(format!(
+                "version {version} is newer than the supported version \
+                 {current}; upgrade autopulsed to read this configuration"
+            ));
+        }
+
+        let mut notes = Vec::new();
+        for migration in self.migrations.iter().filter(|m| m.version > version)
+        {
+            for change in migration.changes {
+                let keys: Vec<_> = change.path().split('.').collect();
+                let cursor = Cursor {
+                    keys: &keys,
+                    prefix: String::new(),
+                };
+                visit(root, cursor, &mut |parent, found| {
+                    notes.push(change_key(parent, found, change));
+                });
+            }
+        }
+        if version < current {
+            root.insert("config_version".into(), current.into());
+            notes.push(format!("Updated from version {version} to {current}"));
+        }
+        Ok(notes)
+    }
+}
+
+/// Where a walk along a path stands
+struct Cursor<'a> {
+    /// Keys still to follow
+    keys: &'a [&'a str],
+    /// Path followed so far, ending with a dot unless empty
+    prefix: String,
+}
+
+/// Key found at the end of a path
+struct FoundKey<'a> {
+    /// Path of the mapping holding the key, as in [`Cursor::prefix`]
+    prefix: &'a str,
+    key: &'a str,
+}
+
+/// Call `f` with the mapping holding the last key of the path, for every
+/// entry the wildcards match
+fn visit(
+    mapping: &mut Mapping,
+    Cursor { keys, prefix }: Cursor<'_>,
+    f: &mut dyn FnMut(&mut Mapping, FoundKey<'_>),
+) {
+    match keys {
+        [] => {}
+        [last] => {
+            if mapping.contains_key(*last) {
+                let found = FoundKey {
+                    prefix: &prefix,
+                    key: last,
+                };
+                f(mapping, found);
+            }
+        }
+        [first, rest @ ..] => {
+            for (key, value) in mapping.iter_mut() {
+                let (Some(key), Some(child)) =
+                    (key.as_str(), value.as_mapping_mut())
+                else {
+                    continue;
+                };
+                if *first == "*" || *first == key {
+                    let cursor = Cursor {
+                        keys: rest,
+                        prefix: format!("{prefix}{key}."),
+                    };
+                    visit(child, cursor, f);
+                }
+            }
+        }
+    }
+}
+
+/// Apply the change to the key, describing what was done
+fn change_key(
+    parent: &mut Mapping,
+    FoundKey { prefix, key }: FoundKey<'_>,
+    change: &Change,
+) -> String {
+    let value = parent.remove(key).unwrap_or_default();
+    match change {
+        Change::Rename(_, to) if parent.contains_key(*to) => format!(
+            "'{prefix}{key}' is ignored in favor of '{prefix}{to}', which \
+             replaces it"
+        ),
+        Change::Rename(_, to) => {
+            parent.insert((*to).into(), value);
+            format!("'{prefix}{key}' is now called '{prefix}{to}'")
+        }
+        Change::Retire(_, hint) => {
+            format!("'{prefix}{key}' is no longer supported: {hint}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 2,
+            changes: &[
+                Change::Rename("sinks.*.remap.rate", "sample_rate"),
+                Change::Retire("debug", "use --verbose instead"),
+            ],
+        },
+        Migration {
+            version: 3,
+            changes: &[Change::Rename("strict", "strict_refs")],
+        },
+    ];
+
+    const TEST_MIGRATOR: Migrator = Migrator {
+        migrations: TEST_MIGRATIONS,
+        current: 3,
+    };
+
+    fn migrate_test(content: &str) -> Result<(Value, Vec<String>), String> {
+        let mut raw: Value = serde_yaml::from_str(content).unwrap();
+        let notes = TEST_MIGRATOR.migrate(&mut raw)?;
+        Ok((raw, notes))
+    }
+
+    #[test]
+    fn test_migrate() {
+        let (raw, notes) = migrate_test(
+            "debug: true\n\
+             strict: true\n\
+             sinks:\n  \
+               a: {remap: {master: b, rate: 48000}}\n  \
+               b: {detect: {}}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            notes,
+            [
+                "'sinks.a.remap.rate' is now called \
+                 'sinks.a.remap.sample_rate'",
+                "'debug' is no longer supported: use --verbose instead",
+                "'strict' is now called 'strict_refs'",
+                "Updated from version 1 to 3",
+            ]
+        );
+        assert_eq!(raw["sinks"]["a"]["remap"]["sample_rate"], 48000);
+        assert!(raw.get("debug").is_none());
+        assert_eq!(raw["strict_refs"], true);
+        assert_eq!(raw["config_version"], 3);
+
+        // Only the migrations after the version of the file apply
+        let (raw, notes) =
+            migrate_test("config_version: 2\nstrict: true\ndebug: 1\n")
+                .unwrap();
+        assert_eq!(notes.len(), 2);
+        assert_eq!(raw["debug"], 1);
+
+        let (_, notes) = migrate_test("config_version: 3\n").unwrap();
+        assert!(notes.is_empty());
+        assert!(migrate_test("config_version: 4\n").is_err());
+        assert!(migrate_test("config_version: latest\n").is_err());
+    }
+
+    #[test]
+    fn test_current_version() {
+        let mut raw: Value = serde_yaml::from_str("strict: true").unwrap();
+        assert!(migrate(&mut raw).unwrap().is_empty());
+        assert!(MIGRATIONS.iter().all(|m| m.version <= CURRENT_VERSION));
+    }
+}
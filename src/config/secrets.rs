@@ -0,0 +1,89 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::{Path, PathBuf};
+
+/// Set by systemd for services with `LoadCredential=` or `SetCredential=`
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+/// Secret stored in a file, which relative paths name among the systemd
+/// credentials of the service when there are any
+pub fn read(path: &Path) -> Result<String, String> {
+    let credentials =
+        std::env::var_os(CREDENTIALS_DIRECTORY).map(PathBuf::from);
+    let path = resolve(path, credentials.as_deref());
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        format!("Failed to read secret from {}: {e}", path.display())
+    })?;
+    // Files written by editors or echo end with a newline
+    Ok(content.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn resolve(path: &Path, credentials: Option<&Path>) -> PathBuf {
+    match credentials {
+        Some(directory) if path.is_relative() => directory.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Fill in the secret from its file, if one is named instead of the value
+pub fn resolve_field(
+    value: &mut Option<String>,
+    file: Option<&Path>,
+    name: &str,
+) -> Result<(), String> {
+    let Some(file) = file else {
+        return Ok(());
+    };
+    if value.is_some() {
+        return Err(format!("Both {name} and {name}_file are set"));
+    }
+    *value = Some(read(file)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_relative_to_credentials() {
+        let credentials = Path::new("/run/credentials/autopulsed.service");
+        assert_eq!(
+            resolve(Path::new("http-token"), Some(credentials)),
+            credentials.join("http-token")
+        );
+        assert_eq!(
+            resolve(Path::new("/etc/autopulsed/token"), Some(credentials)),
+            Path::new("/etc/autopulsed/token")
+        );
+        assert_eq!(
+            resolve(Path::new("http-token"), None),
+            Path::new("http-token")
+        );
+    }
+
+    #[test]
+    fn test_resolve_field() {
+        let mut value = Some("inline".to_string());
+        assert!(resolve_field(&mut value, None, "token").is_ok());
+        assert_eq!(value.as_deref(), Some("inline"));
+        assert!(
+            resolve_field(&mut value, Some(Path::new("token")), "token")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,170 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use libpulse_binding::error::{Code, PAErr};
+use log::{info, warn};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often and how long to look for a server socket that is not mounted
+/// yet, as containers may start before the host session
+const SOCKET_WAIT_ATTEMPTS: u32 = 30;
+const SOCKET_WAIT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Adjustments for running in a container against the socket of the host
+pub struct Container {
+    /// Server socket, unless connecting over TCP
+    pub socket: Option<PathBuf>,
+    /// Created once the initial enumeration is complete, for health checks
+    pub ready_file: PathBuf,
+}
+
+impl Container {
+    pub fn new(server: Option<&str>, runtime_dir: &Path) -> Self {
+        Self {
+            socket: socket_path(server, |name| std::env::var_os(name)),
+            ready_file: runtime_dir.join("ready"),
+        }
+    }
+
+    /// Wait for the server socket to be mounted
+    pub fn wait_for_socket(&self) -> Result<(), String> {
+        let Some(socket) = &self.socket else {
+            return Ok(());
+        };
+        for attempt in 1..=SOCKET_WAIT_ATTEMPTS {
+            if socket.exists() {
+                return Ok(());
+            }
+            if attempt == 1 {
+                info!("Waiting for the server socket {}", socket.display());
+            }
+            std::thread::sleep(SOCKET_WAIT_INTERVAL);
+        }
+        Err(format!(
+            "Server socket {} does not exist; mount the pulse socket of the \
+             host into the container, e.g. with \
+             `-v $XDG_RUNTIME_DIR/pulse/native:{}`",
+            socket.display(),
+            socket.display()
+        ))
+    }
+
+    pub fn mark_ready(&self) {
+        let result = self
+            .ready_file
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&self.ready_file, b""));
+        if let Err(e) = result {
+            warn!(
+                "Failed to create readiness file {}: {e}",
+                self.ready_file.display()
+            );
+        }
+    }
+
+    pub fn clear_ready(&self) {
+        let _ = std::fs::remove_file(&self.ready_file);
+    }
+
+    /// Why the connection has failed, telling a socket nobody listens on
+    /// from one that denies access
+    pub fn explain_failure(&self, error: PAErr) -> String {
+        let target = self.socket.as_ref().map_or_else(
+            || "the server".to_string(),
+            |socket| socket.display().to_string(),
+        );
+        let code = error.0.abs();
+        if code == Code::Access as i32 || code == Code::AuthKey as i32 {
+            format!(
+                "Access to {target} was denied; share the cookie of the \
+                 host (~/.config/pulse/cookie) with the container through \
+                 PULSE_COOKIE, or run the container as the user owning the \
+                 socket"
+            )
+        } else if code == Code::ConnectionRefused as i32 {
+            format!(
+                "Nobody accepts connections on {target}; the socket may be \
+                 stale or the server of the host is not running"
+            )
+        } else {
+            format!("Connection to {target} failed: {error}")
+        }
+    }
+}
+
+/// Path of the UNIX socket the server is reached by, from `--server`,
+/// `PULSE_SERVER` or the default location
+pub fn socket_path(
+    server: Option<&str>,
+    var: impl Fn(&str) -> Option<OsString>,
+) -> Option<PathBuf> {
+    let server = server.map(str::to_string).or_else(|| {
+        var("PULSE_SERVER").map(|s| s.to_string_lossy().into_owned())
+    });
+    match server {
+        Some(server) => {
+            let path = server.strip_prefix("unix:").unwrap_or(&server);
+            path.starts_with('/').then(|| PathBuf::from(path))
+        }
+        None => var("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join("pulse").join("native")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_path() {
+        let no_vars = |_: &str| None;
+        assert_eq!(
+            socket_path(Some("unix:/run/pulse/native"), no_vars),
+            Some(PathBuf::from("/run/pulse/native"))
+        );
+        assert_eq!(socket_path(Some("tcp:host:4713"), no_vars), None);
+
+        let vars = |name: &str| match name {
+            "XDG_RUNTIME_DIR" => Some(OsString::from("/run/user/1000")),
+            _ => None,
+        };
+        assert_eq!(
+            socket_path(None, vars),
+            Some(PathBuf::from("/run/user/1000/pulse/native"))
+        );
+    }
+
+    #[test]
+    fn test_explain_failure() {
+        let container = Container {
+            socket: Some(PathBuf::from("/run/pulse/native")),
+            ready_file: PathBuf::from("/run/autopulsed/ready"),
+        };
+        assert!(
+            container
+                .explain_failure(PAErr(-(Code::Access as i32)))
+                .starts_with("Access to /run/pulse/native was denied")
+        );
+        assert!(
+            container
+                .explain_failure(PAErr(-(Code::ConnectionRefused as i32)))
+                .starts_with("Nobody accepts connections")
+        );
+    }
+}
@@ -0,0 +1,224 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// Reference to a configured device by group and config name
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum DeviceRef {
+    Sink { sink: String },
+    Source { source: String },
+}
+
+/// Action requested from outside the daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Command {
+    /// Prefer the device over the priorities until it disappears
+    SetDefault {
+        #[serde(flatten)]
+        device: DeviceRef,
+    },
+    ToggleMute {
+        #[serde(flatten)]
+        device: DeviceRef,
+    },
+    SetMute {
+        #[serde(flatten)]
+        device: DeviceRef,
+        muted: bool,
+    },
+    Status,
+    Devices,
+    /// Keep all sources muted while enabled
+    Privacy(PrivacySettings),
+    /// Stop changing defaults and loading remap devices until resumed
+    Pause,
+    Resume,
+    Reload,
+    /// Restore the default device that the last switch replaced
+    Undo,
+    /// List devices and modules again and apply defaults and remaps anew,
+    /// as after resuming from sleep
+    Resync,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema,
+)]
+pub struct PrivacySettings {
+    pub enabled: bool,
+    /// Also suspend the sources while enabled
+    #[serde(default)]
+    pub suspend: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusReport {
+    pub status: String,
+    pub paused: bool,
+    /// Whether privacy mode keeps the sources muted
+    pub privacy: bool,
+    pub server: Option<String>,
+    /// Configs of the devices last made default by the daemon
+    pub default_sink: Option<String>,
+    pub default_source: Option<String>,
+    /// Peak levels (0.0 to 1.0) of the defaults when `peak_monitor` is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sink_level: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_level: Option<f32>,
+    /// Config of the best device by group when `default_scope` is set
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub sink_groups: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub source_groups: BTreeMap<String, String>,
+    /// Names of the devices matching no config
+    pub unmanaged_sinks: Vec<String>,
+    pub unmanaged_sources: Vec<String>,
+    /// Server events handled during the last second
+    pub events_per_second: u32,
+    /// Differences to the server repaired by the periodic reconciliation
+    pub drift_repairs: u64,
+    pub memory: MemoryReport,
+    /// Config entries left out because they could not be parsed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_entries: Vec<String>,
+}
+
+/// What the daemon holds in memory, to watch over long-running instances
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryReport {
+    /// Resident size of the process, where the system tells it
+    pub resident_bytes: Option<u64>,
+    pub cached_devices: usize,
+    /// Bytes of the properties kept for the devices
+    pub cached_property_bytes: usize,
+    /// Properties left out of snapshots over `limits.device_properties`
+    pub dropped_properties: u64,
+    /// Events HTTP and MQTT subscribers missed for falling behind
+    pub dropped_events: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceEntry {
+    pub index: u32,
+    pub name: String,
+    pub recognized_as: Vec<String>,
+    pub muted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceReport {
+    pub sinks: Vec<DeviceEntry>,
+    pub sources: Vec<DeviceEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Response {
+    Message { message: String },
+    Status(Box<StatusReport>),
+    Devices(DeviceReport),
+}
+
+impl Response {
+    pub fn message(message: impl Into<String>) -> Self {
+        Response::Message {
+            message: message.into(),
+        }
+    }
+}
+
+pub type Reply = Result<Response, String>;
+
+pub struct ControlRequest {
+    pub command: Command,
+    pub reply: Option<Sender<Reply>>,
+}
+
+/// Channel through which control inputs running in their own threads send
+/// commands to the main loop
+pub fn channel() -> (Sender<ControlRequest>, Receiver<ControlRequest>) {
+    mpsc::channel()
+}
+
+/// Send a command to the main loop and wait for its reply, returning `None`
+/// if the main loop does not respond in time
+pub fn request(
+    sender: &Sender<ControlRequest>,
+    command: Command,
+    timeout: Duration,
+) -> Option<Reply> {
+    let (reply_sender, reply_receiver) = mpsc::channel();
+    let request = ControlRequest {
+        command,
+        reply: Some(reply_sender),
+    };
+    sender.send(request).ok()?;
+    reply_receiver.recv_timeout(timeout).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_parsing() {
+        let command: Command =
+            serde_yaml::from_str("{action: set_default, sink: speakers}")
+                .unwrap();
+        assert_eq!(
+            command,
+            Command::SetDefault {
+                device: DeviceRef::Sink {
+                    sink: "speakers".to_string()
+                }
+            }
+        );
+
+        let command: Command =
+            serde_yaml::from_str("{action: toggle_mute, source: mic}")
+                .unwrap();
+        assert_eq!(
+            command,
+            Command::ToggleMute {
+                device: DeviceRef::Source {
+                    source: "mic".to_string()
+                }
+            }
+        );
+
+        let command: Command =
+            serde_yaml::from_str("{action: pause}").unwrap();
+        assert_eq!(command, Command::Pause);
+
+        let command: Command =
+            serde_yaml::from_str("{action: privacy, enabled: true}").unwrap();
+        assert_eq!(
+            command,
+            Command::Privacy(PrivacySettings {
+                enabled: true,
+                suspend: false,
+            })
+        );
+    }
+}
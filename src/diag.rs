@@ -0,0 +1,487 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Information about the environment that bug reports need
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::journal;
+
+/// Features the binary was built with
+const FEATURES: &[(&str, bool)] = &[
+    ("alsa", cfg!(feature = "alsa")),
+    ("mqtt", cfg!(feature = "mqtt")),
+    ("osc", cfg!(feature = "osc")),
+    ("script", cfg!(feature = "script")),
+    ("udev", cfg!(feature = "udev")),
+];
+
+/// Newest journal entries that go into a bundle
+pub const BUNDLE_JOURNAL_ENTRIES: usize = 100;
+
+/// Config keys whose values are never put into a bundle
+const SECRET_KEYS: &[&str] = &["token", "password"];
+
+/// Values shorter than this are not redacted, so that e.g. a serial number
+/// of "0" does not mangle every other number
+const MIN_REDACTED_LEN: usize = 4;
+
+/// One place libpulse looks for the server address, in the order it does
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCandidate {
+    pub source: &'static str,
+    pub address: Option<String>,
+}
+
+/// Everything about the environment the daemon runs in that is known
+/// without the server, plus what the server reported once connected
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub server_chain: Vec<ServerCandidate>,
+    pub cookie: Option<PathBuf>,
+    pub runtime_dir: PathBuf,
+    pub server: Option<String>,
+    pub session_managers: Option<Vec<String>>,
+}
+
+impl Diagnostics {
+    pub fn detect(server: Option<&str>, runtime_dir: &Path) -> Self {
+        let var = |name: &str| std::env::var_os(name);
+        let client_conf = client_conf_path(var)
+            .and_then(|path| fs::read_to_string(path).ok());
+
+        Self {
+            server_chain: server_chain(server, var, client_conf.as_deref()),
+            cookie: cookie_path(var, |path| path.exists()),
+            runtime_dir: runtime_dir.to_path_buf(),
+            server: None,
+            session_managers: None,
+        }
+    }
+
+    /// Lines of the diagnostics block, without any prefix
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "version: {} {}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        )];
+
+        let chosen = self
+            .server_chain
+            .iter()
+            .find(|candidate| candidate.address.is_some());
+        lines.push(match chosen {
+            Some(ServerCandidate {
+                source,
+                address: Some(address),
+            }) => format!("server address: {address} (from {source})"),
+            _ => "server address: none found, libpulse decides".to_string(),
+        });
+        for candidate in &self.server_chain {
+            lines.push(format!(
+                "  {}: {}",
+                candidate.source,
+                candidate.address.as_deref().unwrap_or("not set")
+            ));
+        }
+
+        lines.push(format!(
+            "server: {}",
+            self.server.as_deref().unwrap_or("unknown")
+        ));
+        lines.push(format!(
+            "session manager: {}",
+            match &self.session_managers {
+                Some(names) if names.is_empty() => "none".to_string(),
+                Some(names) => names.join(", "),
+                None => "unknown".to_string(),
+            }
+        ));
+        lines.push(format!(
+            "cookie: {}",
+            self.cookie
+                .as_ref()
+                .map_or("none found".to_string(), |path| {
+                    path.display().to_string()
+                })
+        ));
+        lines.push(format!("runtime dir: {}", self.runtime_dir.display()));
+
+        let features = active_features();
+        lines.push(format!(
+            "features: {}",
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            }
+        ));
+        lines
+    }
+
+    /// Hostnames that must not end up in a bundle: ours and those of
+    /// configured remote servers
+    pub fn sensitive_hosts(&self) -> Vec<String> {
+        let local = fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .map(|name| name.trim().to_string());
+        let remote = self
+            .server_chain
+            .iter()
+            .filter_map(|candidate| candidate.address.as_deref())
+            .filter_map(address_host)
+            .map(str::to_string);
+        local.into_iter().chain(remote).collect()
+    }
+}
+
+/// Device as the server reported it
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceSnapshot {
+    pub index: u32,
+    pub name: String,
+    pub owner_module: Option<u32>,
+    pub active_port: Option<String>,
+    pub muted: bool,
+    pub properties: BTreeMap<String, String>,
+    pub recognized_as: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeviceSnapshots {
+    pub sinks: Vec<DeviceSnapshot>,
+    pub sources: Vec<DeviceSnapshot>,
+}
+
+/// Everything a bug report needs, in one file
+#[derive(Debug, Serialize)]
+pub struct Bundle {
+    pub diagnostics: Vec<String>,
+    /// Configuration as parsed, or null if it could not be
+    pub config: Value,
+    pub config_issues: Vec<String>,
+    /// Devices, or none if the server could not be reached
+    pub devices: Option<DeviceSnapshots>,
+    pub journal: Vec<journal::Entry>,
+}
+
+impl Bundle {
+    /// JSON of the bundle with secrets, the given hostnames and those and
+    /// serial numbers found in device properties redacted
+    pub fn to_redacted_json(
+        &self,
+        hosts: &[String],
+    ) -> serde_json::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        Redactor::new(hosts, self.devices.as_ref()).apply(&mut value);
+        serde_json::to_string_pretty(&value)
+    }
+}
+
+/// Replaces sensitive values wherever they appear, e.g. also in device
+/// names and journal entries, with placeholders that keep equal values
+/// recognizable as equal
+struct Redactor {
+    replacements: Vec<(String, String)>,
+}
+
+impl Redactor {
+    fn new(hosts: &[String], devices: Option<&DeviceSnapshots>) -> Self {
+        let mut hosts: BTreeSet<&str> =
+            hosts.iter().map(String::as_str).collect();
+        let mut serials = BTreeSet::new();
+        let properties = devices
+            .into_iter()
+            .flat_map(|devices| devices.sinks.iter().chain(&devices.sources))
+            .flat_map(|device| &device.properties);
+        for (key, value) in properties {
+            if key.contains("serial") {
+                serials.insert(value.as_str());
+            } else if key.ends_with(".host") || key.ends_with(".hostname") {
+                hosts.insert(value.as_str());
+            }
+        }
+
+        let numbered = |values: BTreeSet<&str>, kind: &str| {
+            values
+                .into_iter()
+                .filter(|value| value.len() >= MIN_REDACTED_LEN)
+                .enumerate()
+                .map(|(i, value)| {
+                    (value.to_string(), format!("<{kind}-{}>", i + 1))
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut replacements = numbered(hosts, "host");
+        replacements.extend(numbered(serials, "serial"));
+        // Longer values first, so that no part of one is replaced before it
+        replacements.sort_by_key(|(value, _)| std::cmp::Reverse(value.len()));
+        Self { replacements }
+    }
+
+    fn apply(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => {
+                for (from, to) in &self.replacements {
+                    if text.contains(from.as_str()) {
+                        *text = text.replace(from.as_str(), to);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                items.iter_mut().for_each(|item| self.apply(item));
+            }
+            Value::Object(map) => {
+                for (key, item) in map.iter_mut() {
+                    if SECRET_KEYS.contains(&key.as_str()) && !item.is_null() {
+                        *item = Value::from("<redacted>");
+                    } else {
+                        self.apply(item);
+                    }
+                }
+            }
+            Value::Null | Value::Bool(_) | Value::Number(_) => {}
+        }
+    }
+}
+
+/// Host of a TCP server address such as `tcp:host:4713`
+fn address_host(address: &str) -> Option<&str> {
+    let rest = ["tcp:", "tcp4:", "tcp6:"]
+        .iter()
+        .find_map(|prefix| address.strip_prefix(prefix))?;
+    let host = match rest.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next()?,
+        None => rest.split(':').next()?,
+    };
+    (!host.is_empty() && host != "localhost").then_some(host)
+}
+
+fn active_features() -> Vec<&'static str> {
+    FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Where the server address can come from, in the order libpulse tries
+/// them, except for the X11 root window which we cannot read
+fn server_chain(
+    server: Option<&str>,
+    var: impl Fn(&str) -> Option<OsString>,
+    client_conf: Option<&str>,
+) -> Vec<ServerCandidate> {
+    let string_var = |name| {
+        var(name)
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string_lossy().into_owned())
+    };
+
+    vec![
+        ServerCandidate {
+            source: "--server",
+            address: server.map(str::to_string),
+        },
+        ServerCandidate {
+            source: "PULSE_SERVER",
+            address: string_var("PULSE_SERVER"),
+        },
+        ServerCandidate {
+            source: "client.conf default-server",
+            address: client_conf.and_then(client_conf_server),
+        },
+        ServerCandidate {
+            source: "$XDG_RUNTIME_DIR/pulse/native",
+            address: string_var("XDG_RUNTIME_DIR")
+                .map(|dir| format!("unix:{dir}/pulse/native")),
+        },
+    ]
+}
+
+/// Configuration file of libpulse clients, if there is one
+fn client_conf_path(
+    var: impl Fn(&str) -> Option<OsString>,
+) -> Option<PathBuf> {
+    if let Some(path) = var("PULSE_CLIENTCONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let user = config_home(&var).map(|dir| dir.join("pulse/client.conf"));
+    [user, Some(PathBuf::from("/etc/pulse/client.conf"))]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+}
+
+/// `default-server` set in the content of a client.conf
+fn client_conf_server(content: &str) -> Option<String> {
+    content.lines().rev().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "default-server")
+            .then(|| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    })
+}
+
+/// Cookie libpulse authenticates with, the first existing one of the
+/// places it looks
+fn cookie_path(
+    var: impl Fn(&str) -> Option<OsString>,
+    exists: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    let home = var("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from);
+    [
+        var("PULSE_COOKIE").map(PathBuf::from),
+        config_home(&var).map(|dir| dir.join("pulse/cookie")),
+        home.map(|home| home.join(".pulse-cookie")),
+    ]
+    .into_iter()
+    .flatten()
+    .find(|path| exists(path))
+}
+
+fn config_home(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let dir_var = |name| var(name).filter(|value| !value.is_empty());
+    dir_var("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| {
+        dir_var("HOME").map(|home| PathBuf::from(home).join(".config"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vars(
+        pairs: &[(&str, &str)],
+    ) -> impl Fn(&str) -> Option<OsString> + use<> {
+        let vars: HashMap<String, OsString> = pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), OsString::from(value)))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_server_chain() {
+        let chain = server_chain(
+            None,
+            vars(&[("XDG_RUNTIME_DIR", "/run/user/1000")]),
+            Some("; comment\ndefault-server = tcp:host:4713\n"),
+        );
+        let addresses: Vec<_> = chain
+            .iter()
+            .map(|candidate| candidate.address.as_deref())
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                None,
+                None,
+                Some("tcp:host:4713"),
+                Some("unix:/run/user/1000/pulse/native")
+            ]
+        );
+
+        let diagnostics = Diagnostics {
+            server_chain: chain,
+            cookie: None,
+            runtime_dir: PathBuf::from("/run/user/1000/autopulsed"),
+            server: None,
+            session_managers: Some(Vec::new()),
+        };
+        let lines = diagnostics.lines();
+        assert!(
+            lines.contains(
+                &"server address: tcp:host:4713 (from client.conf \
+              default-server)"
+                    .to_string()
+            )
+        );
+        assert!(lines.contains(&"session manager: none".to_string()));
+        assert!(lines.contains(&"cookie: none found".to_string()));
+    }
+
+    #[test]
+    fn test_bundle_redaction() {
+        let device = DeviceSnapshot {
+            index: 3,
+            name: "alsa_output.usb-Vendor_DAC_SN123456-00.analog-stereo"
+                .to_string(),
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            properties: BTreeMap::from([
+                ("device.serial".to_string(), "SN123456".to_string()),
+                ("tunnel.remote.host".to_string(), "studio.lan".to_string()),
+            ]),
+            recognized_as: Vec::new(),
+        };
+        let bundle = Bundle {
+            diagnostics: vec!["server address: tcp:studio.lan:4713".into()],
+            config: serde_json::json!({"http": {"token": "hunter2"}}),
+            config_issues: Vec::new(),
+            devices: Some(DeviceSnapshots {
+                sinks: vec![device],
+                sources: Vec::new(),
+            }),
+            journal: Vec::new(),
+        };
+        let json = bundle.to_redacted_json(&["desk".to_string()]).unwrap();
+
+        assert!(!json.contains("SN123456"));
+        assert!(json.contains("Vendor_DAC_<serial-1>-00"));
+        assert!(json.contains("tcp:<host-2>:4713"));
+        assert!(!json.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_address_host() {
+        assert_eq!(address_host("tcp:studio.lan:4713"), Some("studio.lan"));
+        assert_eq!(address_host("tcp6:[fe80::1]:4713"), Some("fe80::1"));
+        assert_eq!(address_host("tcp:localhost"), None);
+        assert_eq!(address_host("unix:/run/pulse/native"), None);
+    }
+
+    #[test]
+    fn test_cookie_path() {
+        let home = vars(&[("HOME", "/home/a")]);
+        assert_eq!(
+            cookie_path(&home, |path| path.ends_with(".pulse-cookie")),
+            Some(PathBuf::from("/home/a/.pulse-cookie"))
+        );
+        assert_eq!(
+            cookie_path(&home, |_| true),
+            Some(PathBuf::from("/home/a/.config/pulse/cookie"))
+        );
+        assert_eq!(cookie_path(&home, |_| false), None);
+
+        let explicit = vars(&[("PULSE_COOKIE", "/tmp/cookie")]);
+        assert_eq!(
+            cookie_path(explicit, |_| true),
+            Some(PathBuf::from("/tmp/cookie"))
+        );
+    }
+}
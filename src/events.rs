@@ -0,0 +1,200 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Events queued for a subscriber that has not caught up, unless limited
+/// by the configuration
+pub const DEFAULT_BACKLOG: usize = 1000;
+
+/// Notable change made or observed by the daemon
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Recognized {
+        kind: &'static str,
+        index: u32,
+        config: String,
+    },
+    Unrecognized {
+        kind: &'static str,
+        index: u32,
+        config: String,
+    },
+    DefaultChanged {
+        kind: &'static str,
+        index: u32,
+        name: String,
+        config: Option<String>,
+    },
+    ModuleLoaded {
+        kind: &'static str,
+        config: String,
+        module: u32,
+    },
+    ModuleUnloaded {
+        kind: &'static str,
+        config: String,
+        module: u32,
+    },
+    ModuleFailed {
+        kind: &'static str,
+        config: String,
+        error: String,
+    },
+    /// A stream of another client started recording from a managed source
+    RecordingStarted {
+        index: u32,
+        source: u32,
+        config: String,
+    },
+    RecordingStopped {
+        index: u32,
+        source: u32,
+        config: String,
+    },
+    /// The default device stayed silent while playing and was failed over
+    SilenceDetected {
+        kind: &'static str,
+        index: u32,
+        name: String,
+        config: Option<String>,
+    },
+}
+
+/// Fans events out to subscribers running in other threads, dropping the
+/// events a subscriber has no room for
+#[derive(Clone)]
+pub struct EventFeed {
+    subscribers: Arc<Mutex<Vec<SyncSender<Arc<Event>>>>>,
+    backlog: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Default for EventFeed {
+    fn default() -> Self {
+        Self {
+            subscribers: Arc::default(),
+            backlog: Arc::new(AtomicUsize::new(DEFAULT_BACKLOG)),
+            dropped: Arc::default(),
+        }
+    }
+}
+
+impl EventFeed {
+    /// Queue up to this many events for each subscriber from now on
+    pub fn set_backlog(&self, backlog: usize) {
+        self.backlog.store(backlog.max(1), Ordering::Relaxed);
+    }
+
+    /// Events dropped so far for subscribers that had fallen behind
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self) -> Receiver<Arc<Event>> {
+        let (sender, receiver) =
+            mpsc::sync_channel(self.backlog.load(Ordering::Relaxed));
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: Event) {
+        self.publish_with(|| event);
+    }
+
+    /// Publish an event that is only built if anybody is listening, for
+    /// events raised on every device change
+    pub fn publish_with(&self, make_event: impl FnOnce() -> Event) {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let event = Arc::new(make_event());
+        // Subscribers that have gone are dropped here
+        subscribers.retain(|sender| {
+            match sender.try_send(Arc::clone(&event)) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_to_subscribers() {
+        let feed = EventFeed::default();
+        let event = Event::ModuleLoaded {
+            kind: "sink",
+            config: "mono".to_string(),
+            module: 42,
+        };
+
+        // Nobody is listening
+        feed.publish(event.clone());
+
+        let receiver = feed.subscribe();
+        let dropped = feed.subscribe();
+        drop(dropped);
+        feed.publish(event.clone());
+
+        assert_eq!(*receiver.try_recv().unwrap(), event);
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(feed.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_backlog_is_bounded() {
+        let feed = EventFeed::default();
+        feed.set_backlog(2);
+        let receiver = feed.subscribe();
+        for module in 0..5 {
+            feed.publish(Event::ModuleLoaded {
+                kind: "sink",
+                config: "mono".to_string(),
+                module,
+            });
+        }
+
+        // The oldest events are kept, the rest are counted
+        assert_eq!(receiver.try_iter().count(), 2);
+        assert_eq!(feed.dropped(), 3);
+        feed.publish(Event::ModuleUnloaded {
+            kind: "sink",
+            config: "mono".to_string(),
+            module: 0,
+        });
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+}
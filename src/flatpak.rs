@@ -0,0 +1,103 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Written by Flatpak into the root of every sandbox
+const INFO_FILE: &str = "/.flatpak-info";
+
+/// Flatpak sandbox the daemon runs in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    pub app_id: String,
+}
+
+impl Sandbox {
+    pub fn detect() -> Option<Self> {
+        let app_id = std::env::var("FLATPAK_ID").ok();
+        if app_id.is_none() && !Path::new(INFO_FILE).exists() {
+            return None;
+        }
+        Some(Self {
+            app_id: app_id.unwrap_or_else(|| "unknown".to_string()),
+        })
+    }
+
+    /// Server the sandbox gives access to, from `PULSE_SERVER` or the
+    /// socket shared at the usual place, as the X11 and D-Bus ways libpulse
+    /// also tries are not available
+    pub fn server_address(&self) -> Option<String> {
+        server_address_from(
+            |name| std::env::var_os(name),
+            |path| Path::new(path).exists(),
+        )
+    }
+
+    /// Explanation for a configuration file that cannot be read
+    pub fn config_access_hint(&self, path: &Path) -> String {
+        format!(
+            "{} may not be shared with the Flatpak sandbox; grant access \
+             with `flatpak override --filesystem={} {}`",
+            path.display(),
+            path.parent().unwrap_or(path).display(),
+            self.app_id
+        )
+    }
+}
+
+fn server_address_from(
+    var: impl Fn(&str) -> Option<OsString>,
+    exists: impl Fn(&str) -> bool,
+) -> Option<String> {
+    if let Some(server) = var("PULSE_SERVER").filter(|s| !s.is_empty()) {
+        return Some(server.to_string_lossy().into_owned());
+    }
+    let runtime_dir = var("XDG_RUNTIME_DIR")?;
+    let socket = format!("{}/pulse/native", runtime_dir.to_string_lossy());
+    exists(&socket).then(|| format!("unix:{socket}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_address() {
+        let address = server_address_from(
+            |name| match name {
+                "PULSE_SERVER" => Some("unix:/run/flatpak/pulse".into()),
+                "XDG_RUNTIME_DIR" => Some("/run/user/1000".into()),
+                _ => None,
+            },
+            |_| true,
+        );
+        assert_eq!(address.as_deref(), Some("unix:/run/flatpak/pulse"));
+
+        let address = server_address_from(
+            |name| {
+                (name == "XDG_RUNTIME_DIR").then(|| "/run/user/1000".into())
+            },
+            |path| path == "/run/user/1000/pulse/native",
+        );
+        assert_eq!(
+            address.as_deref(),
+            Some("unix:/run/user/1000/pulse/native")
+        );
+
+        assert_eq!(server_address_from(|_| None, |_| true), None);
+    }
+}
@@ -0,0 +1,556 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::config::HttpConfig;
+use crate::control::{
+    self, Command, ControlRequest, DeviceRef, PrivacySettings,
+};
+use crate::events::EventFeed;
+use crate::websocket;
+
+const MAX_HEADER_SIZE: u64 = 16 * 1024;
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Interval of pings detecting event clients that have gone
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What every connection needs, shared between their threads
+struct Shared {
+    token: String,
+    sender: Sender<ControlRequest>,
+    events: EventFeed,
+}
+
+#[derive(Debug, PartialEq)]
+enum Endpoint {
+    Command(Command),
+    Events,
+}
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.split('&').find_map(|pair| {
+            pair.strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('='))
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Response {
+    status: u16,
+    body: String, // JSON
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Response { status: 200, body },
+            Err(e) => Response::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        let body = serde_json::to_string(&ErrorBody { error: message })
+            .unwrap_or_default();
+        Response { status, body }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            426 => "Upgrade Required",
+            431 => "Request Header Fields Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            self.reason(),
+            self.body.len()
+        );
+        if self.status == 401 {
+            head.push_str("WWW-Authenticate: Bearer\r\n");
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes())?;
+        stream.write_all(self.body.as_bytes())?;
+        stream.flush()
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request, Response> {
+    let bad_request = |_| Response::error(400, "Malformed request");
+
+    let mut head = (&mut *reader).take(MAX_HEADER_SIZE);
+    let mut line = String::new();
+    head.read_line(&mut line).map_err(bad_request)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Response::error(400, "Malformed request line"));
+    };
+    let method = method.to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        head.read_line(&mut line).map_err(bad_request)?;
+        if line.is_empty() {
+            return Err(Response::error(400, "Incomplete request"));
+        }
+        if !line.ends_with('\n') {
+            return Err(Response::error(431, "Request header is too large"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(Response::error(400, "Malformed header"));
+        };
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        query,
+        headers,
+        body: Vec::new(),
+    };
+
+    let length = match request.header("Content-Length") {
+        Some(value) => value
+            .parse::<u64>()
+            .map_err(|_| Response::error(400, "Invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(Response::error(413, "Request body is too large"));
+    }
+    request.body.resize(length as usize, 0);
+    reader.read_exact(&mut request.body).map_err(bad_request)?;
+
+    Ok(request)
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    let credentials = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Browsers cannot add headers to WebSocket handshakes
+    let credentials = credentials.or_else(|| {
+        (request.path == "/events")
+            .then(|| request.query_param("token"))
+            .flatten()
+    });
+    let Some(credentials) = credentials else {
+        return false;
+    };
+
+    // Compare in constant time to not reveal the token by timing
+    credentials.len() == token.len()
+        && credentials
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn route(request: &Request) -> Result<Endpoint, Response> {
+    if request.path == "/events" {
+        return if request.method == "GET" {
+            Ok(Endpoint::Events)
+        } else {
+            Err(Response::error(405, "Method not allowed"))
+        };
+    }
+
+    let command = match request.path.as_str() {
+        "/status" => (request.method == "GET").then_some(Command::Status),
+        "/devices" => (request.method == "GET").then_some(Command::Devices),
+        "/default" if request.method == "POST" => {
+            let device: DeviceRef = serde_json::from_slice(&request.body)
+                .map_err(|_| {
+                    Response::error(
+                        400,
+                        r#"Expected {"sink": name} or {"source": name}"#,
+                    )
+                })?;
+            Some(Command::SetDefault { device })
+        }
+        "/default" => None,
+        "/privacy" if request.method == "POST" => {
+            let settings: PrivacySettings =
+                serde_json::from_slice(&request.body).map_err(|_| {
+                    Response::error(
+                        400,
+                        r#"Expected {"enabled": bool, "suspend": bool}"#,
+                    )
+                })?;
+            Some(Command::Privacy(settings))
+        }
+        "/privacy" => None,
+        "/pause" => (request.method == "POST").then_some(Command::Pause),
+        "/resume" => (request.method == "POST").then_some(Command::Resume),
+        "/reload" => (request.method == "POST").then_some(Command::Reload),
+        "/undo" => (request.method == "POST").then_some(Command::Undo),
+        _ => return Err(Response::error(404, "Unknown endpoint")),
+    };
+    command
+        .map(Endpoint::Command)
+        .ok_or_else(|| Response::error(405, "Method not allowed"))
+}
+
+/// Hand the command over to the main loop and wait for the result
+fn execute(command: Command, sender: &Sender<ControlRequest>) -> Response {
+    match control::request(sender, command, REPLY_TIMEOUT) {
+        Some(Ok(response)) => Response::json(&response),
+        Some(Err(message)) => Response::error(409, &message),
+        None => Response::error(503, "Daemon is not responding"),
+    }
+}
+
+/// Switch the connection to WebSocket and push events until the client
+/// goes away
+fn stream_events(
+    request: &Request,
+    mut stream: &TcpStream,
+    events: &EventFeed,
+) -> io::Result<()> {
+    let key = request
+        .header("Upgrade")
+        .filter(|value| value.eq_ignore_ascii_case("websocket"))
+        .and(request.header("Sec-WebSocket-Key"));
+    let Some(key) = key else {
+        return Response::error(426, "Expected a WebSocket handshake")
+            .write_to(&mut stream);
+    };
+
+    let handshake = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        websocket::accept_key(key)
+    );
+    stream.write_all(handshake.as_bytes())?;
+    debug!("Event client connected");
+
+    // Messages from the client are not read; a closed connection is
+    // noticed when writing to it
+    let receiver = events.subscribe();
+    loop {
+        match receiver.recv_timeout(PING_INTERVAL) {
+            Ok(event) => match serde_json::to_string(&*event) {
+                Ok(json) => websocket::write_text(&mut stream, &json)?,
+                Err(e) => warn!("Failed to serialize event: {e}"),
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                websocket::write_ping(&mut stream)?
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(response) => return response.write_to(&mut &stream),
+    };
+    debug!("HTTP {} {}", request.method, request.path);
+    if !is_authorized(&request, &shared.token) {
+        return Response::error(401, "Invalid or missing token")
+            .write_to(&mut &stream);
+    }
+
+    let response = match route(&request) {
+        Ok(Endpoint::Command(command)) => execute(command, &shared.sender),
+        Ok(Endpoint::Events) => {
+            return stream_events(&request, &stream, &shared.events);
+        }
+        Err(response) => response,
+    };
+    response.write_to(&mut &stream)
+}
+
+/// Serve the REST API on threads of their own, passing commands to the main
+/// loop through the sender and pushing events from the feed
+pub fn spawn(
+    config: &HttpConfig,
+    sender: Sender<ControlRequest>,
+    events: EventFeed,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(&config.listen)?;
+    let shared = Arc::new(Shared {
+        token: config.token.clone(),
+        sender,
+        events,
+    });
+    info!("Serving the REST API on {}", config.listen);
+
+    std::thread::Builder::new()
+        .name("http".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept HTTP connection: {e}");
+                        continue;
+                    }
+                };
+                let shared = Arc::clone(&shared);
+                // Event streams keep their connections open
+                let spawned = std::thread::Builder::new()
+                    .name("http-connection".to_string())
+                    .spawn(move || {
+                        if let Err(e) = handle_connection(stream, &shared) {
+                            debug!("HTTP connection failed: {e}");
+                        }
+                    });
+                if let Err(e) = spawned {
+                    warn!("Failed to handle HTTP connection: {e}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Send a command without a body to the REST API of a running daemon and
+/// return the message of its reply
+pub fn post(config: &HttpConfig, path: &str) -> Result<String, String> {
+    let failed = |e: io::Error| format!("Failed to reach the daemon: {e}");
+    let mut stream = TcpStream::connect(&config.listen).map_err(failed)?;
+    stream
+        .set_read_timeout(Some(IO_TIMEOUT + REPLY_TIMEOUT))
+        .map_err(failed)?;
+    let head = format!(
+        "POST {path} HTTP/1.1\r\nHost: {}\r\n\
+         Authorization: Bearer {}\r\nContent-Length: 0\r\n\
+         Connection: close\r\n\r\n",
+        config.listen, config.token
+    );
+    stream.write_all(head.as_bytes()).map_err(failed)?;
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).map_err(failed)?;
+    parse_reply(&raw)
+}
+
+/// The message of a successful reply, or the error of a failed one
+fn parse_reply(raw: &str) -> Result<String, String> {
+    let malformed = || "Malformed reply from the daemon".to_string();
+    let (head, body) = raw.split_once("\r\n\r\n").ok_or_else(malformed)?;
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(malformed)?;
+    let value: serde_json::Value =
+        serde_json::from_str(body).map_err(|_| malformed())?;
+    let field = if status == 200 { "message" } else { "error" };
+    let text = value
+        .get(field)
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or(body)
+        .to_string();
+    if status == 200 { Ok(text) } else { Err(text) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, Response> {
+        read_request(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn test_read_request() {
+        let request = parse(
+            "POST /default?x=1 HTTP/1.1\r\nHost: localhost\r\n\
+             content-length: 16\r\n\r\n{\"sink\": \"hdmi\"}",
+        )
+        .unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/default");
+        assert_eq!(request.header("Host"), Some("localhost"));
+        assert_eq!(request.body, b"{\"sink\": \"hdmi\"}");
+
+        assert_eq!(parse("GARBAGE\r\n\r\n").unwrap_err().status, 400);
+        assert_eq!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 999999\r\n\r\n")
+                .unwrap_err()
+                .status,
+            413
+        );
+    }
+
+    #[test]
+    fn test_authorization() {
+        let request = |header: &str| {
+            parse(&format!("GET /status HTTP/1.1\r\n{header}\r\n\r\n"))
+                .unwrap()
+        };
+
+        assert!(is_authorized(
+            &request("Authorization: Bearer secret"),
+            "secret"
+        ));
+        assert!(!is_authorized(
+            &request("Authorization: Bearer secreT"),
+            "secret"
+        ));
+        assert!(!is_authorized(
+            &request("Authorization: Basic secret"),
+            "secret"
+        ));
+        assert!(!is_authorized(&request("Host: localhost"), "secret"));
+
+        // Only the event stream accepts the token in the query
+        let query = |target: &str| {
+            parse(&format!("GET {target} HTTP/1.1\r\n\r\n")).unwrap()
+        };
+        assert!(is_authorized(&query("/events?token=secret"), "secret"));
+        assert!(!is_authorized(&query("/events?token=wrong"), "secret"));
+        assert!(!is_authorized(&query("/status?token=secret"), "secret"));
+    }
+
+    #[test]
+    fn test_route() {
+        let route_raw = |raw: &str| route(&parse(raw).unwrap());
+
+        assert_eq!(
+            route_raw("GET /status HTTP/1.1\r\n\r\n").unwrap(),
+            Endpoint::Command(Command::Status)
+        );
+        assert_eq!(
+            route_raw("GET /events HTTP/1.1\r\n\r\n").unwrap(),
+            Endpoint::Events
+        );
+        assert_eq!(
+            route_raw(
+                "POST /default HTTP/1.1\r\nContent-Length: 16\r\n\r\n\
+                 {\"source\":\"mic\"}"
+            )
+            .unwrap(),
+            Endpoint::Command(Command::SetDefault {
+                device: DeviceRef::Source {
+                    source: "mic".to_string()
+                }
+            })
+        );
+        assert_eq!(
+            route_raw("POST /default HTTP/1.1\r\n\r\n")
+                .unwrap_err()
+                .status,
+            400
+        );
+        assert_eq!(
+            route_raw(
+                "POST /privacy HTTP/1.1\r\nContent-Length: 31\r\n\r\n\
+                 {\"enabled\":true,\"suspend\":true}"
+            )
+            .unwrap(),
+            Endpoint::Command(Command::Privacy(PrivacySettings {
+                enabled: true,
+                suspend: true,
+            }))
+        );
+        assert_eq!(
+            route_raw("GET /pause HTTP/1.1\r\n\r\n").unwrap_err().status,
+            405
+        );
+        assert_eq!(
+            route_raw("POST /undo HTTP/1.1\r\n\r\n").unwrap(),
+            Endpoint::Command(Command::Undo)
+        );
+        assert_eq!(
+            route_raw("GET /unknown HTTP/1.1\r\n\r\n")
+                .unwrap_err()
+                .status,
+            404
+        );
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = |response: Response| {
+            let mut raw = Vec::new();
+            response.write_to(&mut raw).unwrap();
+            parse_reply(&String::from_utf8(raw).unwrap())
+        };
+
+        assert_eq!(
+            reply(Response::json(&control::Response::message("Undone"))),
+            Ok("Undone".to_string())
+        );
+        assert_eq!(
+            reply(Response::error(409, "Nothing to undo")),
+            Err("Nothing to undo".to_string())
+        );
+        assert!(parse_reply("HTTP/1.1 200 OK").is_err());
+    }
+}
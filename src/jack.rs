@@ -0,0 +1,114 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use crate::config::JackConfig;
+use crate::modargs::arg;
+
+/// Properties set by module-jack-sink/source on their devices
+const DEVICE_API: &str = "device.api";
+const CLIENT_NAME: &str = "jack.client_name";
+
+/// Whether the device is a JACK bridge matching the config
+pub fn matches(
+    config: &JackConfig,
+    properties: &BTreeMap<String, String>,
+) -> bool {
+    if properties.get(DEVICE_API).map(String::as_str) != Some("jack") {
+        return false;
+    }
+
+    match &config.client_name {
+        Some(client_name) => properties.get(CLIENT_NAME) == Some(client_name),
+        None => true,
+    }
+}
+
+/// Arguments for module-jack-sink/source
+pub fn module_args(config: &JackConfig) -> String {
+    let mut args = Vec::new();
+
+    if let Some(client_name) = &config.client_name {
+        args.push(arg("client_name", client_name));
+    }
+    if let Some(connect) = config.connect {
+        args.push(format!("connect={}", if connect { "yes" } else { "no" }));
+    }
+
+    args.join(" ")
+}
+
+/// Whether a JACK server (jackd or jackdbus) is running on this machine
+pub fn is_server_running() -> bool {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return false;
+    };
+
+    entries.flatten().any(|entry| {
+        std::fs::read_to_string(entry.path().join("comm"))
+            .is_ok_and(|comm| matches!(comm.trim_end(), "jackd" | "jackdbus"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_matches_jack_bridges() {
+        let any = JackConfig::default();
+        let named = JackConfig {
+            client_name: Some("pulse_out".to_string()),
+            ..JackConfig::default()
+        };
+
+        let bridge = properties(&[
+            ("device.api", "jack"),
+            ("jack.client_name", "pulse_out"),
+        ]);
+        let other_bridge = properties(&[
+            ("device.api", "jack"),
+            ("jack.client_name", "PulseAudio JACK Sink"),
+        ]);
+        let alsa = properties(&[("device.api", "alsa")]);
+
+        assert!(matches(&any, &bridge));
+        assert!(matches(&any, &other_bridge));
+        assert!(!matches(&any, &alsa));
+        assert!(matches(&named, &bridge));
+        assert!(!matches(&named, &other_bridge));
+    }
+
+    #[test]
+    fn test_module_args() {
+        let config = JackConfig {
+            client_name: Some("pulse_out".to_string()),
+            load: true,
+            connect: Some(false),
+            ..JackConfig::default()
+        };
+        assert_eq!(module_args(&config), "client_name=pulse_out connect=no");
+        assert_eq!(module_args(&JackConfig::default()), "");
+    }
+}
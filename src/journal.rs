@@ -0,0 +1,296 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const FILE_NAME: &str = "journal.jsonl";
+
+/// Entries kept in the journal unless limited by the configuration; once
+/// there are more, the oldest are dropped down to three quarters of the
+/// limit so that trimming is rare
+pub const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    SetDefault,
+    LoadModule,
+    MoveStream,
+}
+
+/// Action the daemon took on the server, why, and how it went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch at completion
+    pub time: u64,
+    pub action: ActionKind,
+    /// What the action changed, e.g. "default sink to #3"
+    pub target: String,
+    pub reason: String,
+    pub success: bool,
+}
+
+/// Journal file the daemon appends to, bounded to `max_entries`
+pub struct Journal {
+    path: PathBuf,
+    entries: usize,
+    max_entries: usize,
+}
+
+impl Journal {
+    pub fn open(state_dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(state_dir)?;
+        let path = path(state_dir);
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(content) => content.lines().count(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            entries,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        })
+    }
+
+    /// Bound the journal to fewer or more entries, trimming it on the next
+    /// append if it has grown beyond
+    pub fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries.max(1);
+    }
+
+    pub fn append(&mut self, entry: &Entry) {
+        if let Err(e) = self.try_append(entry) {
+            warn!("Failed to write to {}: {e}", self.path.display());
+        }
+    }
+
+    fn try_append(&mut self, entry: &Entry) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            self.trim()?;
+        }
+        Ok(())
+    }
+
+    fn trim(&mut self) -> std::io::Result<()> {
+        let content = std::fs::read_to_string(&self.path)?;
+        let lines: Vec<_> = content.lines().collect();
+        let keep = lines.len().saturating_sub(self.max_entries * 3 / 4);
+        let keep = &lines[keep..];
+        let mut trimmed = keep.join("\n");
+        trimmed.push('\n');
+        let temporary = self.path.with_extension("jsonl.tmp");
+        std::fs::write(&temporary, trimmed)?;
+        std::fs::rename(&temporary, &self.path)?;
+        self.entries = keep.len();
+        Ok(())
+    }
+}
+
+pub fn path(state_dir: &Path) -> PathBuf {
+    state_dir.join(FILE_NAME)
+}
+
+/// Entries of the journal, oldest first, skipping lines that cannot be
+/// parsed such as one cut short by a crash
+pub fn read(path: &Path) -> std::io::Result<Vec<Entry>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Which entries to show
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Earliest time, in seconds since the Unix epoch
+    pub since: Option<u64>,
+    pub action: Option<ActionKind>,
+    pub failed_only: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.since.is_none_or(|since| entry.time >= since)
+            && self.action.is_none_or(|action| entry.action == action)
+            && (!self.failed_only || !entry.success)
+    }
+}
+
+/// Duration such as "90s", "30m", "1h" or "2d"
+pub fn parse_age(text: &str) -> Result<Duration, String> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+    let (number, unit) = text.split_at(split);
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if seconds > 0 => Ok(Duration::from_secs(number * seconds)),
+        _ => Err(format!(
+            "Invalid age '{text}', expected a number with s, m, h or d"
+        )),
+    }
+}
+
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// UTC date and time of a Unix timestamp, like "2025-08-06 12:34:56Z"
+pub fn format_time(time: u64) -> String {
+    let (days, seconds) = (time / 86400, time % 86400);
+    // Civil date from days since the epoch, after Howard Hinnant
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.to_possible_value().ok_or(fmt::Error)?;
+        write!(f, "{}", value.get_name())
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {:<11}  {:<6}  {} ({})",
+            format_time(self.time),
+            self.action.to_string(),
+            if self.success { "ok" } else { "failed" },
+            self.target,
+            self.reason
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(time: u64, action: ActionKind, success: bool) -> Entry {
+        Entry {
+            time,
+            action,
+            target: "default sink to #3".to_string(),
+            reason: "recognized as 'usb'".to_string(),
+            success,
+        }
+    }
+
+    #[test]
+    fn test_journal_is_bounded() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut journal = Journal::open(dir.path()).unwrap();
+        for time in 0..=DEFAULT_MAX_ENTRIES as u64 {
+            journal.append(&entry(time, ActionKind::SetDefault, true));
+        }
+
+        let entries = read(&path(dir.path())).unwrap();
+        assert_eq!(entries.len(), DEFAULT_MAX_ENTRIES * 3 / 4);
+        assert_eq!(entries.last().unwrap().time, DEFAULT_MAX_ENTRIES as u64);
+        assert_eq!(Journal::open(dir.path()).unwrap().entries, entries.len());
+
+        // A lower limit applies from the next entry on
+        journal.set_max_entries(8);
+        journal.append(&entry(0, ActionKind::SetDefault, false));
+        assert_eq!(read(&path(dir.path())).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter {
+            since: Some(100),
+            action: Some(ActionKind::SetDefault),
+            failed_only: false,
+        };
+        assert!(filter.matches(&entry(100, ActionKind::SetDefault, true)));
+        assert!(!filter.matches(&entry(99, ActionKind::SetDefault, true)));
+        assert!(!filter.matches(&entry(100, ActionKind::LoadModule, true)));
+
+        let failed = Filter {
+            failed_only: true,
+            ..Filter::default()
+        };
+        assert!(!failed.matches(&entry(0, ActionKind::MoveStream, true)));
+        assert!(failed.matches(&entry(0, ActionKind::MoveStream, false)));
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_age("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_age("2d"), Ok(Duration::from_secs(172_800)));
+        assert!(parse_age("1w").is_err());
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("10").is_err());
+    }
+
+    #[test]
+    fn test_format_entry() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00Z");
+        assert_eq!(format_time(1_754_483_696), "2025-08-06 12:34:56Z");
+        assert_eq!(format_time(951_782_400), "2000-02-29 00:00:00Z");
+        assert_eq!(
+            entry(0, ActionKind::SetDefault, false).to_string(),
+            "1970-01-01 00:00:00Z  set-default  failed  default sink to #3 \
+             (recognized as 'usb')"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{IsTerminal, Write};
+
+use clap::ValueEnum;
+use log::{Level, LevelFilter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LogFormat {
+    /// Plain `env_logger` lines
+    #[default]
+    Default,
+    /// Colored lines with icons, for watching the daemon in a terminal
+    Pretty,
+}
+
+/// What a log line is about, deciding its color and icon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Error,
+    Warning,
+    Recognition,
+    DefaultChange,
+    Info,
+    Debug,
+}
+
+impl Category {
+    fn of(level: Level, message: &str) -> Self {
+        match level {
+            Level::Error => Category::Error,
+            Level::Warn => Category::Warning,
+            Level::Info if message.contains("recognized as") => {
+                Category::Recognition
+            }
+            Level::Info if message.contains("default") => {
+                Category::DefaultChange
+            }
+            Level::Info => Category::Info,
+            Level::Debug | Level::Trace => Category::Debug,
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Category::Error => "✗",
+            Category::Warning => "!",
+            Category::Recognition => "◆",
+            Category::DefaultChange => "★",
+            Category::Info => "•",
+            Category::Debug => "·",
+        }
+    }
+
+    /// ANSI SGR parameters of the line
+    fn color(self) -> &'static str {
+        match self {
+            Category::Error => "1;31",
+            Category::Warning => "33",
+            Category::Recognition => "35",
+            Category::DefaultChange => "1;36",
+            Category::Info => "0",
+            Category::Debug => "2",
+        }
+    }
+}
+
+pub fn init(format: LogFormat, level: LevelFilter) {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.filter_level(level);
+
+    // Colors only help a person reading along
+    if format == LogFormat::Pretty && std::io::stderr().is_terminal() {
+        builder.format(|buf, record| {
+            let message = record.args().to_string();
+            let category = Category::of(record.level(), &message);
+            writeln!(
+                buf,
+                "\x1b[2m{}\x1b[0m \x1b[{}m{} {:<5} {message}\x1b[0m",
+                buf.timestamp_seconds(),
+                category.color(),
+                category.icon(),
+                record.level(),
+            )
+        });
+    }
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category_of_messages() {
+        assert_eq!(
+            Category::of(Level::Info, "Sink #3 is recognized as 'speakers'"),
+            Category::Recognition
+        );
+        assert_eq!(
+            Category::of(Level::Info, "Successfully set default sink to #3"),
+            Category::DefaultChange
+        );
+        assert_eq!(
+            Category::of(Level::Info, "Starting autopulsed v0.2.0"),
+            Category::Info
+        );
+        assert_eq!(
+            Category::of(Level::Warn, "Failed to set default sink"),
+            Category::Warning
+        );
+        assert_eq!(
+            Category::of(Level::Debug, "Sink #3 is recognized as 'x'"),
+            Category::Debug
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Notification of suspend and resume by systemd-logind
+
+use std::io::{self, BufRead, BufReader};
+use std::process::{Command as Process, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use log::{debug, info, warn};
+
+use crate::control::{Command, ControlRequest};
+
+/// Prints the signals of the logind manager, one per line
+const MONITOR: &[&str] = &[
+    "gdbus",
+    "monitor",
+    "--system",
+    "--dest",
+    "org.freedesktop.login1",
+    "--object-path",
+    "/org/freedesktop/login1",
+];
+
+/// Watch the PrepareForSleep signal, and have the main loop re-synchronize
+/// with the server after every resume
+///
+/// USB audio devices commonly re-enumerate after sleep, and events of the
+/// server may have been lost meanwhile.
+pub fn spawn(sender: Sender<ControlRequest>) -> io::Result<()> {
+    let mut monitor = Process::new(MONITOR[0])
+        .args(&MONITOR[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = monitor.stdout.take().ok_or(io::ErrorKind::BrokenPipe)?;
+
+    thread::Builder::new()
+        .name("logind".to_string())
+        .spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                match parse_prepare_for_sleep(&line) {
+                    Some(true) => info!("The system is going to sleep"),
+                    Some(false) => {
+                        info!("The system has resumed from sleep");
+                        let request = ControlRequest {
+                            command: Command::Resync,
+                            reply: None,
+                        };
+                        if sender.send(request).is_err() {
+                            break;
+                        }
+                    }
+                    None => debug!("Ignoring logind signal: {line}"),
+                }
+            }
+            let _ = monitor.kill();
+            let _ = monitor.wait();
+            warn!("Stopped watching logind for suspend and resume");
+        })?;
+    Ok(())
+}
+
+/// Argument of a PrepareForSleep signal as printed by `gdbus monitor`,
+/// which is true before sleeping and false after resuming
+fn parse_prepare_for_sleep(line: &str) -> Option<bool> {
+    let (_, arguments) = line.split_once(".PrepareForSleep ")?;
+    match arguments.trim() {
+        "(true,)" => Some(true),
+        "(false,)" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prepare_for_sleep() {
+        let signal = |argument| {
+            format!(
+                "/org/freedesktop/login1: \
+                 org.freedesktop.login1.Manager.PrepareForSleep ({argument},)"
+            )
+        };
+        assert_eq!(parse_prepare_for_sleep(&signal("true")), Some(true));
+        assert_eq!(parse_prepare_for_sleep(&signal("false")), Some(false));
+        assert_eq!(
+            parse_prepare_for_sleep(
+                "/org/freedesktop/login1: \
+                 org.freedesktop.login1.Manager.SessionNew ('3', \
+                 objectpath '/org/freedesktop/login1/session/_33')"
+            ),
+            None
+        );
+    }
+}
@@ -15,10 +15,12 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::cell::{Cell, RefCell};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use libpulse_binding::{
     context::Context,
     mainloop::{
@@ -26,14 +28,52 @@ use libpulse_binding::{
         standard::{IterateResult, Mainloop},
     },
     proplist::Proplist,
+    time::MicroSeconds,
 };
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
-mod config;
+#[cfg(feature = "alsa")]
+mod alsa;
+mod container;
+mod diag;
+mod events;
+mod flatpak;
+mod http;
+mod journal;
+mod logging;
+#[cfg(feature = "logind")]
+mod logind;
+mod messages;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "osc")]
+mod osc;
+mod overrides;
+mod owner;
+mod paths;
+mod quirks;
+mod remote;
+mod selftest;
+mod sound;
 mod state;
+mod statistics;
+mod summary;
+mod systemd;
+mod timer;
+#[cfg(feature = "udev")]
+mod udev;
+mod webhook;
+mod websocket;
 
-use config::Config;
+use autopulsed::{config, control, jack, modargs, server};
+
+use config::{Config, Severity, ValidationReport};
+use control::{Command, ControlRequest, Reply, Response};
+use events::EventFeed;
+use logging::LogFormat;
+use messages::Message;
 use state::{State, StateRunner};
+use summary::{Run, ShutdownReason, ShutdownSummary};
 
 #[derive(Parser)]
 #[command(name = env!("CARGO_PKG_NAME"))]
@@ -43,6 +83,24 @@ struct Args {
     #[arg(short, long, value_name = "FILE")]
     config: Option<PathBuf>,
 
+    #[arg(
+        long,
+        value_name = "URL",
+        conflicts_with = "config",
+        help = "Download the configuration at startup and on reload, using \
+                the last good copy when that fails"
+    )]
+    config_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "HEX",
+        requires = "config_url",
+        help = "Accept the downloaded configuration only with this SHA-256 \
+                digest"
+    )]
+    config_sha256: Option<String>,
+
     #[arg(
         short,
         long,
@@ -51,10 +109,182 @@ struct Args {
     )]
     server: Option<String>,
 
+    #[arg(
+        long,
+        help = "Treat remap masters referring to undefined configs as errors"
+    )]
+    strict_refs: bool,
+
+    #[arg(long, help = "Leave sinks alone, like manage_sinks: false")]
+    no_manage_sinks: bool,
+
+    #[arg(long, help = "Leave sources alone, like manage_sources: false")]
+    no_manage_sources: bool,
+
+    #[arg(
+        long,
+        help = "Log every candidate of default and remap decisions with the \
+                reason it was or was not chosen"
+    )]
+    trace_decisions: bool,
+
+    #[arg(
+        long,
+        help = "Exit with an error once a device matches no config, for \
+                testing configurations"
+    )]
+    fail_on_unmatched: bool,
+
+    #[arg(
+        long,
+        help = "Detect devices and report what would be done, but never \
+                change anything on the server"
+    )]
+    observe: bool,
+
+    #[arg(
+        long,
+        help = "Check that the server permits loading modules and changing \
+                defaults, then exit"
+    )]
+    self_test: bool,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory for runtime files, instead of one under \
+                $XDG_RUNTIME_DIR"
+    )]
+    runtime_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Run in a container against the socket of the host: wait for \
+                the socket, create a readiness file and exit with an \
+                explanation when the connection fails"
+    )]
+    container: bool,
+
     #[arg(short, long)]
     verbose: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = LogFormat::Default,
+        help = "Log line format; pretty is only used when stderr is a \
+                terminal"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "LANG",
+        help = "Language of the command output; detected from the locale \
+                by default, while log lines are always in English"
+    )]
+    lang: Option<messages::Lang>,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Play a test tone on the sink recognized as a config, without
+    /// changing anything
+    Test {
+        #[arg(long, value_name = "NAME", help = "Name of a sink config")]
+        device: String,
+    },
+    /// Validate the configuration and print all errors and warnings
+    Check {
+        #[arg(
+            long,
+            help = "Also check the example devices of the assertions section \
+                    against the configs they must be recognized as"
+        )]
+        run_assertions: bool,
+    },
+    /// Update the configuration file to the current format version,
+    /// keeping the previous one as a backup
+    MigrateConfig {
+        #[arg(
+            long,
+            help = "Print the migrated configuration instead of writing it"
+        )]
+        dry_run: bool,
+    },
+    /// Ask the running daemon to restore the default device that its last
+    /// switch replaced, through the REST API
+    Undo,
+    /// Print the server address resolution, server, session manager,
+    /// cookie, runtime directory and features, for bug reports
+    Diag {
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Write a JSON bundle with the diagnostics, configuration, \
+                    devices and recent journal entries, with secrets, \
+                    hostnames and serial numbers redacted"
+        )]
+        bundle: Option<PathBuf>,
+    },
+    /// Print the journal of actions the daemon took, oldest first
+    Log(LogArgs),
+    /// Print the statistics of the defaults kept with the `statistics`
+    /// option
+    Stats,
+    /// Generate files derived from the code
+    Gen {
+        #[command(subcommand)]
+        target: GenTarget,
+    },
+    /// Measure matching and default selection on synthetic devices
+    #[command(hide = true)]
+    Bench {
+        #[arg(long, default_value_t = 1000, help = "Number of devices")]
+        devices: usize,
+        #[arg(long, default_value_t = 100, help = "Number of configs")]
+        configs: usize,
+    },
+}
+
+#[derive(clap::Args)]
+struct LogArgs {
+    #[arg(
+        long,
+        value_name = "AGE",
+        help = "Only actions of the last AGE, e.g. 30m, 1h or 2d"
+    )]
+    since: Option<String>,
+    #[arg(long, value_enum, help = "Only actions of this kind")]
+    action: Option<journal::ActionKind>,
+    #[arg(long, help = "Only actions that failed")]
+    failed: bool,
+}
+
+#[derive(Subcommand)]
+enum GenTarget {
+    /// Print the JSON Schema of the configuration file, for editors
+    /// validating it
+    ConfigSchema,
+    /// Print an example configuration with every option and its default
+    ExampleConfig {
+        #[arg(long, help = "Expand every section down to its last option")]
+        full: bool,
+    },
 }
 
+/// How long the test subcommand waits for the server
+const TEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often commands from control inputs are picked up while any of them
+/// is running
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 struct App {
     // IMPORTANT: Field order matters for destruction sequence!
     // PulseAudio objects must be dropped in this order to prevent crashes:
@@ -64,39 +294,66 @@ struct App {
     // See https://github.com/jnqnfe/pulse-binding-rust/issues/65
     _sigint_handler: Option<SignalEvent>,
     _sigterm_handler: Option<SignalEvent>,
+    _sighup_handler: Option<SignalEvent>,
+    _sigusr1_handler: Option<SignalEvent>,
+    _sigusr2_handler: Option<SignalEvent>,
     state: Rc<RefCell<State>>,
     mainloop: Rc<RefCell<Mainloop>>,
-    quit_requested: Rc<Cell<bool>>,
+    quit_requested: Rc<Cell<Option<&'static str>>>, // By the signal
+    reload_requested: Rc<Cell<bool>>,
+    dump_requested: Rc<Cell<bool>>,
+    rediscover_requested: Rc<Cell<bool>>,
+    config_source: ConfigSource,
+    ready_notified: bool,
+    last_status: String,
+    control_receiver: Receiver<ControlRequest>,
+    control_active: bool,
+    fail_on_unmatched: bool,
+    container: Option<container::Container>,
+    diagnostics: Option<diag::Diagnostics>, // Logged once the server is known
+    /// Why the daemon stopped, if it stopped because of a failure
+    failure: Option<String>,
+    started: Instant,
 }
 
 impl App {
     fn new(
-        config: Config,
+        config_source: ConfigSource,
         server: Option<String>,
+        fail_on_unmatched: bool,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut proplist = Proplist::new().unwrap();
-        proplist
-            .set_str(
-                libpulse_binding::proplist::properties::APPLICATION_NAME,
-                env!("CARGO_PKG_NAME"),
-            )
-            .map_err(|_| "Failed to set application name")?;
+        let config = config_source.load()?;
+        // A summary left from an earlier run must not vouch for this one
+        if let Some(path) = &config.summary_file
+            && let Err(e) = std::fs::remove_file(path)
+            && e.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove the old summary {}: {e}", path.display());
+        }
+        let (control_sender, control_receiver) = control::channel();
+        let events = EventFeed::default();
+        let control_active =
+            start_control_inputs(&config, &control_sender, &events);
+        if let Err(e) = webhook::spawn(&config.webhooks, &events) {
+            error!("Failed to start posting to webhooks: {e}");
+        }
 
         let mainloop = Rc::new(RefCell::new(
             Mainloop::new().ok_or("Failed to create mainloop")?,
         ));
+        let context = new_context(&mainloop.borrow())?;
 
-        let context = {
-            let mainloop_ref = mainloop.borrow();
-            Context::new_with_proplist(
-                &*mainloop_ref,
-                env!("CARGO_PKG_NAME"),
-                &proplist,
-            )
-            .ok_or("Failed to create context")?
-        };
+        let state = State::from_context(context, config, events);
 
-        let state = State::from_context(context, config);
+        let sandbox = flatpak::Sandbox::detect();
+        let server = match &sandbox {
+            Some(sandbox) => {
+                info!("Running in the Flatpak sandbox of {}", sandbox.app_id);
+                state.borrow_mut().mark_sandboxed();
+                server.or_else(|| sandbox.server_address())
+            }
+            None => server,
+        };
 
         // Log server connection target if specified
         if let Some(ref server_str) = server {
@@ -111,31 +368,70 @@ impl App {
         Ok(App {
             _sigint_handler: None,
             _sigterm_handler: None,
+            _sighup_handler: None,
+            _sigusr1_handler: None,
+            _sigusr2_handler: None,
             state,
             mainloop,
-            quit_requested: Rc::new(Cell::new(false)),
+            quit_requested: Rc::new(Cell::new(None)),
+            reload_requested: Rc::new(Cell::new(false)),
+            dump_requested: Rc::new(Cell::new(false)),
+            rediscover_requested: Rc::new(Cell::new(false)),
+            config_source,
+            ready_notified: false,
+            last_status: String::new(),
+            control_receiver,
+            control_active,
+            fail_on_unmatched,
+            container: None,
+            diagnostics: None,
+            failure: None,
+            started: Instant::now(),
         })
     }
 
     fn setup_signal_handler(
         &mut self,
     ) -> Result<(), Box<dyn std::error::Error>> {
+        const SIGHUP: i32 = 1;
         const SIGINT: i32 = 2;
+        const SIGUSR1: i32 = 10;
+        const SIGUSR2: i32 = 12;
         const SIGTERM: i32 = 15;
 
         let create_signal_handler = |sig: i32, sig_name: &'static str| {
             let quit_flag = self.quit_requested.clone();
             SignalEvent::new(sig, move |_sig| {
                 info!("Received {sig_name}, shutting down gracefully...");
-                quit_flag.set(true);
+                quit_flag.set(Some(sig_name));
             })
         };
 
         let sigint_handler = create_signal_handler(SIGINT, "SIGINT");
         let sigterm_handler = create_signal_handler(SIGTERM, "SIGTERM");
 
+        let reload_flag = self.reload_requested.clone();
+        let sighup_handler = SignalEvent::new(SIGHUP, move |_sig| {
+            info!("Received SIGHUP, reloading configuration...");
+            reload_flag.set(true);
+        });
+
         self._sigint_handler = Some(sigint_handler);
         self._sigterm_handler = Some(sigterm_handler);
+        let dump_flag = self.dump_requested.clone();
+        let sigusr1_handler = SignalEvent::new(SIGUSR1, move |_sig| {
+            dump_flag.set(true);
+        });
+
+        let rediscover_flag = self.rediscover_requested.clone();
+        let sigusr2_handler = SignalEvent::new(SIGUSR2, move |_sig| {
+            info!("Received SIGUSR2, listing all devices again...");
+            rediscover_flag.set(true);
+        });
+
+        self._sighup_handler = Some(sighup_handler);
+        self._sigusr1_handler = Some(sigusr1_handler);
+        self._sigusr2_handler = Some(sigusr2_handler);
 
         // Initialize AFTER creating signal handlers to prevent race condition
         self.mainloop.borrow_mut().init_signals()?;
@@ -151,92 +447,811 @@ impl App {
             env!("CARGO_PKG_NAME")
         );
 
-        loop {
-            if self.quit_requested.get() {
+        let reason = loop {
+            if let Some(signal) = self.quit_requested.get() {
                 info!("Signal received, initiating shutdown");
-                break;
+                break ShutdownReason::Signal(signal);
             }
 
-            match self.mainloop.borrow_mut().iterate(true) {
+            match self.iterate_until_next_timer() {
                 IterateResult::Quit(_) => {
                     info!("Mainloop quit");
-                    break;
+                    break ShutdownReason::MainloopQuit;
                 }
                 IterateResult::Err(_) => {
                     error!("Mainloop error");
+                    self.summarize(&ShutdownReason::MainloopError, false);
                     return Err("Mainloop error".into());
                 }
                 IterateResult::Success(_) => {}
             }
-        }
+
+            StateRunner::run_due_timers(&self.state);
+            self.handle_control_requests();
+            self.update_service_status();
+            self.log_diagnostics();
+
+            if let Some(summary) = self.unmatched_failure() {
+                error!("{summary}");
+                self.failure = Some(summary.clone());
+                break ShutdownReason::Failure(summary);
+            }
+            if let Some(explanation) = self.connection_failure() {
+                error!("{explanation}");
+                self.failure = Some(explanation.clone());
+                break ShutdownReason::Failure(explanation);
+            }
+
+            if self.reload_requested.take() {
+                // Failures are logged, and there is nobody else to tell
+                let _ = self.reload_config();
+            }
+            if self.rediscover_requested.take() {
+                StateRunner::with(&self.state, |runner| runner.rediscover());
+            }
+            if self.dump_requested.take() {
+                info!("Received SIGUSR1, dumping the state:");
+                for line in self.state.borrow().dump() {
+                    info!("  {line}");
+                }
+            }
+        };
 
         info!("Cleaning up resources");
+        if let Some(container) = &self.container {
+            container.clear_ready();
+        }
         self.state.borrow_mut().begin_shutdown();
+        // Streams are moved off the remap devices before they disappear
+        StateRunner::with(&self.state, |runner| {
+            runner.restore_initial_defaults();
+            runner.evict_streams();
+        });
+        self.wait_for_shutdown_work();
         StateRunner::with(&self.state, |runner| {
             runner.cleanup_remap_modules();
         });
 
-        if !self.state.borrow().has_pending_unloads() {
+        if !self.state.borrow().has_pending_shutdown_work() {
             info!("No modules to clean up, exiting");
+            self.summarize(&reason, true);
             return Ok(());
         }
+        let completed = self.wait_for_shutdown_work();
+        if completed {
+            info!("All modules unloaded, cleanup completed");
+        }
+        self.summarize(&reason, completed);
+
+        // Signal cleanup is now handled properly through struct field ordering.
+        // SignalEvent fields are dropped before mainloop's destructor runs,
+        // preventing the double-free issue.
+        debug!("Cleanup completed successfully");
+
+        Ok(())
+    }
+
+    /// Log the summary of the run, and write it to the configured file
+    fn summarize(&self, reason: &ShutdownReason, cleanup_completed: bool) {
+        self.state.borrow_mut().close_statistics();
+        let state = self.state.borrow();
+        let summary = ShutdownSummary::new(
+            reason,
+            Run {
+                uptime: self.started.elapsed(),
+                activity: state.activity(),
+            },
+            cleanup_completed,
+        );
+        info!("Shutdown summary: {summary}");
+        if let Some(path) = state.summary_file()
+            && let Err(e) = summary.write(path)
+        {
+            warn!("Failed to write the summary to {}: {e}", path.display());
+        }
+    }
 
-        loop {
+    /// Run the mainloop until the pending shutdown operations have
+    /// completed, returning whether they have
+    fn wait_for_shutdown_work(&mut self) -> bool {
+        while self.state.borrow().has_pending_shutdown_work() {
             match self.mainloop.borrow_mut().iterate(true) {
                 IterateResult::Quit(_) => {
                     info!("Mainloop quit");
-                    break;
+                    return false;
                 }
                 IterateResult::Err(_) => {
                     error!("Error during cleanup");
-                    break;
+                    return false;
                 }
-                IterateResult::Success(_) => {
-                    if !self.state.borrow().has_pending_unloads() {
-                        info!("All modules unloaded, cleanup completed");
-                        break;
-                    }
+                IterateResult::Success(_) => {}
+            }
+        }
+        true
+    }
+
+    /// Like `Mainloop::iterate(true)`, but returns when the next timer of
+    /// the state is due even if no events have arrived
+    fn iterate_until_next_timer(&self) -> IterateResult {
+        let mut remaining =
+            self.state.borrow().next_timer_deadline().map(|deadline| {
+                deadline.saturating_duration_since(Instant::now())
+            });
+        if self.control_active {
+            // Control inputs cannot wake up the mainloop from their threads
+            remaining =
+                Some(remaining.map_or(CONTROL_POLL_INTERVAL, |remaining| {
+                    remaining.min(CONTROL_POLL_INTERVAL)
+                }));
+        }
+
+        iterate_with_timeout(&mut self.mainloop.borrow_mut(), remaining)
+    }
+
+    fn handle_control_requests(&mut self) {
+        while let Ok(request) = self.control_receiver.try_recv() {
+            debug!("Received command: {:?}", request.command);
+            let reply: Reply = match &request.command {
+                Command::Reload => {
+                    info!("Reloading configuration by command");
+                    self.reload_config()
+                        .map(|()| Response::message("Configuration reloaded"))
                 }
+                command => StateRunner::with(&self.state, |runner| {
+                    runner.execute(command)
+                }),
+            };
+            if let Err(e) = &reply {
+                warn!("Command failed: {e}");
+            }
+            if let Some(sender) = request.reply {
+                // The requester may have given up waiting
+                let _ = sender.send(reply);
             }
         }
+    }
 
-        // Signal cleanup is now handled properly through struct field ordering.
-        // SignalEvent fields are dropped before mainloop's destructor runs,
-        // preventing the double-free issue.
-        debug!("Cleanup completed successfully");
+    /// Log the diagnostics block once the server has told what it is
+    fn log_diagnostics(&mut self) {
+        let Some(diagnostics) = &mut self.diagnostics else {
+            return;
+        };
+        let Some((server, session_managers)) =
+            self.state.borrow().server_diagnostics()
+        else {
+            return;
+        };
 
-        Ok(())
+        diagnostics.server = Some(server);
+        diagnostics.session_managers = Some(session_managers);
+        info!("Diagnostics:");
+        for line in diagnostics.lines() {
+            info!("  {line}");
+        }
+        self.diagnostics = None;
+    }
+
+    /// Summary of the devices matching no config when the daemon should
+    /// fail because of them
+    fn unmatched_failure(&self) -> Option<String> {
+        let state = self.state.borrow();
+        if !self.fail_on_unmatched || !state.is_initial_sync_complete() {
+            return None;
+        }
+        state.unmanaged_summary()
+    }
+
+    /// Explanation of a failed connection in container mode, where the
+    /// daemon exits rather than waiting
+    fn connection_failure(&self) -> Option<String> {
+        let container = self.container.as_ref()?;
+        let error = self.state.borrow().connection_error()?;
+        Some(container.explain_failure(error))
+    }
+
+    fn update_service_status(&mut self) {
+        let state = self.state.borrow();
+
+        let status = state.status();
+        if status != self.last_status {
+            if let Err(e) = systemd::notify(&format!("STATUS={status}")) {
+                warn!("Failed to notify systemd of status: {e}");
+            }
+            self.last_status = status;
+        }
+
+        if !self.ready_notified && state.is_initial_sync_complete() {
+            self.ready_notified = true;
+            match systemd::notify("READY=1") {
+                Ok(true) => debug!("Notified systemd of readiness"),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to notify systemd: {e}"),
+            }
+            if let Some(container) = &self.container {
+                container.mark_ready();
+            }
+        }
+    }
+
+    fn reload_config(&mut self) -> Result<(), String> {
+        if !self.config_source.has_file() {
+            warn!("No configuration file to reload");
+            return Err("No configuration file to reload".to_string());
+        }
+
+        match self.config_source.load() {
+            Ok(config) => {
+                StateRunner::with(&self.state, |runner| {
+                    runner.reload_config(config);
+                });
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to reload configuration, keeping current: {e}");
+                Err(format!("Failed to reload configuration: {e}"))
+            }
+        }
+    }
+}
+
+fn new_context(
+    mainloop: &Mainloop,
+) -> Result<Context, Box<dyn std::error::Error>> {
+    let mut proplist = Proplist::new().unwrap();
+    proplist
+        .set_str(
+            libpulse_binding::proplist::properties::APPLICATION_NAME,
+            env!("CARGO_PKG_NAME"),
+        )
+        .map_err(|_| "Failed to set application name")?;
+
+    Context::new_with_proplist(mainloop, env!("CARGO_PKG_NAME"), &proplist)
+        .ok_or_else(|| "Failed to create context".into())
+}
+
+/// Like `Mainloop::iterate(true)`, but returns after the timeout even if no
+/// events have arrived
+fn iterate_with_timeout(
+    mainloop: &mut Mainloop,
+    timeout: Option<Duration>,
+) -> IterateResult {
+    // pa_mainloop_prepare/poll/dispatch() return -2 after quit()
+    const QUIT: i32 = -2;
+
+    let timeout = timeout.map(|timeout| {
+        // The timeout is passed to the C API as a signed 32-bit integer
+        let micros = timeout.as_micros().min(i32::MAX as u128);
+        MicroSeconds(micros as u64)
+    });
+
+    let result = mainloop
+        .prepare(timeout)
+        .and_then(|_| mainloop.poll())
+        .and_then(|_| mainloop.dispatch());
+
+    match result {
+        Ok(count) => IterateResult::Success(count),
+        Err(e) if e.0 == QUIT => IterateResult::Quit(mainloop.get_retval()),
+        Err(e) => IterateResult::Err(e),
+    }
+}
+
+/// Play a test tone on the sink recognized as the config once the devices
+/// are known, without managing anything
+fn run_test_tone(
+    config_source: &ConfigSource,
+    server: Option<&str>,
+    config_name: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config_source.load()?;
+    let mut mainloop = Mainloop::new().ok_or("Failed to create mainloop")?;
+    // Declared after the mainloop to be dropped before it
+    let state = State::from_context(
+        new_context(&mainloop)?,
+        config,
+        EventFeed::default(),
+    );
+    state.borrow_mut().pause();
+    StateRunner::with(&state, |runner| runner.connect(server))?;
+
+    let deadline = Instant::now() + TEST_TIMEOUT;
+    let mut started = false;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("Timed out".into());
+        }
+        match iterate_with_timeout(&mut mainloop, Some(remaining)) {
+            IterateResult::Success(_) => {}
+            _ => return Err("Mainloop error".into()),
+        }
+        StateRunner::run_due_timers(&state);
+
+        if state.borrow().is_connection_failed() {
+            return Err("Failed to connect to PulseAudio server".into());
+        }
+        if !started && state.borrow().is_initial_sync_complete() {
+            StateRunner::with(&state, |runner| {
+                runner.start_test_tone(config_name)
+            })?;
+            started = true;
+        }
+        if let Some(result) = state.borrow().test_tone_result() {
+            return result.map_err(Into::into);
+        }
     }
 }
 
-fn load_config(
-    config_path: Option<PathBuf>,
-) -> Result<Config, Box<dyn std::error::Error>> {
-    if let Some(path) = config_path {
-        let content = std::fs::read_to_string(&path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
-        info!("Loaded config from: {}", path.display());
+/// Print the diagnostics, with what the server reports if it can be
+/// reached, or write them into a bundle together with everything else a
+/// bug report needs
+fn run_diag(
+    server: Option<&str>,
+    paths: &paths::Paths,
+    bundle: Option<(&Path, &ConfigSource)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut diagnostics =
+        diag::Diagnostics::detect(server, &paths.runtime_dir);
+    let devices = match observe_server(server) {
+        Ok((server, session_managers, devices)) => {
+            diagnostics.server = Some(server);
+            diagnostics.session_managers = Some(session_managers);
+            Some(devices)
+        }
+        Err(e) => {
+            diagnostics.server = Some(format!("unreachable ({e})"));
+            None
+        }
+    };
 
-        // Validate configuration
-        config.validate()?;
+    let Some((path, config_source)) = bundle else {
+        for line in diagnostics.lines() {
+            println!("{line}");
+        }
+        return Ok(());
+    };
+    let (config, config_issues) = match config_source.parse() {
+        Ok((config, report)) => (
+            serde_json::to_value(&config)?,
+            report
+                .issues
+                .into_iter()
+                .map(|issue| issue.message)
+                .collect(),
+        ),
+        Err(e) => (serde_json::Value::Null, vec![e.to_string()]),
+    };
+    let mut journal = journal::read(&journal::path(&paths.state_dir))?;
+    journal
+        .drain(..journal.len().saturating_sub(diag::BUNDLE_JOURNAL_ENTRIES));
+
+    let bundle = diag::Bundle {
+        diagnostics: diagnostics.lines(),
+        config,
+        config_issues,
+        devices,
+        journal,
+    };
+    std::fs::write(
+        path,
+        bundle.to_redacted_json(&diagnostics.sensitive_hosts())?,
+    )?;
+    println!("{}", Message::Wrote(path));
+    Ok(())
+}
+
+/// Connect without managing anything until the server, its session
+/// managers and the devices are known
+fn observe_server(
+    server: Option<&str>,
+) -> Result<
+    (String, Vec<String>, diag::DeviceSnapshots),
+    Box<dyn std::error::Error>,
+> {
+    let mut mainloop = Mainloop::new().ok_or("Failed to create mainloop")?;
+    // Declared after the mainloop to be dropped before it
+    let state = State::from_context(
+        new_context(&mainloop)?,
+        Config::default(),
+        EventFeed::default(),
+    );
+    state.borrow_mut().pause();
+    StateRunner::with(&state, |runner| runner.connect(server))?;
+
+    let deadline = Instant::now() + TEST_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("Timed out".into());
+        }
+        match iterate_with_timeout(&mut mainloop, Some(remaining)) {
+            IterateResult::Success(_) => {}
+            _ => return Err("Mainloop error".into()),
+        }
+        StateRunner::run_due_timers(&state);
+
+        if let Some(error) = state.borrow().connection_error() {
+            return Err(format!("{error}").into());
+        }
+        let state = state.borrow();
+        if let Some((server, session_managers)) = state.server_diagnostics()
+            && state.is_initial_sync_complete()
+        {
+            return Ok((server, session_managers, state.device_snapshots()));
+        }
+    }
+}
+
+/// Run the self-test against the server and print a line per step
+fn run_self_test(
+    server: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut mainloop = Mainloop::new().ok_or("Failed to create mainloop")?;
+    let context = new_context(&mainloop)?;
+    let checks = selftest::run(&mut mainloop, context, server)?;
+
+    for check in &checks {
+        match &check.result {
+            Ok(()) => println!("PASS {}", check.step),
+            Err(e) => println!("FAIL {}: {e}", check.step),
+        }
+    }
+    let passed = checks.iter().filter(|check| check.result.is_ok()).count();
+    println!(
+        "{}",
+        Message::StepsPassed {
+            passed,
+            total: checks.len()
+        }
+    );
+    if passed < checks.len() {
+        return Err(Message::SelfTestFailed.to_string().into());
+    }
+    Ok(())
+}
+
+/// Start the control inputs enabled in the configuration, returning whether
+/// any of them is running
+///
+/// Control inputs are only read at startup, so changing them requires a
+/// restart.
+fn start_control_inputs(
+    config: &Config,
+    sender: &Sender<ControlRequest>,
+    events: &EventFeed,
+) -> bool {
+    let mut active = false;
+
+    #[cfg(feature = "osc")]
+    if let Some(osc_config) = &config.osc {
+        active |= start_osc(osc_config, sender);
+    }
+
+    #[cfg(feature = "mqtt")]
+    if config.mqtt.is_some() {
+        active |= start_mqtt(config, sender, events);
+    }
+
+    #[cfg(feature = "logind")]
+    match logind::spawn(sender.clone()) {
+        Ok(()) => active = true,
+        Err(e) => warn!("Not watching logind for suspend and resume: {e}"),
+    }
+
+    if let Some(http_config) = &config.http {
+        match http::spawn(http_config, sender.clone(), events.clone()) {
+            Ok(()) => active = true,
+            Err(e) => {
+                error!("Failed to listen on {}: {e}", http_config.listen)
+            }
+        }
+    }
+
+    active
+}
+
+#[cfg(feature = "osc")]
+fn start_osc(
+    osc_config: &config::OscConfig,
+    sender: &Sender<ControlRequest>,
+) -> bool {
+    match osc::spawn(osc_config, sender.clone()) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to listen on {}: {e}", osc_config.listen);
+            false
+        }
+    }
+}
+
+#[cfg(feature = "mqtt")]
+fn start_mqtt(
+    config: &Config,
+    sender: &Sender<ControlRequest>,
+    events: &EventFeed,
+) -> bool {
+    match mqtt::spawn(config, sender.clone(), events) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to start MQTT integration: {e}");
+            false
+        }
+    }
+}
+
+struct ConfigSource {
+    path: Option<PathBuf>,
+    remote: Option<remote::RemoteConfig>,
+    strict_refs: bool,
+    trace_decisions: bool,
+    unmanaged: (bool, bool), // Sinks and sources turned off by flags
+}
+
+impl ConfigSource {
+    fn has_file(&self) -> bool {
+        self.path.is_some() || self.remote.is_some()
+    }
+
+    /// Parse and validate the configuration, leaving it to the caller what
+    /// to make of the report
+    fn parse(
+        &self,
+    ) -> Result<(Config, ValidationReport), Box<dyn std::error::Error>> {
+        let content = match (&self.path, &self.remote) {
+            (Some(path), _) => std::fs::read_to_string(path).map_err(|e| {
+                match flatpak::Sandbox::detect() {
+                    Some(sandbox) => {
+                        format!("{e}; {}", sandbox.config_access_hint(path))
+                    }
+                    None => e.to_string(),
+                }
+            })?,
+            (None, Some(remote)) => remote.fetch()?,
+            (None, None) => {
+                return Ok((Config::default(), ValidationReport::default()));
+            }
+        };
+        let mut config = Config::from_yaml(&content)?;
+        config.resolve_secrets()?;
+        config.strict |= self.strict_refs;
+        let (no_sinks, no_sources) = self.unmanaged;
+        if no_sinks {
+            config.manage_sinks = Some(false);
+        }
+        if no_sources {
+            config.manage_sources = Some(false);
+        }
+        config.drop_unmanaged_groups();
+        config.trace_decisions |= self.trace_decisions;
+
+        let mut report = config.validate();
+        let raw: serde_yaml::Value = serde_yaml::from_str(&content)?;
+        for key in Config::unknown_keys(&raw) {
+            report.warning(format!("Unknown key '{key}' is ignored"));
+        }
+        for (key, feature) in Config::disabled_keys(&raw) {
+            report.warning(format!(
+                "'{key}' is ignored, autopulsed is built without the \
+                 {feature} feature"
+            ));
+        }
+        Ok((config, report))
+    }
+
+    fn load(&self) -> Result<Config, Box<dyn std::error::Error>> {
+        let (config, report) = self.parse()?;
+        match (&self.path, &self.remote) {
+            (Some(path), _) => {
+                info!("Loaded config from: {}", path.display())
+            }
+            (None, Some(remote)) => {
+                info!("Loaded config from: {}", remote.url)
+            }
+            (None, None) => info!("Using default configuration"),
+        }
+        for group in config.unmanaged_groups() {
+            info!("Not managing {group}");
+        }
+
+        for issue in &report.issues {
+            match issue.severity {
+                Severity::Error => error!("{}", issue.message),
+                Severity::Warning => warn!("{}", issue.message),
+            }
+        }
+        if report.has_errors() {
+            return Err(format!(
+                "Configuration has {} error(s)",
+                report.errors().count()
+            )
+            .into());
+        }
 
         Ok(config)
-    } else {
-        info!("Using default configuration");
-        Ok(Config::default())
+    }
+}
+
+/// Print every finding of the configuration validation, failing if any of
+/// them is an error
+fn run_check(
+    config_source: &ConfigSource,
+    run_assertions: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (config, report) = config_source.parse()?;
+    print!("{report}");
+    println!(
+        "{}",
+        Message::Findings {
+            errors: report.errors().count(),
+            warnings: report.warnings().count()
+        }
+    );
+    if report.has_errors() {
+        return Err(Message::ConfigInvalid.to_string().into());
+    }
+
+    if run_assertions {
+        let outcomes = config.run_assertions();
+        for outcome in &outcomes {
+            match &outcome.result {
+                Ok(()) => println!("PASS {}", outcome.label),
+                Err(e) => println!("FAIL {}: {e}", outcome.label),
+            }
+        }
+        let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+        println!(
+            "{}",
+            Message::AssertionsPassed {
+                passed: outcomes.len() - failed,
+                total: outcomes.len()
+            }
+        );
+        if failed > 0 {
+            return Err(Message::AssertionsFailed.to_string().into());
+        }
+    }
+    Ok(())
+}
+
+fn run_undo(
+    config_source: &ConfigSource,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (config, _) = config_source.parse()?;
+    let Some(http_config) = &config.http else {
+        return Err("Undo reaches the daemon through the REST API, which \
+                    is not enabled (see the `http` option)"
+            .into());
+    };
+    println!("{}", http::post(http_config, "/undo")?);
+    Ok(())
+}
+
+/// Rewrite the configuration file in the current format version
+///
+/// The file is written from the parsed YAML, so comments are lost; the
+/// original is kept next to it with a `.bak` suffix.
+fn run_migrate_config(
+    config_source: &ConfigSource,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(path) = &config_source.path else {
+        return Err(
+            "migrate-config needs a local file given by --config".into()
+        );
+    };
+    let content = std::fs::read_to_string(path)?;
+    let mut raw: serde_yaml::Value = serde_yaml::from_str(&content)?;
+    let changes = config::migrate(&mut raw)
+        .map_err(|e| format!("Invalid config_version: {e}"))?;
+    if changes.is_empty() {
+        println!(
+            "{}",
+            Message::UpToDate {
+                path,
+                version: config::CURRENT_VERSION
+            }
+        );
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{change}");
+    }
+
+    let migrated = serde_yaml::to_string(&raw)?;
+    Config::from_yaml(&migrated)?;
+    if dry_run {
+        print!("\n{migrated}");
+        return Ok(());
+    }
+    let mut backup = path.clone().into_os_string();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    std::fs::copy(path, &backup)?;
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, migrated)?;
+    std::fs::rename(&temporary, path)?;
+    println!(
+        "{}",
+        Message::Migrated {
+            path,
+            backup: &backup
+        }
+    );
+    Ok(())
+}
+
+fn run_log(
+    paths: &paths::Paths,
+    args: LogArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let now = journal::unix_time(std::time::SystemTime::now());
+    let since = args
+        .since
+        .map(|age| journal::parse_age(&age))
+        .transpose()?
+        .map(|age| now.saturating_sub(age.as_secs()));
+    let filter = journal::Filter {
+        since,
+        action: args.action,
+        failed_only: args.failed,
+    };
+    for entry in journal::read(&journal::path(&paths.state_dir))? {
+        if filter.matches(&entry) {
+            println!("{entry}");
+        }
+    }
+    Ok(())
+}
+
+fn run_stats(
+    config_source: &ConfigSource,
+    paths: &paths::Paths,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (config, _) = config_source.parse()?;
+    let path = config
+        .statistics
+        .and_then(|statistics| statistics.file)
+        .unwrap_or_else(|| paths.state_dir.join(statistics::FILE_NAME));
+    let Some(statistics) = statistics::read(&path)? else {
+        return Err(format!(
+            "No statistics in {}; they are only kept with the `statistics` \
+             option",
+            path.display()
+        )
+        .into());
+    };
+    for line in statistics.lines() {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn run_bench(devices: usize, configs: usize) {
+    println!("{devices} devices, {configs} configs");
+    for measurement in state::bench::run(devices, configs) {
+        println!(
+            "{}: {} iterations in {:?} ({:?} each)",
+            measurement.name,
+            measurement.iterations,
+            measurement.elapsed,
+            measurement.per_iteration()
+        );
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    messages::init(args.lang);
 
-    env_logger::Builder::from_default_env()
-        .filter_level(if args.verbose {
+    logging::init(
+        args.log_format,
+        if args.verbose {
             log::LevelFilter::Debug
         } else {
             log::LevelFilter::Info
-        })
-        .init();
+        },
+    );
 
     info!(
         "Starting {} v{}",
@@ -244,9 +1259,107 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         env!("CARGO_PKG_VERSION")
     );
 
-    let config = load_config(args.config)?;
-    let mut app = App::new(config, args.server)?;
+    let paths = paths::Paths::resolve(args.runtime_dir);
+    debug!(
+        "Runtime files in {}, cached files in {}, state in {}",
+        paths.runtime_dir.display(),
+        paths.cache_dir.display(),
+        paths.state_dir.display()
+    );
+
+    let config_source = ConfigSource {
+        path: args.config,
+        remote: args.config_url.map(|url| {
+            remote::RemoteConfig::new(url, args.config_sha256, &paths)
+        }),
+        strict_refs: args.strict_refs,
+        trace_decisions: args.trace_decisions,
+        unmanaged: (args.no_manage_sinks, args.no_manage_sources),
+    };
+
+    if args.self_test {
+        return run_self_test(args.server.as_deref());
+    }
+    match args.command {
+        Some(CliCommand::Test { device }) => {
+            return run_test_tone(
+                &config_source,
+                args.server.as_deref(),
+                &device,
+            );
+        }
+        Some(CliCommand::Check { run_assertions }) => {
+            return run_check(&config_source, run_assertions);
+        }
+        Some(CliCommand::Undo) => return run_undo(&config_source),
+        Some(CliCommand::Diag { bundle }) => {
+            return run_diag(
+                args.server.as_deref(),
+                &paths,
+                bundle.as_deref().map(|path| (path, &config_source)),
+            );
+        }
+        Some(CliCommand::Log(args)) => return run_log(&paths, args),
+        Some(CliCommand::Stats) => return run_stats(&config_source, &paths),
+        Some(CliCommand::MigrateConfig { dry_run }) => {
+            return run_migrate_config(&config_source, dry_run);
+        }
+        Some(CliCommand::Gen {
+            target: GenTarget::ConfigSchema,
+        }) => {
+            let schema = Config::json_schema();
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
+        }
+        Some(CliCommand::Gen {
+            target: GenTarget::ExampleConfig { full },
+        }) => {
+            print!("{}", config::example_yaml(full));
+            return Ok(());
+        }
+        Some(CliCommand::Bench { devices, configs }) => {
+            run_bench(devices, configs);
+            return Ok(());
+        }
+        None => {}
+    }
+    let container = args.container.then(|| {
+        container::Container::new(args.server.as_deref(), &paths.runtime_dir)
+    });
+    if let Some(container) = &container {
+        container.wait_for_socket()?;
+    }
+    #[cfg(feature = "alsa")]
+    if container.is_none() && alsa::lacks_server(args.server.as_deref()) {
+        let config = config_source.load()?;
+        if let Some(fallback) = &config.alsa_fallback {
+            alsa::run(&config, fallback, args.server.as_deref())?;
+        }
+    }
+    let diagnostics =
+        diag::Diagnostics::detect(args.server.as_deref(), &paths.runtime_dir);
+    let mut app =
+        App::new(config_source, args.server, args.fail_on_unmatched)?;
+    app.container = container;
+    if args.observe {
+        info!("Observing only, nothing will be changed on the server");
+        app.state.borrow_mut().observe();
+    } else {
+        app.state.borrow_mut().restore_overrides();
+    }
+    app.diagnostics = Some(diagnostics);
+    match journal::Journal::open(&paths.state_dir) {
+        Ok(journal) => app.state.borrow_mut().set_journal(journal),
+        Err(e) => warn!(
+            "Not keeping a journal of actions in {}: {e}",
+            paths.state_dir.display()
+        ),
+    }
+    app.state.borrow_mut().enable_statistics(&paths.state_dir);
 
     app.run()?;
-    Ok(())
+    match app.failure {
+        Some(failure) => Err(failure.into()),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,192 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Catalog of the messages printed by the subcommands
+//!
+//! Only what is printed for people to read is translated. Log lines stay
+//! in English, so that tools and tests looking for them keep working, and
+//! so do the `PASS` and `FAIL` markers of step results.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Lang {
+    #[default]
+    En,
+    Ja,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+impl Lang {
+    /// The language of the locale, looked up in the variables in the order
+    /// the C library consults them
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(var)
+            .find(|locale| !locale.is_empty())
+            .map_or(Lang::En, |locale| Lang::of_locale(&locale))
+    }
+
+    /// The language of a locale name such as `ja_JP.UTF-8`
+    fn of_locale(locale: &str) -> Self {
+        let language = locale.split(['_', '.', '@']).next().unwrap_or("");
+        match language {
+            "ja" => Lang::Ja,
+            _ => Lang::En,
+        }
+    }
+}
+
+/// Set the language of the messages, detecting it from the locale unless
+/// one is given
+pub fn init(lang: Option<Lang>) {
+    let lang = lang
+        .unwrap_or_else(|| Lang::from_env(|name| std::env::var(name).ok()));
+    let _ = LANG.set(lang);
+}
+
+fn current() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+pub enum Message<'a> {
+    Findings { errors: usize, warnings: usize },
+    ConfigInvalid,
+    AssertionsPassed { passed: usize, total: usize },
+    AssertionsFailed,
+    StepsPassed { passed: usize, total: usize },
+    SelfTestFailed,
+    Wrote(&'a Path),
+    UpToDate { path: &'a Path, version: u64 },
+    Migrated { path: &'a Path, backup: &'a Path },
+}
+
+impl Message<'_> {
+    pub fn text(&self, lang: Lang) -> String {
+        match (self, lang) {
+            (Message::Findings { errors, warnings }, Lang::En) => {
+                format!("{errors} error(s), {warnings} warning(s)")
+            }
+            (Message::Findings { errors, warnings }, Lang::Ja) => {
+                format!("エラー {errors} 件、警告 {warnings} 件")
+            }
+            (Message::ConfigInvalid, Lang::En) => {
+                "Configuration is invalid".to_string()
+            }
+            (Message::ConfigInvalid, Lang::Ja) => "設定が不正です".to_string(),
+            (Message::AssertionsPassed { passed, total }, Lang::En) => {
+                format!("{passed} of {total} assertions passed")
+            }
+            (Message::AssertionsPassed { passed, total }, Lang::Ja) => {
+                format!("アサーション {total} 件中 {passed} 件が成功しました")
+            }
+            (Message::AssertionsFailed, Lang::En) => {
+                "Assertions failed".to_string()
+            }
+            (Message::AssertionsFailed, Lang::Ja) => {
+                "アサーションが失敗しました".to_string()
+            }
+            (Message::StepsPassed { passed, total }, Lang::En) => {
+                format!("{passed} of {total} steps passed")
+            }
+            (Message::StepsPassed { passed, total }, Lang::Ja) => {
+                format!("ステップ {total} 件中 {passed} 件が成功しました")
+            }
+            (Message::SelfTestFailed, Lang::En) => {
+                "Self-test failed".to_string()
+            }
+            (Message::SelfTestFailed, Lang::Ja) => {
+                "セルフテストが失敗しました".to_string()
+            }
+            (Message::Wrote(path), Lang::En) => {
+                format!("Wrote {}", path.display())
+            }
+            (Message::Wrote(path), Lang::Ja) => {
+                format!("{} を書き込みました", path.display())
+            }
+            (Message::UpToDate { path, version }, Lang::En) => {
+                format!("{} is up to date (version {version})", path.display())
+            }
+            (Message::UpToDate { path, version }, Lang::Ja) => {
+                format!("{} は最新です (バージョン {version})", path.display())
+            }
+            (Message::Migrated { path, backup }, Lang::En) => format!(
+                "Wrote {}, the previous version is kept as {}; comments \
+                 are not carried over",
+                path.display(),
+                backup.display()
+            ),
+            (Message::Migrated { path, backup }, Lang::Ja) => format!(
+                "{} を書き込みました。以前の版は {} に残してあります。\
+                 コメントは引き継がれません",
+                path.display(),
+                backup.display()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text(current()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lang_from_env() {
+        let env = |pairs: &'static [(&str, &str)]| {
+            Lang::from_env(|name| {
+                pairs
+                    .iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+        assert_eq!(env(&[]), Lang::En);
+        assert_eq!(env(&[("LANG", "ja_JP.UTF-8")]), Lang::Ja);
+        assert_eq!(env(&[("LANG", "ja")]), Lang::Ja);
+        // LC_ALL overrides, but not when it is set empty
+        assert_eq!(env(&[("LC_ALL", "C"), ("LANG", "ja_JP.UTF-8")]), Lang::En);
+        assert_eq!(
+            env(&[("LC_ALL", ""), ("LC_MESSAGES", "ja_JP.eucJP")]),
+            Lang::Ja
+        );
+        assert_eq!(env(&[("LANG", "jam_JM")]), Lang::En);
+    }
+
+    #[test]
+    fn test_message_text() {
+        let message = Message::AssertionsPassed {
+            passed: 2,
+            total: 3,
+        };
+        assert_eq!(message.text(Lang::En), "2 of 3 assertions passed");
+        assert_eq!(
+            message.text(Lang::Ja),
+            "アサーション 3 件中 2 件が成功しました"
+        );
+    }
+}
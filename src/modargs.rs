@@ -0,0 +1,198 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Module arguments built from the configuration, quoted so that no value
+//! can end its argument early and smuggle in others
+
+use std::collections::HashMap;
+
+use crate::config::RemapConfig;
+
+/// Property set on remap devices to the config they are loaded for, which
+/// identifies them where owner_module cannot
+pub const REMAP_TAG_PROPERTY: &str = "autopulsed.config";
+
+/// `key=value`, quoting the value unless it is a plain word
+pub fn arg(key: &str, value: &str) -> String {
+    if !value.is_empty() && !value.chars().any(needs_quoting) {
+        return format!("{key}={value}");
+    }
+    format!("{key}=\"{}\"", escape(value, '"'))
+}
+
+/// Whether PulseAudio accepts the key in a property list string, which has
+/// no way of quoting keys
+pub fn is_valid_property_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !needs_quoting(c) && c != '=')
+}
+
+fn needs_quoting(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '\\')
+}
+
+/// Backslash-escaped value for quoting, without NUL characters, which would
+/// cut the arguments short on their way to the server
+fn escape(value: &str, quote: char) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars().filter(|&c| c != '\0') {
+        if c == '\\' || c == quote {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Property list string, in the order of the keys
+fn proplist<'a>(
+    properties: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> String {
+    let mut properties: Vec<_> = properties.into_iter().collect();
+    properties.sort();
+    properties
+        .into_iter()
+        .map(|(key, value)| format!("{key}='{}'", escape(value, '\'')))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Names a remap module is loaded with
+pub struct RemapNames<'a> {
+    /// Device the remap device is put on top of
+    pub master_name: &'a str,
+    /// Config the remap device is tagged with
+    pub config_name: &'a str,
+}
+
+/// Arguments for module-remap-sink/source of the given kind, tagging the
+/// device with its config
+pub fn remap(
+    remap_config: &RemapConfig,
+    kind: &str,
+    names: RemapNames<'_>,
+) -> String {
+    let mut args = vec![arg("master", names.master_name)];
+
+    if let Some(device_name) = &remap_config.device_name {
+        args.push(arg(&format!("{kind}_name"), device_name));
+    }
+
+    // Keys PulseAudio would reject are reported by the validation
+    let tag = HashMap::from([(
+        REMAP_TAG_PROPERTY.to_string(),
+        names.config_name.to_string(),
+    )]);
+    let properties = remap_config
+        .device_properties
+        .iter()
+        .flatten()
+        .filter(|(key, _)| {
+            is_valid_property_key(key) && key.as_str() != REMAP_TAG_PROPERTY
+        })
+        .chain(&tag);
+    args.push(arg(&format!("{kind}_properties"), &proplist(properties)));
+
+    if let Some(format) = &remap_config.format {
+        args.push(arg("format", format));
+    }
+
+    if let Some(rate) = remap_config.rate {
+        args.push(format!("rate={rate}"));
+    }
+
+    if let Some(channels) = remap_config.channels {
+        args.push(format!("channels={channels}"));
+    }
+
+    if let Some(channel_map) = &remap_config.channel_map {
+        args.push(arg("channel_map", &channel_map.join(",")));
+    }
+
+    if let Some(master_channel_map) = &remap_config.master_channel_map {
+        args.push(arg("master_channel_map", &master_channel_map.join(",")));
+    }
+
+    if let Some(resample_method) = &remap_config.resample_method {
+        args.push(arg("resample_method", resample_method));
+    }
+
+    if let Some(remix) = remap_config.remix {
+        args.push(format!("remix={}", if remix { "yes" } else { "no" }));
+    }
+
+    args.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MasterRef;
+
+    fn remap_config(yaml: &str) -> RemapConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_arg_quoting() {
+        assert_eq!(arg("sink_name", "speakers"), "sink_name=speakers");
+        assert_eq!(arg("sink_name", ""), "sink_name=\"\"");
+        assert_eq!(arg("sink_name", "a b\" c=d"), "sink_name=\"a b\\\" c=d\"");
+        assert_eq!(arg("format", "s16le\\"), "format=\"s16le\\\\\"");
+        assert_eq!(arg("format", "s16\0le"), "format=\"s16le\"");
+    }
+
+    #[test]
+    fn test_remap_args() {
+        let config = remap_config(
+            r#"
+master: speakers
+device_name: remapped
+device_properties:
+  device.description: "Bob's Speakers"
+  "bad key": x
+channel_map: [front-left, front-right]
+remix: false
+"#,
+        );
+        assert!(matches!(config.master, MasterRef::Name(_)));
+        assert_eq!(
+            remap(
+                &config,
+                "sink",
+                RemapNames {
+                    master_name: "alsa_output.pci",
+                    config_name: "desk",
+                },
+            ),
+            "master=alsa_output.pci sink_name=remapped \
+             sink_properties=\"autopulsed.config='desk' \
+             device.description='Bob\\\\'s Speakers'\" \
+             channel_map=front-left,front-right remix=no"
+        );
+    }
+
+    #[test]
+    fn test_valid_property_keys() {
+        assert!(is_valid_property_key("device.description"));
+        assert!(!is_valid_property_key(""));
+        assert!(!is_valid_property_key("a b"));
+        assert!(!is_valid_property_key("a=b"));
+        assert!(!is_valid_property_key("a'b"));
+    }
+}
@@ -0,0 +1,717 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Minimal MQTT 3.1.1 client publishing the state with Home Assistant
+//! discovery, and accepting default selection from command topics
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::config::{Config, MqttConfig};
+use crate::control::{
+    self, Command, ControlRequest, DeviceRef, DeviceReport, Response,
+    StatusReport,
+};
+use crate::events::{Event, EventFeed};
+
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const PINGREQ: u8 = 12;
+
+const KINDS: [&str; 2] = ["sink", "source"];
+
+fn encode_length(buffer: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        buffer.push(byte);
+        if length == 0 {
+            return;
+        }
+    }
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    encode_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Topics and identifiers derived from the configuration
+struct Topics {
+    prefix: String,
+    discovery_prefix: String,
+    node_id: String,
+}
+
+impl Topics {
+    fn new(config: &MqttConfig) -> Self {
+        let client_id = config.client_id.as_deref().unwrap_or("autopulsed");
+        Topics {
+            prefix: config
+                .topic_prefix
+                .clone()
+                .unwrap_or_else(|| "autopulsed".to_string()),
+            discovery_prefix: config
+                .discovery_prefix
+                .clone()
+                .unwrap_or_else(|| "homeassistant".to_string()),
+            node_id: object_id(client_id),
+        }
+    }
+
+    fn availability(&self) -> String {
+        format!("{}/status", self.prefix)
+    }
+
+    fn default_state(&self, kind: &str) -> String {
+        format!("{}/{kind}/default", self.prefix)
+    }
+
+    fn default_command(&self, kind: &str) -> String {
+        format!("{}/{kind}/default/set", self.prefix)
+    }
+
+    fn presence(&self, kind: &str, config_name: &str) -> String {
+        format!("{}/{kind}/{}/present", self.prefix, object_id(config_name))
+    }
+
+    fn discovery(&self, component: &str, object: &str) -> String {
+        format!(
+            "{}/{component}/{}/{object}/config",
+            self.discovery_prefix, self.node_id
+        )
+    }
+}
+
+/// Config names may contain characters with special meanings in topics
+fn object_id(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn connect_packet(config: &MqttConfig, topics: &Topics) -> Vec<u8> {
+    // Clean session, with a retained will marking the daemon offline
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if config.username.is_some() {
+        flags |= 0x80;
+    }
+    if config.password.is_some() {
+        flags |= 0x40;
+    }
+
+    let mut body = Vec::new();
+    put_bytes(&mut body, b"MQTT");
+    body.push(4); // Protocol level of 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    put_bytes(
+        &mut body,
+        config
+            .client_id
+            .as_deref()
+            .unwrap_or("autopulsed")
+            .as_bytes(),
+    );
+    put_bytes(&mut body, topics.availability().as_bytes());
+    put_bytes(&mut body, b"offline");
+    if let Some(username) = &config.username {
+        put_bytes(&mut body, username.as_bytes());
+    }
+    if let Some(password) = &config.password {
+        put_bytes(&mut body, password.as_bytes());
+    }
+    packet(CONNECT << 4, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8]) -> Vec<u8> {
+    // Everything is published retained with QoS 0
+    let mut body = Vec::new();
+    put_bytes(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH << 4 | 0x01, &body)
+}
+
+fn subscribe_packet(packet_id: u16, filter: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    put_bytes(&mut body, filter.as_bytes());
+    body.push(0); // QoS 0
+    packet(SUBSCRIBE << 4 | 0x02, &body)
+}
+
+fn read_packet(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    let header = byte[0];
+
+    let mut length = 0usize;
+    for shift in (0..28).step_by(7) {
+        reader.read_exact(&mut byte)?;
+        length |= ((byte[0] & 0x7f) as usize) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+    if length > MAX_PACKET_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "MQTT packet is too large",
+        ));
+    }
+
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok((header, body))
+}
+
+/// Topic and payload of a PUBLISH packet
+fn parse_publish(header: u8, body: &[u8]) -> Option<(String, Vec<u8>)> {
+    let length = u16::from_be_bytes(body.get(..2)?.try_into().ok()?) as usize;
+    let topic = std::str::from_utf8(body.get(2..2 + length)?).ok()?;
+    let mut offset = 2 + length;
+    if (header >> 1) & 0x03 > 0 {
+        offset += 2; // Packet identifier
+    }
+    Some((topic.to_string(), body.get(offset..)?.to_vec()))
+}
+
+#[derive(Serialize)]
+struct DeviceDiscovery<'a> {
+    identifiers: [&'a str; 1],
+    name: &'a str,
+    sw_version: &'a str,
+}
+
+#[derive(Serialize)]
+struct SelectDiscovery<'a> {
+    name: String,
+    unique_id: String,
+    state_topic: String,
+    command_topic: String,
+    options: &'a [String],
+    availability_topic: String,
+    device: DeviceDiscovery<'a>,
+}
+
+#[derive(Serialize)]
+struct BinarySensorDiscovery<'a> {
+    name: String,
+    unique_id: String,
+    state_topic: String,
+    device_class: &'a str,
+    availability_topic: String,
+    device: DeviceDiscovery<'a>,
+}
+
+/// Config names by kind, announced to Home Assistant
+struct Entities {
+    topics: Arc<Topics>,
+    names: [Vec<String>; 2],
+}
+
+impl Entities {
+    fn new(config: &Config, topics: Topics) -> Self {
+        let names = [&config.sinks, &config.sources].map(|configs| {
+            let mut names: Vec<_> = configs.keys().cloned().collect();
+            names.sort();
+            names
+        });
+        Entities {
+            topics: Arc::new(topics),
+            names,
+        }
+    }
+
+    fn discovery_messages(&self) -> serde_json::Result<Vec<(String, String)>> {
+        let topics = &self.topics;
+        let device = || DeviceDiscovery {
+            identifiers: [&topics.node_id],
+            name: env!("CARGO_PKG_NAME"),
+            sw_version: env!("CARGO_PKG_VERSION"),
+        };
+        let mut messages = Vec::new();
+
+        for (kind, names) in KINDS.iter().zip(&self.names) {
+            let object = format!("default_{kind}");
+            let select = SelectDiscovery {
+                name: format!("Default {kind}"),
+                unique_id: format!("{}_{object}", topics.node_id),
+                state_topic: topics.default_state(kind),
+                command_topic: topics.default_command(kind),
+                options: names,
+                availability_topic: topics.availability(),
+                device: device(),
+            };
+            messages.push((
+                topics.discovery("select", &object),
+                serde_json::to_string(&select)?,
+            ));
+
+            for name in names {
+                let object = format!("{kind}_{}", object_id(name));
+                let sensor = BinarySensorDiscovery {
+                    name: format!("{name} {kind}"),
+                    unique_id: format!("{}_{object}", topics.node_id),
+                    state_topic: topics.presence(kind, name),
+                    device_class: "plug",
+                    availability_topic: topics.availability(),
+                    device: device(),
+                };
+                messages.push((
+                    topics.discovery("binary_sensor", &object),
+                    serde_json::to_string(&sensor)?,
+                ));
+            }
+        }
+
+        Ok(messages)
+    }
+
+    fn state_messages(
+        &self,
+        status: &StatusReport,
+        devices: &DeviceReport,
+    ) -> Vec<(String, String)> {
+        let topics = &self.topics;
+        let defaults = [&status.default_sink, &status.default_source];
+        let found = [&devices.sinks, &devices.sources];
+        let mut messages = Vec::new();
+
+        for (i, kind) in KINDS.iter().enumerate() {
+            // Home Assistant takes "None" as no option being selected
+            let default = defaults[i].as_deref().unwrap_or("None");
+            messages.push((topics.default_state(kind), default.to_string()));
+
+            for name in &self.names[i] {
+                let present = found[i]
+                    .iter()
+                    .any(|device| device.recognized_as.contains(name));
+                messages.push((
+                    topics.presence(kind, name),
+                    if present { "ON" } else { "OFF" }.to_string(),
+                ));
+            }
+        }
+
+        messages
+    }
+}
+
+/// Turn a message on a command topic into a command
+fn parse_command(
+    topics: &Topics,
+    topic: &str,
+    payload: &[u8],
+) -> Option<Command> {
+    let config_name = std::str::from_utf8(payload).ok()?.trim().to_string();
+    let device = if topic == topics.default_command("sink") {
+        DeviceRef::Sink { sink: config_name }
+    } else if topic == topics.default_command("source") {
+        DeviceRef::Source {
+            source: config_name,
+        }
+    } else {
+        return None;
+    };
+    Some(Command::SetDefault { device })
+}
+
+struct Bridge {
+    config: MqttConfig,
+    entities: Entities,
+    sender: Sender<ControlRequest>,
+    events: Receiver<Arc<Event>>,
+}
+
+struct Session {
+    stream: TcpStream,
+    published: HashMap<String, String>,
+}
+
+impl Session {
+    fn publish(&mut self, topic: &str, payload: &str) -> io::Result<()> {
+        if self
+            .published
+            .get(topic)
+            .is_some_and(|last| last == payload)
+        {
+            return Ok(());
+        }
+        self.stream
+            .write_all(&publish_packet(topic, payload.as_bytes()))?;
+        self.published
+            .insert(topic.to_string(), payload.to_string());
+        Ok(())
+    }
+}
+
+impl Bridge {
+    fn run(&self) {
+        loop {
+            if let Err(e) = self.connect_and_serve() {
+                warn!("MQTT connection to {} failed: {e}", self.config.broker);
+            }
+            std::thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    fn connect_and_serve(&self) -> io::Result<()> {
+        let topics = &self.entities.topics;
+        let mut stream = TcpStream::connect(&self.config.broker)?;
+        stream.write_all(&connect_packet(&self.config, topics))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (header, body) = read_packet(&mut reader)?;
+        if header >> 4 != CONNACK || body.get(1) != Some(&0) {
+            return Err(io::Error::other(format!(
+                "Connection refused with code {}",
+                body.get(1).copied().unwrap_or_default()
+            )));
+        }
+        info!("Connected to MQTT broker {}", self.config.broker);
+
+        let filter = format!("{}/+/default/set", topics.prefix);
+        stream.write_all(&subscribe_packet(1, &filter))?;
+        self.spawn_reader(reader, stream.try_clone()?)?;
+
+        let mut session = Session {
+            stream,
+            published: HashMap::new(),
+        };
+        let discovery = self
+            .entities
+            .discovery_messages()
+            .map_err(io::Error::other)?;
+        for (topic, payload) in discovery {
+            session.publish(&topic, &payload)?;
+        }
+        session.publish(&topics.availability(), "online")?;
+        self.publish_state(&mut session)?;
+
+        loop {
+            match self.events.recv_timeout(PING_INTERVAL) {
+                Ok(event) => {
+                    if matches!(
+                        *event,
+                        Event::Recognized { .. }
+                            | Event::Unrecognized { .. }
+                            | Event::DefaultChanged { .. }
+                    ) {
+                        // Events tend to come in bursts
+                        while self.events.try_recv().is_ok() {}
+                        self.publish_state(&mut session)?;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    session.stream.write_all(&packet(PINGREQ << 4, &[]))?;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    /// Pass commands arriving from the broker to the main loop. The
+    /// connection is shut down when reading fails so that the next write
+    /// of the publishing side fails and reconnects.
+    fn spawn_reader(
+        &self,
+        mut reader: BufReader<TcpStream>,
+        stream: TcpStream,
+    ) -> io::Result<()> {
+        let topics = Arc::clone(&self.entities.topics);
+        let sender = self.sender.clone();
+
+        std::thread::Builder::new()
+            .name("mqtt-reader".to_string())
+            .spawn(move || {
+                while let Ok((header, body)) = read_packet(&mut reader) {
+                    if header >> 4 != PUBLISH {
+                        continue;
+                    }
+                    let Some((topic, payload)) = parse_publish(header, &body)
+                    else {
+                        continue;
+                    };
+                    if let Some(command) =
+                        parse_command(&topics, &topic, &payload)
+                    {
+                        debug!("MQTT command on {topic}: {command:?}");
+                        let request = ControlRequest {
+                            command,
+                            reply: None,
+                        };
+                        if sender.send(request).is_err() {
+                            break;
+                        }
+                    }
+                }
+                let _ = stream.shutdown(Shutdown::Both);
+            })?;
+        Ok(())
+    }
+
+    fn publish_state(&self, session: &mut Session) -> io::Result<()> {
+        let request = |command| match control::request(
+            &self.sender,
+            command,
+            REPLY_TIMEOUT,
+        ) {
+            Some(Ok(response)) => Ok(response),
+            _ => Err(io::Error::other("Daemon is not responding")),
+        };
+        let (Response::Status(status), Response::Devices(devices)) =
+            (request(Command::Status)?, request(Command::Devices)?)
+        else {
+            return Err(io::Error::other("Unexpected response"));
+        };
+
+        let messages = self.entities.state_messages(&status, &devices);
+        for (topic, payload) in messages {
+            session.publish(&topic, &payload)?;
+        }
+        Ok(())
+    }
+}
+
+/// Connect to the broker on a thread of its own, reconnecting whenever the
+/// connection is lost
+pub fn spawn(
+    config: &Config,
+    sender: Sender<ControlRequest>,
+    events: &EventFeed,
+) -> io::Result<()> {
+    let Some(mqtt_config) = &config.mqtt else {
+        return Ok(());
+    };
+    let bridge = Bridge {
+        config: mqtt_config.clone(),
+        entities: Entities::new(config, Topics::new(mqtt_config)),
+        sender,
+        events: events.subscribe(),
+    };
+
+    std::thread::Builder::new()
+        .name("mqtt".to_string())
+        .spawn(move || bridge.run())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::DeviceEntry;
+    use std::collections::BTreeMap;
+
+    fn mqtt_config() -> MqttConfig {
+        MqttConfig {
+            broker: "localhost:1883".to_string(),
+            username: None,
+            password: None,
+            password_file: None,
+            client_id: None,
+            discovery_prefix: None,
+            topic_prefix: None,
+        }
+    }
+
+    #[test]
+    fn test_encode_length() {
+        let encode = |length| {
+            let mut buffer = Vec::new();
+            encode_length(&mut buffer, length);
+            buffer
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(127), [0x7f]);
+        assert_eq!(encode(128), [0x80, 0x01]);
+        assert_eq!(encode(16_383), [0xff, 0x7f]);
+        assert_eq!(encode(16_384), [0x80, 0x80, 0x01]);
+    }
+
+    #[test]
+    fn test_packets_round_trip() {
+        let packet = publish_packet("a/b", b"ON");
+        assert_eq!(packet, [0x31, 7, 0, 3, b'a', b'/', b'b', b'O', b'N']);
+
+        let (header, body) = read_packet(&mut packet.as_slice()).unwrap();
+        assert_eq!(
+            parse_publish(header, &body),
+            Some(("a/b".to_string(), b"ON".to_vec()))
+        );
+
+        // QoS 1 messages carry a packet identifier after the topic
+        let body = [0, 1, b't', 0, 42, b'x'];
+        assert_eq!(
+            parse_publish(PUBLISH << 4 | 0x02, &body),
+            Some(("t".to_string(), b"x".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_read_packet_lengths() {
+        for length in [0, 127, 128, 16_383, 16_384] {
+            let body = vec![0x5a; length];
+            let packet = packet(PINGREQ << 4, &body);
+            let mut reader = packet.as_slice();
+            assert_eq!(read_packet(&mut reader).unwrap(), (0xc0, body));
+            assert!(reader.is_empty(), "{length} bytes");
+        }
+
+        // The length is cut off after its continuation bit
+        assert!(read_packet(&mut [0x30, 0x80].as_slice()).is_err());
+        let mut oversized = vec![0x30];
+        encode_length(&mut oversized, MAX_PACKET_SIZE + 1);
+        assert!(read_packet(&mut oversized.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_parse_publish_with_qos() {
+        let body = [0, 1, b't', 0, 7, b'O', b'N'];
+        for qos in [1, 2] {
+            assert_eq!(
+                parse_publish(PUBLISH << 4 | qos << 1, &body),
+                Some(("t".to_string(), b"ON".to_vec()))
+            );
+        }
+        // Retained and duplicate flags do not shift the payload
+        assert_eq!(
+            parse_publish(PUBLISH << 4 | 0x08 | 0x02 | 0x01, &body),
+            Some(("t".to_string(), b"ON".to_vec()))
+        );
+        // Too short for the packet identifier
+        assert_eq!(parse_publish(PUBLISH << 4 | 0x02, &[0, 1, b't', 0]), None);
+    }
+
+    #[test]
+    fn test_connect_packet() {
+        let mut config = mqtt_config();
+        config.username = Some("user".to_string());
+        let topics = Topics::new(&config);
+        let packet = connect_packet(&config, &topics);
+
+        assert_eq!(packet[0], 0x10);
+        // Protocol name, level, flags and keep alive
+        assert_eq!(&packet[2..12], b"\0\x04MQTT\x04\xa6\0\x3c");
+        assert!(packet.ends_with(b"\0\x04user"));
+    }
+
+    #[test]
+    fn test_topics_and_commands() {
+        let topics = Topics::new(&mqtt_config());
+
+        assert_eq!(
+            topics.presence("sink", "a/b+c"),
+            "autopulsed/sink/a_b_c/present"
+        );
+        assert_eq!(
+            topics.discovery("select", "default_sink"),
+            "homeassistant/select/autopulsed/default_sink/config"
+        );
+        assert_eq!(
+            parse_command(&topics, "autopulsed/source/default/set", b"mic\n"),
+            Some(Command::SetDefault {
+                device: DeviceRef::Source {
+                    source: "mic".to_string()
+                }
+            })
+        );
+        assert_eq!(
+            parse_command(&topics, "autopulsed/sink/default", b"hdmi"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_state_messages() {
+        let config: Config = serde_yaml::from_str(
+            "sinks: {hdmi: {detect: {}}, usb: {detect: {}}}",
+        )
+        .unwrap();
+        let entities = Entities::new(&config, Topics::new(&mqtt_config()));
+        let status = StatusReport {
+            status: String::new(),
+            paused: false,
+            privacy: false,
+            server: None,
+            default_sink: Some("usb".to_string()),
+            default_source: None,
+            sink_level: None,
+            source_level: None,
+            sink_groups: BTreeMap::new(),
+            source_groups: BTreeMap::new(),
+            unmanaged_sinks: Vec::new(),
+            unmanaged_sources: Vec::new(),
+            events_per_second: 0,
+            drift_repairs: 0,
+            memory: control::MemoryReport::default(),
+            skipped_entries: Vec::new(),
+        };
+        let devices = DeviceReport {
+            sinks: vec![DeviceEntry {
+                index: 1,
+                name: "alsa_output.usb".to_string(),
+                recognized_as: vec!["usb".to_string()],
+                muted: false,
+            }],
+            sources: Vec::new(),
+        };
+
+        let messages = entities.state_messages(&status, &devices);
+        let expected = [
+            ("autopulsed/sink/default", "usb"),
+            ("autopulsed/sink/hdmi/present", "OFF"),
+            ("autopulsed/sink/usb/present", "ON"),
+            ("autopulsed/source/default", "None"),
+        ];
+        assert_eq!(
+            messages,
+            expected.map(|(topic, payload)| (
+                topic.to_string(),
+                payload.to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,222 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::net::UdpSocket;
+use std::sync::mpsc::Sender;
+
+use crate::config::OscConfig;
+use crate::control::{Command, ControlRequest};
+
+#[derive(Debug, Clone, PartialEq)]
+enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct OscMessage {
+    address: String,
+    args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    /// Control surfaces send a non-zero value on press and zero on
+    /// release; messages without arguments are triggers as well
+    fn is_trigger(&self) -> bool {
+        match self.args.first() {
+            Some(OscArg::Int(value)) => *value != 0,
+            Some(OscArg::Float(value)) => *value != 0.0,
+            Some(OscArg::Bool(value)) => *value,
+            _ => true,
+        }
+    }
+}
+
+/// Reads a null-terminated string padded to a multiple of four bytes
+fn read_string(data: &mut &[u8]) -> Option<String> {
+    let end = data.iter().position(|&b| b == 0)?;
+    let string = std::str::from_utf8(&data[..end]).ok()?.to_string();
+    let padded = (end + 4) & !3;
+    *data = data.get(padded..)?;
+    Some(string)
+}
+
+fn read_u32(data: &mut &[u8]) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(..4)?.try_into().ok()?;
+    *data = &data[4..];
+    Some(u32::from_be_bytes(bytes))
+}
+
+fn parse_message(mut data: &[u8]) -> Option<OscMessage> {
+    let address = read_string(&mut data)?;
+    if data.is_empty() {
+        // Type tags are optional in old implementations
+        return Some(OscMessage {
+            address,
+            args: Vec::new(),
+        });
+    }
+
+    let tags = read_string(&mut data)?;
+    let mut args = Vec::new();
+    for tag in tags.strip_prefix(',')?.chars() {
+        let arg = match tag {
+            'i' => OscArg::Int(read_u32(&mut data)? as i32),
+            'f' => OscArg::Float(f32::from_bits(read_u32(&mut data)?)),
+            's' => OscArg::String(read_string(&mut data)?),
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            // Arguments after unsupported types cannot be located
+            _ => {
+                args.push(OscArg::Other);
+                break;
+            }
+        };
+        args.push(arg);
+    }
+
+    Some(OscMessage { address, args })
+}
+
+/// Messages of a packet, which is either a message or a bundle
+fn parse_packet(data: &[u8]) -> Vec<OscMessage> {
+    let Some(mut rest) = data.strip_prefix(b"#bundle\0") else {
+        return parse_message(data).into_iter().collect();
+    };
+
+    let mut messages = Vec::new();
+    // Skip the time tag, since everything is executed immediately
+    rest = rest.get(8..).unwrap_or_default();
+    while let Some(size) = read_u32(&mut rest) {
+        let Some(element) = rest.get(..size as usize) else {
+            break;
+        };
+        messages.extend(parse_packet(element));
+        rest = &rest[size as usize..];
+    }
+    messages
+}
+
+/// Starts listening for OSC messages in a background thread
+pub fn spawn(
+    config: &OscConfig,
+    sender: Sender<ControlRequest>,
+) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(&config.listen)?;
+    let mappings = config.mappings.clone();
+    info!("Listening for OSC messages on {}", config.listen);
+
+    std::thread::Builder::new()
+        .name("osc".to_string())
+        .spawn(move || listen(&socket, &mappings, &sender))?;
+    Ok(())
+}
+
+fn listen(
+    socket: &UdpSocket,
+    mappings: &HashMap<String, Command>,
+    sender: &Sender<ControlRequest>,
+) {
+    let mut buffer = [0u8; 4096];
+    loop {
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(e) => {
+                error!("Failed to receive OSC message: {e}");
+                return;
+            }
+        };
+
+        for message in parse_packet(&buffer[..size]) {
+            let Some(command) = mappings.get(&message.address) else {
+                debug!("Ignoring unmapped OSC address {}", message.address);
+                continue;
+            };
+            if !message.is_trigger() {
+                continue;
+            }
+
+            let request = ControlRequest {
+                command: command.clone(),
+                reply: None,
+            };
+            if sender.send(request).is_err() {
+                warn!("Main loop has gone, stopping OSC listener");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad(bytes: &[u8]) -> Vec<u8> {
+        let mut padded = bytes.to_vec();
+        padded.resize((bytes.len() + 4) & !3, 0);
+        padded
+    }
+
+    #[test]
+    fn test_parse_message_with_arguments() {
+        let mut packet = pad(b"/autopulsed/speakers");
+        packet.extend(pad(b",fi"));
+        packet.extend(1.0f32.to_bits().to_be_bytes());
+        packet.extend(0i32.to_be_bytes());
+
+        let messages = parse_packet(&packet);
+        assert_eq!(
+            messages,
+            vec![OscMessage {
+                address: "/autopulsed/speakers".to_string(),
+                args: vec![OscArg::Float(1.0), OscArg::Int(0)],
+            }]
+        );
+        assert!(messages[0].is_trigger());
+    }
+
+    #[test]
+    fn test_parse_bundle() {
+        let mut message = pad(b"/mute");
+        message.extend(pad(b",i"));
+        message.extend(0i32.to_be_bytes());
+
+        let mut packet = b"#bundle\0".to_vec();
+        packet.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend((message.len() as u32).to_be_bytes());
+        packet.extend(&message);
+
+        let messages = parse_packet(&packet);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].address, "/mute");
+        assert!(!messages[0].is_trigger());
+    }
+
+    #[test]
+    fn test_parse_truncated_packet() {
+        let mut packet = pad(b"/speakers");
+        packet.extend(pad(b",i"));
+        packet.extend([0, 0]);
+
+        assert!(parse_packet(&packet).is_empty());
+    }
+}
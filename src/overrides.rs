@@ -0,0 +1,92 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Adjustments made by commands, kept apart from the configuration
+//!
+//! The configuration may be managed for a whole fleet and read-only, while
+//! operators still choose defaults or pause the daemon on one machine.
+//! Those choices are written to the overrides file, so that they survive
+//! restarts without touching the configuration.
+
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use autopulsed::control::PrivacySettings;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Overrides {
+    /// Config chosen as the default sink by command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_sink: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_source: Option<String>,
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<PrivacySettings>,
+}
+
+impl Overrides {
+    /// The overrides in the file, or none if there is no file yet
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace the file, so that a crash leaves either version
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.to_path_buf().into_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_and_save() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let path = directory.path().join("overrides.json");
+        assert_eq!(Overrides::load(&path).unwrap(), Overrides::default());
+
+        let overrides = Overrides {
+            default_sink: Some("headphones".to_string()),
+            default_source: None,
+            paused: true,
+            privacy: Some(PrivacySettings {
+                enabled: true,
+                suspend: false,
+            }),
+        };
+        overrides.save(&path).unwrap();
+        assert_eq!(Overrides::load(&path).unwrap(), overrides);
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("source"));
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(Overrides::load(&path).is_err());
+    }
+}
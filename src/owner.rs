@@ -0,0 +1,86 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Users owning the clients of a server, for rules on streams of multi-user
+//! system servers
+
+/// Set by pipewire-pulse from the credentials of the client connection
+const SECURE_UID_PROPERTY: &str = "pipewire.sec.uid";
+
+/// Set by the clients themselves
+const PROCESS_USER_PROPERTY: &str = "application.process.user";
+
+const PASSWD_PATH: &str = "/etc/passwd";
+
+/// Uid of the user running a client, given its properties: the one the
+/// server took from the connection where it reports it, or that of the
+/// user name the client claims otherwise
+pub fn client_uid(property: impl Fn(&str) -> Option<String>) -> Option<u32> {
+    if let Some(uid) = property(SECURE_UID_PROPERTY) {
+        return uid.parse().ok();
+    }
+    let user = property(PROCESS_USER_PROPERTY)?;
+    let passwd = std::fs::read_to_string(PASSWD_PATH).ok()?;
+    uid_in_passwd(&passwd, &user)
+}
+
+/// Uid of the named user in the contents of a passwd file
+fn uid_in_passwd(passwd: &str, user: &str) -> Option<u32> {
+    passwd.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != user {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uid_in_passwd() {
+        let passwd = "root:x:0:0:root:/root:/bin/bash\n\
+                      kiosk:x:1001:1001::/home/kiosk:/bin/sh\n\
+                      broken:x\n";
+        assert_eq!(uid_in_passwd(passwd, "root"), Some(0));
+        assert_eq!(uid_in_passwd(passwd, "kiosk"), Some(1001));
+        assert_eq!(uid_in_passwd(passwd, "kio"), None);
+        assert_eq!(uid_in_passwd(passwd, "broken"), None);
+    }
+
+    #[test]
+    fn test_client_uid() {
+        let properties = |pairs: &'static [(&str, &str)]| {
+            move |key: &str| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        // The credentials of the connection win over the claimed user
+        assert_eq!(
+            client_uid(properties(&[
+                ("pipewire.sec.uid", "1001"),
+                ("application.process.user", "root"),
+            ])),
+            Some(1001)
+        );
+        assert_eq!(client_uid(properties(&[])), None);
+    }
+}
@@ -0,0 +1,145 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "autopulsed";
+
+/// Where files produced by the daemon go, resolved once at startup so that
+/// every subsystem agrees on them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    /// Files that only make sense while the daemon runs
+    pub runtime_dir: PathBuf,
+    /// Files that may be lost, such as downloaded copies
+    pub cache_dir: PathBuf,
+    /// Files kept across restarts, such as the journal of actions
+    pub state_dir: PathBuf,
+}
+
+impl Paths {
+    pub fn resolve(runtime_dir: Option<PathBuf>) -> Self {
+        Self::from_env(runtime_dir, |name| std::env::var_os(name))
+    }
+
+    fn from_env(
+        runtime_dir: Option<PathBuf>,
+        var: impl Fn(&str) -> Option<OsString>,
+    ) -> Self {
+        let dir_var = |name| var(name).filter(|value| !value.is_empty());
+        let home = dir_var("HOME").map(PathBuf::from);
+        let fallback = || {
+            let user = var("USER").unwrap_or_else(|| "user".into());
+            let mut name = OsString::from(format!("{APP_DIR}-"));
+            name.push(user);
+            std::env::temp_dir().join(name)
+        };
+
+        let runtime_dir = runtime_dir.unwrap_or_else(|| {
+            match (dir_var("XDG_RUNTIME_DIR"), dir_var("FLATPAK_ID")) {
+                // Only the directory of the app is shared with the host
+                (Some(base), Some(app)) => {
+                    PathBuf::from(base).join("app").join(app)
+                }
+                (Some(base), None) => PathBuf::from(base).join(APP_DIR),
+                (None, _) => fallback(),
+            }
+        });
+        // Flatpak points XDG_CACHE_HOME into the data of the app
+        let cache_dir = dir_var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".cache")))
+            .map_or_else(fallback, |base| base.join(APP_DIR));
+        let state_dir = dir_var("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".local/state")))
+            .map_or_else(fallback, |base| base.join(APP_DIR));
+
+        Self {
+            runtime_dir,
+            cache_dir,
+            state_dir,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve_with(
+        runtime_dir: Option<&str>,
+        vars: &[(&str, &str)],
+    ) -> Paths {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Paths::from_env(runtime_dir.map(PathBuf::from), |name| {
+            vars.get(name).map(OsString::from)
+        })
+    }
+
+    #[test]
+    fn test_resolve_paths() {
+        let paths = resolve_with(
+            None,
+            &[("XDG_RUNTIME_DIR", "/run/user/1000"), ("HOME", "/home/a")],
+        );
+        assert_eq!(
+            paths.runtime_dir,
+            PathBuf::from("/run/user/1000/autopulsed")
+        );
+        assert_eq!(
+            paths.cache_dir,
+            PathBuf::from("/home/a/.cache/autopulsed")
+        );
+        assert_eq!(
+            paths.state_dir,
+            PathBuf::from("/home/a/.local/state/autopulsed")
+        );
+
+        let paths = resolve_with(
+            Some("/srv/autopulsed"),
+            &[("XDG_RUNTIME_DIR", "/run/user/1000")],
+        );
+        assert_eq!(paths.runtime_dir, PathBuf::from("/srv/autopulsed"));
+    }
+
+    #[test]
+    fn test_resolve_paths_in_flatpak() {
+        let paths = resolve_with(
+            None,
+            &[
+                ("XDG_RUNTIME_DIR", "/run/user/1000"),
+                ("FLATPAK_ID", "world.flokart.Autopulsed"),
+                (
+                    "XDG_CACHE_HOME",
+                    "/home/a/.var/app/world.flokart.Autopulsed/cache",
+                ),
+            ],
+        );
+        assert_eq!(
+            paths.runtime_dir,
+            PathBuf::from("/run/user/1000/app/world.flokart.Autopulsed")
+        );
+        assert_eq!(
+            paths.cache_dir,
+            PathBuf::from(
+                "/home/a/.var/app/world.flokart.Autopulsed/cache/autopulsed"
+            )
+        );
+    }
+}
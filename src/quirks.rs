@@ -0,0 +1,94 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::config::ServerQuirksConfig;
+use crate::server::ServerFlavor;
+
+/// Behavior adjustments for differences between server implementations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// Recognize remap devices by their configured `device_name` when the
+    /// server does not report them as owned by the remap module
+    pub match_remaps_by_name: bool,
+    /// Check some time after setting a default that the server did not
+    /// revert it, and set it again if it did
+    pub verify_default: bool,
+}
+
+impl Quirks {
+    const NONE: Quirks = Quirks {
+        match_remaps_by_name: false,
+        verify_default: false,
+    };
+
+    const PIPEWIRE: Quirks = Quirks {
+        match_remaps_by_name: true,
+        verify_default: true,
+    };
+
+    /// Quirks for the detected server, or for the preset chosen in the
+    /// config, with individual settings overridden by the config
+    pub fn resolve(
+        detected: Option<ServerFlavor>,
+        config: &ServerQuirksConfig,
+    ) -> Quirks {
+        let base = match config.preset.or(detected) {
+            Some(ServerFlavor::PipeWire) => Self::PIPEWIRE,
+            Some(ServerFlavor::PulseAudio) | None => Self::NONE,
+        };
+
+        Quirks {
+            match_remaps_by_name: config
+                .match_remaps_by_name
+                .unwrap_or(base.match_remaps_by_name),
+            verify_default: config
+                .verify_default
+                .unwrap_or(base.verify_default),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_from_detected_flavor() {
+        let config = ServerQuirksConfig::default();
+        assert_eq!(Quirks::resolve(None, &config), Quirks::NONE);
+        assert_eq!(
+            Quirks::resolve(Some(ServerFlavor::PulseAudio), &config),
+            Quirks::NONE
+        );
+        assert_eq!(
+            Quirks::resolve(Some(ServerFlavor::PipeWire), &config),
+            Quirks::PIPEWIRE
+        );
+    }
+
+    #[test]
+    fn test_resolve_with_overrides() {
+        let config = ServerQuirksConfig {
+            preset: Some(ServerFlavor::PipeWire),
+            match_remaps_by_name: None,
+            verify_default: Some(false),
+        };
+        let quirks = Quirks::resolve(Some(ServerFlavor::PulseAudio), &config);
+
+        assert!(quirks.match_remaps_by_name);
+        assert!(!quirks.verify_default);
+    }
+}
@@ -0,0 +1,147 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::paths::Paths;
+
+/// How long a download of the configuration may take
+const FETCH_TIMEOUT_SECS: &str = "10";
+
+/// Configuration downloaded from a URL, with the last good copy kept for
+/// starts without network
+pub struct RemoteConfig {
+    pub url: String,
+    /// Expected SHA-256 digest of the file in hex
+    pub sha256: Option<String>,
+    pub cache: PathBuf,
+}
+
+impl RemoteConfig {
+    pub fn new(url: String, sha256: Option<String>, paths: &Paths) -> Self {
+        Self {
+            url,
+            sha256: sha256.map(|digest| digest.to_ascii_lowercase()),
+            cache: paths.cache_dir.join("config.yml"),
+        }
+    }
+
+    /// Download the configuration, falling back to the cached copy
+    pub fn fetch(&self) -> Result<String, String> {
+        let error = match download(&self.url)
+            .and_then(|content| self.verify(content))
+        {
+            Ok(content) => {
+                info!("Fetched configuration from {}", self.url);
+                self.store(&content);
+                return Ok(content);
+            }
+            Err(e) => e,
+        };
+        warn!("Failed to fetch configuration from {}: {error}", self.url);
+
+        let cache = &self.cache;
+        let cached = std::fs::read_to_string(cache).map_err(|e| {
+            format!("{error}; no cached copy at {}: {e}", cache.display())
+        })?;
+        let cached = self.verify(cached)?;
+        warn!("Using the cached configuration from {}", cache.display());
+        Ok(cached)
+    }
+
+    /// Reject the content unless it has the pinned digest
+    fn verify(&self, content: String) -> Result<String, String> {
+        let Some(expected) = &self.sha256 else {
+            return Ok(content);
+        };
+        let actual = format!("{:x}", Sha256::digest(&content));
+        if actual != *expected {
+            return Err(format!(
+                "SHA-256 digest {actual} does not match the pinned {expected}"
+            ));
+        }
+        Ok(content)
+    }
+
+    fn store(&self, content: &str) {
+        let cache = &self.cache;
+        let result = cache
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| write_atomically(cache, content));
+        if let Err(e) = result {
+            warn!("Failed to cache configuration at {}: {e}", cache.display());
+        }
+    }
+}
+
+fn write_atomically(path: &Path, content: &str) -> std::io::Result<()> {
+    let temporary = path.with_extension("yml.tmp");
+    std::fs::write(&temporary, content)?;
+    std::fs::rename(&temporary, path)
+}
+
+/// Download with curl, which brings the TLS support std lacks
+///
+/// Only HTTPS is accepted, redirects included, so that nobody on the way
+/// can swap in a policy of their own.
+fn download(url: &str) -> Result<String, String> {
+    if !url.starts_with("https://") {
+        return Err(format!("Unsupported URL '{url}', expected https://"));
+    }
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--proto", "=https", "--proto-redir", "=https"])
+        .args(["--max-time", FETCH_TIMEOUT_SECS, "--", url])
+        .output()
+        .map_err(|e| format!("Failed to run curl: {e}"))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string());
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| "Configuration is not valid UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_pinned_digest() {
+        // Digests are compared regardless of case
+        let remote = RemoteConfig::new(
+            "https://example.com/config.yml".to_string(),
+            Some(
+                "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD"
+                    .to_string(),
+            ),
+            &Paths::resolve(None),
+        );
+        assert!(remote.verify("abc".to_string()).is_ok());
+        assert!(remote.verify("abd".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_download_requires_https() {
+        let error = download("http://example.com/config.yml").unwrap_err();
+        assert!(error.contains("expected https://"), "{error}");
+    }
+}
@@ -0,0 +1,714 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A small expression language for matching devices by their properties
+//!
+//! A script is one expression over the properties of a device, such as
+//! `prop("device.bus") == "usb" && int(last(prop("device.serial"), 1)) % 2
+//! == 0`. It can only read the properties it is given, and its evaluation
+//! is bounded in steps and time.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const MAX_SOURCE_LENGTH: usize = 4096;
+/// Nesting of parentheses, calls and operators, bounding the recursion
+const MAX_DEPTH: usize = 64;
+const LIMITS: Limits = Limits {
+    steps: 10_000,
+    timeout: Duration::from_millis(10),
+};
+/// Steps between checks of the deadline
+const CLOCK_INTERVAL: u32 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    /// A missing property, or what is made of one
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Str(_) => "string",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Prop,
+    Has,
+    StartsWith,
+    EndsWith,
+    Contains,
+    Lower,
+    Len,
+    Int,
+    First,
+    Last,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "prop" => Function::Prop,
+            "has" => Function::Has,
+            "starts_with" => Function::StartsWith,
+            "ends_with" => Function::EndsWith,
+            "contains" => Function::Contains,
+            "lower" => Function::Lower,
+            "len" => Function::Len,
+            "int" => Function::Int,
+            "first" => Function::First,
+            "last" => Function::Last,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Prop
+            | Function::Has
+            | Function::Lower
+            | Function::Len
+            | Function::Int => 1,
+            Function::StartsWith
+            | Function::EndsWith
+            | Function::Contains
+            | Function::First
+            | Function::Last => 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+/// A compiled script
+#[derive(Debug)]
+pub struct Program {
+    expr: Expr,
+}
+
+/// Compile a script, reporting the first syntax error with its position
+pub fn compile(source: &str) -> Result<Program, String> {
+    if source.len() > MAX_SOURCE_LENGTH {
+        return Err(format!(
+            "script is longer than {MAX_SOURCE_LENGTH} bytes"
+        ));
+    }
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        position: 0,
+        depth: 0,
+    };
+    let expr = parser.or()?;
+    if let Some((token, offset)) = parser.tokens.get(parser.position) {
+        return Err(format!("unexpected {token} at offset {offset}"));
+    }
+    Ok(Program { expr })
+}
+
+/// Work an evaluation may do before it is given up on
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    steps: u32,
+    timeout: Duration,
+}
+
+impl Program {
+    /// Whether the properties satisfy the script
+    pub fn evaluate(
+        &self,
+        properties: &BTreeMap<String, String>,
+    ) -> Result<bool, String> {
+        self.evaluate_within(properties, LIMITS)
+    }
+
+    fn evaluate_within(
+        &self,
+        properties: &BTreeMap<String, String>,
+        limits: Limits,
+    ) -> Result<bool, String> {
+        let mut evaluator = Evaluator {
+            properties,
+            steps_left: limits.steps,
+            deadline: Instant::now() + limits.timeout,
+        };
+        match evaluator.eval(&self.expr)? {
+            Value::Bool(result) => Ok(result),
+            Value::Null => Ok(false),
+            value => Err(format!(
+                "script returned {}, expected true or false",
+                value.type_name()
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{name}'"),
+            Token::Str(text) => write!(f, "string {text:?}"),
+            Token::Int(number) => write!(f, "number {number}"),
+            Token::Symbol(symbol) => write!(f, "'{symbol}'"),
+        }
+    }
+}
+
+/// Longest first, so that `<=` is not read as `<`
+const SYMBOLS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/",
+    "%", "(", ")", ",",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => text.push(escaped),
+                        None => break,
+                    },
+                    Some((_, end)) if end == c => {
+                        tokens.push((Token::Str(text), offset));
+                        break;
+                    }
+                    Some((_, other)) => text.push(other),
+                    None => {
+                        return Err(format!(
+                            "unterminated string at offset {offset}"
+                        ));
+                    }
+                }
+            }
+        } else if c.is_ascii_digit() {
+            let mut digits = String::new();
+            while let Some(&(_, digit)) = chars.peek() {
+                if !digit.is_ascii_digit() {
+                    break;
+                }
+                digits.push(digit);
+                chars.next();
+            }
+            let number = digits.parse().map_err(|_| {
+                format!("number at offset {offset} is too large")
+            })?;
+            tokens.push((Token::Int(number), offset));
+        } else if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&(_, letter)) = chars.peek() {
+                if !letter.is_alphanumeric() && letter != '_' {
+                    break;
+                }
+                name.push(letter);
+                chars.next();
+            }
+            tokens.push((Token::Ident(name), offset));
+        } else {
+            let rest = &source[offset..];
+            let Some(symbol) =
+                SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+            else {
+                return Err(format!("unexpected '{c}' at offset {offset}"));
+            };
+            for _ in 0..symbol.len() {
+                chars.next();
+            }
+            tokens.push((Token::Symbol(symbol), offset));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some((Token::Symbol(symbol), _)) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.tokens.get(self.position) {
+            Some((Token::Symbol(found), _)) if *found == symbol => {
+                self.position += 1;
+                Ok(())
+            }
+            Some((token, offset)) => Err(format!(
+                "expected '{symbol}' at offset {offset}, found {token}"
+            )),
+            None => Err(format!("expected '{symbol}' at the end")),
+        }
+    }
+
+    fn descend(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("script is nested deeper than {MAX_DEPTH}"));
+        }
+        Ok(())
+    }
+
+    /// Left-associative operators of one precedence level
+    fn binary(
+        &mut self,
+        operators: &[(&str, BinaryOp)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+        while let Some(&(_, op)) = operators
+            .iter()
+            .find(|(symbol, _)| Some(*symbol) == self.peek_symbol())
+        {
+            self.position += 1;
+            self.descend()?;
+            let right = operand(self)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.sum()?;
+        let op = match self.peek_symbol() {
+            Some("==") => BinaryOp::Eq,
+            Some("!=") => BinaryOp::Ne,
+            Some("<") => BinaryOp::Lt,
+            Some("<=") => BinaryOp::Le,
+            Some(">") => BinaryOp::Gt,
+            Some(">=") => BinaryOp::Ge,
+            _ => return Ok(left),
+        };
+        self.position += 1;
+        let right = self.sum()?;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Self::product,
+        )
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("%", BinaryOp::Rem),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let wrap: fn(Box<Expr>) -> Expr = match self.peek_symbol() {
+            Some("!") => Expr::Not,
+            Some("-") => Expr::Neg,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        self.descend()?;
+        Ok(wrap(Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let Some((token, offset)) = self.tokens.get(self.position) else {
+            return Err("unexpected end of script".to_string());
+        };
+        self.position += 1;
+        match token {
+            Token::Str(text) => Ok(Expr::Literal(Value::Str(text.clone()))),
+            Token::Int(number) => Ok(Expr::Literal(Value::Int(*number))),
+            Token::Symbol("(") => {
+                self.descend()?;
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                _ => self.call(name, *offset),
+            },
+            Token::Symbol(_) => {
+                Err(format!("unexpected {token} at offset {offset}"))
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, offset: usize) -> Result<Expr, String> {
+        let function = Function::from_name(name).ok_or_else(|| {
+            format!("unknown function '{name}' at offset {offset}")
+        })?;
+        self.descend()?;
+        self.expect("(")?;
+        let mut args = Vec::new();
+        if self.peek_symbol() != Some(")") {
+            args.push(self.or()?);
+            while self.peek_symbol() == Some(",") {
+                self.position += 1;
+                args.push(self.or()?);
+            }
+        }
+        self.expect(")")?;
+        if args.len() != function.arity() {
+            return Err(format!(
+                "'{name}' at offset {offset} takes {} argument(s), got {}",
+                function.arity(),
+                args.len()
+            ));
+        }
+        Ok(Expr::Call(function, args))
+    }
+}
+
+struct Evaluator<'a> {
+    properties: &'a BTreeMap<String, String>,
+    steps_left: u32,
+    deadline: Instant,
+}
+
+impl Evaluator<'_> {
+    fn step(&mut self) -> Result<(), String> {
+        if self.steps_left == 0 {
+            return Err("script took too many steps".to_string());
+        }
+        self.steps_left -= 1;
+        if self.steps_left.is_multiple_of(CLOCK_INTERVAL)
+            && Instant::now() > self.deadline
+        {
+            return Err("script timed out".to_string());
+        }
+        Ok(())
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Value, String> {
+        self.step()?;
+        match expr {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Not(operand) => {
+                Ok(Value::Bool(!truth(&self.eval(operand)?)?))
+            }
+            Expr::Neg(operand) => match self.eval(operand)? {
+                Value::Int(number) => {
+                    number.checked_neg().map(Value::Int).ok_or_else(overflow)
+                }
+                Value::Null => Ok(Value::Null),
+                value => Err(mismatch("-", &value)),
+            },
+            Expr::Binary(BinaryOp::Or, left, right) => Ok(Value::Bool(
+                truth(&self.eval(left)?)? || truth(&self.eval(right)?)?,
+            )),
+            Expr::Binary(BinaryOp::And, left, right) => Ok(Value::Bool(
+                truth(&self.eval(left)?)? && truth(&self.eval(right)?)?,
+            )),
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                binary(*op, &left, &right)
+            }
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.call(*function, &args)
+            }
+        }
+    }
+
+    fn call(
+        &self,
+        function: Function,
+        args: &[Value],
+    ) -> Result<Value, String> {
+        let text = |index: usize| match &args[index] {
+            Value::Str(text) => Ok(Some(text.as_str())),
+            Value::Null => Ok(None),
+            value => Err(format!(
+                "expected a string as argument {} of {function:?}, got {}",
+                index + 1,
+                value.type_name()
+            )),
+        };
+        let count = || match &args[1] {
+            Value::Int(count) => Ok(usize::try_from(*count).unwrap_or(0)),
+            value => Err(format!(
+                "expected a number of characters, got {}",
+                value.type_name()
+            )),
+        };
+        let test = |check: fn(&str, &str) -> bool| {
+            Ok(match (text(0)?, text(1)?) {
+                (Some(text), Some(part)) => Value::Bool(check(text, part)),
+                _ => Value::Bool(false),
+            })
+        };
+        let or_null = |value: Option<Value>| value.unwrap_or(Value::Null);
+        match function {
+            Function::Prop => Ok(or_null(text(0)?.and_then(|key| {
+                self.properties.get(key).cloned().map(Value::Str)
+            }))),
+            Function::Has => Ok(Value::Bool(
+                text(0)?.is_some_and(|key| self.properties.contains_key(key)),
+            )),
+            Function::StartsWith => test(|text, part| text.starts_with(part)),
+            Function::EndsWith => test(|text, part| text.ends_with(part)),
+            Function::Contains => test(|text, part| text.contains(part)),
+            Function::Lower => Ok(or_null(
+                text(0)?.map(|text| Value::Str(text.to_lowercase())),
+            )),
+            Function::Len => Ok(or_null(
+                text(0)?.map(|text| Value::Int(text.chars().count() as i64)),
+            )),
+            Function::Int => Ok(match &args[0] {
+                Value::Int(number) => Value::Int(*number),
+                _ => or_null(
+                    text(0)?
+                        .and_then(|text| text.trim().parse().ok())
+                        .map(Value::Int),
+                ),
+            }),
+            Function::First => {
+                let count = count()?;
+                Ok(or_null(text(0)?.map(|text| {
+                    Value::Str(text.chars().take(count).collect())
+                })))
+            }
+            Function::Last => {
+                let count = count()?;
+                Ok(or_null(text(0)?.map(|text| {
+                    let skip = text.chars().count().saturating_sub(count);
+                    Value::Str(text.chars().skip(skip).collect())
+                })))
+            }
+        }
+    }
+}
+
+/// Conditions treat missing values as false
+fn truth(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(value) => Ok(*value),
+        Value::Null => Ok(false),
+        value => Err(format!("expected a bool, got {}", value.type_name())),
+    }
+}
+
+fn overflow() -> String {
+    "arithmetic overflow".to_string()
+}
+
+fn mismatch(operator: &str, value: &Value) -> String {
+    format!("'{operator}' cannot be applied to {}", value.type_name())
+}
+
+fn binary(op: BinaryOp, left: &Value, right: &Value) -> Result<Value, String> {
+    use std::cmp::Ordering;
+
+    let ordering = || match (left, right) {
+        (Value::Int(left), Value::Int(right)) => Ok(Some(left.cmp(right))),
+        (Value::Str(left), Value::Str(right)) => Ok(Some(left.cmp(right))),
+        (Value::Null, _) | (_, Value::Null) => Ok(None),
+        _ => Err(format!(
+            "cannot compare {} with {}",
+            left.type_name(),
+            right.type_name()
+        )),
+    };
+    let compare = |accept: fn(Ordering) -> bool| {
+        Ok(Value::Bool(ordering()?.is_some_and(accept)))
+    };
+    let (left, right) = match (op, left, right) {
+        (BinaryOp::Eq, ..) => return Ok(Value::Bool(left == right)),
+        (BinaryOp::Ne, ..) => return Ok(Value::Bool(left != right)),
+        (BinaryOp::Lt, ..) => return compare(Ordering::is_lt),
+        (BinaryOp::Le, ..) => return compare(Ordering::is_le),
+        (BinaryOp::Gt, ..) => return compare(Ordering::is_gt),
+        (BinaryOp::Ge, ..) => return compare(Ordering::is_ge),
+        (_, Value::Int(left), Value::Int(right)) => (*left, *right),
+        (_, Value::Null, _) | (_, _, Value::Null) => return Ok(Value::Null),
+        (_, Value::Int(_), value) | (_, value, _) => {
+            return Err(mismatch(symbol(op), value));
+        }
+    };
+    let result = match op {
+        BinaryOp::Add => left.checked_add(right),
+        BinaryOp::Sub => left.checked_sub(right),
+        BinaryOp::Mul => left.checked_mul(right),
+        BinaryOp::Div | BinaryOp::Rem if right == 0 => {
+            return Err("division by zero".to_string());
+        }
+        BinaryOp::Div => left.checked_div(right),
+        BinaryOp::Rem => left.checked_rem(right),
+        _ => unreachable!("handled above"),
+    };
+    result.map(Value::Int).ok_or_else(overflow)
+}
+
+fn symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Or => "||",
+        BinaryOp::And => "&&",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::Ge => ">=",
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Rem => "%",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn properties(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn run(source: &str, pairs: &[(&str, &str)]) -> Result<bool, String> {
+        compile(source)?.evaluate(&properties(pairs))
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let even_usb = r#"prop("device.bus") == "usb"
+            && int(last(prop("device.serial"), 1)) % 2 == 0"#;
+        let usb = |serial| [("device.bus", "usb"), ("device.serial", serial)];
+
+        assert_eq!(run(even_usb, &usb("A1B4")), Ok(true));
+        assert_eq!(run(even_usb, &usb("A1B3")), Ok(false));
+        assert_eq!(run(even_usb, &usb("A1BX")), Ok(false));
+        assert_eq!(run(even_usb, &[("device.bus", "usb")]), Ok(false));
+        assert_eq!(run(even_usb, &[]), Ok(false));
+
+        let props = [("device.description", "Scarlett 2i2 USB")];
+        assert_eq!(
+            run(
+                r#"contains(lower(prop("device.description")), "2i2")"#,
+                &props
+            ),
+            Ok(true)
+        );
+        assert_eq!(run(r#"!has("device.bus")"#, &props), Ok(true));
+        assert_eq!(run("-(2 + 3) * 2 < -9 || false", &[]), Ok(true));
+        assert_eq!(run("'a' <= 'b' && 1 != 2", &[]), Ok(true));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| run(source, &[]).unwrap_err();
+
+        assert!(error("prop(\"a\"").contains("expected ')'"));
+        assert!(error("1 + ").contains("end of script"));
+        assert!(error("exec(\"rm\")").contains("unknown function 'exec'"));
+        assert!(error("has()").contains("takes 1 argument(s), got 0"));
+        assert!(error("1 / 0 == 1").contains("division by zero"));
+        assert!(error("1 + 'a' == 1").contains("'+' cannot be applied"));
+        assert!(error("1 + 1").contains("expected true or false"));
+        assert!(error("true 1").contains("unexpected number 1 at offset 5"));
+        assert!(error(&"(".repeat(100)).contains("nested deeper"));
+    }
+
+    #[test]
+    fn test_budget() {
+        let program = compile("1 + 1 + 1 + 1 == 4").unwrap();
+        let properties = BTreeMap::new();
+
+        assert_eq!(program.evaluate(&properties), Ok(true));
+        assert!(
+            program
+                .evaluate_within(&properties, Limits { steps: 3, ..LIMITS })
+                .unwrap_err()
+                .contains("too many steps")
+        );
+    }
+}
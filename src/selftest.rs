@@ -0,0 +1,236 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Scripted check that the server permits everything the daemon does
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use libpulse_binding::{
+    context::{Context, FlagSet, State},
+    def::INVALID_INDEX,
+    mainloop::standard::{IterateResult, Mainloop},
+};
+
+const NULL_SINK_NAME: &str = "autopulsed_self_test";
+const REMAP_SINK_NAME: &str = "autopulsed_self_test_remap";
+
+/// Outcome of one step of the self-test
+pub struct Check {
+    pub step: &'static str,
+    pub result: Result<(), String>,
+}
+
+struct Runner<'a> {
+    mainloop: &'a mut Mainloop,
+    context: Context,
+}
+
+impl Runner<'_> {
+    /// Iterate the mainloop until a callback has stored a value in the cell
+    fn wait<T>(&mut self, cell: &Rc<RefCell<Option<T>>>) -> Result<T, String> {
+        loop {
+            if let Some(value) = cell.borrow_mut().take() {
+                return Ok(value);
+            }
+            if !self.context.get_state().is_good() {
+                return Err("Lost the connection to the server".to_string());
+            }
+            if !matches!(
+                self.mainloop.iterate(true),
+                IterateResult::Success(_)
+            ) {
+                return Err("Mainloop error".to_string());
+            }
+        }
+    }
+
+    fn connect(&mut self, server: Option<&str>) -> Result<(), String> {
+        self.context
+            .connect(server, FlagSet::NOAUTOSPAWN, None)
+            .map_err(|e| format!("Failed to connect: {e}"))?;
+        loop {
+            match self.context.get_state() {
+                State::Ready => return Ok(()),
+                State::Failed | State::Terminated => {
+                    return Err("Failed to connect".to_string());
+                }
+                _ => {}
+            }
+            if !matches!(
+                self.mainloop.iterate(true),
+                IterateResult::Success(_)
+            ) {
+                return Err("Mainloop error".to_string());
+            }
+        }
+    }
+
+    fn default_sink(&mut self) -> Result<Option<String>, String> {
+        let cell = Rc::new(RefCell::new(None));
+        let result = cell.clone();
+        let _op = self.context.introspect().get_server_info(move |info| {
+            let name = info.default_sink_name.as_deref().map(str::to_string);
+            *result.borrow_mut() = Some(name);
+        });
+        self.wait(&cell)
+    }
+
+    fn load_module(
+        &mut self,
+        name: &str,
+        argument: &str,
+    ) -> Result<u32, String> {
+        let cell = Rc::new(RefCell::new(None));
+        let result = cell.clone();
+        let _op = self.context.introspect().load_module(
+            name,
+            argument,
+            move |index| *result.borrow_mut() = Some(index),
+        );
+        match self.wait(&cell)? {
+            INVALID_INDEX => Err(format!("The server refused to load {name}")),
+            index => Ok(index),
+        }
+    }
+
+    fn unload_module(&mut self, index: u32) -> Result<(), String> {
+        let cell = Rc::new(RefCell::new(None));
+        let result = cell.clone();
+        let _op = self
+            .context
+            .introspect()
+            .unload_module(index, move |success| {
+                *result.borrow_mut() = Some(success)
+            });
+        self.succeeded(&cell, format!("The server refused to unload #{index}"))
+    }
+
+    fn set_default_sink(&mut self, name: &str) -> Result<(), String> {
+        let cell = Rc::new(RefCell::new(None));
+        let result = cell.clone();
+        let _op = self.context.set_default_sink(name, move |success| {
+            *result.borrow_mut() = Some(success)
+        });
+        self.succeeded(
+            &cell,
+            format!("The server refused to make {name} default"),
+        )
+    }
+
+    fn succeeded(
+        &mut self,
+        cell: &Rc<RefCell<Option<bool>>>,
+        error: String,
+    ) -> Result<(), String> {
+        if self.wait(cell)? { Ok(()) } else { Err(error) }
+    }
+}
+
+/// Record the outcome of a step, returning its value if it succeeded
+fn record<T>(
+    checks: &mut Vec<Check>,
+    step: &'static str,
+    result: Result<T, String>,
+) -> Option<T> {
+    match result {
+        Ok(value) => {
+            checks.push(Check {
+                step,
+                result: Ok(()),
+            });
+            Some(value)
+        }
+        Err(e) => {
+            checks.push(Check {
+                step,
+                result: Err(e),
+            });
+            None
+        }
+    }
+}
+
+/// Load a null sink and a remap sink on it, make the remap sink default,
+/// switch back and unload both, undoing whatever has succeeded
+///
+/// Returns an error only if the server cannot be used at all.
+pub fn run(
+    mainloop: &mut Mainloop,
+    context: Context,
+    server: Option<&str>,
+) -> Result<Vec<Check>, String> {
+    let mut runner = Runner { mainloop, context };
+    runner.connect(server)?;
+    let previous = runner.default_sink()?;
+
+    let mut checks = Vec::new();
+    let null_sink = runner.load_module(
+        "module-null-sink",
+        &format!(
+            "sink_name={NULL_SINK_NAME} \
+             sink_properties=device.description=autopulsed-self-test"
+        ),
+    );
+    let Some(null_sink) = record(&mut checks, "Load a null sink", null_sink)
+    else {
+        return Ok(checks);
+    };
+
+    let remap = runner.load_module(
+        "module-remap-sink",
+        &format!("sink_name={REMAP_SINK_NAME} master={NULL_SINK_NAME}"),
+    );
+    if let Some(remap) = record(&mut checks, "Load a remap sink", remap) {
+        let set = runner.set_default_sink(REMAP_SINK_NAME);
+        if record(&mut checks, "Set the remap sink as default", set).is_some()
+            && let Some(previous) = &previous
+        {
+            let revert = runner.set_default_sink(previous);
+            record(&mut checks, "Restore the previous default", revert);
+        }
+        let unload = runner.unload_module(remap);
+        record(&mut checks, "Unload the remap sink", unload);
+    }
+
+    let unload = runner.unload_module(null_sink);
+    record(&mut checks, "Unload the null sink", unload);
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut checks = Vec::new();
+        assert_eq!(record(&mut checks, "Load", Ok(7)), Some(7));
+        assert_eq!(
+            record::<u32>(&mut checks, "Unload", Err("denied".to_string())),
+            None
+        );
+
+        let results: Vec<_> = checks
+            .iter()
+            .map(|check| (check.step, check.result.clone()))
+            .collect();
+        assert_eq!(
+            results,
+            [("Load", Ok(())), ("Unload", Err("denied".to_string()))]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Implementation of the PulseAudio protocol we are connected to
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerFlavor {
+    PulseAudio,
+    PipeWire, // pipewire-pulse
+}
+
+impl fmt::Display for ServerFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerFlavor::PulseAudio => write!(f, "PulseAudio"),
+            ServerFlavor::PipeWire => write!(f, "pipewire-pulse"),
+        }
+    }
+}
+
+/// What we know about the server, used to gate version-dependent features
+#[derive(Debug, Clone)]
+pub struct ServerDetails {
+    pub flavor: ServerFlavor,
+    pub name: String,
+    pub version: String,
+    pub protocol_version: Option<u32>,
+}
+
+impl ServerDetails {
+    pub fn new(name: &str, version: &str) -> Self {
+        // pipewire-pulse reports e.g. "PulseAudio (on PipeWire 1.0.5)"
+        let flavor = if name.contains("PipeWire") {
+            ServerFlavor::PipeWire
+        } else {
+            ServerFlavor::PulseAudio
+        };
+
+        Self {
+            flavor,
+            name: name.to_string(),
+            version: version.to_string(),
+            protocol_version: None,
+        }
+    }
+
+    /// Major and minor part of the reported version, if it can be parsed
+    pub fn version_number(&self) -> Option<(u32, u32)> {
+        let mut parts = self.version.split(['.', '-', '+']);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+        Some((major, minor))
+    }
+
+    fn is_at_least(&self, major: u32, minor: u32) -> bool {
+        // Unparsable versions are assumed to be recent
+        self.version_number()
+            .is_none_or(|version| version >= (major, minor))
+    }
+
+    /// Whether the server provides the given module
+    pub fn supports_module(&self, module_name: &str) -> bool {
+        match (self.flavor, module_name) {
+            (ServerFlavor::PulseAudio, "module-remap-source") => {
+                self.is_at_least(4, 0)
+            }
+            _ => true,
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let protocol = self
+            .protocol_version
+            .map(|version| version.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let unsupported: Vec<_> = ["module-remap-sink", "module-remap-source"]
+            .into_iter()
+            .filter(|module| !self.supports_module(module))
+            .collect();
+
+        let mut summary = format!(
+            "{} ({} {}, protocol {})",
+            self.flavor, self.name, self.version, protocol
+        );
+        if !unsupported.is_empty() {
+            summary.push_str(&format!(
+                ", unsupported: {}",
+                unsupported.join(", ")
+            ));
+        }
+        summary
+    }
+}
+
+/// Whether a client is a session manager that manages defaults itself
+pub fn is_session_manager(application_name: Option<&str>) -> bool {
+    matches!(
+        application_name,
+        Some("WirePlumber" | "pipewire-media-session")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flavor_detection() {
+        let pulseaudio = ServerDetails::new("pulseaudio", "16.1");
+        assert_eq!(pulseaudio.flavor, ServerFlavor::PulseAudio);
+        assert_eq!(pulseaudio.version_number(), Some((16, 1)));
+
+        let pipewire =
+            ServerDetails::new("PulseAudio (on PipeWire 1.0.5)", "15.0.0");
+        assert_eq!(pipewire.flavor, ServerFlavor::PipeWire);
+        assert_eq!(pipewire.version_number(), Some((15, 0)));
+    }
+
+    #[test]
+    fn test_module_support_by_version() {
+        let old = ServerDetails::new("pulseaudio", "3.0");
+        assert!(old.supports_module("module-remap-sink"));
+        assert!(!old.supports_module("module-remap-source"));
+        assert!(old.summary().contains("unsupported: module-remap-source"));
+
+        let unknown = ServerDetails::new("pulseaudio", "git");
+        assert!(unknown.supports_module("module-remap-source"));
+    }
+
+    #[test]
+    fn test_session_manager_detection() {
+        assert!(is_session_manager(Some("WirePlumber")));
+        assert!(is_session_manager(Some("pipewire-media-session")));
+        assert!(!is_session_manager(Some("pavucontrol")));
+        assert!(!is_session_manager(None));
+    }
+}
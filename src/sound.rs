@@ -0,0 +1,204 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use libpulse_binding::sample::{Format, Spec};
+use std::f32::consts::TAU;
+use std::path::Path;
+use std::time::Duration;
+
+/// Format of generated tones
+pub const TONE_SPEC: Spec = Spec {
+    format: Format::S16le,
+    rate: 44100,
+    channels: 1,
+};
+
+/// Ramps avoiding clicks at both ends of a tone
+const FADE: Duration = Duration::from_millis(10);
+
+/// Sine wave in the format of `TONE_SPEC`
+pub fn tone(frequency: f32, duration: Duration) -> Vec<u8> {
+    let rate = TONE_SPEC.rate as f32;
+    let samples = (duration.as_secs_f32() * rate) as usize;
+    let fade = ((FADE.as_secs_f32() * rate) as usize).min(samples / 2);
+
+    let mut data = Vec::with_capacity(samples * 2);
+    for i in 0..samples {
+        let envelope = if fade == 0 {
+            1.0
+        } else {
+            (i.min(samples - 1 - i) as f32 / fade as f32).min(1.0)
+        };
+        let value = (TAU * frequency * i as f32 / rate).sin() * envelope;
+        let sample = (value * 0.3 * i16::MAX as f32) as i16;
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    data
+}
+
+/// Silence in the format of `TONE_SPEC`
+pub fn silence(duration: Duration) -> Vec<u8> {
+    let samples = (duration.as_secs_f32() * TONE_SPEC.rate as f32) as usize;
+    vec![0; samples * 2]
+}
+
+/// Two short rising tones, used when no sound file is configured
+pub fn chime() -> Vec<u8> {
+    let mut data = tone(880.0, Duration::from_millis(90));
+    data.extend(tone(1320.0, Duration::from_millis(140)));
+    data
+}
+
+pub fn read_wav(path: &Path) -> Result<(Spec, Vec<u8>), String> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    parse_wav(&data).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+/// Sample spec and PCM data of a WAV file
+fn parse_wav(data: &[u8]) -> Result<(Spec, Vec<u8>), String> {
+    const PCM: u16 = 1;
+    const FLOAT: u16 = 3;
+    const EXTENSIBLE: u16 = 0xfffe;
+
+    let u16_at = |bytes: &[u8], offset: usize| {
+        bytes
+            .get(offset..offset + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let u32_at = |bytes: &[u8], offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    if data.get(..4) != Some(b"RIFF") || data.get(8..12) != Some(b"WAVE") {
+        return Err("Not a WAV file".to_string());
+    }
+
+    let mut spec = None;
+    let mut offset = 12;
+    while let (Some(id), Some(size)) =
+        (data.get(offset..offset + 4), u32_at(data, offset + 4))
+    {
+        let start = offset + 8;
+        let chunk = data
+            .get(start..start + size as usize)
+            .ok_or("Truncated WAV file")?;
+
+        match id {
+            b"fmt " => {
+                let malformed = || "Malformed format chunk".to_string();
+                let mut encoding = u16_at(chunk, 0).ok_or_else(malformed)?;
+                if encoding == EXTENSIBLE {
+                    encoding = u16_at(chunk, 24).ok_or_else(malformed)?;
+                }
+                let channels = u16_at(chunk, 2).ok_or_else(malformed)?;
+                let rate = u32_at(chunk, 4).ok_or_else(malformed)?;
+                let bits = u16_at(chunk, 14).ok_or_else(malformed)?;
+
+                let format = match (encoding, bits) {
+                    (PCM, 8) => Format::U8,
+                    (PCM, 16) => Format::S16le,
+                    (PCM, 24) => Format::S24le,
+                    (PCM, 32) => Format::S32le,
+                    (FLOAT, 32) => Format::F32le,
+                    _ => {
+                        return Err(format!(
+                            "Unsupported encoding {encoding} with {bits} bits"
+                        ));
+                    }
+                };
+                let spec_found = Spec {
+                    format,
+                    rate,
+                    channels: u8::try_from(channels)
+                        .map_err(|_| "Too many channels")?,
+                };
+                if !spec_found.is_valid() {
+                    return Err("Unsupported sample spec".to_string());
+                }
+                spec = Some(spec_found);
+            }
+            b"data" => {
+                let spec = spec.ok_or("Data chunk precedes format chunk")?;
+                return Ok((spec, chunk.to_vec()));
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to even sizes
+        offset = start + size as usize + (size as usize & 1);
+    }
+
+    Err("No data chunk".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(format_chunk: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", format_chunk), (b"data", data)] {
+            wav.extend_from_slice(id);
+            wav.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            wav.extend_from_slice(chunk);
+        }
+        wav
+    }
+
+    fn format_chunk(encoding: u16, channels: u16, bits: u16) -> Vec<u8> {
+        let mut chunk = encoding.to_le_bytes().to_vec();
+        chunk.extend_from_slice(&channels.to_le_bytes());
+        chunk.extend_from_slice(&48000u32.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes()); // Byte rate
+        chunk.extend_from_slice(&0u16.to_le_bytes()); // Block align
+        chunk.extend_from_slice(&bits.to_le_bytes());
+        chunk
+    }
+
+    #[test]
+    fn test_parse_wav() {
+        let (spec, data) =
+            parse_wav(&wav(&format_chunk(1, 2, 16), &[1, 2, 3, 4])).unwrap();
+        assert_eq!(
+            spec,
+            Spec {
+                format: Format::S16le,
+                rate: 48000,
+                channels: 2
+            }
+        );
+        assert_eq!(data, [1, 2, 3, 4]);
+
+        let (spec, _) =
+            parse_wav(&wav(&format_chunk(3, 1, 32), &[0; 4])).unwrap();
+        assert_eq!(spec.format, Format::F32le);
+
+        assert!(parse_wav(&wav(&format_chunk(2, 1, 4), &[0])).is_err());
+        assert!(parse_wav(b"RIFF\0\0\0\0AVI ").is_err());
+    }
+
+    #[test]
+    fn test_tone() {
+        let data = tone(440.0, Duration::from_millis(100));
+        assert_eq!(data.len(), 4410 * 2);
+        // Faded in from silence
+        assert_eq!(&data[..2], &[0, 0]);
+        assert!(!chime().is_empty());
+    }
+}
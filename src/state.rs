@@ -14,42 +14,747 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::Path;
 use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 
 use libpulse_binding::{
     callbacks::ListResult,
     context::{
         Context,
-        introspect::{SinkInfo, SourceInfo},
+        introspect::{
+            CardInfo, ClientInfo, Introspector, ServerInfo, SinkInfo,
+            SinkInputInfo, SourceInfo, SourceOutputInfo,
+        },
+        subscribe::InterestMaskSet,
     },
+    def::{
+        BufferAttr, Device as RestoreType, INVALID_INDEX, SinkFlagSet,
+        SinkState, SourceFlagSet, SourceState,
+    },
+    error::{Code, PAErr},
+    format::{Encoding, Info as FormatInfo},
+    operation::Operation,
+    sample::{Format, Spec},
+    stream::{
+        FlagSet as StreamFlagSet, PeekResult, SeekMode, State as StreamState,
+        Stream,
+    },
+};
+use log::{Level, debug, error, info, log_enabled, warn};
+
+use crate::config::{
+    BundleConfig, CATCH_ALL_CONFIG, CapabilityRequirements, Config,
+    DeviceConfig, DeviceMatchConfig, DeviceRunState, ExternalUnloadPolicy,
+    HeadsetProfileConfig, LimitsConfig, MasterRef, MultiMatchPolicy,
+    NameCollisionPolicy, PartialBundlePolicy, PassthroughFormat, RemapConfig,
+    SessionManagerPolicy, SilenceFailoverConfig,
+};
+use crate::control::{
+    Command, DeviceEntry, DeviceRef, DeviceReport, MemoryReport,
+    PrivacySettings, Reply, Response, StatusReport,
 };
-use log::{debug, error, info};
+use crate::diag::{DeviceSnapshot, DeviceSnapshots};
+use crate::events::{self, Event, EventFeed};
+use crate::jack;
+use crate::journal::{self, Entry, Journal};
+use crate::modargs::{self, REMAP_TAG_PROPERTY};
+use crate::overrides::Overrides;
+use crate::owner;
+use crate::quirks::Quirks;
+use crate::server::{ServerDetails, is_session_manager};
+use crate::sound;
+use crate::statistics;
+use crate::summary::Activity;
+use crate::timer::{self, Timer, TimerQueue};
+
+mod action;
+pub mod bench;
+mod policy;
+mod server_event;
+pub mod trace;
+
+use action::{Action, ActionExecutor, Admission, Submission, Target};
+use policy::{Policy, PolicyAction, PolicyContext, PolicyEvent};
+use server_event::{DeviceKind, ServerEvent};
+
+/// How long to wait for more device events before re-evaluating defaults
+/// and remaps, so that bursts of events are handled at once
+const REEVALUATION_WINDOW: Duration = Duration::from_millis(100);
+const REEVALUATION_TIMER: &str = "reevaluation";
+
+/// Devices of a list matched at once before callbacks are dispatched again
+const ENUMERATION_CHUNK: usize = 64;
+
+/// Retries of a failed device list query, with linearly growing delays
+const LIST_RETRY_LIMIT: u32 = 3;
+const LIST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How long after setting a default it is checked when the server is known
+/// to revert it, and how often it is set again
+const DEFAULT_VERIFY_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_REAPPLY_LIMIT: u32 = 3;
+
+/// How often setting a default the server refused is retried, and the
+/// delay before the first retry, which doubles for every further one
+const DEFAULT_RETRY_LIMIT: u32 = 4;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// How often to look for a running JACK server when bridges are managed
+const JACK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const JACK_POLL_TIMER: &str = "jack-poll";
+
+/// Name of the sample played after switching the default sink
+const TEST_SOUND_SAMPLE: &str = "autopulsed-test-sound";
+const SAMPLE_UPLOAD_TIMER: &str = "sample-upload";
+
+const TEST_TONE_FREQUENCY: f32 = 440.0;
+const TEST_TONE_DURATION: Duration = Duration::from_secs(1);
+
+/// Peak monitors record one float sample, the peak of the fragment, per
+/// period at this rate
+const PEAK_MONITOR_SPEC: Spec = Spec {
+    format: Format::F32le,
+    rate: 25,
+    channels: 1,
+};
+
+const SILENCE_FAILOVER_TIMER: &str = "silence-failover";
+
+const UNMANAGED_SUMMARY_TIMER: &str = "unmanaged-summary";
+
+const RECONCILE_TIMER: &str = "reconcile";
 
-use crate::config::{Config, DeviceConfig, DeviceMatchConfig};
+/// Seconds between reconciliations unless configured otherwise
+const DEFAULT_RECONCILE_INTERVAL: u64 = 300;
 
+/// How long a default restored by the undo command is kept over the
+/// priorities
+const UNDO_EXEMPTION: Duration = Duration::from_secs(10 * 60);
+
+/// Period over which handled server events are counted
+const EVENT_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Silence written to a sink by its playback check
+const PLAYBACK_CHECK_DURATION: Duration = Duration::from_millis(100);
+
+pub type TimerCallback = Box<dyn FnOnce(&mut StateRunner<'_>)>;
+
+#[derive(Clone)]
 struct AudioDevice {
     original_name: String,
-    recognized_as: Vec<String>, // Config names
+    monitor_name: Option<String>, // Monitor source of a sink
+    owner_module: Option<u32>,
+    active_port: Option<String>,
+    muted: bool,
+    running: bool, // Whether streams are playing or recording
+    suspended: bool,
+    silent: bool, // Failed over from for staying silent while running
+    playback_verified: Option<bool>, // Outcome of the playback check
+    card: Option<u32>,
+    properties: BTreeMap<String, String>, // Proplist at detection time
+    capabilities: Capabilities,
+    recognized_as: Vec<ConfigId>,
+    match_scores: BTreeMap<ConfigId, u32>, // Of recognitions by scoring
+}
+
+impl AudioDevice {
+    /// Whether the other snapshot differs in anything we act upon, as
+    /// opposed to volume or mute changes
+    fn has_same_snapshot(&self, other: &AudioDevice) -> bool {
+        self.original_name == other.original_name
+            && self.monitor_name == other.monitor_name
+            && self.owner_module == other.owner_module
+            && self.active_port == other.active_port
+            && self.properties == other.properties
+            && self.capabilities == other.capabilities
+    }
+
+    /// Whether the other snapshot differs in nothing we keep track of
+    fn is_up_to_date(&self, other: &AudioDevice) -> bool {
+        self.has_same_snapshot(other)
+            && self.muted == other.muted
+            && self.running == other.running
+            && self.suspended == other.suspended
+    }
+
+    fn run_state(&self) -> DeviceRunState {
+        if self.running {
+            DeviceRunState::Running
+        } else if self.suspended {
+            DeviceRunState::Suspended
+        } else {
+            DeviceRunState::Idle
+        }
+    }
+
+    /// Whether the device is the monitor source of a sink
+    fn is_monitor(&self) -> bool {
+        self.properties
+            .get("device.class")
+            .is_some_and(|class| class == "monitor")
+    }
+
+    /// Whether the device can be made default for the config, which may
+    /// require a successful playback check first
+    fn is_eligible_as(&self, config: &DeviceConfig) -> bool {
+        !config.requires_playback_check()
+            || self.playback_verified == Some(true)
+    }
+
+    /// Names of the configs this device matches, narrowed down to the
+    /// exclusive ones among them and then by the multi-match policy
+    ///
+    /// The catch-all config only matches devices no other config matches.
+    fn recognize<'a>(&self, env: &MatchEnvironment<'a>) -> Vec<&'a str> {
+        let mut matched: Vec<_> = env
+            .configs
+            .iter()
+            .filter(|&(name, config)| {
+                name != CATCH_ALL_CONFIG
+                    && self.matches(env, NamedConfig { name, config })
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        matched.sort();
+
+        if matched.is_empty() {
+            return env
+                .configs
+                .get_key_value(CATCH_ALL_CONFIG)
+                .filter(|&(name, config)| {
+                    !self.is_monitor()
+                        && self.matches(env, NamedConfig { name, config })
+                })
+                .map(|(name, _)| vec![name.as_str()])
+                .unwrap_or_default();
+        }
+
+        if matched.iter().any(|&name| env.configs[name].exclusive) {
+            matched.retain(|&name| env.configs[name].exclusive);
+        }
+        if matched.len() < 2 {
+            return matched;
+        }
+        match env.multi_match {
+            MultiMatchPolicy::Allow => matched,
+            MultiMatchPolicy::Warn => {
+                warn!(
+                    "{} matches several configs: {}",
+                    self.original_name,
+                    matched.join(", ")
+                );
+                matched
+            }
+            MultiMatchPolicy::Error => {
+                error!(
+                    "{} matches several configs, not recognizing it as any: \
+                     {}",
+                    self.original_name,
+                    matched.join(", ")
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Match scores of the recognitions as configs in scoring mode
+    fn recognition_scores(
+        &self,
+        configs: &HashMap<String, DeviceConfig>,
+        config_ids: &ConfigIds,
+    ) -> BTreeMap<ConfigId, u32> {
+        self.recognized_as
+            .iter()
+            .filter_map(|&id| {
+                let config = configs.get(config_ids.name(id))?;
+                Some((id, config.match_score(&self.properties)?))
+            })
+            .collect()
+    }
+
+    /// Whether the device matches the config
+    fn matches(
+        &self,
+        env: &MatchEnvironment<'_>,
+        config: NamedConfig<'_>,
+    ) -> bool {
+        check_device_match(&DeviceMatchContext {
+            device_config: config.config,
+            config_name: config.name,
+            device_name: &self.original_name,
+            properties: &self.properties,
+            capabilities: &self.capabilities,
+            runtime: (self.muted, self.run_state()),
+            owner_module: self.owner_module,
+            remap_module: env
+                .config_ids
+                .get(config.name)
+                .and_then(|id| env.remap_module_indices.get(&id))
+                .copied(),
+            match_remaps_by_name: env.quirks.match_remaps_by_name,
+        })
+    }
+}
+
+fn passthrough_encoding(format: PassthroughFormat) -> Encoding {
+    match format {
+        PassthroughFormat::Ac3 => Encoding::AC3_IEC61937,
+        PassthroughFormat::Eac3 => Encoding::EAC3_IEC61937,
+        PassthroughFormat::Mpeg => Encoding::MPEG_IEC61937,
+        PassthroughFormat::Dts => Encoding::DTS_IEC61937,
+        PassthroughFormat::Mpeg2Aac => Encoding::MPEG2_AAC_IEC61937,
+    }
+}
+
+/// Latest level in a fragment recorded by a peak monitor
+fn last_peak(data: &[u8]) -> Option<f32> {
+    data.rchunks_exact(std::mem::size_of::<f32>())
+        .next()
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(|bytes| f32::from_le_bytes(bytes).clamp(0.0, 1.0))
+}
+
+fn snapshot_proplist(
+    proplist: &libpulse_binding::proplist::Proplist,
+) -> BTreeMap<String, String> {
+    // Non-string values are not usable for matching
+    proplist
+        .iter()
+        .filter_map(|key| {
+            let value = proplist.get_str(&key)?;
+            Some((key, value))
+        })
+        .collect()
+}
+
+/// Properties the daemon itself reads, kept however large the snapshot
+const INTERNAL_PROPERTIES: &[&str] = &[
+    "device.class",
+    "device.description",
+    "device.api",
+    "jack.client_name",
+    REMAP_TAG_PROPERTY,
+];
+
+/// How large the properties of a device may get
+struct PropertyBudget<'a> {
+    /// Bytes of keys and values together
+    limit: usize,
+    /// Properties kept however large they are
+    kept: &'a HashSet<&'a str>,
+}
+
+impl PropertyBudget<'_> {
+    /// Drop the longest properties that are not to be kept until the rest
+    /// fit in the limit, returning how many were dropped
+    fn trim(&self, properties: &mut BTreeMap<String, String>) -> usize {
+        let size = |(key, value): (&String, &String)| key.len() + value.len();
+        let mut total: usize = properties.iter().map(size).sum();
+        if total <= self.limit {
+            return 0;
+        }
+
+        let mut droppable: Vec<_> = properties
+            .iter()
+            .filter(|(key, _)| !self.kept.contains(key.as_str()))
+            .map(|entry| (Reverse(size(entry)), entry.0.clone()))
+            .collect();
+        droppable.sort();
+        let mut dropped = 0;
+        for (Reverse(bytes), key) in droppable {
+            if total <= self.limit {
+                break;
+            }
+            properties.remove(&key);
+            total -= bytes;
+            dropped += 1;
+        }
+        dropped
+    }
+}
+
+/// Resident size of this process as the kernel reports it
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kilobytes = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// A config name interned as a small id, cheap to copy and compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct ConfigId(u32);
+
+/// Both ways between the config names seen by a device group and their ids
+#[derive(Default)]
+struct ConfigIds {
+    names: Vec<String>,
+    ids: HashMap<String, ConfigId>,
+}
+
+impl ConfigIds {
+    fn intern(&mut self, name: &str) -> ConfigId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = ConfigId(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn get(&self, name: &str) -> Option<ConfigId> {
+        self.ids.get(name).copied()
+    }
+
+    fn name(&self, id: ConfigId) -> &str {
+        &self.names[id.0 as usize]
+    }
+}
+
+/// A device along with its index on the server
+struct IndexedDevice {
+    index: u32,
+    device: AudioDevice,
+}
+
+/// A recognition of a device as a config with a priority, ordered from
+/// the highest priority and then from the highest match score
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RankedDevice {
+    priority: u32,
+    score: Reverse<u32>,
+    config: ConfigId,
+    index: u32,
+}
+
+/// What recognitions are ranked by
+struct Ranking<'a, F> {
+    config_ids: &'a ConfigIds,
+    priority_of: &'a F,
+}
+
+impl<'a, F: Fn(&str) -> Option<u32>> Ranking<'a, F> {
+    /// Recognitions of the device as configs with a priority
+    fn entries(
+        &self,
+        index: u32,
+        device: &'a AudioDevice,
+    ) -> impl Iterator<Item = RankedDevice> + use<'a, F> {
+        let Ranking {
+            config_ids,
+            priority_of,
+        } = *self;
+        device.recognized_as.iter().filter_map(move |&config| {
+            Some(RankedDevice {
+                priority: priority_of(config_ids.name(config))?,
+                score: Reverse(
+                    device.match_scores.get(&config).copied().unwrap_or(0),
+                ),
+                config,
+                index,
+            })
+        })
+    }
 }
 
 struct AudioDeviceGroup {
     found_devices: HashMap<u32, AudioDevice>,
-    remap_module_indices: HashMap<String, u32>,
-    pending_default_index: Option<u32>,
-    pending_default_callback: Option<Box<dyn FnMut(bool) + 'static>>,
+    config_ids: ConfigIds,
+    ranking: BTreeSet<RankedDevice>, // Recognitions by priority
+    by_config: HashMap<ConfigId, BTreeSet<u32>>, // Recognized devices
+    pending_devices: VecDeque<IndexedDevice>, // Listed, not matched
+    remap_module_indices: HashMap<ConfigId, u32>,
+    remap_module_arguments: HashMap<ConfigId, String>,
+    remap_masters: HashMap<ConfigId, u32>, // Master devices loaded against
+    unloading_modules: HashSet<u32>,       // Unloads we have requested
+    accepted_unloads: HashSet<ConfigId>,   // Unloaded by others, left so
+    name_collisions: HashSet<ConfigId>,    // Remaps whose name is taken
+    needs_reevaluation: bool,
+    enumerated: bool, // Whether the initial list query has completed
+    list_error_count: u32, // Consecutive failures of the list query
+    default_reapply_count: u32, // Consecutive reverts of our default
+    default_retry_count: u32, // Consecutive refusals to set the default
+    default_override: Option<String>, // Config name chosen by a command
+    current_default: Option<String>, // Config name we last made default
+    observed_default: Option<String>, // Would have been made default
+    initial_default: Option<String>, // Server default when we connected
+    peak_monitor: Option<PeakMonitor>, // Level of the current default
+    playback_checks: HashMap<u32, Stream>, // Test streams by device index
 }
 
 impl AudioDeviceGroup {
     fn new() -> Self {
         Self {
             found_devices: HashMap::new(),
+            config_ids: ConfigIds::default(),
+            ranking: BTreeSet::new(),
+            by_config: HashMap::new(),
+            pending_devices: VecDeque::new(),
             remap_module_indices: HashMap::new(),
-            pending_default_index: None,
-            pending_default_callback: None,
+            remap_module_arguments: HashMap::new(),
+            remap_masters: HashMap::new(),
+            unloading_modules: HashSet::new(),
+            accepted_unloads: HashSet::new(),
+            name_collisions: HashSet::new(),
+            needs_reevaluation: false,
+            enumerated: false,
+            list_error_count: 0,
+            default_reapply_count: 0,
+            default_retry_count: 0,
+            default_override: None,
+            current_default: None,
+            observed_default: None,
+            initial_default: None,
+            peak_monitor: None,
+            playback_checks: HashMap::new(),
+        }
+    }
+
+    /// Give every config of the group an id, so that devices can be
+    /// recognized as them
+    fn intern_configs(&mut self, configs: &HashMap<String, DeviceConfig>) {
+        for name in configs.keys() {
+            self.config_ids.intern(name);
+        }
+    }
+
+    /// Add or replace a device, updating the ranking with the priorities
+    /// of the configs it is recognized as
+    fn insert_device(
+        &mut self,
+        IndexedDevice { index, device }: IndexedDevice,
+        priority_of: impl Fn(&str) -> Option<u32>,
+    ) -> Option<AudioDevice> {
+        let previous = self.remove_device(index, &priority_of);
+        let ranking = Ranking {
+            config_ids: &self.config_ids,
+            priority_of: &priority_of,
+        };
+        self.ranking.extend(ranking.entries(index, &device));
+        for &config in &device.recognized_as {
+            self.by_config.entry(config).or_default().insert(index);
+        }
+        self.found_devices.insert(index, device);
+        previous
+    }
+
+    fn remove_device(
+        &mut self,
+        index: u32,
+        priority_of: impl Fn(&str) -> Option<u32>,
+    ) -> Option<AudioDevice> {
+        // A device gone before it was matched is not matched at all
+        self.pending_devices
+            .retain(|pending| pending.index != index);
+        let device = self.found_devices.remove(&index)?;
+        let ranking = Ranking {
+            config_ids: &self.config_ids,
+            priority_of: &priority_of,
+        };
+        for entry in ranking.entries(index, &device) {
+            self.ranking.remove(&entry);
+        }
+        for config in &device.recognized_as {
+            if let Some(indices) = self.by_config.get_mut(config) {
+                indices.remove(&index);
+            }
+        }
+        Some(device)
+    }
+
+    /// Rebuild the ranking and the devices by config after recognitions or
+    /// priorities have changed
+    fn reindex(&mut self, priority_of: impl Fn(&str) -> Option<u32>) {
+        let ranking = Ranking {
+            config_ids: &self.config_ids,
+            priority_of: &priority_of,
+        };
+        self.ranking = self
+            .found_devices
+            .iter()
+            .flat_map(|(&index, device)| ranking.entries(index, device))
+            .collect();
+        self.by_config.clear();
+        for (&index, device) in &self.found_devices {
+            for &config in &device.recognized_as {
+                self.by_config.entry(config).or_default().insert(index);
+            }
+        }
+    }
+
+    /// Names of the configs the device is recognized as
+    fn config_names<'a>(
+        &'a self,
+        device: &'a AudioDevice,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        device
+            .recognized_as
+            .iter()
+            .map(|&config| self.config_ids.name(config))
+    }
+
+    fn remap_module_index(&self, config_name: &str) -> Option<u32> {
+        self.config_ids
+            .get(config_name)
+            .and_then(|config| self.remap_module_indices.get(&config))
+            .copied()
+    }
+
+    /// Default the server had when we connected, if we have changed it
+    fn default_to_restore(&self) -> Option<&str> {
+        self.current_default
+            .as_ref()
+            .and(self.initial_default.as_deref())
+    }
+
+    /// Known devices missing from a fresh list of the server
+    fn missing_from(&self, listed: &[IndexedDevice]) -> Vec<u32> {
+        let listed: HashSet<u32> =
+            listed.iter().map(|device| device.index).collect();
+        self.found_devices
+            .keys()
+            .filter(|index| !listed.contains(index))
+            .copied()
+            .collect()
+    }
+
+    /// Whether a freshly listed device is new or differs from what we
+    /// know of it
+    fn is_outdated(&self, listed: &IndexedDevice) -> bool {
+        !self
+            .found_devices
+            .get(&listed.index)
+            .is_some_and(|known| known.is_up_to_date(&listed.device))
+    }
+
+    /// Our modules missing from a fresh list of the loaded ones
+    fn unloaded_modules(&self, loaded: &HashSet<u32>) -> Vec<u32> {
+        self.remap_module_indices
+            .values()
+            .filter(|module| !loaded.contains(module))
+            .copied()
+            .collect()
+    }
+
+    /// Take the next listed devices to match, at most a chunk of them
+    fn take_pending_chunk(&mut self) -> Vec<IndexedDevice> {
+        let count = self.pending_devices.len().min(ENUMERATION_CHUNK);
+        self.pending_devices.drain(..count).collect()
+    }
+
+    /// Count a failed list query, giving the delay before the next try
+    /// unless the retries are used up
+    fn list_failed(&mut self) -> Option<Duration> {
+        self.list_error_count += 1;
+        (self.list_error_count <= LIST_RETRY_LIMIT)
+            .then(|| LIST_RETRY_DELAY * self.list_error_count)
+    }
+
+    /// Forget every device, keeping what is known about our modules and
+    /// the defaults, so that the devices can be listed anew
+    fn forget_devices(&mut self) {
+        self.found_devices.clear();
+        self.ranking.clear();
+        self.by_config.clear();
+        self.pending_devices.clear();
+    }
+
+    /// Forget the module loaded for the config, once it is gone
+    fn forget_module(&mut self, config: ConfigId) {
+        self.remap_module_indices.remove(&config);
+        self.remap_module_arguments.remove(&config);
+        self.remap_masters.remove(&config);
+    }
+
+    /// Whether a device other than the remap of the config uses the name
+    fn is_name_taken(&self, name: &str, config_name: &str) -> bool {
+        let module = self.remap_module_index(config_name);
+        self.found_devices.values().any(|device| {
+            device.original_name == name
+                && (module.is_none() || device.owner_module != module)
+                && device
+                    .properties
+                    .get(REMAP_TAG_PROPERTY)
+                    .map(String::as_str)
+                    != Some(config_name)
+        })
+    }
+
+    /// The remap to load for the config, renamed if its device name is
+    /// taken and the config asks for a suffix, or None if it must not
+    /// be loaded for the name being taken
+    fn remap_to_load<'a>(
+        &self,
+        config_name: &str,
+        remap: &'a RemapConfig,
+    ) -> Option<Cow<'a, RemapConfig>> {
+        let Some(name) = &remap.device_name else {
+            return Some(Cow::Borrowed(remap));
+        };
+        if !self.is_name_taken(name, config_name) {
+            return Some(Cow::Borrowed(remap));
+        }
+        match remap.on_name_collision {
+            NameCollisionPolicy::Error | NameCollisionPolicy::Adopt => None,
+            NameCollisionPolicy::Suffix => {
+                let mut renamed = remap.clone();
+                renamed.device_name = (2..)
+                    .map(|counter| format!("{name}.{counter}"))
+                    .find(|name| !self.is_name_taken(name, config_name));
+                Some(Cow::Owned(renamed))
+            }
         }
     }
+
+    /// Recognitions from the highest priority that can be made default,
+    /// skipping silent devices and those whose playback check is pending
+    /// or has failed
+    fn ranked_candidates<'a>(
+        &'a self,
+        configs: &'a HashMap<String, DeviceConfig>,
+    ) -> impl Iterator<Item = (&'a RankedDevice, &'a AudioDevice)> + 'a {
+        self.ranking.iter().filter_map(|entry| {
+            let device = self.found_devices.get(&entry.index)?;
+            let config = configs.get(self.config_ids.name(entry.config))?;
+            (!device.silent && device.is_eligible_as(config))
+                .then_some((entry, device))
+        })
+    }
+
+    /// The candidate with the highest priority among those accepted
+    fn find_default_device<'a>(
+        &'a self,
+        configs: &'a HashMap<String, DeviceConfig>,
+        accept: impl Fn(&RankedDevice, &AudioDevice) -> bool,
+    ) -> Option<&'a RankedDevice> {
+        self.ranked_candidates(configs)
+            .find(|(entry, device)| accept(entry, device))
+            .map(|(entry, _)| entry)
+    }
+
+    fn find_by_config_name(&self, config_name: &str) -> Option<&AudioDevice> {
+        self.find_entry_by_config_name(config_name)
+            .map(|(_, device)| device)
+    }
+
+    fn find_entry_by_config_name(
+        &self,
+        config_name: &str,
+    ) -> Option<(u32, &AudioDevice)> {
+        let index = self.find_index_by_config_name(config_name)?;
+        Some((index, self.found_devices.get(&index)?))
+    }
+
+    fn find_index_by_config_name(&self, config_name: &str) -> Option<u32> {
+        let config = self.config_ids.get(config_name)?;
+        self.by_config.get(&config)?.first().copied()
+    }
 }
 
 struct AudioDeviceRoot {
@@ -64,31 +769,232 @@ impl AudioDeviceRoot {
             sources: AudioDeviceGroup::new(),
         }
     }
+
+    /// Remap and JACK modules no longer matching their configuration
+    fn outdated_modules<T: DeviceType>(
+        &self,
+        configs: &HashMap<String, DeviceConfig>,
+        jack_running: bool,
+    ) -> Vec<String> {
+        let devices = T::select(self);
+        let mut outdated = Vec::new();
+
+        for config in devices.remap_module_indices.keys() {
+            let config_name = devices.config_ids.name(*config);
+            // Reload if the parameters have changed
+            let arguments_changed = |expected: String| {
+                devices
+                    .remap_module_arguments
+                    .get(config)
+                    .is_some_and(|argument| *argument != expected)
+            };
+
+            let should_unload = match configs
+                .get(config_name)
+                .map(|config| &config.match_config)
+            {
+                Some(DeviceMatchConfig::Remap(remap)) => {
+                    match T::resolve_master(self, &remap.master) {
+                        // Replaced by another device, maybe of the same name
+                        Some((master, _))
+                            if devices
+                                .remap_masters
+                                .get(config)
+                                .is_some_and(|&loaded| loaded != master) =>
+                        {
+                            true
+                        }
+                        Some((_, master_name)) => devices
+                            .remap_to_load(config_name, remap)
+                            // Keep ours if another device took its name
+                            .is_some_and(|remap| {
+                                arguments_changed(modargs::remap(
+                                    &remap,
+                                    T::name_lower_case(),
+                                    modargs::RemapNames {
+                                        master_name,
+                                        config_name,
+                                    },
+                                ))
+                            }),
+                        None => true, // Master device no longer exists
+                    }
+                }
+                Some(DeviceMatchConfig::Jack(jack)) if jack.load => {
+                    !jack_running || arguments_changed(jack::module_args(jack))
+                }
+                Some(_) => true, // Config changed to detection only
+                None => true,    // Config removed
+            };
+
+            if should_unload {
+                outdated.push(config_name.to_string());
+            }
+        }
+
+        outdated
+    }
+
+    /// Index of the device to move the streams of each of our remap
+    /// devices to: its master, or else the initial default
+    fn eviction_targets<T: DeviceType>(
+        &self,
+        configs: &HashMap<String, DeviceConfig>,
+    ) -> HashMap<u32, u32> {
+        let scope = T::select(self);
+        let index_by_name = |name: &str| {
+            scope
+                .found_devices
+                .iter()
+                .find(|(_, device)| device.original_name == name)
+                .map(|(&index, _)| index)
+        };
+
+        let mut targets = HashMap::new();
+        for (&config, module_index) in &scope.remap_module_indices {
+            let Some(remap_index) = scope
+                .found_devices
+                .iter()
+                .find(|(_, device)| device.owner_module == Some(*module_index))
+                .map(|(&index, _)| index)
+            else {
+                continue;
+            };
+            let config_name = scope.config_ids.name(config);
+            let master =
+                match configs.get(config_name).map(|c| &c.match_config) {
+                    Some(DeviceMatchConfig::Remap(remap)) => {
+                        T::resolve_master(self, &remap.master)
+                            .map(|(_, name)| name)
+                    }
+                    _ => None,
+                };
+            let target = master
+                .or(scope.initial_default.as_deref())
+                .and_then(index_by_name)
+                .filter(|&index| index != remap_index);
+            if let Some(target) = target {
+                targets.insert(remap_index, target);
+            }
+        }
+        targets
+    }
+
+    /// Names of the devices matching no config, except monitor sources
+    fn unmanaged<T: DeviceType>(&self) -> Vec<String> {
+        let mut names: Vec<_> = T::select(self)
+            .found_devices
+            .values()
+            .filter(|device| {
+                device.recognized_as.is_empty() && !device.is_monitor()
+            })
+            .map(|device| device.original_name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Description of the devices matching no config, if there are any
+    fn unmanaged_summary(&self) -> Option<String> {
+        let groups = [
+            (Sink::name_lower_case(), self.unmanaged::<Sink>()),
+            (Source::name_lower_case(), self.unmanaged::<Source>()),
+        ];
+        let parts: Vec<_> = groups
+            .iter()
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(kind, names)| format!("{kind}s {}", names.join(", ")))
+            .collect();
+        (!parts.is_empty()).then(|| {
+            format!("Devices matching no config: {}", parts.join("; "))
+        })
+    }
+}
+
+/// What a device can do, from its sample spec and flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Capabilities {
+    channels: u8,
+    rate: u32,
+    hardware_volume: bool,
+}
+
+impl Capabilities {
+    fn meet(&self, requirements: &CapabilityRequirements) -> bool {
+        requirements
+            .min_channels
+            .is_none_or(|channels| self.channels >= channels)
+            && (requirements.sample_rates.is_empty()
+                || requirements.sample_rates.contains(&self.rate))
+            && requirements
+                .hardware_volume
+                .is_none_or(|required| self.hardware_volume == required)
+    }
 }
 
 struct DeviceInfo<'a> {
     index: u32,
     name: Option<&'a str>,
-    description: Option<&'a str>,
     proplist: &'a libpulse_binding::proplist::Proplist,
     owner_module: Option<u32>,
+    monitor_name: Option<&'a str>,
+    active_port: Option<&'a str>,
+    muted: bool,
+    running: bool,
+    suspended: bool,
+    card: Option<u32>,
+    capabilities: Capabilities,
 }
 
 trait DeviceType {
     type Info<'a>;
+    fn kind() -> DeviceKind;
     fn name_lower_case() -> &'static str;
     fn name_camel_case() -> &'static str;
+    /// Key of the device configs in the configuration
+    fn group_name() -> &'static str;
     fn module_name() -> &'static str;
+    fn jack_module_name() -> &'static str;
+    fn can_play_samples() -> bool;
+    /// Whether privacy mode covers the devices
+    fn captures_audio() -> bool;
+    fn suspend(
+        context: &mut Context,
+        index: u32,
+        suspend: bool,
+    ) -> Operation<dyn FnMut(bool)>;
+    fn restore_type() -> RestoreType;
+    fn bundle_member(bundle: &BundleConfig) -> &str;
+    fn connect_check(stream: &mut Stream, device: &str) -> Result<(), PAErr>;
+    fn peak_source(device: &AudioDevice) -> Option<&str>;
     #[allow(dead_code)]
     fn select(devices: &AudioDeviceRoot) -> &AudioDeviceGroup;
     fn select_mut(devices: &mut AudioDeviceRoot) -> &mut AudioDeviceGroup;
     fn get_definitions(config: &Config) -> &HashMap<String, DeviceConfig>;
+    fn silence_failover(config: &Config) -> Option<&SilenceFailoverConfig>;
+    fn query_all(runner: &mut StateRunner<'_>);
+    fn list_all(
+        introspector: &Introspector,
+        callback: impl for<'a, 'b> FnMut(ListResult<&'a Self::Info<'b>>) + 'static,
+    );
     fn set_default(
         context: &mut Context,
         name: &str,
         callback: impl FnMut(bool) + 'static,
     );
+    fn server_default_name<'a>(info: &'a ServerInfo<'_>) -> Option<&'a str>;
+    fn set_mute(
+        context: &mut Context,
+        index: u32,
+        muted: bool,
+    ) -> Operation<dyn FnMut(bool)>;
     fn extract_info<'a, 'b>(info: &'a Self::Info<'b>) -> DeviceInfo<'a>;
+    /// Index of the device providing the master of a remap, and the name
+    /// of the master
+    fn resolve_master<'a>(
+        devices: &'a AudioDeviceRoot,
+        master: &MasterRef,
+    ) -> Option<(u32, &'a str)>;
 }
 
 struct Sink;
@@ -96,6 +1002,10 @@ struct Sink;
 impl DeviceType for Sink {
     type Info<'a> = SinkInfo<'a>;
 
+    fn kind() -> DeviceKind {
+        DeviceKind::Sink
+    }
+
     fn name_lower_case() -> &'static str {
         "sink"
     }
@@ -104,10 +1014,58 @@ impl DeviceType for Sink {
         "Sink"
     }
 
+    fn group_name() -> &'static str {
+        "sinks"
+    }
+
     fn module_name() -> &'static str {
         "module-remap-sink"
     }
 
+    fn jack_module_name() -> &'static str {
+        "module-jack-sink"
+    }
+
+    fn can_play_samples() -> bool {
+        true
+    }
+
+    fn captures_audio() -> bool {
+        false
+    }
+
+    fn suspend(
+        context: &mut Context,
+        index: u32,
+        suspend: bool,
+    ) -> Operation<dyn FnMut(bool)> {
+        context
+            .introspect()
+            .suspend_sink_by_index(index, suspend, None)
+    }
+
+    fn restore_type() -> RestoreType {
+        RestoreType::Sink
+    }
+
+    fn bundle_member(bundle: &BundleConfig) -> &str {
+        &bundle.sink
+    }
+
+    fn connect_check(stream: &mut Stream, device: &str) -> Result<(), PAErr> {
+        stream.connect_playback(
+            Some(device),
+            None,
+            StreamFlagSet::NOFLAGS,
+            None,
+            None,
+        )
+    }
+
+    fn peak_source(device: &AudioDevice) -> Option<&str> {
+        device.monitor_name.as_deref()
+    }
+
     fn select(devices: &AudioDeviceRoot) -> &AudioDeviceGroup {
         &devices.sinks
     }
@@ -120,6 +1078,21 @@ impl DeviceType for Sink {
         &config.sinks
     }
 
+    fn silence_failover(config: &Config) -> Option<&SilenceFailoverConfig> {
+        config.silence_failover.as_ref()
+    }
+
+    fn query_all(runner: &mut StateRunner<'_>) {
+        runner.query_all_sinks();
+    }
+
+    fn list_all(
+        introspector: &Introspector,
+        callback: impl for<'a, 'b> FnMut(ListResult<&'a Self::Info<'b>>) + 'static,
+    ) {
+        let _op = introspector.get_sink_info_list(callback);
+    }
+
     fn set_default(
         context: &mut Context,
         name: &str,
@@ -128,13 +1101,61 @@ impl DeviceType for Sink {
         context.set_default_sink(name, callback);
     }
 
-    fn extract_info<'a, 'b>(info: &'a Self::Info<'b>) -> DeviceInfo<'a> {
-        DeviceInfo {
-            index: info.index,
-            name: info.name.as_deref(),
-            description: info.description.as_deref(),
-            proplist: &info.proplist,
-            owner_module: info.owner_module,
+    fn server_default_name<'a>(info: &'a ServerInfo<'_>) -> Option<&'a str> {
+        info.default_sink_name.as_deref()
+    }
+
+    fn set_mute(
+        context: &mut Context,
+        index: u32,
+        muted: bool,
+    ) -> Operation<dyn FnMut(bool)> {
+        context
+            .introspect()
+            .set_sink_mute_by_index(index, muted, None)
+    }
+
+    fn extract_info<'a, 'b>(info: &'a Self::Info<'b>) -> DeviceInfo<'a> {
+        DeviceInfo {
+            index: info.index,
+            name: info.name.as_deref(),
+            proplist: &info.proplist,
+            owner_module: info.owner_module,
+            monitor_name: info.monitor_source_name.as_deref(),
+            active_port: info
+                .active_port
+                .as_ref()
+                .and_then(|port| port.name.as_deref()),
+            muted: info.mute,
+            running: info.state == SinkState::Running,
+            suspended: info.state == SinkState::Suspended,
+            card: info.card,
+            capabilities: Capabilities {
+                channels: info.sample_spec.channels,
+                rate: info.sample_spec.rate,
+                hardware_volume: info
+                    .flags
+                    .contains(SinkFlagSet::HW_VOLUME_CTRL),
+            },
+        }
+    }
+
+    fn resolve_master<'a>(
+        devices: &'a AudioDeviceRoot,
+        master: &MasterRef,
+    ) -> Option<(u32, &'a str)> {
+        match master {
+            MasterRef::Name(name)
+            | MasterRef::Sink {
+                sink: name,
+                monitor: false,
+            } => devices
+                .sinks
+                .find_entry_by_config_name(name)
+                .map(|(index, device)| (index, device.original_name.as_str())),
+            // Rejected by config validation
+            MasterRef::Sink { monitor: true, .. }
+            | MasterRef::Source { .. } => None,
         }
     }
 }
@@ -144,6 +1165,10 @@ struct Source;
 impl DeviceType for Source {
     type Info<'a> = SourceInfo<'a>;
 
+    fn kind() -> DeviceKind {
+        DeviceKind::Source
+    }
+
     fn name_lower_case() -> &'static str {
         "source"
     }
@@ -152,10 +1177,52 @@ impl DeviceType for Source {
         "Source"
     }
 
+    fn group_name() -> &'static str {
+        "sources"
+    }
+
     fn module_name() -> &'static str {
         "module-remap-source"
     }
 
+    fn jack_module_name() -> &'static str {
+        "module-jack-source"
+    }
+
+    fn can_play_samples() -> bool {
+        false
+    }
+
+    fn captures_audio() -> bool {
+        true
+    }
+
+    fn suspend(
+        context: &mut Context,
+        index: u32,
+        suspend: bool,
+    ) -> Operation<dyn FnMut(bool)> {
+        context
+            .introspect()
+            .suspend_source_by_index(index, suspend, None)
+    }
+
+    fn restore_type() -> RestoreType {
+        RestoreType::Source
+    }
+
+    fn bundle_member(bundle: &BundleConfig) -> &str {
+        &bundle.source
+    }
+
+    fn connect_check(stream: &mut Stream, device: &str) -> Result<(), PAErr> {
+        stream.connect_record(Some(device), None, StreamFlagSet::NOFLAGS)
+    }
+
+    fn peak_source(device: &AudioDevice) -> Option<&str> {
+        Some(&device.original_name)
+    }
+
     fn select(devices: &AudioDeviceRoot) -> &AudioDeviceGroup {
         &devices.sources
     }
@@ -168,6 +1235,21 @@ impl DeviceType for Source {
         &config.sources
     }
 
+    fn silence_failover(_config: &Config) -> Option<&SilenceFailoverConfig> {
+        None
+    }
+
+    fn query_all(runner: &mut StateRunner<'_>) {
+        runner.query_all_sources();
+    }
+
+    fn list_all(
+        introspector: &Introspector,
+        callback: impl for<'a, 'b> FnMut(ListResult<&'a Self::Info<'b>>) + 'static,
+    ) {
+        let _op = introspector.get_source_info_list(callback);
+    }
+
     fn set_default(
         context: &mut Context,
         name: &str,
@@ -176,1106 +1258,6850 @@ impl DeviceType for Source {
         context.set_default_source(name, callback);
     }
 
+    fn server_default_name<'a>(info: &'a ServerInfo<'_>) -> Option<&'a str> {
+        info.default_source_name.as_deref()
+    }
+
+    fn set_mute(
+        context: &mut Context,
+        index: u32,
+        muted: bool,
+    ) -> Operation<dyn FnMut(bool)> {
+        context
+            .introspect()
+            .set_source_mute_by_index(index, muted, None)
+    }
+
     fn extract_info<'a, 'b>(info: &'a Self::Info<'b>) -> DeviceInfo<'a> {
         DeviceInfo {
             index: info.index,
             name: info.name.as_deref(),
-            description: info.description.as_deref(),
             proplist: &info.proplist,
             owner_module: info.owner_module,
+            monitor_name: None,
+            active_port: info
+                .active_port
+                .as_ref()
+                .and_then(|port| port.name.as_deref()),
+            muted: info.mute,
+            running: info.state == SourceState::Running,
+            suspended: info.state == SourceState::Suspended,
+            card: info.card,
+            capabilities: Capabilities {
+                channels: info.sample_spec.channels,
+                rate: info.sample_spec.rate,
+                hardware_volume: info
+                    .flags
+                    .contains(SourceFlagSet::HW_VOLUME_CTRL),
+            },
+        }
+    }
+
+    fn resolve_master<'a>(
+        devices: &'a AudioDeviceRoot,
+        master: &MasterRef,
+    ) -> Option<(u32, &'a str)> {
+        match master {
+            MasterRef::Name(name) | MasterRef::Source { source: name } => {
+                devices.sources.find_entry_by_config_name(name).map(
+                    |(index, device)| (index, device.original_name.as_str()),
+                )
+            }
+            // Identified by the index of the sink, as monitors come and go
+            // with it
+            MasterRef::Sink { sink, .. } => devices
+                .sinks
+                .find_entry_by_config_name(sink)
+                .and_then(|(index, device)| {
+                    Some((index, device.monitor_name.as_deref()?))
+                }),
         }
     }
 }
 
+/// A config along with its name
+struct NamedConfig<'a> {
+    name: &'a str,
+    config: &'a DeviceConfig,
+}
+
+/// What devices of one type are matched against
+struct MatchEnvironment<'a> {
+    configs: &'a HashMap<String, DeviceConfig>,
+    config_ids: &'a ConfigIds,
+    remap_module_indices: &'a HashMap<ConfigId, u32>,
+    quirks: Quirks,
+    multi_match: MultiMatchPolicy,
+}
+
 struct DeviceMatchContext<'a> {
     device_config: &'a DeviceConfig,
-    proplist: &'a libpulse_binding::proplist::Proplist,
-    owner_module: Option<u32>,
-    remap_module_indices: &'a HashMap<String, u32>,
     config_name: &'a str,
+    device_name: &'a str,
+    properties: &'a BTreeMap<String, String>,
+    capabilities: &'a Capabilities,
+    runtime: (bool, DeviceRunState), // Muted and run state
+    owner_module: Option<u32>,
+    remap_module: Option<u32>, // Loaded by us for the config
+    match_remaps_by_name: bool,
 }
 
 fn check_device_match(context: &DeviceMatchContext<'_>) -> bool {
-    match &context.device_config.match_config {
-        DeviceMatchConfig::Detect(detect) => {
-            for (key, expected_value) in detect {
-                if let Some(actual_value) = context.proplist.get_str(key) {
-                    if &actual_value != expected_value {
-                        return false;
-                    }
-                } else {
-                    return false;
-                }
-            }
-            true
+    if let Some(requirements) = &context.device_config.requires
+        && !context.capabilities.meet(requirements)
+    {
+        return false;
+    }
+    if let Some(condition) = &context.device_config.when {
+        let (muted, run_state) = context.runtime;
+        if condition.muted.is_some_and(|expected| muted != expected)
+            || condition
+                .state
+                .is_some_and(|expected| run_state != expected)
+        {
+            return false;
         }
-        DeviceMatchConfig::Remap(_) => {
+    }
+    match &context.device_config.match_config {
+        DeviceMatchConfig::Remap(remap) => {
             // Check if this device is created by our remap module
-            match (
-                context.owner_module,
-                context.remap_module_indices.get(context.config_name),
-            ) {
-                (Some(owner), Some(&module)) => owner == module,
-                _ => false,
+            let Some(module) = context.remap_module else {
+                // Or taken over in place of loading one
+                return remap.on_name_collision == NameCollisionPolicy::Adopt
+                    && remap.device_name.as_deref()
+                        == Some(context.device_name);
+            };
+            if context.owner_module == Some(module) {
+                return true;
+            }
+            // pipewire-pulse reports no or other owner modules
+            if (context.owner_module.is_none() || context.match_remaps_by_name)
+                && context
+                    .properties
+                    .get(REMAP_TAG_PROPERTY)
+                    .map(String::as_str)
+                    == Some(context.config_name)
+            {
+                return true;
             }
+            context.match_remaps_by_name
+                && remap.device_name.as_deref() == Some(context.device_name)
         }
+        _ => context
+            .device_config
+            .matches_properties(context.device_name, context.properties)
+            .unwrap_or(false),
     }
 }
 
-pub struct State {
-    context: Context,
-    config: Config,
-    all_devices: AudioDeviceRoot,
-    shutting_down: bool,
-    num_pending_unloads: u32,
+/// Device name of a remap being taken by another device
+struct NameCollision<'a> {
+    config_name: &'a str,
+    /// The remap as configured
+    remap: &'a RemapConfig,
+    /// The remap as it is going to be loaded, if at all
+    to_load: Option<&'a RemapConfig>,
 }
 
-impl State {
-    fn new(context: Context, config: Config) -> Self {
-        Self {
-            context,
-            config,
-            all_devices: AudioDeviceRoot::new(),
-            shutting_down: false,
-            num_pending_unloads: 0,
+impl NameCollision<'_> {
+    /// Tell what is done about it
+    fn report<T: DeviceType>(&self) {
+        let config_name = self.config_name;
+        let name = self.remap.device_name.as_deref().unwrap_or_default();
+        match (self.remap.on_name_collision, self.to_load) {
+            (NameCollisionPolicy::Adopt, _) => info!(
+                "Adopting the existing {} '{}' as '{}'",
+                T::name_lower_case(),
+                name,
+                config_name
+            ),
+            (_, Some(renamed)) => warn!(
+                "{} name '{}' is taken, loading '{}' as '{}'",
+                T::name_camel_case(),
+                name,
+                config_name,
+                renamed.device_name.as_deref().unwrap_or_default()
+            ),
+            (_, None) => warn!(
+                "Not loading '{}', {} name '{}' is taken by another {}",
+                config_name,
+                T::name_lower_case(),
+                name,
+                T::name_lower_case()
+            ),
         }
     }
+}
 
-    fn add_device<'a, 'b, T>(&mut self, info: &'a T::Info<'b>) -> usize
-    where
-        T: DeviceType,
-    {
-        let device_info = T::extract_info(info);
-        let configs = T::get_definitions(&self.config);
+/// Whether any config asks for loading JACK bridges
+fn has_managed_jack(config: &Config) -> bool {
+    config.sinks.values().chain(config.sources.values()).any(|device| {
+        matches!(&device.match_config, DeviceMatchConfig::Jack(jack) if jack.load)
+    })
+}
 
-        let AudioDeviceGroup {
-            found_devices: devices,
-            remap_module_indices,
-            ..
-        } = T::select_mut(&mut self.all_devices);
+/// Whether any config has modules loaded, by remapping or JACK bridging
+fn loads_modules(config: &Config) -> bool {
+    config.sinks.values().chain(config.sources.values()).any(|device| {
+        matches!(&device.match_config, DeviceMatchConfig::Remap(_))
+            || matches!(&device.match_config, DeviceMatchConfig::Jack(jack) if jack.load)
+    })
+}
 
-        let mut device = AudioDevice {
-            original_name: device_info
-                .name
-                .map(|s| s.to_string())
-                .unwrap_or_default(),
-            recognized_as: Vec::new(),
-        };
+/// Whether a failed module load means the server denies every one, as
+/// with allow-module-loading=no
+fn denies_module_loading(err: PAErr) -> bool {
+    err.0.abs() == Code::Access as i32
+}
 
-        info!(
-            "Found {} #{}, name = {}, description = {}",
-            T::name_lower_case(),
-            device_info.index,
-            device.original_name,
-            device_info.description.unwrap_or_default()
-        );
+/// What to do when someone else unloads the module loaded for the config
+fn external_unload_policy(config: &DeviceConfig) -> ExternalUnloadPolicy {
+    match &config.match_config {
+        DeviceMatchConfig::Remap(remap) => remap.on_external_unload,
+        DeviceMatchConfig::Jack(jack) => jack.on_external_unload,
+        _ => ExternalUnloadPolicy::Reload,
+    }
+}
+
+/// Event facilities the config needs to be notified of
+fn subscription_interests(config: &Config) -> InterestMaskSet {
+    let mut interests = InterestMaskSet::NULL;
+
+    let has_sink_monitor_master = config.sources.values().any(|device| {
+        matches!(
+            &device.match_config,
+            DeviceMatchConfig::Remap(RemapConfig {
+                master: MasterRef::Sink { .. },
+                ..
+            })
+        )
+    });
+    if !config.sinks.is_empty() || has_sink_monitor_master {
+        interests |= InterestMaskSet::SINK;
+    }
+    if !config.sources.is_empty() {
+        interests |= InterestMaskSet::SOURCE;
+    }
+    if config.session_manager_policy != SessionManagerPolicy::Override {
+        interests |= InterestMaskSet::CLIENT;
+    }
+    // Recordings from managed sources are reported to the hook and to the
+    // event subscribers of the control interfaces
+    #[cfg(feature = "mqtt")]
+    let has_mqtt = config.mqtt.is_some();
+    #[cfg(not(feature = "mqtt"))]
+    let has_mqtt = false;
+    let reports_recordings = !config.sources.is_empty()
+        && (config.recording_hook.is_some()
+            || config.http.is_some()
+            || has_mqtt);
+    if reports_recordings || config.headset_profile_switching.is_some() {
+        interests |= InterestMaskSet::SOURCE_OUTPUT;
+    }
+    if config.notifications.is_some() {
+        interests |= InterestMaskSet::SINK_INPUT;
+    }
+    // Modules of ours may be unloaded by others
+    if loads_modules(config) {
+        interests |= InterestMaskSet::MODULE;
+    }
 
-        for (name, device_config) in configs {
-            let match_context = DeviceMatchContext {
-                device_config,
-                proplist: device_info.proplist,
-                owner_module: device_info.owner_module,
-                remap_module_indices,
-                config_name: name,
+    interests
+}
+
+/// Profile of a Bluetooth card to use while recording: the configured one,
+/// or the available headset or handsfree profile with the highest priority
+fn choose_headset_profile(
+    card: &CardInfo<'_>,
+    config: &HeadsetProfileConfig,
+) -> Option<String> {
+    card.profiles
+        .iter()
+        .filter(|profile| profile.available)
+        .filter_map(|profile| {
+            let name = profile.name.as_deref()?;
+            let wanted = match &config.headset_profile {
+                Some(wanted) => name == wanted,
+                None => {
+                    profile.n_sources > 0
+                        && (name.starts_with("headset")
+                            || name.starts_with("handsfree"))
+                }
             };
+            wanted.then_some((profile.priority, name))
+        })
+        .max_by_key(|&(priority, _)| priority)
+        .map(|(_, name)| name.to_string())
+}
 
-            if check_device_match(&match_context) {
-                info!(
-                    "{} #{} is recognized as '{}'",
-                    T::name_camel_case(),
-                    device_info.index,
-                    name
-                );
-                device.recognized_as.push(name.clone());
-            }
+/// Run the recording hook without blocking the main loop
+fn run_recording_hook(command: &str, recording: bool) {
+    let child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("AUTOPULSED_RECORDING", if recording { "1" } else { "0" })
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            std::thread::spawn(move || {
+                let _ = child.wait();
+            });
         }
-
-        let match_count = device.recognized_as.len();
-        devices.insert(device_info.index, device);
-        match_count
+        Err(e) => warn!("Failed to run the recording hook: {e}"),
     }
+}
 
-    fn remove_device<T>(&mut self, index: u32)
-    where
-        T: DeviceType,
-    {
-        let devices = &mut T::select_mut(&mut self.all_devices).found_devices;
+/// Sources muted and suspended by privacy mode
+#[derive(Default)]
+struct Privacy {
+    suspend: bool,
+    muted: HashSet<u32>, // Unmuted again when privacy mode ends
+    suspended: HashSet<u32>, // Suspended once
+}
 
-        if devices.remove(&index).is_some() {
-            info!("Lost {} #{}", T::name_lower_case(), index);
+/// What privacy mode changes on a source
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PrivacyChange {
+    mute: bool,
+    suspend: bool,
+}
+
+/// Sources to change back when privacy mode ends
+#[derive(Debug, Default, PartialEq, Eq)]
+struct PrivacyRelease {
+    unmute: Vec<u32>,
+    resume: Vec<u32>,
+}
+
+impl Privacy {
+    /// Keep the source muted, and suspended if requested, returning what
+    /// has to be changed on the server
+    fn enforce(
+        &mut self,
+        index: u32,
+        device: &mut AudioDevice,
+    ) -> PrivacyChange {
+        let mute = !device.muted;
+        if mute {
+            device.muted = true;
+            self.muted.insert(index);
+        }
+        PrivacyChange {
+            mute,
+            suspend: self.suspend && self.suspended.insert(index),
         }
     }
 
-    fn find_default_device<'a>(
-        devices: &'a HashMap<u32, AudioDevice>,
-        configs: &'a HashMap<String, DeviceConfig>,
-    ) -> Option<(&'a String, u32)> {
-        devices
-            .iter()
-            .flat_map(|(&device_index, device)| {
-                device.recognized_as.iter().filter_map(move |config_name| {
-                    configs
-                        .get(config_name)
-                        .and_then(|config| config.priority)
-                        .map(|priority| (device_index, config_name, priority))
-                })
-            })
-            .min_by_key(|&(_, _, priority)| priority)
-            .map(|(index, config_name, _)| (config_name, index))
+    /// End privacy mode, unmuting the sources it has muted that are still
+    /// present and resuming every source if it has suspended them
+    fn release(
+        self,
+        sources: &mut HashMap<u32, AudioDevice>,
+    ) -> PrivacyRelease {
+        let mut unmute: Vec<u32> = self
+            .muted
+            .into_iter()
+            .filter(|index| sources.contains_key(index))
+            .collect();
+        unmute.sort_unstable();
+        for index in &unmute {
+            if let Some(device) = sources.get_mut(index) {
+                device.muted = false;
+            }
+        }
+        let mut resume = Vec::new();
+        if self.suspend {
+            resume.extend(sources.keys().copied());
+            resume.sort_unstable();
+        }
+        PrivacyRelease { unmute, resume }
     }
+}
 
-    fn handle_set_default_result<T>(
+/// Remap module of ours to unload on shutdown
+#[derive(Clone)]
+struct LoadedRemap {
+    kind: &'static str,
+    group: &'static str,
+    config_name: String,
+    module_index: u32,
+}
+
+/// Remap modules to unload on shutdown, torn down from the end of chains
+#[derive(Default)]
+struct ShutdownUnloads {
+    queued: Vec<LoadedRemap>, // Waiting for their users to go
+    in_flight: Vec<LoadedRemap>, // Unloading, still using their masters
+}
+
+impl ShutdownUnloads {
+    fn is_empty(&self) -> bool {
+        self.queued.is_empty() && self.in_flight.is_empty()
+    }
+
+    /// Take the queued modules that no queued or unloading module uses as
+    /// master, marking them as unloading
+    fn take_leaves<'a>(
         &mut self,
-        device_index: u32,
-        success: bool,
-    ) where
-        T: DeviceType,
-    {
-        let state = T::select_mut(&mut self.all_devices);
-        if success {
-            info!(
-                "Successfully set default {} to #{}",
-                T::name_lower_case(),
-                device_index
-            );
-            if let Some(callback) = state.pending_default_callback.take() {
-                // Target changed during execution, retry with current target
-                let new_device_index = state.pending_default_index.unwrap();
-                if let Some(new_device) =
-                    state.found_devices.get(&new_device_index)
-                {
-                    debug!(
-                        "Setting default {} to #{}",
-                        T::name_lower_case(),
-                        new_device_index
-                    );
-                    T::set_default(
-                        &mut self.context,
-                        &new_device.original_name,
-                        callback,
-                    );
-                }
-            } else {
-                // No pending operation, clear the index
-                state.pending_default_index = None;
-            }
+        master_of: impl Fn(&LoadedRemap) -> Option<(&'a str, &'a str)>,
+    ) -> Vec<LoadedRemap> {
+        let is_master = |module: &LoadedRemap| {
+            self.queued.iter().chain(&self.in_flight).any(|other| {
+                master_of(other)
+                    == Some((module.group, module.config_name.as_str()))
+            })
+        };
+        let (mut leaves, rest): (Vec<_>, Vec<_>) = self
+            .queued
+            .iter()
+            .cloned()
+            .partition(|module| !is_master(module));
+        if leaves.is_empty() && self.in_flight.is_empty() {
+            // Only possible with a cycle, which validation rejects
+            leaves = rest;
+            self.queued.clear();
         } else {
-            error!("Failed to set default {}", T::name_lower_case());
-            state.pending_default_callback = None;
+            self.queued = rest;
         }
+        self.in_flight.extend(leaves.iter().cloned());
+        leaves
     }
 
-    pub fn from_context(
-        context: Context,
-        config: Config,
-    ) -> Rc<RefCell<Self>> {
-        Rc::new(RefCell::new(Self::new(context, config)))
+    /// Forget a module that has finished unloading, successfully or not
+    fn complete(&mut self, module_index: u32) {
+        self.in_flight
+            .retain(|module| module.module_index != module_index);
     }
 }
 
-pub struct StateRunner<'scope> {
-    origin: Rc<RefCell<State>>,
-    state: &'scope mut State,
+/// Bluetooth card switched to a headset profile while something records
+#[derive(Debug, PartialEq, Eq)]
+struct HeadsetSwitch {
+    card: u32,
+    previous_profile: String,
 }
 
-struct RemapModuleParams<'a> {
-    config_name: &'a str,
-    remap_config: &'a crate::config::RemapConfig,
-    master_index: u32,
+/// Recordings of other clients, and the card switched for them
+#[derive(Default)]
+struct HeadsetRecordings {
+    streams: HashSet<u32>, // Source outputs of other clients
+    switch: Option<HeadsetSwitch>,
 }
 
-impl<'scope> StateRunner<'scope> {
-    fn update_default_device<T: DeviceType>(&mut self) {
-        let State {
-            all_devices: devices,
-            context,
-            ..
-        } = self.state;
-        let scope = T::select_mut(devices);
-        let default_device = State::find_default_device(
-            &scope.found_devices,
-            T::get_definitions(&self.state.config),
-        );
+impl HeadsetRecordings {
+    /// Count the recording in, returning whether it is the first one and
+    /// the card of the default sink is to be switched for it
+    fn start(&mut self, index: u32, paused: bool) -> bool {
+        let first = self.streams.is_empty();
+        self.streams.insert(index);
+        first && self.switch.is_none() && !paused
+    }
 
-        if let Some((config_name, device_index)) = default_device {
-            let weak_origin = Rc::downgrade(&self.origin);
-            let callback = move |success: bool| {
-                if let Some(origin) = weak_origin.upgrade() {
-                    StateRunner::with(&origin, |runner| {
-                        runner.state.handle_set_default_result::<T>(
-                            device_index,
-                            success,
-                        );
-                    });
-                }
-            };
+    /// Whether a card is still to be switched, as recording may have
+    /// stopped while the card was queried
+    fn wants_switch(&self) -> bool {
+        !self.streams.is_empty() && self.switch.is_none()
+    }
 
-            info!(
-                "Using {} '{}' as default",
-                T::name_lower_case(),
-                config_name
-            );
-            let pending = scope.pending_default_index.is_some();
-            scope.pending_default_index = Some(device_index);
-            if pending {
-                debug!(
-                    "Default {} is being changed... deferring setting",
-                    T::name_lower_case()
-                );
-                scope.pending_default_callback = Some(Box::new(callback));
-            } else if let Some(device) = scope.found_devices.get(&device_index)
-            {
-                debug!(
-                    "Setting default {} to #{}",
-                    T::name_lower_case(),
-                    device_index
-                );
-                T::set_default(context, &device.original_name, callback);
-            }
-        } else {
-            scope.pending_default_index = None;
-            scope.pending_default_callback = None;
+    /// Count the recording out, returning the card to switch back once the
+    /// last recording has stopped
+    fn stop(&mut self, index: u32) -> Option<HeadsetSwitch> {
+        if !self.streams.remove(&index) || !self.streams.is_empty() {
+            return None;
         }
+        self.switch.take()
     }
+}
 
-    fn make_device_callback<T: DeviceType>(
-        &self,
-    ) -> impl for<'a, 'b> FnMut(ListResult<&'a T::Info<'b>>) + 'static {
-        let weak_origin = Rc::downgrade(&self.origin);
-        let mut should_update = false;
-        move |list_result| {
-            if let Some(origin) = weak_origin.upgrade() {
-                match list_result {
-                    ListResult::Item(info) => {
-                        StateRunner::with(&origin, |runner| {
-                            let match_count =
-                                runner.state.add_device::<T>(info);
-                            should_update = should_update || match_count > 0;
-                        });
-                    }
-                    ListResult::End => {
-                        debug!(
-                            "Finished loading list result for {}s",
-                            T::name_lower_case()
-                        );
-                        if should_update {
-                            StateRunner::with(&origin, |runner| {
-                                runner.update_default_device::<T>();
-                                runner.check_and_load_remaps::<T>();
-                            });
-                        }
-                    }
-                    ListResult::Error => {
-                        error!(
-                            "Error loading list result for {}s",
-                            T::name_lower_case()
-                        );
-                    }
-                }
-            }
-        }
-    }
-
-    fn query_all_sinks(&mut self) {
-        let callback = self.make_device_callback::<Sink>();
-        let _op = self.state.context.introspect().get_sink_info_list(callback);
-    }
+/// Source recorded from by another client, and its config
+#[derive(Debug, PartialEq)]
+struct Capture {
+    source: u32,
+    config: String,
+}
 
-    fn query_sink_by_index(&mut self, index: u32) {
-        let callback = self.make_device_callback::<Sink>();
-        let _op = self
-            .state
-            .context
-            .introspect()
-            .get_sink_info_by_index(index, callback);
-    }
+/// Recordings of other clients from managed sources
+#[derive(Default)]
+struct CapturingStreams {
+    streams: HashMap<u32, Capture>,
+}
 
-    fn query_all_sources(&mut self) {
-        let callback = self.make_device_callback::<Source>();
-        let _op = self
-            .state
-            .context
-            .introspect()
-            .get_source_info_list(callback);
+impl CapturingStreams {
+    /// Count the recording in, returning whether it is the first one
+    fn start(&mut self, index: u32, capture: Capture) -> bool {
+        let first = self.streams.is_empty();
+        self.streams.insert(index, capture);
+        first
     }
 
-    fn query_source_by_index(&mut self, index: u32) {
-        let callback = self.make_device_callback::<Source>();
-        let _op = self
-            .state
-            .context
-            .introspect()
-            .get_source_info_by_index(index, callback);
+    /// Count the recording out, if it was counted in
+    fn stop(&mut self, index: u32) -> Option<Capture> {
+        self.streams.remove(&index)
     }
 
-    fn handle_device_removed<T: DeviceType>(&mut self, index: u32) {
-        self.state.remove_device::<T>(index);
-        self.update_default_device::<T>();
-        self.check_and_unload_remaps::<T>();
+    fn is_empty(&self) -> bool {
+        self.streams.is_empty()
     }
+}
 
-    fn subscribe_to_events(
-        &mut self,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let interests =
-            libpulse_binding::context::subscribe::InterestMaskSet::SINK
-                | libpulse_binding::context::subscribe::InterestMaskSet::SOURCE;
+/// Last change of a default device between two configs, for undoing it
+struct DefaultSwitch {
+    kind: DeviceKind,
+    from: String,
+    to: String,
+}
 
-        let weak_origin = Rc::downgrade(&self.origin);
-        let _op = self.state.context.subscribe(interests, move |success| {
-            if success {
-                info!("Successfully subscribed to PulseAudio events");
-                if let Some(origin) = weak_origin.upgrade() {
-                    StateRunner::with(&origin, |runner| {
-                        runner.query_all_sinks();
-                        runner.query_all_sources();
-                    });
-                }
-            } else {
-                error!("Failed to subscribe to PulseAudio events");
-            }
-        });
+/// Upload of the test sound to the sample cache of the server
+struct SampleUpload {
+    stream: Stream,
+    data: Vec<u8>,
+}
 
-        Ok(())
-    }
+/// Record stream following the peak level of a default device
+struct PeakMonitor {
+    stream: Stream,
+    device_index: u32,
+    level: f32,
+    silent_since: Option<Instant>, // While running, for silence failover
+}
 
-    fn on_context_state_changed(
-        &mut self,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let context_state = self.state.context.get_state();
+/// Playback of a generated tone for the test subcommand
+struct TestTone {
+    stream: Stream,
+    data: Vec<u8>,
+    result: Option<Result<(), String>>,
+}
 
-        debug!("The context state is <{context_state:?}> now");
+/// Number of server events handled per period, for the status
+struct EventRate {
+    window_start: Instant,
+    count: u32, // Events since the start of the window
+    last: u32,  // Events in the previous window
+}
 
-        if context_state == libpulse_binding::context::State::Ready {
-            info!("Connected to PulseAudio server");
-            self.subscribe_to_events()?;
+impl EventRate {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            count: 0,
+            last: 0,
         }
-
-        Ok(())
     }
 
-    pub fn connect(
-        &mut self,
-        server: Option<&str>,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let context = &mut self.state.context;
-
-        // Since callbacks will be called within pa_context_connect(),
-        // we call connect() before registering callbacks to prevent race
-        // condition.
-        context
-            .connect(
-                server,
-                libpulse_binding::context::FlagSet::NOAUTOSPAWN,
-                None,
-            )
-            .map_err(|e| {
-                format!(
-                    "Failed to connect to PulseAudio server{}: {}",
-                    server.map_or(String::new(), |s| format!(" at '{s}'")),
-                    e
-                )
-            })?;
+    fn record(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= EVENT_RATE_WINDOW {
+            self.last = self.per_window(now);
+            self.window_start = now;
+            self.count = 0;
+        }
+        self.count += 1;
+    }
 
-        let weak_origin = Rc::downgrade(&self.origin);
-        context.set_state_callback(Some(Box::new(move || {
-            if let Some(origin) = weak_origin.upgrade() {
-                StateRunner::with(&origin, |runner| {
-                    if let Err(e) = runner.on_context_state_changed() {
-                        error!("Failed to handle state change: {e}");
-                    }
-                });
-            }
-        })));
+    /// Events in the last complete window
+    fn per_window(&self, now: Instant) -> u32 {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= EVENT_RATE_WINDOW * 2 {
+            0 // No events for a whole window since
+        } else if elapsed >= EVENT_RATE_WINDOW {
+            self.count
+        } else {
+            self.last
+        }
+    }
+}
 
-        let weak_origin = Rc::downgrade(&self.origin);
-        context.set_subscribe_callback(Some(Box::new(move |facility, operation, index| {
-            if let Some(origin) = weak_origin.upgrade() {
-                StateRunner::with(&origin, |runner| match facility {
-                    Some(libpulse_binding::context::subscribe::Facility::Sink) => match operation {
-                        Some(libpulse_binding::context::subscribe::Operation::New) => {
-                            debug!("Got notified by new sink #{index}");
-                            runner.query_sink_by_index(index);
-                        }
-                        Some(libpulse_binding::context::subscribe::Operation::Removed) => {
-                            debug!("Got notified by removed sink #{index}");
-                            runner.handle_device_removed::<Sink>(index);
-                        }
-                        Some(libpulse_binding::context::subscribe::Operation::Changed) => {
-                            debug!("Got notified by changed sink #{index}");
-                        }
-                        _ => {}
-                    },
-                    Some(libpulse_binding::context::subscribe::Facility::Source) => match operation
-                    {
-                        Some(libpulse_binding::context::subscribe::Operation::New) => {
-                            debug!("Got notified by new source #{index}");
-                            runner.query_source_by_index(index);
-                        }
-                        Some(libpulse_binding::context::subscribe::Operation::Removed) => {
-                            debug!("Got notified by removed source #{index}");
-                            runner.handle_device_removed::<Source>(index);
-                        }
-                        Some(libpulse_binding::context::subscribe::Operation::Changed) => {
-                            debug!("Got notified by changed source #{index}");
-                        }
-                        _ => {}
-                    },
-                    _ => {}
-                });
-            }
-        })));
+pub struct State {
+    context: Context,
+    config: Config,
+    all_devices: AudioDeviceRoot,
+    shutting_down: bool,
+    num_pending_restores: u32,
+    num_pending_evictions: u32,
+    shutdown_unloads: ShutdownUnloads,
+    timers: TimerQueue<TimerCallback>,
+    interests: InterestMaskSet,
+    module_loading_disabled: bool,
+    sandboxed: bool, // Running in a Flatpak sandbox
+    server: Option<ServerDetails>,
+    session_manager_clients: HashSet<u32>,
+    session_manager_names: Option<Vec<String>>, // Listed once, for diagnostics
+    recordings: HeadsetRecordings,
+    capturing: CapturingStreams,
+    last_switch: Option<DefaultSwitch>,
+    jack_running: bool,
+    paused: bool,    // Set by a command to stop making changes
+    observing: bool, // Paused for good, reporting what would be done
+    privacy: Option<Privacy>,
+    events: EventFeed,
+    sample_upload: Option<SampleUpload>,
+    test_sound_ready: bool, // The sample is in the cache of the server
+    test_tone: Option<TestTone>,
+    pending_actions: Vec<Command>, // From on_appear/on_disappear
+    pending_events: VecDeque<ServerEvent>,
+    policies: Vec<Box<dyn Policy>>,
+    actions: ActionExecutor, // Changes in flight on the server
+    journal: Option<Journal>,
+    event_rate: EventRate,
+    drift_repairs: u64, // Differences found by reconciliation
+    resync_pending: bool, // Re-apply everything once reconciled
+    dropped_properties: u64, // Over limits.device_properties
+    activity: Activity, // For the summary on exit
+    overrides: Overrides, // As last saved or restored
+    statistics: Option<statistics::Recorder>,
+}
 
-        self.on_context_state_changed()?;
-        Ok(())
+impl State {
+    fn new(context: Context, config: Config, events: EventFeed) -> Self {
+        let mut all_devices = AudioDeviceRoot::new();
+        all_devices.sinks.intern_configs(&config.sinks);
+        all_devices.sources.intern_configs(&config.sources);
+        Self {
+            context,
+            config,
+            all_devices,
+            shutting_down: false,
+            num_pending_restores: 0,
+            num_pending_evictions: 0,
+            shutdown_unloads: ShutdownUnloads::default(),
+            timers: TimerQueue::new(),
+            interests: InterestMaskSet::NULL,
+            module_loading_disabled: false,
+            sandboxed: false,
+            server: None,
+            session_manager_clients: HashSet::new(),
+            session_manager_names: None,
+            recordings: HeadsetRecordings::default(),
+            capturing: CapturingStreams::default(),
+            last_switch: None,
+            jack_running: false,
+            paused: false,
+            observing: false,
+            privacy: None,
+            events,
+            sample_upload: None,
+            test_sound_ready: false,
+            test_tone: None,
+            pending_actions: Vec::new(),
+            pending_events: VecDeque::new(),
+            policies: policy::built_in(),
+            actions: ActionExecutor::default(),
+            journal: None,
+            event_rate: EventRate::new(Instant::now()),
+            drift_repairs: 0,
+            resync_pending: false,
+            dropped_properties: 0,
+            activity: Activity::default(),
+            overrides: Overrides::default(),
+            statistics: None,
+        }
     }
 
-    fn has_device_with_config_name(
-        devices: &AudioDeviceGroup,
-        config_name: &str,
-    ) -> bool {
-        // TODO: O(N) search could be problematic in environments with many devices.
-        // Consider adding reverse index: HashMap<String, Vec<u32>> for config_name -> device_indices
-        devices.found_devices.iter().any(|(_, device)| {
-            device.recognized_as.iter().any(|name| name == config_name)
-        })
+    /// Apply the configured limits to the journal and the event feed
+    fn apply_limits(&mut self) {
+        let limits = self.config.limits.clone().unwrap_or_default();
+        if let Some(journal) = &mut self.journal {
+            journal.set_max_entries(
+                limits
+                    .journal_entries
+                    .unwrap_or(journal::DEFAULT_MAX_ENTRIES),
+            );
+        }
+        self.events.set_backlog(
+            limits.event_backlog.unwrap_or(events::DEFAULT_BACKLOG),
+        );
     }
 
-    fn find_device_index_by_config_name(
-        devices: &AudioDeviceGroup,
-        config_name: &str,
-    ) -> Option<u32> {
-        // TODO: O(N) search could be problematic in environments with many devices.
-        // Consider adding reverse index: HashMap<String, Vec<u32>> for config_name -> device_indices
-        devices
-            .found_devices
-            .iter()
-            .find(|(_, device)| {
-                device.recognized_as.iter().any(|name| name == config_name)
-            })
-            .map(|(&index, _)| index)
+    /// Bound the properties of a device snapshot to the configured limit,
+    /// keeping those that configs or the daemon refer to
+    fn trim_properties(&self, device: &mut AudioDevice) -> usize {
+        let limit = self.config.limits.as_ref().map_or_else(
+            || LimitsConfig::default().device_properties(),
+            |limits| limits.device_properties(),
+        );
+        let mut kept = self.config.referenced_properties();
+        kept.extend(INTERNAL_PROPERTIES);
+        PropertyBudget { limit, kept: &kept }.trim(&mut device.properties)
     }
 
-    fn build_remap_module_args<T: DeviceType>(
-        remap_config: &crate::config::RemapConfig,
-        master_name: &str,
-    ) -> String {
-        let mut args = Vec::new();
-        args.push(format!("master={master_name}"));
-
-        if let Some(device_name) = &remap_config.device_name {
-            args.push(format!(
-                "{}_name={}",
-                T::name_lower_case(),
-                device_name
-            ));
+    fn memory_report(&self) -> MemoryReport {
+        let groups = [&self.all_devices.sinks, &self.all_devices.sources];
+        let devices = groups.iter().flat_map(|group| {
+            group.found_devices.values().chain(
+                group.pending_devices.iter().map(|pending| &pending.device),
+            )
+        });
+        MemoryReport {
+            resident_bytes: resident_bytes(),
+            cached_devices: devices.clone().count(),
+            cached_property_bytes: devices
+                .flat_map(|device| &device.properties)
+                .map(|(key, value)| key.len() + value.len())
+                .sum(),
+            dropped_properties: self.dropped_properties,
+            dropped_events: self.events.dropped(),
         }
+    }
 
-        if let Some(device_properties) = &remap_config.device_properties {
-            // Convert HashMap to PulseAudio property string format
-            let props = device_properties
-                .iter()
-                .map(|(k, v)| {
-                    // Escape single quotes in values
-                    let escaped_value = v.replace("'", "'\\''\\'");
-                    format!("{k}='{escaped_value}'")
-                })
-                .collect::<Vec<_>>()
-                .join(" ");
-
-            args.push(format!(
-                "{}_properties=\"{}\"",
-                T::name_lower_case(),
-                props
-            ));
+    fn session_manager_policy(&self) -> SessionManagerPolicy {
+        if self.session_manager_clients.is_empty() {
+            SessionManagerPolicy::Override
+        } else {
+            self.config.session_manager_policy
         }
+    }
 
-        if let Some(format) = &remap_config.format {
-            args.push(format!("format={format}"));
-        }
+    fn quirks(&self) -> Quirks {
+        let detected = self.server.as_ref().map(|server| server.flavor);
+        Quirks::resolve(detected, &self.config.server_quirks)
+    }
 
-        if let Some(rate) = remap_config.rate {
-            args.push(format!("rate={rate}"));
+    /// Owned copy of what is known about the device from its info, not
+    /// yet matched against the configs
+    fn snapshot_device<T: DeviceType>(info: &T::Info<'_>) -> IndexedDevice {
+        let device_info = T::extract_info(info);
+        let properties = snapshot_proplist(device_info.proplist);
+        #[cfg(feature = "udev")]
+        let properties = crate::udev::enrich(properties);
+        let device = AudioDevice {
+            original_name: device_info
+                .name
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            monitor_name: device_info.monitor_name.map(|s| s.to_string()),
+            owner_module: device_info.owner_module,
+            active_port: device_info.active_port.map(|s| s.to_string()),
+            muted: device_info.muted,
+            running: device_info.running,
+            suspended: device_info.suspended,
+            silent: false,
+            playback_verified: None,
+            card: device_info.card,
+            properties,
+            capabilities: device_info.capabilities,
+            recognized_as: Vec::new(),
+            match_scores: BTreeMap::new(),
+        };
+        IndexedDevice {
+            index: device_info.index,
+            device,
         }
+    }
 
-        if let Some(channels) = remap_config.channels {
-            args.push(format!("channels={channels}"));
+    /// Returns whether the device is or was recognized by any config, in
+    /// which case defaults and remaps need to be re-evaluated
+    fn add_device<T>(
+        &mut self,
+        IndexedDevice { index, mut device }: IndexedDevice,
+    ) -> bool
+    where
+        T: DeviceType,
+    {
+        self.dropped_properties += self.trim_properties(&mut device) as u64;
+        let configs = T::get_definitions(&self.config);
+        let quirks = self.quirks();
+        let state_matters =
+            configs.values().any(|config| config.when.is_some());
+
+        let group = T::select_mut(&mut self.all_devices);
+        // A snapshot still waiting to be matched is older than this one
+        group
+            .pending_devices
+            .retain(|pending| pending.index != index);
+
+        if let Some(known) = group.found_devices.get_mut(&index)
+            && known.has_same_snapshot(&device)
+        {
+            known.muted = device.muted;
+            known.running = device.running;
+            known.suspended = device.suspended;
+            // Unless recognitions depend on the mute or run state
+            let unchanged = !state_matters || {
+                let names = known.recognize(&MatchEnvironment {
+                    configs,
+                    config_ids: &group.config_ids,
+                    remap_module_indices: &group.remap_module_indices,
+                    quirks,
+                    multi_match: self.config.multi_match,
+                });
+                names
+                    .iter()
+                    .filter_map(|name| group.config_ids.get(name))
+                    .eq(known.recognized_as.iter().copied())
+            };
+            if unchanged {
+                debug!(
+                    "{} #{} has no relevant changes",
+                    T::name_camel_case(),
+                    index
+                );
+                return false;
+            }
         }
 
-        if let Some(channel_map) = &remap_config.channel_map {
-            args.push(format!("channel_map={}", channel_map.join(",")));
+        info!(
+            "Found {} #{}, name = {}, description = {}",
+            T::name_lower_case(),
+            index,
+            device.original_name,
+            device
+                .properties
+                .get("device.description")
+                .map_or("", String::as_str)
+        );
+        // Not even walked unless logged, as it is done on every change
+        if log_enabled!(Level::Debug) {
+            for (key, value) in &device.properties {
+                debug!(
+                    "{} #{} property: {key} = \"{value}\"",
+                    T::name_camel_case(),
+                    index
+                );
+            }
         }
 
-        if let Some(master_channel_map) = &remap_config.master_channel_map {
-            args.push(format!(
-                "master_channel_map={}",
-                master_channel_map.join(",")
-            ));
+        let recognized_as = device.recognize(&MatchEnvironment {
+            configs,
+            config_ids: &group.config_ids,
+            remap_module_indices: &group.remap_module_indices,
+            quirks,
+            multi_match: self.config.multi_match,
+        });
+        device.recognized_as = recognized_as
+            .iter()
+            .filter_map(|name| group.config_ids.get(name))
+            .collect();
+        device.match_scores =
+            device.recognition_scores(configs, &group.config_ids);
+        for (&name, id) in recognized_as.iter().zip(&device.recognized_as) {
+            info!(
+                "{} #{} is recognized as '{}'",
+                T::name_camel_case(),
+                index,
+                name
+            );
+            self.events.publish_with(|| Event::Recognized {
+                kind: T::name_lower_case(),
+                index,
+                config: name.to_string(),
+            });
+            // Not again when a change only re-recognizes the device
+            if !group
+                .found_devices
+                .get(&index)
+                .is_some_and(|known| known.recognized_as.contains(id))
+                && let Some(config) = configs.get(name)
+            {
+                self.pending_actions.extend_from_slice(&config.on_appear);
+            }
         }
 
-        if let Some(resample_method) = &remap_config.resample_method {
-            args.push(format!("resample_method={resample_method}"));
+        if let Some(known) = group.found_devices.get(&index) {
+            for &id in &known.recognized_as {
+                if device.recognized_as.contains(&id) {
+                    continue;
+                }
+                let name = group.config_ids.name(id);
+                info!(
+                    "{} #{} is no longer recognized as '{}'",
+                    T::name_camel_case(),
+                    index,
+                    name
+                );
+                self.events.publish_with(|| Event::Unrecognized {
+                    kind: T::name_lower_case(),
+                    index,
+                    config: name.to_string(),
+                });
+                if let Some(config) = configs.get(name) {
+                    self.pending_actions
+                        .extend_from_slice(&config.on_disappear);
+                }
+            }
         }
 
-        if let Some(remix) = remap_config.remix {
-            args.push(format!("remix={}", if remix { "yes" } else { "no" }));
+        let is_recognized = !device.recognized_as.is_empty();
+        let previous = group
+            .insert_device(IndexedDevice { index, device }, |name| {
+                self.config.resolve_priority(T::group_name(), name)
+            });
+        if is_recognized {
+            self.apply_passthrough::<T>(index);
         }
-
-        args.join(" ")
+        is_recognized
+            || previous.is_some_and(|device| !device.recognized_as.is_empty())
     }
 
-    fn load_remap_module<T: DeviceType>(
-        &mut self,
-        params: RemapModuleParams<'_>,
-    ) {
-        // Get master device name from index
-        let devices = T::select(&self.state.all_devices);
-        let master_name = match devices.found_devices.get(&params.master_index)
-        {
-            Some(device) => &device.original_name,
-            None => {
-                error!(
-                    "Master device #{} not found for remap '{}'",
-                    params.master_index, params.config_name
-                );
-                return;
-            }
+    /// Let the device accept PCM and the passthrough formats of the
+    /// highest-priority config it is recognized as, if one has them
+    fn apply_passthrough<T: DeviceType>(&self, index: u32) {
+        let configs = T::get_definitions(&self.config);
+        let scope = T::select(&self.all_devices);
+        let Some(device) = scope.found_devices.get(&index) else {
+            return;
+        };
+        let Some(passthrough) = scope
+            .config_names(device)
+            .filter_map(|name| configs.get_key_value(name))
+            .filter(|(_, config)| config.passthrough.is_some())
+            .min_by_key(|(name, _)| {
+                self.config
+                    .resolve_priority(T::group_name(), name)
+                    .unwrap_or(u32::MAX)
+            })
+            .and_then(|(_, config)| config.passthrough.as_ref())
+        else {
+            return;
         };
 
-        let argument = Self::build_remap_module_args::<T>(
-            params.remap_config,
-            master_name,
-        );
-        let weak_origin = Rc::downgrade(&self.origin);
-        let config_name_owned = params.config_name.to_string();
+        let mut formats: Vec<FormatInfo> = std::iter::once(Encoding::PCM)
+            .chain(passthrough.iter().copied().map(passthrough_encoding))
+            .filter_map(|encoding| {
+                let mut format = FormatInfo::new()?;
+                format.set_encoding(encoding);
+                Some(format)
+            })
+            .collect();
+        let mut formats: Vec<&mut FormatInfo> = formats.iter_mut().collect();
 
+        if self.observing {
+            info!(
+                "Observing: would set passthrough formats of {} to {:?}",
+                device.original_name, passthrough
+            );
+            return;
+        }
+        if self.paused {
+            debug!(
+                "Paused, not setting passthrough formats of {}",
+                device.original_name
+            );
+            return;
+        }
         info!(
-            "Loading {} remap module for '{}' with master #{}",
-            T::name_lower_case(),
-            params.config_name,
-            params.master_index
+            "Setting passthrough formats of {} to {:?}",
+            device.original_name, passthrough
         );
-
-        let _op = self.state.context.introspect().load_module(
-            T::module_name(),
-            &argument,
-            move |module_index| {
-                if let Some(origin) = weak_origin.upgrade() {
-                    StateRunner::with(&origin, |runner| {
-                        let devices =
-                            T::select_mut(&mut runner.state.all_devices);
-                        devices
-                            .remap_module_indices
-                            .insert(config_name_owned.clone(), module_index);
-                        info!(
-                            "Successfully loaded {} remap module #{} for '{}'",
-                            T::name_lower_case(),
-                            module_index,
-                            config_name_owned
-                        );
-                    });
+        let name = device.original_name.clone();
+        let _op = self.context.device_restore().save_formats(
+            T::restore_type(),
+            index,
+            &mut formats,
+            move |success| {
+                if !success {
+                    warn!("Failed to set the passthrough formats of {name}");
                 }
             },
         );
     }
 
-    fn unload_remap_module<T: DeviceType>(&mut self, config_name: &str) {
-        let module_index = {
-            let devices = T::select(&self.state.all_devices);
-            devices.remap_module_indices.get(config_name).copied()
+    /// Set the passthrough formats of every known sink again
+    fn apply_all_passthrough(&self) {
+        for &index in self.all_devices.sinks.found_devices.keys() {
+            self.apply_passthrough::<Sink>(index);
+        }
+    }
+
+    /// Re-match all known devices against the current config using the
+    /// cached properties. Returns whether any recognition has changed.
+    fn rematch_devices<T: DeviceType>(&mut self) -> bool {
+        let quirks = self.quirks();
+        let configs = T::get_definitions(&self.config);
+        let group = T::select_mut(&mut self.all_devices);
+        // Configs may have been added by a reload
+        group.intern_configs(configs);
+        let AudioDeviceGroup {
+            found_devices,
+            config_ids,
+            remap_module_indices,
+            ..
+        } = group;
+        let env = MatchEnvironment {
+            configs,
+            config_ids,
+            remap_module_indices,
+            quirks,
+            multi_match: self.config.multi_match,
         };
+        let mut changed = false;
 
-        if let Some(index) = module_index {
-            let weak_origin = Rc::downgrade(&self.origin);
-            let config_name_owned = config_name.to_string();
+        for (&index, device) in found_devices.iter_mut() {
+            let names = device.recognize(&env);
+            let recognized_as: Vec<_> = names
+                .iter()
+                .filter_map(|name| env.config_ids.get(name))
+                .collect();
+            changed = changed || recognized_as != device.recognized_as;
+
+            for (&name, id) in names.iter().zip(&recognized_as) {
+                if !device.recognized_as.contains(id) {
+                    info!(
+                        "{} #{} is recognized as '{}'",
+                        T::name_camel_case(),
+                        index,
+                        name
+                    );
+                    self.events.publish_with(|| Event::Recognized {
+                        kind: T::name_lower_case(),
+                        index,
+                        config: name.to_string(),
+                    });
+                    self.pending_actions
+                        .extend_from_slice(&env.configs[name].on_appear);
+                }
+            }
+            for id in &device.recognized_as {
+                if !recognized_as.contains(id) {
+                    let name = env.config_ids.name(*id);
+                    info!(
+                        "{} #{} is no longer recognized as '{}'",
+                        T::name_camel_case(),
+                        index,
+                        name
+                    );
+                    self.events.publish_with(|| Event::Unrecognized {
+                        kind: T::name_lower_case(),
+                        index,
+                        config: name.to_string(),
+                    });
+                    if let Some(config) = env.configs.get(name) {
+                        self.pending_actions
+                            .extend_from_slice(&config.on_disappear);
+                    }
+                }
+            }
+
+            device.recognized_as = recognized_as;
+            device.match_scores =
+                device.recognition_scores(env.configs, env.config_ids);
+        }
+
+        T::select_mut(&mut self.all_devices).reindex(|name| {
+            self.config.resolve_priority(T::group_name(), name)
+        });
+        changed
+    }
+
+    fn remove_device<T>(&mut self, index: u32)
+    where
+        T: DeviceType,
+    {
+        let group = T::select_mut(&mut self.all_devices);
+        let removed = group.remove_device(index, |name| {
+            self.config.resolve_priority(T::group_name(), name)
+        });
+
+        if let Some(device) = removed {
+            info!("Lost {} #{}", T::name_lower_case(), index);
+            let configs = T::get_definitions(&self.config);
+            let scope = T::select(&self.all_devices);
+            for name in scope.config_names(&device) {
+                if let Some(config) = configs.get(name) {
+                    self.pending_actions
+                        .extend_from_slice(&config.on_disappear);
+                }
+                self.events.publish_with(|| Event::Unrecognized {
+                    kind: T::name_lower_case(),
+                    index,
+                    config: name.to_string(),
+                });
+            }
+        }
+    }
+
+    /// The device to make default and the config it is chosen for: the one
+    /// chosen by a command if present, then the member of the complete
+    /// bundle with the highest priority, then the device with the highest
+    /// priority
+    fn choose_default<T: DeviceType>(&self) -> Option<(String, u32)> {
+        let scope = T::select(&self.all_devices);
+        let configs = T::get_definitions(&self.config);
+        if let Some(config_name) = &scope.default_override
+            && let Some(index) = scope.find_index_by_config_name(config_name)
+            && let Some(device) = scope.found_devices.get(&index)
+            && !device.silent
+            && device.is_eligible_as(&configs[config_name])
+        {
+            return Some((config_name.clone(), index));
+        }
+
+        let (complete, incomplete) = self.partition_bundles();
+        if let Some(bundle) = complete
+            .iter()
+            .min_by_key(|bundle| bundle.priority.unwrap_or(u32::MAX))
+        {
+            let config_name = T::bundle_member(bundle);
+            return scope
+                .find_index_by_config_name(config_name)
+                .map(|index| (config_name.to_string(), index));
+        }
+
+        let excluded = Self::ignored_bundle_members::<T>(&incomplete);
+        scope
+            .find_default_device(configs, |entry, device| {
+                !excluded.contains(scope.config_ids.name(entry.config))
+                    && self.is_in_default_scope(device)
+            })
+            .map(|entry| {
+                let config_name = scope.config_ids.name(entry.config);
+                (config_name.to_string(), entry.index)
+            })
+    }
+
+    /// Bundles whose sink and source configs both have a device, and the
+    /// others
+    fn partition_bundles(&self) -> (Vec<&BundleConfig>, Vec<&BundleConfig>) {
+        self.config.bundles.values().partition(|bundle| {
+            self.all_devices
+                .sinks
+                .find_index_by_config_name(&bundle.sink)
+                .is_some()
+                && self
+                    .all_devices
+                    .sources
+                    .find_index_by_config_name(&bundle.source)
+                    .is_some()
+        })
+    }
+
+    /// Members of incomplete bundles that may not become default alone
+    fn ignored_bundle_members<'a, T: DeviceType>(
+        incomplete: &[&'a BundleConfig],
+    ) -> HashSet<&'a str> {
+        incomplete
+            .iter()
+            .filter(|bundle| bundle.partial == PartialBundlePolicy::Ignore)
+            .map(|bundle| T::bundle_member(bundle))
+            .collect()
+    }
+
+    /// Every recognition with the reason it was or was not chosen as the
+    /// default, from the highest priority
+    fn default_candidates<T: DeviceType>(
+        &self,
+        chosen: Option<&(String, u32)>,
+    ) -> Vec<Vec<String>> {
+        let scope = T::select(&self.all_devices);
+        let configs = T::get_definitions(&self.config);
+        let (_, incomplete) = self.partition_bundles();
+        let excluded = Self::ignored_bundle_members::<T>(&incomplete);
+        let chosen_entry = chosen.and_then(|(config_name, index)| {
+            let config = scope.config_ids.get(config_name)?;
+            scope
+                .ranking
+                .iter()
+                .find(|entry| entry.config == config && entry.index == *index)
+        });
+
+        let mut rows = Vec::new();
+        for entry in &scope.ranking {
+            let config_name = scope.config_ids.name(entry.config);
+            let (Some(device), Some(config)) = (
+                scope.found_devices.get(&entry.index),
+                configs.get(config_name),
+            ) else {
+                continue;
+            };
+            let decision = if chosen_entry == Some(entry) {
+                "chosen"
+            } else if device.silent {
+                "silent"
+            } else if !device.is_eligible_as(config) {
+                "playback not verified"
+            } else if excluded.contains(config_name) {
+                "bundle incomplete"
+            } else if !self.is_in_default_scope(device) {
+                "outside default scope"
+            } else if chosen_entry.is_some_and(|chosen| chosen < entry) {
+                "lower priority"
+            } else {
+                "chosen by command or bundle instead"
+            };
+            rows.push(vec![
+                format!("#{} {}", entry.index, device.original_name),
+                config_name.to_string(),
+                entry.priority.to_string(),
+                decision.to_string(),
+            ]);
+        }
+        rows
+    }
+
+    fn trace_default<T: DeviceType>(&self, chosen: Option<&(String, u32)>) {
+        let rows = self.default_candidates::<T>(chosen);
+        if rows.is_empty() {
+            info!("No candidates for the default {}", T::name_lower_case());
+            return;
+        }
+        info!("Candidates for the default {}:", T::name_lower_case());
+        let header = ["Device", "Config", "Priority", "Decision"];
+        for line in trace::format_table(&header, &rows) {
+            info!("  {line}");
+        }
+    }
+
+    /// Group of the device under the configured default scope
+    fn device_group<'a>(&self, device: &'a AudioDevice) -> Option<&'a str> {
+        self.config
+            .default_scope
+            .as_ref()
+            .and_then(|scope| scope.group_of(&device.properties))
+    }
+
+    /// Whether the device may become default by priority
+    fn is_in_default_scope(&self, device: &AudioDevice) -> bool {
+        self.config
+            .default_scope
+            .as_ref()
+            .is_none_or(|scope| scope.includes(&device.properties))
+    }
+
+    /// Config of the device with the highest priority in each group, when
+    /// devices are grouped
+    fn best_by_group<T: DeviceType>(&self) -> BTreeMap<String, String> {
+        let scope = T::select(&self.all_devices);
+        let configs = T::get_definitions(&self.config);
+        let mut best = BTreeMap::new();
+        for (entry, device) in scope.ranked_candidates(configs) {
+            if let Some(group) = self.device_group(device) {
+                best.entry(group.to_string()).or_insert_with(|| {
+                    scope.config_ids.name(entry.config).to_string()
+                });
+            }
+        }
+        best
+    }
 
+    fn handle_set_default_result<T>(
+        &mut self,
+        device_index: u32,
+        success: bool,
+    ) where
+        T: DeviceType,
+    {
+        // The config the device was chosen for, unless the choice has
+        // changed since
+        let config = self
+            .choose_default::<T>()
+            .filter(|&(_, index)| index == device_index)
+            .map(|(config_name, _)| config_name);
+        let state = T::select_mut(&mut self.all_devices);
+        if success {
             info!(
-                "Unloading {} remap module #{} for '{}'",
+                "Successfully set default {} to #{}",
                 T::name_lower_case(),
-                index,
-                config_name
+                device_index
             );
+            // Not when the first default is set or an old one is re-applied
+            let switched = state.current_default.is_some()
+                && state.current_default != config;
+            if switched
+                && let (Some(from), Some(to)) =
+                    (&state.current_default, &config)
+            {
+                self.last_switch = Some(DefaultSwitch {
+                    kind: T::kind(),
+                    from: from.clone(),
+                    to: to.clone(),
+                });
+            }
+            state.current_default.clone_from(&config);
+            state.default_retry_count = 0;
+            if let Some(statistics) = &mut self.statistics {
+                let now = journal::unix_time(SystemTime::now());
+                statistics.record_default(
+                    statistics::NewDefault {
+                        kind: T::name_lower_case(),
+                        config_name: config.as_deref(),
+                    },
+                    now,
+                );
+            }
+            if let Some(device) = state.found_devices.get(&device_index) {
+                if switched
+                    && T::can_play_samples()
+                    && self.config.play_test_sound
+                    && self.test_sound_ready
+                {
+                    debug!("Playing test sound on {}", device.original_name);
+                    let _op = self.context.play_sample(
+                        TEST_SOUND_SAMPLE,
+                        Some(&device.original_name),
+                        None,
+                        None,
+                    );
+                }
+                self.activity.default_switches += 1;
+                self.events.publish(Event::DefaultChanged {
+                    kind: T::name_lower_case(),
+                    index: device_index,
+                    name: device.original_name.clone(),
+                    config,
+                });
+            }
+        } else {
+            error!("Failed to set default {}", T::name_lower_case());
+        }
+    }
 
-            let _op = self.state.context.introspect().unload_module(
-                index,
-                move |success| {
-                    if let Some(origin) = weak_origin.upgrade() {
-                        StateRunner::with(&origin, |runner| {
-                            if success {
-                                let devices = T::select_mut(&mut runner.state.all_devices);
-                                devices.remap_module_indices.remove(&config_name_owned);
-                                info!(
-                                    "Successfully unloaded {} remap module #{} for '{}'",
-                                    T::name_lower_case(),
-                                    index,
-                                    config_name_owned
-                                );
-                            } else {
-                                error!(
-                                    "Failed to unload {} remap module #{} for '{}'",
-                                    T::name_lower_case(),
-                                    index,
-                                    config_name_owned
-                                );
-                            }
-                        });
-                    }
+    pub fn from_context(
+        context: Context,
+        config: Config,
+        events: EventFeed,
+    ) -> Rc<RefCell<Self>> {
+        let mut state = Self::new(context, config, events);
+        state.apply_limits();
+        Rc::new(RefCell::new(state))
+    }
+}
+
+pub struct StateRunner<'scope> {
+    origin: Rc<RefCell<State>>,
+    state: &'scope mut State,
+}
+
+struct RemapModuleParams<'a> {
+    config_name: &'a str,
+    remap_config: &'a crate::config::RemapConfig,
+    master_name: &'a str,
+}
+
+/// Module to load for a config, as the action requesting it has it
+struct ModuleLoad {
+    config_name: String,
+    module_name: &'static str,
+    argument: String,
+}
+
+struct ModuleLoadParams<'a> {
+    config_name: &'a str,
+    module_name: &'static str,
+    argument: String,
+    reason: String, // Why the module is loaded, for the journal
+}
+
+impl<'scope> StateRunner<'scope> {
+    fn update_default_device<T: DeviceType>(&mut self) {
+        if self.state.observing {
+            self.observe_default::<T>();
+            return;
+        }
+        if self.state.paused {
+            debug!(
+                "Paused, not changing the default {}",
+                T::name_lower_case()
+            );
+            return;
+        }
+        if self.state.session_manager_policy()
+            != SessionManagerPolicy::Override
+        {
+            debug!(
+                "Leaving the default {} to the session manager",
+                T::name_lower_case()
+            );
+            return;
+        }
+
+        let default_device = self.state.choose_default::<T>();
+        if self.state.config.trace_decisions {
+            self.state.trace_default::<T>(default_device.as_ref());
+        }
+
+        if let Some((config_name, device)) = default_device {
+            info!(
+                "Using {} '{}' as default",
+                T::name_lower_case(),
+                config_name
+            );
+            let scope = T::select(&self.state.all_devices);
+            let reason =
+                if scope.default_override.as_ref() == Some(&config_name) {
+                    format!("'{config_name}' chosen by command")
+                } else {
+                    format!("'{config_name}' is the best present config")
+                };
+            self.submit(
+                Action::SetDefault {
+                    kind: T::kind(),
+                    device,
                 },
+                reason,
             );
+        } else {
+            self.state
+                .actions
+                .cancel_deferred(&Target::Default(T::kind()));
+            T::select_mut(&mut self.state.all_devices).current_default = None;
+            self.update_peak_monitor::<T>();
         }
     }
 
-    fn check_and_load_remaps<T: DeviceType>(&mut self) {
-        // Skip remap loading if shutting down
-        if self.state.shutting_down {
-            debug!("Skipping remap loading during shutdown");
+    /// Log the default that would be set whenever it changes
+    fn observe_default<T: DeviceType>(&mut self) {
+        let choice = self
+            .state
+            .choose_default::<T>()
+            .map(|(config_name, _)| config_name);
+        let scope = T::select_mut(&mut self.state.all_devices);
+        if scope.observed_default == choice {
             return;
         }
 
-        let configs = T::get_definitions(&self.state.config);
-        let devices = T::select(&self.state.all_devices);
-
-        // Find all remap configs that should be loaded
-        let mut remaps_to_load = Vec::new();
+        match &choice {
+            Some(config_name) => info!(
+                "Observing: would use {} '{}' as default",
+                T::name_lower_case(),
+                config_name
+            ),
+            None => info!(
+                "Observing: would leave the default {} alone",
+                T::name_lower_case()
+            ),
+        }
+        scope.observed_default = choice;
+    }
 
-        for (config_name, config) in configs {
-            if let crate::config::DeviceMatchConfig::Remap(remap) =
-                &config.match_config
-            {
-                // Check if the master device exists
-                let master_exists =
-                    Self::has_device_with_config_name(devices, &remap.master);
+    fn set_default<T: DeviceType>(&mut self, device_index: u32) {
+        let scope = T::select(&self.state.all_devices);
+        let Some(device) = scope.found_devices.get(&device_index) else {
+            // Gone meanwhile; whatever replaces it is chosen anew
+            self.finish_action(&Target::Default(T::kind()), false);
+            return;
+        };
 
-                if master_exists
-                    && !devices.remap_module_indices.contains_key(config_name)
-                {
-                    // Find the master device index
-                    if let Some(master_index) =
-                        Self::find_device_index_by_config_name(
-                            devices,
-                            &remap.master,
-                        )
-                    {
-                        remaps_to_load.push((
-                            config_name.clone(),
-                            remap.clone(),
-                            master_index,
-                        ));
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback = move |success: bool| {
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    runner
+                        .state
+                        .handle_set_default_result::<T>(device_index, success);
+                    if success {
+                        runner.schedule_default_verification::<T>();
+                        runner.update_peak_monitor::<T>();
+                    } else {
+                        runner.schedule_default_retry::<T>();
                     }
-                }
+                    runner.finish_action(&Target::Default(T::kind()), success);
+                });
+            }
+        };
+        debug!(
+            "Setting default {} to #{}",
+            T::name_lower_case(),
+            device_index
+        );
+        let name = device.original_name.clone();
+        T::set_default(&mut self.state.context, &name, callback);
+    }
+
+    /// Start the action unless the same one is in flight, or defer it
+    /// until the action in flight for its target has completed
+    fn submit(&mut self, action: Action, reason: String) {
+        if self.state.observing {
+            info!("Observing: would run {action:?} ({reason})");
+            return;
+        }
+        let submission = Submission {
+            action: action.clone(),
+            reason,
+        };
+        match self.state.actions.submit(submission) {
+            Admission::Start => self.start_action(action),
+            Admission::Duplicate => {
+                debug!("{action:?} is in flight already");
+            }
+            Admission::Deferred => {
+                debug!("Deferring {action:?} until the one in flight is done");
+            }
+        }
+    }
+
+    fn start_action(&mut self, action: Action) {
+        match action {
+            Action::SetDefault {
+                kind: DeviceKind::Sink,
+                device,
+            } => self.set_default::<Sink>(device),
+            Action::SetDefault {
+                kind: DeviceKind::Source,
+                device,
+            } => self.set_default::<Source>(device),
+            Action::LoadModule {
+                kind: DeviceKind::Sink,
+                config,
+                module,
+                argument,
+            } => self.load_module::<Sink>(ModuleLoad {
+                config_name: config,
+                module_name: module,
+                argument,
+            }),
+            Action::LoadModule {
+                kind: DeviceKind::Source,
+                config,
+                module,
+                argument,
+            } => self.load_module::<Source>(ModuleLoad {
+                config_name: config,
+                module_name: module,
+                argument,
+            }),
+            Action::MoveStream {
+                kind: DeviceKind::Sink,
+                stream,
+                device,
+            } => self.move_sink_input(stream, device),
+            Action::MoveStream {
+                kind: DeviceKind::Source,
+                stream,
+                device,
+            } => self.move_source_output(stream, device),
+        }
+    }
+
+    /// Record the completed action in the journal and report it to the
+    /// policies, then start the action deferred for its target meanwhile
+    fn finish_action(&mut self, target: &Target, success: bool) {
+        let (finished, next) = self.state.actions.complete(target);
+        if let Some(Submission { action, reason }) = finished {
+            if let Some(journal) = &mut self.state.journal {
+                journal.append(&Entry {
+                    time: journal::unix_time(SystemTime::now()),
+                    action: action.kind(),
+                    target: action.to_string(),
+                    reason,
+                    success,
+                });
+            }
+            self.apply_policies(PolicyEvent::ActionCompleted {
+                action,
+                success,
+            });
+        }
+        if let Some(next) = next {
+            self.start_action(next);
+        }
+    }
+
+    /// Fail over from the default device if it has stayed silent
+    fn check_silence<T: DeviceType>(&mut self) {
+        let Some(index) = self.state.is_silent_too_long::<T>() else {
+            return;
+        };
+
+        let scope = T::select_mut(&mut self.state.all_devices);
+        let Some(device) = scope.found_devices.get_mut(&index) else {
+            return;
+        };
+        device.silent = true;
+        warn!(
+            "{} #{} ({}) has been silent while playing, failing over",
+            T::name_camel_case(),
+            index,
+            device.original_name
+        );
+        self.state.events.publish(Event::SilenceDetected {
+            kind: T::name_lower_case(),
+            index,
+            name: device.original_name.clone(),
+            config: scope.current_default.clone(),
+        });
+
+        // The peak monitor cannot be dropped from its own callback
+        self.state.timers.schedule(
+            SILENCE_FAILOVER_TIMER,
+            Timer {
+                delay: Duration::ZERO,
+                callback: Box::new(|runner| {
+                    runner.update_default_device::<T>();
+                }),
+            },
+        );
+    }
+
+    /// Follow the level of the current default device if enabled, by
+    /// recording the peaks of its monitor source (or of the source itself)
+    fn update_peak_monitor<T: DeviceType>(&mut self) {
+        let State {
+            all_devices: devices,
+            context,
+            config,
+            ..
+        } = self.state;
+        let scope = T::select_mut(devices);
+        let target =
+            if config.peak_monitor || T::silence_failover(config).is_some() {
+                scope
+                    .current_default
+                    .as_deref()
+                    .and_then(|name| scope.find_index_by_config_name(name))
+            } else {
+                None
+            };
+        if scope.peak_monitor.as_ref().map(|m| m.device_index) == target {
+            return;
+        }
+
+        if let Some(mut monitor) = scope.peak_monitor.take() {
+            let _ = monitor.stream.disconnect();
+        }
+        let Some(device_index) = target else {
+            return;
+        };
+        let Some(source) = scope
+            .found_devices
+            .get(&device_index)
+            .and_then(T::peak_source)
+        else {
+            return;
+        };
+
+        let Some(mut stream) =
+            Stream::new(context, "Peak monitor", &PEAK_MONITOR_SPEC, None)
+        else {
+            warn!("Failed to create a peak monitor for {source}");
+            return;
+        };
+        let weak_origin = Rc::downgrade(&self.origin);
+        stream.set_read_callback(Some(Box::new(move |_| {
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    runner.state.read_peak::<T>();
+                    runner.check_silence::<T>();
+                });
+            }
+        })));
+        let attr = BufferAttr {
+            maxlength: u32::MAX,
+            tlength: u32::MAX,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: std::mem::size_of::<f32>() as u32,
+        };
+        let flags = StreamFlagSet::PEAK_DETECT
+            | StreamFlagSet::ADJUST_LATENCY
+            | StreamFlagSet::DONT_MOVE
+            | StreamFlagSet::DONT_INHIBIT_AUTO_SUSPEND;
+        if let Err(e) = stream.connect_record(Some(source), Some(&attr), flags)
+        {
+            warn!("Failed to monitor the level of {source}: {e}");
+            return;
+        }
+
+        debug!("Monitoring the level of {source}");
+        scope.peak_monitor = Some(PeakMonitor {
+            stream,
+            device_index,
+            level: 0.0,
+            silent_since: None,
+        });
+    }
+
+    /// Set the default again some time after the server refused, as it may
+    /// only be busy setting up the device
+    fn schedule_default_retry<T: DeviceType>(&mut self) {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        scope.default_retry_count += 1;
+        let attempt = scope.default_retry_count;
+        if attempt > DEFAULT_RETRY_LIMIT {
+            error!(
+                "Giving up setting the default {} after {} retries",
+                T::name_lower_case(),
+                DEFAULT_RETRY_LIMIT
+            );
+            // The next change of devices starts over
+            scope.default_retry_count = 0;
+            return;
+        }
+
+        let delay = timer::backoff(DEFAULT_RETRY_DELAY, attempt);
+        warn!(
+            "Retrying to set the default {} in {} ms",
+            T::name_lower_case(),
+            delay.as_millis()
+        );
+        self.state.timers.schedule(
+            &format!("retry-default-{}", T::name_lower_case()),
+            Timer {
+                delay,
+                callback: Box::new(|runner| {
+                    runner.update_default_device::<T>()
+                }),
+            },
+        );
+    }
+
+    fn schedule_default_verification<T: DeviceType>(&mut self) {
+        if !self.state.quirks().verify_default {
+            return;
+        }
+
+        self.state.timers.schedule(
+            &format!("verify-default-{}", T::name_lower_case()),
+            Timer {
+                delay: DEFAULT_VERIFY_DELAY,
+                callback: Box::new(|runner| {
+                    runner.verify_default_device::<T>()
+                }),
+            },
+        );
+    }
+
+    fn verify_default_device<T: DeviceType>(&mut self) {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let _op = self.state.context.introspect().get_server_info(
+            move |server_info| {
+                let actual = T::server_default_name(server_info);
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.handle_server_default::<T>(actual);
+                    });
+                }
+            },
+        );
+    }
+
+    fn handle_server_default<T: DeviceType>(&mut self, actual: Option<&str>) {
+        let chosen = self.state.choose_default::<T>();
+        if self.state.actions.is_busy(&Target::Default(T::kind())) {
+            // Another change is in flight and will be verified on its own
+            return;
+        }
+        let scope = T::select_mut(&mut self.state.all_devices);
+
+        let expected = chosen
+            .and_then(|(_, index)| scope.found_devices.get(&index))
+            .map(|device| device.original_name.as_str());
+        if expected.is_none() || expected == actual {
+            scope.default_reapply_count = 0;
+            return;
+        }
+
+        scope.default_reapply_count += 1;
+        if scope.default_reapply_count > DEFAULT_REAPPLY_LIMIT {
+            warn!(
+                "The server keeps changing the default {}, giving up",
+                T::name_lower_case()
+            );
+            return;
+        }
+
+        warn!(
+            "The server changed the default {} to '{}', setting it again",
+            T::name_lower_case(),
+            actual.unwrap_or_default()
+        );
+        self.update_default_device::<T>();
+    }
+
+    fn make_device_callback<T: DeviceType>(
+        &self,
+        is_full_list: bool,
+    ) -> impl for<'a, 'b> FnMut(ListResult<&'a T::Info<'b>>) + 'static {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let mut should_update = false;
+        move |list_result| {
+            if let Some(origin) = weak_origin.upgrade() {
+                match list_result {
+                    // Full lists are matched in chunks once complete
+                    ListResult::Item(info) if is_full_list => {
+                        let snapshot = State::snapshot_device::<T>(info);
+                        StateRunner::with(&origin, |runner| {
+                            T::select_mut(&mut runner.state.all_devices)
+                                .pending_devices
+                                .push_back(snapshot);
+                        });
+                    }
+                    ListResult::Item(info) => {
+                        StateRunner::with(&origin, |runner| {
+                            let snapshot = State::snapshot_device::<T>(info);
+                            let index = snapshot.index;
+                            let is_relevant =
+                                runner.state.add_device::<T>(snapshot);
+                            should_update = should_update || is_relevant;
+                            runner.enforce_privacy::<T>(index);
+                        });
+                    }
+                    ListResult::End => {
+                        debug!(
+                            "Finished loading list result for {}s",
+                            T::name_lower_case()
+                        );
+                        StateRunner::with(&origin, |runner| {
+                            if should_update {
+                                runner.request_reevaluation::<T>();
+                            }
+                            if is_full_list {
+                                T::select_mut(&mut runner.state.all_devices)
+                                    .list_error_count = 0;
+                                runner.match_pending_devices::<T>();
+                            }
+                        });
+                    }
+                    ListResult::Error => {
+                        error!(
+                            "Error loading list result for {}s",
+                            T::name_lower_case()
+                        );
+                        // Errors for single devices are usually caused by
+                        // devices that have gone in the meantime
+                        if is_full_list {
+                            StateRunner::with(&origin, |runner| {
+                                runner.handle_list_error::<T>();
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Match a chunk of the listed devices, and schedule the next one so
+    /// that callbacks are dispatched in between, until the enumeration is
+    /// complete
+    fn match_pending_devices<T: DeviceType>(&mut self) {
+        let mut is_relevant = false;
+        let chunk =
+            T::select_mut(&mut self.state.all_devices).take_pending_chunk();
+        for pending in chunk {
+            let index = pending.index;
+            is_relevant |= self.state.add_device::<T>(pending);
+            self.enforce_privacy::<T>(index);
+        }
+        if is_relevant {
+            self.request_reevaluation::<T>();
+        }
+
+        if T::select(&self.state.all_devices)
+            .pending_devices
+            .is_empty()
+        {
+            self.complete_enumeration::<T>();
+        } else {
+            self.state.timers.schedule(
+                &format!("enumeration-{}", T::name_lower_case()),
+                Timer {
+                    delay: Duration::ZERO,
+                    callback: Box::new(|runner| {
+                        runner.match_pending_devices::<T>();
+                    }),
+                },
+            );
+        }
+    }
+
+    fn query_all_sinks(&mut self) {
+        let callback = self.make_device_callback::<Sink>(true);
+        let _op = self.state.context.introspect().get_sink_info_list(callback);
+    }
+
+    fn query_sink_by_index(&mut self, index: u32) {
+        let callback = self.make_device_callback::<Sink>(false);
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_sink_info_by_index(index, callback);
+    }
+
+    fn query_all_sources(&mut self) {
+        let callback = self.make_device_callback::<Source>(true);
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_source_info_list(callback);
+    }
+
+    fn query_source_by_index(&mut self, index: u32) {
+        let callback = self.make_device_callback::<Source>(false);
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_source_info_by_index(index, callback);
+    }
+
+    fn handle_list_error<T: DeviceType>(&mut self) {
+        let group = T::select_mut(&mut self.state.all_devices);
+        let Some(delay) = group.list_failed() else {
+            error!(
+                "Giving up loading the list of {}s after {} attempts",
+                T::name_lower_case(),
+                group.list_error_count
+            );
+            return;
+        };
+
+        warn!(
+            "Retrying to load the list of {}s in {} seconds",
+            T::name_lower_case(),
+            delay.as_secs()
+        );
+        self.state.timers.schedule(
+            &format!("list-retry-{}", T::name_lower_case()),
+            Timer {
+                delay,
+                callback: Box::new(|runner| T::query_all(runner)),
+            },
+        );
+    }
+
+    /// Re-evaluate defaults and remaps once the current burst of device
+    /// events is over
+    fn request_reevaluation<T: DeviceType>(&mut self) {
+        T::select_mut(&mut self.state.all_devices).needs_reevaluation = true;
+
+        if !self.state.timers.is_scheduled(REEVALUATION_TIMER) {
+            self.state.timers.schedule(
+                REEVALUATION_TIMER,
+                Timer {
+                    delay: REEVALUATION_WINDOW,
+                    callback: Box::new(|runner| runner.reevaluate()),
+                },
+            );
+        }
+    }
+
+    /// Mark the initial enumeration of the device type as done, and apply
+    /// the batched re-evaluation right away once both types are done
+    fn complete_enumeration<T: DeviceType>(&mut self) {
+        let group = T::select_mut(&mut self.state.all_devices);
+        if group.enumerated {
+            return;
+        }
+        group.enumerated = true;
+        debug!("Initial enumeration of {}s completed", T::name_lower_case());
+
+        if self.state.is_initial_sync_complete() {
+            info!("Initial synchronization completed");
+            self.apply_restored_overrides();
+            if self.state.timers.cancel(REEVALUATION_TIMER) {
+                self.reevaluate();
+            }
+        }
+    }
+
+    /// Choose the defaults chosen before the last restart again, if their
+    /// devices are still present, and turn privacy mode back on
+    fn apply_restored_overrides(&mut self) {
+        if self.state.observing {
+            return;
+        }
+        let overrides = self.state.overrides.clone();
+        self.restore_default::<Sink>(overrides.default_sink.as_deref());
+        self.restore_default::<Source>(overrides.default_source.as_deref());
+        if let Some(privacy) = overrides.privacy {
+            self.set_privacy(privacy);
+        }
+        // Without the defaults whose devices have gone meanwhile
+        self.state.save_overrides();
+    }
+
+    fn restore_default<T: DeviceType>(&mut self, config_name: Option<&str>) {
+        let Some(config_name) = config_name else {
+            return;
+        };
+        let scope = T::select_mut(&mut self.state.all_devices);
+        if scope.find_by_config_name(config_name).is_none() {
+            info!(
+                "Chosen default {} '{}' has gone since the restart, using \
+                 priorities",
+                T::name_lower_case(),
+                config_name
+            );
+            return;
+        }
+        info!(
+            "Choosing {} '{}' as default again as before the restart",
+            T::name_lower_case(),
+            config_name
+        );
+        scope.default_override = Some(config_name.to_string());
+    }
+
+    fn reevaluate(&mut self) {
+        let devices = &mut self.state.all_devices;
+        let sinks = std::mem::take(&mut devices.sinks.needs_reevaluation);
+        let sources = std::mem::take(&mut devices.sources.needs_reevaluation);
+        self.apply_policies(PolicyEvent::DevicesChanged { sinks, sources });
+    }
+
+    /// Let every policy react to the event, and perform what they decide
+    fn apply_policies(&mut self, event: PolicyEvent) {
+        let context = PolicyContext {
+            has_bundles: !self.state.config.bundles.is_empty(),
+        };
+        let actions =
+            policy::decide(&mut self.state.policies, &event, &context);
+        for action in actions {
+            match action {
+                PolicyAction::StartPlaybackChecks(DeviceKind::Sink) => {
+                    self.start_playback_checks::<Sink>();
+                }
+                PolicyAction::StartPlaybackChecks(DeviceKind::Source) => {
+                    self.start_playback_checks::<Source>();
+                }
+                PolicyAction::UpdateDefault(DeviceKind::Sink) => {
+                    self.update_default_device::<Sink>();
+                }
+                PolicyAction::UpdateDefault(DeviceKind::Source) => {
+                    self.update_default_device::<Source>();
+                }
+                PolicyAction::UnloadRemaps => {
+                    self.check_and_unload_all_remaps();
+                }
+                PolicyAction::LoadRemaps => self.check_and_load_all_remaps(),
+            }
+        }
+    }
+
+    fn make_client_callback(
+        &self,
+    ) -> impl for<'a, 'b> FnMut(ListResult<&'a ClientInfo<'b>>) + 'static {
+        let weak_origin = Rc::downgrade(&self.origin);
+        move |list_result| {
+            if let ListResult::Item(info) = list_result {
+                let application_name = info.proplist.get_str(
+                    libpulse_binding::proplist::properties::APPLICATION_NAME,
+                );
+                if !is_session_manager(application_name.as_deref()) {
+                    return;
+                }
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        info!(
+                            "Session manager {} detected as client #{}",
+                            application_name.unwrap_or_default(),
+                            info.index
+                        );
+                        runner.update_session_manager_clients(|clients| {
+                            clients.insert(info.index);
+                        });
+                    });
+                }
+            }
+        }
+    }
+
+    /// List the session managers once for the diagnostics, whether or not
+    /// client events are subscribed to
+    fn query_session_manager_names(&mut self) {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let mut names = Vec::new();
+        let _op = self.state.context.introspect().get_client_info_list(
+            move |list_result| match list_result {
+                ListResult::Item(info) => {
+                    let application_name = info.proplist.get_str(
+                        libpulse_binding::proplist::properties::APPLICATION_NAME,
+                    );
+                    if is_session_manager(application_name.as_deref()) {
+                        names.extend(application_name);
+                    }
+                }
+                ListResult::End | ListResult::Error => {
+                    let names = std::mem::take(&mut names);
+                    if let Some(origin) = weak_origin.upgrade() {
+                        StateRunner::with(&origin, |runner| {
+                            runner.state.session_manager_names = Some(names);
+                        });
+                    }
+                }
+            },
+        );
+    }
+
+    fn query_all_clients(&mut self) {
+        let callback = self.make_client_callback();
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_client_info_list(callback);
+    }
+
+    fn query_client_by_index(&mut self, index: u32) {
+        let callback = self.make_client_callback();
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_client_info(index, callback);
+    }
+
+    fn query_sink_input_by_index(&mut self, index: u32) {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback = move |list_result: ListResult<&SinkInputInfo<'_>>| {
+            if let ListResult::Item(info) = list_result
+                && let Some(origin) = weak_origin.upgrade()
+            {
+                StateRunner::with(&origin, |runner| {
+                    runner.route_notification(info);
+                });
+            }
+        };
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_sink_input_info(index, callback);
+    }
+
+    /// Move a new stream with a notification role to the configured sink
+    fn route_notification(&mut self, info: &SinkInputInfo<'_>) {
+        let Some(notifications) = &self.state.config.notifications else {
+            return;
+        };
+        let role = info
+            .proplist
+            .get_str(libpulse_binding::proplist::properties::MEDIA_ROLE);
+        if !role.is_some_and(|role| notifications.routes_role(&role)) {
+            return;
+        }
+        let index = info.index;
+        if notifications.owner_uid.is_none() {
+            self.move_notification(index, info.sink);
+            return;
+        }
+        // Owners are told by the properties of the client
+        let Some(client) = info.client else {
+            debug!("Notification #{index} has no client, leaving it");
+            return;
+        };
+        let current_sink = info.sink;
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback = move |list_result: ListResult<&ClientInfo<'_>>| {
+            let ListResult::Item(client) = list_result else {
+                return;
+            };
+            let uid = owner::client_uid(|key| client.proplist.get_str(key));
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    let routes_owner = runner
+                        .state
+                        .config
+                        .notifications
+                        .as_ref()
+                        .is_some_and(|config| config.routes_owner(uid));
+                    if routes_owner {
+                        runner.move_notification(index, current_sink);
+                    } else {
+                        debug!(
+                            "Notification #{index} belongs to uid {uid:?}, \
+                             leaving it"
+                        );
+                    }
+                });
+            }
+        };
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_client_info(client, callback);
+    }
+
+    /// Move a notification stream, currently on the given sink, to the
+    /// configured sink
+    fn move_notification(&mut self, index: u32, current_sink: u32) {
+        let Some(notifications) = &self.state.config.notifications else {
+            return;
+        };
+        let Some(sink) = self
+            .state
+            .all_devices
+            .sinks
+            .find_index_by_config_name(&notifications.sink)
+        else {
+            debug!(
+                "No sink is recognized as '{}', leaving notification #{index}",
+                notifications.sink
+            );
+            return;
+        };
+        if sink == current_sink {
+            return;
+        }
+        if self.state.observing {
+            info!("Observing: would move notification #{index} to #{sink}");
+            return;
+        }
+
+        debug!("Moving notification stream #{index} to sink #{sink}");
+        let _op = self.state.context.introspect().move_sink_input_by_index(
+            index,
+            sink,
+            Some(Box::new(move |success| {
+                if !success {
+                    warn!("Failed to move notification stream #{index}");
+                }
+            })),
+        );
+    }
+
+    fn query_source_output_by_index(&mut self, index: u32) {
+        let own_client = self.state.context.get_index();
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback = move |list_result: ListResult<
+            &SourceOutputInfo<'_>,
+        >| {
+            // Our own streams such as peak monitors do not count
+            if let ListResult::Item(info) = list_result
+                && info.client.is_some()
+                && info.client != own_client
+                && let Some(origin) = weak_origin.upgrade()
+            {
+                StateRunner::with(&origin, |runner| {
+                    runner.handle_recording_started(info.index, info.source);
+                });
+            }
+        };
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_source_output_info(index, callback);
+    }
+
+    fn handle_recording_started(&mut self, index: u32, source: u32) {
+        self.report_recording_started(index, source);
+        self.switch_headset_for_recording(index);
+    }
+
+    fn handle_recording_stopped(&mut self, index: u32) {
+        self.report_recording_stopped(index);
+        self.switch_headset_back(index);
+    }
+
+    /// Publish recordings from managed sources, for privacy indicators
+    fn report_recording_started(&mut self, index: u32, source: u32) {
+        let state = &mut *self.state;
+        let sources = &state.all_devices.sources;
+        let Some(config) = sources
+            .found_devices
+            .get(&source)
+            .and_then(|device| sources.config_names(device).next())
+        else {
+            return;
+        };
+
+        info!("Stream #{index} started recording from '{config}'");
+        state.events.publish(Event::RecordingStarted {
+            index,
+            source,
+            config: config.to_string(),
+        });
+        let capture = Capture {
+            source,
+            config: config.to_string(),
+        };
+        if state.capturing.start(index, capture)
+            && let Some(hook) = &state.config.recording_hook
+        {
+            run_recording_hook(hook, true);
+        }
+    }
+
+    fn report_recording_stopped(&mut self, index: u32) {
+        let state = &mut *self.state;
+        let Some(Capture { source, config }) = state.capturing.stop(index)
+        else {
+            return;
+        };
+
+        info!("Stream #{index} stopped recording from '{config}'");
+        state.events.publish(Event::RecordingStopped {
+            index,
+            source,
+            config,
+        });
+        if state.capturing.is_empty()
+            && let Some(hook) = &state.config.recording_hook
+        {
+            run_recording_hook(hook, false);
+        }
+    }
+
+    /// Switch the Bluetooth card of the default sink to a headset profile
+    /// when the first recording starts
+    fn switch_headset_for_recording(&mut self, index: u32) {
+        let state = &mut *self.state;
+        if state.headset_profile_switching_config().is_none() {
+            return;
+        }
+        if !state.recordings.start(index, state.paused) {
+            return;
+        }
+
+        let sinks = &state.all_devices.sinks;
+        let Some(card) = sinks
+            .current_default
+            .as_deref()
+            .and_then(|name| sinks.find_by_config_name(name))
+            .and_then(|device| device.card)
+        else {
+            return;
+        };
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback = move |list_result: ListResult<&CardInfo<'_>>| {
+            let ListResult::Item(info) = list_result else {
+                return;
+            };
+            let bus = info
+                .proplist
+                .get_str(libpulse_binding::proplist::properties::DEVICE_BUS);
+            if bus.as_deref() != Some("bluetooth") {
+                return;
+            }
+            let Some(origin) = weak_origin.upgrade() else {
+                return;
+            };
+            StateRunner::with(&origin, |runner| {
+                runner.switch_to_headset_profile(info);
+            });
+        };
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_card_info_by_index(card, callback);
+    }
+
+    fn switch_to_headset_profile(&mut self, card: &CardInfo<'_>) {
+        let Some(config) = self.state.headset_profile_switching_config()
+        else {
+            return;
+        };
+        if !self.state.recordings.wants_switch() {
+            return;
+        }
+
+        let Some(active) = card
+            .active_profile
+            .as_ref()
+            .and_then(|profile| profile.name.as_deref())
+        else {
+            return;
+        };
+        let Some(profile) = choose_headset_profile(card, config) else {
+            debug!("Card #{} has no headset profile to switch to", card.index);
+            return;
+        };
+        if profile == active {
+            return;
+        }
+
+        info!(
+            "Switching card #{} from {} to {} while recording",
+            card.index, active, profile
+        );
+        self.state.recordings.switch = Some(HeadsetSwitch {
+            card: card.index,
+            previous_profile: active.to_string(),
+        });
+        let _op = self.state.context.introspect().set_card_profile_by_index(
+            card.index,
+            &profile,
+            Some(Box::new(|success| {
+                if !success {
+                    warn!("Failed to switch to the headset profile");
+                }
+            })),
+        );
+    }
+
+    /// Switch the card back once the last recording has stopped
+    fn switch_headset_back(&mut self, index: u32) {
+        let Some(switch) = self.state.recordings.stop(index) else {
+            return;
+        };
+
+        info!(
+            "Recording has stopped, switching card #{} back to {}",
+            switch.card, switch.previous_profile
+        );
+        let _op = self.state.context.introspect().set_card_profile_by_index(
+            switch.card,
+            &switch.previous_profile,
+            Some(Box::new(|success| {
+                if !success {
+                    warn!("Failed to switch back from the headset profile");
+                }
+            })),
+        );
+    }
+
+    fn handle_client_removed(&mut self, index: u32) {
+        if self.state.session_manager_clients.contains(&index) {
+            info!("Session manager client #{index} has gone");
+        }
+        self.update_session_manager_clients(|clients| {
+            clients.remove(&index);
+        });
+    }
+
+    /// Apply the change and take over again when the session manager has
+    /// gone, or step back when one has appeared
+    fn update_session_manager_clients(
+        &mut self,
+        change: impl FnOnce(&mut HashSet<u32>),
+    ) {
+        let previous = self.state.session_manager_policy();
+        change(&mut self.state.session_manager_clients);
+        let current = self.state.session_manager_policy();
+
+        if previous != current {
+            info!("Session manager policy is now {current:?}");
+            self.apply_policies(PolicyEvent::SessionManagerChanged);
+        }
+    }
+
+    fn handle_device_removed<T: DeviceType>(&mut self, index: u32) {
+        self.state.remove_device::<T>(index);
+
+        let scope = T::select_mut(&mut self.state.all_devices);
+        if let Some(config_name) = &scope.default_override
+            && scope.find_by_config_name(config_name).is_none()
+        {
+            info!(
+                "Chosen default {} '{}' has gone, using priorities again",
+                T::name_lower_case(),
+                config_name
+            );
+            scope.default_override = None;
+            self.state.save_overrides();
+        }
+
+        self.apply_policies(PolicyEvent::DeviceRemoved(T::kind()));
+    }
+
+    fn subscribe_to_events(
+        &mut self,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let interests = self.state.wanted_interests();
+        self.state.interests = interests;
+        debug!("Subscribing to PulseAudio events: {interests:?}");
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        let _op = self.state.context.subscribe(interests, move |success| {
+            if success {
+                info!("Successfully subscribed to PulseAudio events");
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.query_all_sinks();
+                        runner.query_all_sources();
+                        runner.poll_jack_server();
+                        runner.log_unmanaged_summary();
+                        runner.schedule_reconciliation();
+                        if runner
+                            .state
+                            .interests
+                            .contains(InterestMaskSet::CLIENT)
+                        {
+                            runner.query_all_clients();
+                        }
+                    });
+                }
+            } else {
+                error!("Failed to subscribe to PulseAudio events");
+            }
+        });
+
+        Ok(())
+    }
+
+    fn resubscribe(&mut self, interests: InterestMaskSet) {
+        self.state.interests = interests;
+        debug!("Changing subscribed PulseAudio events to {interests:?}");
+
+        let _op = self.state.context.subscribe(interests, |success| {
+            if !success {
+                error!("Failed to change subscribed PulseAudio events");
+            }
+        });
+    }
+
+    fn query_server_info(&mut self) {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let _op = self.state.context.introspect().get_server_info(
+            move |server_info| {
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        let mut details = ServerDetails::new(
+                            server_info.server_name.as_deref().unwrap_or(""),
+                            server_info
+                                .server_version
+                                .as_deref()
+                                .unwrap_or(""),
+                        );
+                        details.protocol_version =
+                            runner.state.context.get_server_protocol_version();
+                        info!("Server: {}", details.summary());
+                        runner.state.server = Some(details);
+                        runner.record_initial_default::<Sink>(server_info);
+                        runner.record_initial_default::<Source>(server_info);
+                        runner.query_session_manager_names();
+                    });
+                }
+            },
+        );
+    }
+
+    /// Remember the default the server had before we changed anything, to
+    /// be restored on exit
+    fn record_initial_default<T: DeviceType>(
+        &mut self,
+        info: &ServerInfo<'_>,
+    ) {
+        let name = T::server_default_name(info).map(str::to_string);
+        debug!("Initial default {}: {name:?}", T::name_lower_case());
+        T::select_mut(&mut self.state.all_devices).initial_default = name;
+    }
+
+    fn on_context_state_changed(
+        &mut self,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let context_state = self.state.context.get_state();
+
+        debug!("The context state is <{context_state:?}> now");
+
+        if context_state == libpulse_binding::context::State::Ready {
+            info!("Connected to PulseAudio server");
+            // Replies arrive in order, so the server details are known
+            // before any device is listed
+            self.query_server_info();
+            self.subscribe_to_events()?;
+            if self.state.config.play_test_sound {
+                self.upload_test_sound();
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn connect(
+        &mut self,
+        server: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let context = &mut self.state.context;
+
+        // Since callbacks will be called within pa_context_connect(),
+        // we call connect() before registering callbacks to prevent race
+        // condition.
+        context
+            .connect(
+                server,
+                libpulse_binding::context::FlagSet::NOAUTOSPAWN,
+                None,
+            )
+            .map_err(|e| {
+                format!(
+                    "Failed to connect to PulseAudio server{}: {}",
+                    server.map_or(String::new(), |s| format!(" at '{s}'")),
+                    e
+                )
+            })?;
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        context.set_state_callback(Some(Box::new(move || {
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    if let Err(e) = runner.on_context_state_changed() {
+                        error!("Failed to handle state change: {e}");
+                    }
+                });
+            }
+        })));
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        context.set_subscribe_callback(Some(Box::new(
+            move |facility, operation, index| {
+                let event =
+                    ServerEvent::from_subscription(facility, operation, index);
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.state.event_rate.record(Instant::now());
+                        runner.state.pending_events.extend(event);
+                    });
+                }
+            },
+        )));
+
+        self.on_context_state_changed()?;
+        Ok(())
+    }
+
+    fn load_remap_module<T: DeviceType>(
+        &mut self,
+        params: RemapModuleParams<'_>,
+    ) {
+        info!(
+            "Loading {} remap module for '{}' with master {}",
+            T::name_lower_case(),
+            params.config_name,
+            params.master_name
+        );
+
+        self.load_managed_module::<T>(ModuleLoadParams {
+            config_name: params.config_name,
+            module_name: T::module_name(),
+            argument: modargs::remap(
+                params.remap_config,
+                T::name_lower_case(),
+                modargs::RemapNames {
+                    master_name: params.master_name,
+                    config_name: params.config_name,
+                },
+            ),
+            reason: format!("master {} is present", params.master_name),
+        });
+    }
+
+    fn load_managed_module<T: DeviceType>(
+        &mut self,
+        params: ModuleLoadParams<'_>,
+    ) {
+        self.submit(
+            Action::LoadModule {
+                kind: T::kind(),
+                config: params.config_name.to_string(),
+                module: params.module_name,
+                argument: params.argument,
+            },
+            params.reason,
+        );
+    }
+
+    fn load_module<T: DeviceType>(&mut self, load: ModuleLoad) {
+        let target = Target::Module(T::kind(), load.config_name.clone());
+        if T::select(&self.state.all_devices)
+            .remap_module_index(&load.config_name)
+            .is_some()
+        {
+            // Loaded by an action deferred before this one
+            self.finish_action(&target, true);
+            return;
+        }
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        let argument = load.argument.clone();
+        let _op = self.state.context.introspect().load_module(
+            load.module_name,
+            &argument,
+            move |module_index| {
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        let success = module_index != INVALID_INDEX;
+                        runner.finish_module_load::<T>(&load, module_index);
+                        runner.finish_action(&target, success);
+                    });
+                }
+            },
+        );
+    }
+
+    fn finish_module_load<T: DeviceType>(
+        &mut self,
+        load: &ModuleLoad,
+        module_index: u32,
+    ) {
+        let config_name = load.config_name.as_str();
+        let label = if load.module_name == T::jack_module_name() {
+            "JACK"
+        } else {
+            "remap"
+        };
+        if module_index == INVALID_INDEX {
+            self.handle_module_load_failure::<T>(config_name);
+            return;
+        }
+
+        // The master is resolved by name when the module is loaded, so the
+        // device it is bound to is the one present now
+        let master = match T::get_definitions(&self.state.config)
+            .get(config_name)
+            .map(|config| &config.match_config)
+        {
+            Some(DeviceMatchConfig::Remap(remap)) => {
+                T::resolve_master(&self.state.all_devices, &remap.master)
+                    .map(|(index, _)| index)
+            }
+            _ => None,
+        };
+        let devices = T::select_mut(&mut self.state.all_devices);
+        let config = devices.config_ids.intern(config_name);
+        devices.remap_module_indices.insert(config, module_index);
+        devices
+            .remap_module_arguments
+            .insert(config, load.argument.clone());
+        if let Some(master) = master {
+            devices.remap_masters.insert(config, master);
+        }
+        self.state.activity.modules_loaded += 1;
+        info!(
+            "Successfully loaded {} {} module #{} for '{}'",
+            T::name_lower_case(),
+            label,
+            module_index,
+            config_name
+        );
+        self.state.events.publish(Event::ModuleLoaded {
+            kind: T::name_lower_case(),
+            config: config_name.to_string(),
+            module: module_index,
+        });
+        // The remap device may have been reported before its owner module
+        // was known, and unchanged snapshots are not re-matched
+        if self.state.rematch_devices::<T>() {
+            self.update_default_device::<T>();
+            self.check_and_load_all_remaps();
+        }
+    }
+
+    fn handle_module_load_failure<T: DeviceType>(
+        &mut self,
+        config_name: &str,
+    ) {
+        let err = self.state.context.errno();
+
+        if denies_module_loading(err) {
+            if !self.state.module_loading_disabled {
+                self.state.module_loading_disabled = true;
+                if self.state.sandboxed {
+                    warn!(
+                        "Module loading is denied to the Flatpak sandbox; \
+                         remap and JACK bridge devices will not be created"
+                    );
+                } else {
+                    warn!(
+                        "Module loading is disabled on the server; remap \
+                         and JACK bridge devices will not be created"
+                    );
+                }
+            }
+            return;
+        }
+
+        error!(
+            "Failed to load {} module for '{}': {}",
+            T::name_lower_case(),
+            config_name,
+            err
+        );
+        self.state.events.publish(Event::ModuleFailed {
+            kind: T::name_lower_case(),
+            config: config_name.to_string(),
+            error: format!("{err}"),
+        });
+    }
+
+    fn unload_remap_module<T: DeviceType>(&mut self, config_name: &str) {
+        let module = {
+            let devices = T::select(&self.state.all_devices);
+            devices.config_ids.get(config_name).and_then(|config| {
+                let index = devices.remap_module_indices.get(&config)?;
+                Some((config, *index))
+            })
+        };
+
+        if let Some((config, index)) = module {
+            T::select_mut(&mut self.state.all_devices)
+                .unloading_modules
+                .insert(index);
+            let weak_origin = Rc::downgrade(&self.origin);
+            let config_name_owned = config_name.to_string();
+
+            info!(
+                "Unloading {} module #{} for '{}'",
+                T::name_lower_case(),
+                index,
+                config_name
+            );
+
+            let _op = self.state.context.introspect().unload_module(
+                index,
+                move |success| {
+                    if let Some(origin) = weak_origin.upgrade() {
+                        StateRunner::with(&origin, |runner| {
+                            T::select_mut(&mut runner.state.all_devices)
+                                .unloading_modules
+                                .remove(&index);
+                            if success {
+                                T::select_mut(&mut runner.state.all_devices)
+                                    .forget_module(config);
+                                runner.state.activity.modules_unloaded += 1;
+                                info!(
+                                    "Successfully unloaded {} module #{} for '{}'",
+                                    T::name_lower_case(),
+                                    index,
+                                    config_name_owned
+                                );
+                                runner.state.events.publish(Event::ModuleUnloaded {
+                                    kind: T::name_lower_case(),
+                                    config: config_name_owned.clone(),
+                                    module: index,
+                                });
+                                // Reload if it was unloaded for a parameter change
+                                runner.check_and_load_all_remaps();
+                            } else {
+                                error!(
+                                    "Failed to unload {} module #{} for '{}'",
+                                    T::name_lower_case(),
+                                    index,
+                                    config_name_owned
+                                );
+                                // The server may have unloaded it along
+                                // with its master, which would leave the
+                                // remap unloaded for good if not noticed
+                                runner.reconcile_modules();
+                            }
+                        });
+                    }
+                },
+            );
+        }
+    }
+
+    fn check_and_load_remaps<T: DeviceType>(&mut self) {
+        // Skip remap loading if shutting down
+        if self.state.shutting_down {
+            debug!("Skipping remap loading during shutdown");
+            return;
+        }
+        if self.state.module_loading_disabled {
+            return;
+        }
+        if self.state.paused {
+            debug!("Paused, not loading remap devices");
+            return;
+        }
+        if self.state.session_manager_policy() == SessionManagerPolicy::Defer {
+            debug!("Leaving remap devices to the session manager");
+            return;
+        }
+
+        let remaps_supported = self
+            .state
+            .server
+            .as_ref()
+            .is_none_or(|server| server.supports_module(T::module_name()));
+
+        let configs = T::get_definitions(&self.state.config);
+        let all_devices = &self.state.all_devices;
+        let devices = T::select(all_devices);
+
+        // Find all remap and JACK configs that should be loaded
+        let mut remaps_to_load = Vec::new();
+        let mut jacks_to_load = Vec::new();
+        let mut trace_rows = Vec::new();
+        let mut name_collisions = HashSet::new();
+
+        for (config_name, config) in configs {
+            if devices.config_ids.get(config_name).is_some_and(|config| {
+                devices.accepted_unloads.contains(&config)
+            }) {
+                if let DeviceMatchConfig::Remap(remap) = &config.match_config {
+                    trace_rows.push(vec![
+                        config_name.clone(),
+                        remap.master.to_string(),
+                        "unloaded by someone else".to_string(),
+                    ]);
+                }
+                continue;
+            }
+            if let Some(index) = devices.remap_module_index(config_name) {
+                if let DeviceMatchConfig::Remap(remap) = &config.match_config {
+                    trace_rows.push(vec![
+                        config_name.clone(),
+                        remap.master.to_string(),
+                        format!("loaded as module #{index}"),
+                    ]);
+                }
+                continue;
+            }
+
+            match &config.match_config {
+                DeviceMatchConfig::Remap(remap) if !remaps_supported => {
+                    debug!(
+                        "Skipping {} remap '{}' unsupported by the server",
+                        T::name_lower_case(),
+                        config_name
+                    );
+                    trace_rows.push(vec![
+                        config_name.clone(),
+                        remap.master.to_string(),
+                        "unsupported by the server".to_string(),
+                    ]);
+                }
+                DeviceMatchConfig::Remap(remap) => {
+                    // Check if the master device exists
+                    let master = T::resolve_master(all_devices, &remap.master)
+                        .map(|(_, name)| name);
+                    let to_load = master.and_then(|_| {
+                        devices.remap_to_load(config_name, remap)
+                    });
+                    trace_rows.push(vec![
+                        config_name.clone(),
+                        remap.master.to_string(),
+                        match (master, &to_load) {
+                            (Some(name), Some(_)) => {
+                                format!("loading on {name}")
+                            }
+                            (Some(_), None) => "device name taken".to_string(),
+                            (None, _) => "master not present".to_string(),
+                        },
+                    ]);
+                    let collides = match &to_load {
+                        Some(loaded) => {
+                            loaded.device_name != remap.device_name
+                        }
+                        None => master.is_some(),
+                    };
+                    if collides
+                        && let Some(config) =
+                            devices.config_ids.get(config_name)
+                    {
+                        name_collisions.insert(config);
+                        if !devices.name_collisions.contains(&config) {
+                            NameCollision {
+                                config_name,
+                                remap,
+                                to_load: to_load.as_deref(),
+                            }
+                            .report::<T>();
+                        }
+                    }
+                    if let (Some(master_name), Some(remap)) = (master, to_load)
+                    {
+                        remaps_to_load.push((
+                            config_name.clone(),
+                            remap.into_owned(),
+                            master_name.to_string(),
+                        ));
+                    }
+                }
+                DeviceMatchConfig::Jack(jack)
+                    if jack.load && self.state.jack_running =>
+                {
+                    jacks_to_load
+                        .push((config_name.clone(), jack::module_args(jack)));
+                }
+                _ => {}
+            }
+        }
+
+        if self.state.config.trace_decisions && !trace_rows.is_empty() {
+            trace_rows.sort();
+            info!("Remap {} decisions:", T::name_lower_case());
+            let header = ["Config", "Master", "Decision"];
+            for line in trace::format_table(&header, &trace_rows) {
+                info!("  {line}");
+            }
+        }
+
+        // Report collisions only until they are resolved
+        T::select_mut(&mut self.state.all_devices).name_collisions =
+            name_collisions;
+
+        // Load all pending remaps
+        for (config_name, remap, master_name) in remaps_to_load {
+            self.load_remap_module::<T>(RemapModuleParams {
+                config_name: &config_name,
+                remap_config: &remap,
+                master_name: &master_name,
+            });
+        }
+
+        for (config_name, argument) in jacks_to_load {
+            info!("Loading {} for '{}'", T::jack_module_name(), config_name);
+            self.load_managed_module::<T>(ModuleLoadParams {
+                config_name: &config_name,
+                module_name: T::jack_module_name(),
+                argument,
+                reason: "a JACK server is running".to_string(),
+            });
+        }
+    }
+
+    fn check_and_unload_remaps<T: DeviceType>(&mut self) {
+        let configs = T::get_definitions(&self.state.config);
+        let remaps_to_unload = self
+            .state
+            .all_devices
+            .outdated_modules::<T>(configs, self.state.jack_running);
+
+        // Unload all pending remaps
+        for config_name in remaps_to_unload {
+            self.unload_remap_module::<T>(&config_name);
+        }
+    }
+
+    /// Carry out a command from a control input
+    pub fn execute(&mut self, command: &Command) -> Reply {
+        if self.state.observing
+            && !matches!(
+                command,
+                Command::Status | Command::Devices | Command::Resync
+            )
+        {
+            return Err("Observing only, not changing anything".to_string());
+        }
+        let reply = match command {
+            Command::SetDefault { device } => match device {
+                DeviceRef::Sink { sink } => self.choose_default::<Sink>(sink),
+                DeviceRef::Source { source } => {
+                    self.choose_default::<Source>(source)
+                }
+            },
+            Command::ToggleMute { device } => match device {
+                DeviceRef::Sink { sink } => self.set_mute::<Sink>(sink, None),
+                DeviceRef::Source { source } => {
+                    self.set_mute::<Source>(source, None)
+                }
+            },
+            Command::SetMute { device, muted } => match device {
+                DeviceRef::Sink { sink } => {
+                    self.set_mute::<Sink>(sink, Some(*muted))
+                }
+                DeviceRef::Source { source } => {
+                    self.set_mute::<Source>(source, Some(*muted))
+                }
+            },
+            Command::Status => {
+                Ok(Response::Status(Box::new(self.state.status_report())))
+            }
+            Command::Devices => {
+                Ok(Response::Devices(self.state.device_report()))
+            }
+            Command::Pause => {
+                if !self.state.paused {
+                    info!("Pausing by command");
+                    self.state.paused = true;
+                }
+                Ok(Response::message("Paused"))
+            }
+            Command::Resume => {
+                if self.state.paused {
+                    info!("Resuming by command");
+                    self.state.paused = false;
+                    self.state.apply_all_passthrough();
+                    self.apply_policies(PolicyEvent::Resumed);
+                }
+                Ok(Response::message("Resumed"))
+            }
+            Command::Privacy(settings) => Ok(self.set_privacy(*settings)),
+            Command::Resync => {
+                self.resync();
+                Ok(Response::message("Re-synchronizing"))
+            }
+            Command::Undo => match self.state.last_switch.take() {
+                Some(switch) if switch.kind == DeviceKind::Sink => {
+                    self.undo_switch::<Sink>(switch)
+                }
+                Some(switch) => self.undo_switch::<Source>(switch),
+                None => Err("Nothing to undo".to_string()),
+            },
+            // Needs the configuration source, which only the caller has
+            Command::Reload => Err("Reloading is not available".to_string()),
+        };
+        // Undone switches are only kept over the priorities for a while
+        if !matches!(command, Command::Undo) {
+            self.state.save_overrides();
+        }
+        reply
+    }
+
+    fn choose_default<T: DeviceType>(&mut self, config_name: &str) -> Reply {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        if scope.find_by_config_name(config_name).is_none() {
+            return Err(format!(
+                "No {} is recognized as '{}'",
+                T::name_lower_case(),
+                config_name
+            ));
+        }
+
+        info!(
+            "Choosing {} '{}' as default by command",
+            T::name_lower_case(),
+            config_name
+        );
+        scope.default_override = Some(config_name.to_string());
+        self.update_default_device::<T>();
+        Ok(Response::message(format!(
+            "Default {} is now '{}'",
+            T::name_lower_case(),
+            config_name
+        )))
+    }
+
+    /// Make the previous default the default again, keeping it over the
+    /// priorities for a while so that it is not switched away from at once
+    fn undo_switch<T: DeviceType>(&mut self, switch: DefaultSwitch) -> Reply {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        if scope.find_by_config_name(&switch.from).is_none() {
+            return Err(format!(
+                "No {} is recognized as '{}' any more",
+                T::name_lower_case(),
+                switch.from
+            ));
+        }
+
+        info!(
+            "Undoing the switch of the default {} from '{}' to '{}'",
+            T::name_lower_case(),
+            switch.from,
+            switch.to
+        );
+        scope.default_override = Some(switch.from.clone());
+        self.update_default_device::<T>();
+
+        let config_name = switch.from.clone();
+        self.state.timers.schedule(
+            &format!("undo-{}", T::name_lower_case()),
+            Timer {
+                delay: UNDO_EXEMPTION,
+                callback: Box::new(move |runner| {
+                    runner.end_undo_exemption::<T>(&config_name);
+                }),
+            },
+        );
+        Ok(Response::message(format!(
+            "Default {} is '{}' again for {} minutes",
+            T::name_lower_case(),
+            switch.from,
+            UNDO_EXEMPTION.as_secs() / 60
+        )))
+    }
+
+    fn end_undo_exemption<T: DeviceType>(&mut self, config_name: &str) {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        // Unless another command has chosen a default since
+        if scope.default_override.as_deref() != Some(config_name) {
+            return;
+        }
+        info!(
+            "Undo of the default {} has expired, following the priorities \
+             again",
+            T::name_lower_case()
+        );
+        scope.default_override = None;
+        self.state.save_overrides();
+        self.update_default_device::<T>();
+    }
+
+    fn set_privacy(&mut self, settings: PrivacySettings) -> Response {
+        if !settings.enabled {
+            let Some(privacy) = self.state.privacy.take() else {
+                return Response::message("Privacy mode is off");
+            };
+            info!("Privacy mode off");
+            let release = privacy
+                .release(&mut self.state.all_devices.sources.found_devices);
+            for index in release.unmute {
+                let _ =
+                    Source::set_mute(&mut self.state.context, index, false);
+            }
+            for index in release.resume {
+                let _ = Source::suspend(&mut self.state.context, index, false);
+            }
+            let interests = self.state.wanted_interests();
+            if interests != self.state.interests {
+                self.resubscribe(interests);
+            }
+            return Response::message("Privacy mode is off");
+        }
+
+        info!(
+            "Privacy mode on{}",
+            if settings.suspend {
+                ", suspending sources"
+            } else {
+                ""
+            }
+        );
+        let privacy = self.state.privacy.get_or_insert_with(Privacy::default);
+        privacy.suspend |= settings.suspend;
+        let interests = self.state.wanted_interests();
+        if interests != self.state.interests {
+            // Sources must be followed to keep them muted
+            self.resubscribe(interests);
+        }
+        let indices: Vec<u32> = self
+            .state
+            .all_devices
+            .sources
+            .found_devices
+            .keys()
+            .copied()
+            .collect();
+        for index in indices {
+            self.enforce_privacy::<Source>(index);
+        }
+        // Catch sources that are not followed yet
+        self.query_all_sources();
+        Response::message("Privacy mode is on")
+    }
+
+    /// Mute the source, and suspend it if requested, while in privacy mode
+    fn enforce_privacy<T: DeviceType>(&mut self, index: u32) {
+        if !T::captures_audio() {
+            return;
+        }
+        let State {
+            all_devices,
+            context,
+            privacy,
+            ..
+        } = self.state;
+        let Some(privacy) = privacy else {
+            return;
+        };
+        let Some(device) =
+            T::select_mut(all_devices).found_devices.get_mut(&index)
+        else {
+            return;
+        };
+
+        let change = privacy.enforce(index, device);
+        if change.mute {
+            info!("Muting {} for privacy", device.original_name);
+            let _ = T::set_mute(context, index, true);
+        }
+        if change.suspend {
+            let _ = T::suspend(context, index, true);
+        }
+    }
+
+    /// Mute or unmute the device, or toggle it without `muted`
+    fn set_mute<T: DeviceType>(
+        &mut self,
+        config_name: &str,
+        muted: Option<bool>,
+    ) -> Reply {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        let Some(index) = scope.find_index_by_config_name(config_name) else {
+            return Err(format!(
+                "No {} is recognized as '{}'",
+                T::name_lower_case(),
+                config_name
+            ));
+        };
+
+        // Updated by the change event as well, but commands may come in
+        // faster than that
+        let device = scope.found_devices.get_mut(&index).unwrap();
+        device.muted = muted.unwrap_or(!device.muted);
+        let muted = device.muted;
+
+        info!(
+            "{} {} '{}' by command",
+            if muted { "Muting" } else { "Unmuting" },
+            T::name_lower_case(),
+            config_name
+        );
+        let _op = T::set_mute(&mut self.state.context, index, muted);
+        Ok(Response::message(format!(
+            "{} '{}' is now {}",
+            T::name_camel_case(),
+            config_name,
+            if muted { "muted" } else { "unmuted" }
+        )))
+    }
+
+    /// Replace the configuration and re-match all known devices
+    pub fn reload_config(&mut self, config: Config) {
+        let sound_changed = config.test_sound != self.state.config.test_sound;
+        self.state.config = config;
+        self.state.apply_limits();
+
+        let interests = self.state.wanted_interests();
+        let added = interests - self.state.interests;
+        if !interests.contains(InterestMaskSet::CLIENT) {
+            // Client events are no longer followed
+            self.state.session_manager_clients.clear();
+        }
+        if interests != self.state.interests
+            && self.state.context.get_state()
+                == libpulse_binding::context::State::Ready
+        {
+            self.resubscribe(interests);
+            // Devices of newly subscribed types may be out of date
+            if added.contains(InterestMaskSet::SINK) {
+                self.query_all_sinks();
+            }
+            if added.contains(InterestMaskSet::SOURCE) {
+                self.query_all_sources();
+            }
+            if added.contains(InterestMaskSet::CLIENT) {
+                self.query_all_clients();
+            }
+        }
+
+        if !self.state.timers.is_scheduled(JACK_POLL_TIMER) {
+            self.poll_jack_server();
+        }
+        if !self.state.timers.is_scheduled(UNMANAGED_SUMMARY_TIMER) {
+            self.log_unmanaged_summary();
+        }
+        if !self.state.timers.is_scheduled(RECONCILE_TIMER) {
+            self.schedule_reconciliation();
+        }
+
+        if self.state.config.play_test_sound
+            && (sound_changed || !self.state.test_sound_ready)
+            && self.state.context.get_state()
+                == libpulse_binding::context::State::Ready
+        {
+            self.upload_test_sound();
+        }
+
+        // Modules left unloaded are loaded again, under the new config
+        self.state.all_devices.sinks.accepted_unloads.clear();
+        self.state.all_devices.sources.accepted_unloads.clear();
+
+        info!("Re-matching known devices against the new configuration");
+        self.state.rematch_devices::<Sink>();
+        self.state.rematch_devices::<Source>();
+        self.state.apply_all_passthrough();
+
+        self.apply_policies(PolicyEvent::ConfigReloaded);
+        self.update_peak_monitor::<Sink>();
+        self.update_peak_monitor::<Source>();
+    }
+
+    /// Put the configured test sound, or the built-in chime, into the
+    /// sample cache of the server
+    fn upload_test_sound(&mut self) {
+        if self.state.observing {
+            debug!("Observing, not uploading the test sound");
+            return;
+        }
+        let (spec, data) = match &self.state.config.test_sound {
+            Some(path) => match sound::read_wav(path) {
+                Ok(sound) => sound,
+                Err(e) => {
+                    warn!("Failed to load the test sound: {e}");
+                    return;
+                }
+            },
+            None => (sound::TONE_SPEC, sound::chime()),
+        };
+
+        let Some(mut stream) = Stream::new(
+            &mut self.state.context,
+            TEST_SOUND_SAMPLE,
+            &spec,
+            None,
+        ) else {
+            warn!("Failed to create a stream for the test sound");
+            return;
+        };
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        stream.set_state_callback(Some(Box::new(move || {
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    runner.handle_sample_upload_state();
+                });
+            }
+        })));
+        if let Err(e) = stream.connect_upload(data.len()) {
+            warn!("Failed to upload the test sound: {e}");
+            return;
+        }
+
+        self.state.test_sound_ready = false;
+        self.state.sample_upload = Some(SampleUpload { stream, data });
+    }
+
+    /// Open a short test stream on each device recognized as a config with
+    /// `verify_playback` that has not been checked yet
+    fn start_playback_checks<T: DeviceType>(&mut self) {
+        let State {
+            all_devices,
+            context,
+            config,
+            ..
+        } = self.state;
+        let AudioDeviceGroup {
+            found_devices,
+            config_ids,
+            playback_checks,
+            ..
+        } = T::select_mut(all_devices);
+        let configs = T::get_definitions(config);
+
+        for (&index, device) in found_devices.iter() {
+            if device.playback_verified.is_some()
+                || playback_checks.contains_key(&index)
+                || !device.recognized_as.iter().any(|&config| {
+                    configs
+                        .get(config_ids.name(config))
+                        .is_some_and(DeviceConfig::requires_playback_check)
+                })
+            {
+                continue;
+            }
+
+            let Some(mut stream) = Stream::new(
+                context,
+                "Playback check",
+                &sound::TONE_SPEC,
+                None,
+            ) else {
+                warn!(
+                    "Failed to create a stream to check {}",
+                    device.original_name
+                );
+                continue;
+            };
+            let weak_origin = Rc::downgrade(&self.origin);
+            stream.set_state_callback(Some(Box::new(move || {
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.handle_playback_check_state::<T>(index);
+                    });
+                }
+            })));
+            let weak_origin = Rc::downgrade(&self.origin);
+            stream.set_read_callback(Some(Box::new(move |_| {
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.finish_playback_check::<T>(index, true);
+                    });
+                }
+            })));
+            if let Err(e) =
+                T::connect_check(&mut stream, &device.original_name)
+            {
+                warn!("Failed to check {}: {e}", device.original_name);
+                continue;
+            }
+
+            debug!(
+                "Checking {} #{} with a test stream",
+                T::name_lower_case(),
+                index
+            );
+            playback_checks.insert(index, stream);
+        }
+    }
+
+    fn handle_playback_check_state<T: DeviceType>(&mut self, index: u32) {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        let Some(stream) = scope.playback_checks.get_mut(&index) else {
+            return;
+        };
+
+        match stream.get_state() {
+            // Sources pass once data has been recorded
+            StreamState::Ready if T::can_play_samples() => {
+                let data = sound::silence(PLAYBACK_CHECK_DURATION);
+                if stream.write(&data, None, 0, SeekMode::Relative).is_err() {
+                    self.finish_playback_check::<T>(index, false);
+                    return;
+                }
+
+                let weak_origin = Rc::downgrade(&self.origin);
+                let _op = stream.drain(Some(Box::new(move |success| {
+                    if let Some(origin) = weak_origin.upgrade() {
+                        StateRunner::with(&origin, |runner| {
+                            runner.finish_playback_check::<T>(index, success);
+                        });
+                    }
+                })));
+            }
+            StreamState::Failed | StreamState::Terminated => {
+                self.finish_playback_check::<T>(index, false);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish_playback_check<T: DeviceType>(
+        &mut self,
+        index: u32,
+        success: bool,
+    ) {
+        let scope = T::select_mut(&mut self.state.all_devices);
+        let Some(device) = scope.found_devices.get_mut(&index) else {
+            return;
+        };
+        if device.playback_verified.is_some() {
+            return;
+        }
+
+        device.playback_verified = Some(success);
+        if success {
+            info!(
+                "{} #{} passed the playback check",
+                T::name_camel_case(),
+                index
+            );
+        } else {
+            warn!(
+                "{} #{} ({}) failed the playback check, not making it default",
+                T::name_camel_case(),
+                index,
+                device.original_name
+            );
+        }
+
+        // The stream cannot be dropped from its own callbacks
+        self.state.timers.schedule(
+            &format!("playback-check-{}-{index}", T::name_lower_case()),
+            Timer {
+                delay: Duration::ZERO,
+                callback: Box::new(move |runner| {
+                    T::select_mut(&mut runner.state.all_devices)
+                        .playback_checks
+                        .remove(&index);
+                    if success {
+                        runner.request_reevaluation::<T>();
+                    }
+                }),
+            },
+        );
+    }
+
+    fn handle_sample_upload_state(&mut self) {
+        let Some(upload) = &mut self.state.sample_upload else {
+            return;
+        };
+
+        match upload.stream.get_state() {
+            StreamState::Ready => {
+                let result = upload
+                    .stream
+                    .write(&upload.data, None, 0, SeekMode::Relative)
+                    .and_then(|()| upload.stream.finish_upload());
+                match result {
+                    Ok(()) => {
+                        debug!("Uploaded the test sound");
+                        self.state.test_sound_ready = true;
+                    }
+                    Err(e) => warn!("Failed to upload the test sound: {e}"),
+                }
+            }
+            StreamState::Failed => {
+                warn!(
+                    "Failed to upload the test sound: {}",
+                    self.state.context.errno()
+                );
+            }
+            _ => {}
+        }
+
+        if !upload.stream.get_state().is_good() {
+            // The stream cannot be dropped from its own callback
+            self.state.timers.schedule(
+                SAMPLE_UPLOAD_TIMER,
+                Timer {
+                    delay: Duration::ZERO,
+                    callback: Box::new(|runner| {
+                        // Unless another upload has started meanwhile
+                        let upload = &mut runner.state.sample_upload;
+                        if upload.as_ref().is_some_and(|upload| {
+                            !upload.stream.get_state().is_good()
+                        }) {
+                            *upload = None;
+                        }
+                    }),
+                },
+            );
+        }
+    }
+
+    /// Play a tone on the sink recognized as the config, to check which
+    /// physical output it is
+    pub fn start_test_tone(
+        &mut self,
+        config_name: &str,
+    ) -> Result<(), String> {
+        let sinks = &self.state.all_devices.sinks;
+        let Some(device) = sinks.find_by_config_name(config_name) else {
+            return Err(
+                if self.state.config.sinks.contains_key(config_name) {
+                    format!("No sink is recognized as '{config_name}'")
+                } else {
+                    format!("'{config_name}' is not a sink config")
+                },
+            );
+        };
+        let device_name = device.original_name.clone();
+
+        let mut stream = Stream::new(
+            &mut self.state.context,
+            "Test tone",
+            &sound::TONE_SPEC,
+            None,
+        )
+        .ok_or("Failed to create a playback stream")?;
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        stream.set_state_callback(Some(Box::new(move || {
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    runner.handle_test_tone_state();
+                });
+            }
+        })));
+        stream
+            .connect_playback(
+                Some(&device_name),
+                None,
+                StreamFlagSet::NOFLAGS,
+                None,
+                None,
+            )
+            .map_err(|e| format!("Failed to play on {device_name}: {e}"))?;
+
+        info!("Playing a test tone on {device_name}");
+        self.state.test_tone = Some(TestTone {
+            stream,
+            data: sound::tone(TEST_TONE_FREQUENCY, TEST_TONE_DURATION),
+            result: None,
+        });
+        Ok(())
+    }
+
+    fn handle_test_tone_state(&mut self) {
+        let Some(tone) = &mut self.state.test_tone else {
+            return;
+        };
+
+        match tone.stream.get_state() {
+            StreamState::Ready => {
+                if let Err(e) =
+                    tone.stream.write(&tone.data, None, 0, SeekMode::Relative)
+                {
+                    tone.result = Some(Err(format!("Failed to play: {e}")));
+                    return;
+                }
+
+                let weak_origin = Rc::downgrade(&self.origin);
+                let _op = tone.stream.drain(Some(Box::new(move |success| {
+                    if let Some(origin) = weak_origin.upgrade() {
+                        StateRunner::with(&origin, |runner| {
+                            if let Some(tone) = &mut runner.state.test_tone {
+                                tone.result = Some(if success {
+                                    Ok(())
+                                } else {
+                                    Err("Failed to play".to_string())
+                                });
+                            }
+                        });
+                    }
+                })));
+            }
+            StreamState::Failed | StreamState::Terminated
+                if tone.result.is_none() =>
+            {
+                tone.result = Some(Err(format!(
+                    "Failed to play: {}",
+                    self.state.context.errno()
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    /// Follow whether a JACK server is running, as long as any config
+    /// wants JACK bridges to be loaded
+    fn poll_jack_server(&mut self) {
+        if !has_managed_jack(&self.state.config) {
+            return;
+        }
+
+        let running = jack::is_server_running();
+        if running != self.state.jack_running {
+            if running {
+                info!("JACK server is running");
+            } else {
+                info!("JACK server has stopped");
+            }
+            self.state.jack_running = running;
+            self.apply_policies(PolicyEvent::JackServerChanged);
+        }
+
+        self.state.timers.schedule(
+            JACK_POLL_TIMER,
+            Timer {
+                delay: JACK_POLL_INTERVAL,
+                callback: Box::new(|runner| runner.poll_jack_server()),
+            },
+        );
+    }
+
+    /// Log the devices matching no config, and again after the configured
+    /// interval
+    fn log_unmanaged_summary(&mut self) {
+        let Some(interval) = self.state.config.unmanaged_summary_interval
+        else {
+            return;
+        };
+
+        if self.state.is_initial_sync_complete() {
+            match self.state.unmanaged_summary() {
+                Some(summary) => info!("{summary}"),
+                None => info!("All devices are managed"),
+            }
+        }
+
+        self.state.timers.schedule(
+            UNMANAGED_SUMMARY_TIMER,
+            Timer {
+                delay: Duration::from_secs(interval),
+                callback: Box::new(|runner| runner.log_unmanaged_summary()),
+            },
+        );
+    }
+
+    fn schedule_reconciliation(&mut self) {
+        let interval = self
+            .state
+            .config
+            .reconcile_interval
+            .unwrap_or(DEFAULT_RECONCILE_INTERVAL);
+        if interval == 0 {
+            return;
+        }
+
+        self.state.timers.schedule(
+            RECONCILE_TIMER,
+            Timer {
+                delay: Duration::from_secs(interval),
+                callback: Box::new(|runner| runner.reconcile()),
+            },
+        );
+    }
+
+    /// List the devices and modules again and repair what events missed
+    /// by the subscription left behind, e.g. after suspend and resume
+    fn reconcile(&mut self) {
+        if self.state.is_initial_sync_complete() {
+            debug!("Reconciling the state with the server");
+            self.reconcile_devices::<Sink>();
+            self.reconcile_devices::<Source>();
+            self.reconcile_modules();
+        }
+        self.schedule_reconciliation();
+    }
+
+    /// Reconcile right away, and re-apply defaults and remaps afterwards
+    /// even where nothing seems to have changed
+    fn resync(&mut self) {
+        if !self.state.is_initial_sync_complete() {
+            return;
+        }
+        info!("Re-synchronizing with the server");
+        self.state.resync_pending = true;
+        self.reconcile_devices::<Sink>();
+        self.reconcile_devices::<Source>();
+        // Listed last, so that the devices are known by then
+        self.reconcile_modules();
+    }
+
+    /// Forget all devices and list them again, without unloading our
+    /// modules, for drift the reconciliation does not repair
+    pub fn rediscover(&mut self) {
+        if !self.state.is_initial_sync_complete() {
+            return;
+        }
+        info!("Forgetting all devices and listing them again");
+        self.state.all_devices.sinks.forget_devices();
+        self.state.all_devices.sources.forget_devices();
+        // Defaults and remaps are applied again once the modules are known
+        self.state.resync_pending = true;
+        self.query_all_sinks();
+        self.query_all_sources();
+        self.reconcile_modules();
+    }
+
+    fn reconcile_devices<T: DeviceType>(&mut self) {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let mut listed = Vec::new();
+        T::list_all(&self.state.context.introspect(), move |list_result| {
+            match list_result {
+                ListResult::Item(info) => {
+                    listed.push(State::snapshot_device::<T>(info));
+                }
+                ListResult::End => {
+                    let listed = std::mem::take(&mut listed);
+                    if let Some(origin) = weak_origin.upgrade() {
+                        StateRunner::with(&origin, |runner| {
+                            runner.repair_devices::<T>(listed);
+                        });
+                    }
+                }
+                ListResult::Error => warn!(
+                    "Failed to list the {}s for reconciliation",
+                    T::name_lower_case()
+                ),
+            }
+        });
+    }
+
+    /// Bring the known devices in line with a fresh list of the server
+    fn repair_devices<T: DeviceType>(&mut self, listed: Vec<IndexedDevice>) {
+        let gone = T::select(&self.state.all_devices).missing_from(&listed);
+        let mut drift = gone.len();
+        for index in gone {
+            warn!("{} #{index} has gone without notice", T::name_camel_case());
+            self.handle_device_removed::<T>(index);
+        }
+
+        let mut is_relevant = false;
+        for mut fresh in listed {
+            // Compared as it would be kept
+            self.state.trim_properties(&mut fresh.device);
+            if !T::select(&self.state.all_devices).is_outdated(&fresh) {
+                continue;
+            }
+            let index = fresh.index;
+            warn!(
+                "{} #{index} has appeared or changed without notice",
+                T::name_camel_case()
+            );
+            drift += 1;
+            is_relevant |= self.state.add_device::<T>(fresh);
+            self.enforce_privacy::<T>(index);
+        }
+        if is_relevant {
+            self.request_reevaluation::<T>();
+        }
+        self.state.drift_repairs += drift as u64;
+    }
+
+    fn reconcile_modules(&mut self) {
+        let weak_origin = Rc::downgrade(&self.origin);
+        let mut loaded = HashSet::new();
+        let _op = self.state.context.introspect().get_module_info_list(
+            move |list_result| match list_result {
+                ListResult::Item(info) => {
+                    loaded.insert(info.index);
+                }
+                ListResult::End => {
+                    let loaded = std::mem::take(&mut loaded);
+                    if let Some(origin) = weak_origin.upgrade() {
+                        StateRunner::with(&origin, |runner| {
+                            runner.repair_modules(&loaded);
+                        });
+                    }
+                }
+                ListResult::Error => {
+                    warn!("Failed to list the modules for reconciliation")
+                }
+            },
+        );
+    }
+
+    /// Forget the modules we loaded that are gone, and load them again
+    fn repair_modules(&mut self, loaded: &HashSet<u32>) {
+        let drift = self.forget_unloaded_modules::<Sink>(loaded)
+            + self.forget_unloaded_modules::<Source>(loaded);
+        if drift > 0 {
+            self.state.drift_repairs += drift as u64;
+            self.check_and_load_all_remaps();
+        }
+        if std::mem::take(&mut self.state.resync_pending) {
+            self.request_reevaluation::<Sink>();
+            self.request_reevaluation::<Source>();
+        }
+    }
+
+    fn forget_unloaded_modules<T: DeviceType>(
+        &mut self,
+        loaded: &HashSet<u32>,
+    ) -> usize {
+        let gone = T::select(&self.state.all_devices).unloaded_modules(loaded);
+        gone.into_iter()
+            .filter(|&module| self.forget_unloaded_module::<T>(module))
+            .count()
+    }
+
+    /// Forget a module of ours that has been unloaded without us asking
+    /// for it, and leave it unloaded if its config accepts that
+    fn forget_unloaded_module<T: DeviceType>(&mut self, module: u32) -> bool {
+        let all_devices = &self.state.all_devices;
+        let group = T::select(all_devices);
+        // Unloaded by ourselves, if shutting down all of them
+        if self.state.shutting_down
+            || group.unloading_modules.contains(&module)
+        {
+            return false;
+        }
+        let Some(config) = group
+            .remap_module_indices
+            .iter()
+            .find(|&(_, &index)| index == module)
+            .map(|(&config, _)| config)
+        else {
+            return false;
+        };
+        let config_name = group.config_ids.name(config).to_string();
+        let device_config =
+            T::get_definitions(&self.state.config).get(&config_name);
+
+        // The server unloads remaps along with their master by itself
+        let master_present = match device_config
+            .map(|device_config| &device_config.match_config)
+        {
+            Some(DeviceMatchConfig::Remap(remap)) => {
+                T::resolve_master(all_devices, &remap.master).is_some_and(
+                    |(master, _)| {
+                        group.remap_masters.get(&config) == Some(&master)
+                    },
+                )
+            }
+            _ => true,
+        };
+        let policy = device_config
+            .map_or(ExternalUnloadPolicy::Reload, external_unload_policy);
+
+        let group = T::select_mut(&mut self.state.all_devices);
+        group.forget_module(config);
+        if !master_present {
+            debug!(
+                "{} module #{module} for '{config_name}' has gone with its \
+                 master",
+                T::name_camel_case()
+            );
+        } else {
+            warn!(
+                "Module #{module} for '{config_name}' has been unloaded \
+                 without notice"
+            );
+            if policy == ExternalUnloadPolicy::Accept {
+                info!("Leaving '{config_name}' unloaded as configured");
+                group.accepted_unloads.insert(config);
+            }
+        }
+        self.state.events.publish(Event::ModuleUnloaded {
+            kind: T::name_lower_case(),
+            config: config_name,
+            module,
+        });
+        true
+    }
+
+    fn check_and_load_all_remaps(&mut self) {
+        // Source remaps may be backed by sink monitors, so both groups are
+        // re-evaluated whenever either of them changes
+        self.check_and_load_remaps::<Sink>();
+        self.check_and_load_remaps::<Source>();
+    }
+
+    fn check_and_unload_all_remaps(&mut self) {
+        self.check_and_unload_remaps::<Sink>();
+        self.check_and_unload_remaps::<Source>();
+    }
+
+    pub fn with<Fn, Ret>(scope: &Rc<RefCell<State>>, proc: Fn) -> Ret
+    where
+        Fn: FnOnce(&mut StateRunner<'_>) -> Ret,
+    {
+        let mut runner = StateRunner {
+            origin: Rc::clone(scope),
+            state: &mut scope.borrow_mut(),
+        };
+        let ret = proc(&mut runner);
+        runner.dispatch_events();
+        runner.run_pending_actions();
+        ret
+    }
+
+    /// Handle the queued server events, including those queued by the
+    /// handlers themselves
+    fn dispatch_events(&mut self) {
+        while let Some(event) = self.state.pending_events.pop_front() {
+            debug!("Got notified by {event}");
+            self.handle_event(event);
+        }
+    }
+
+    /// Apply the policies to a server event
+    fn handle_event(&mut self, event: ServerEvent) {
+        match event {
+            // Only relevant differences of changed devices trigger
+            // re-evaluation
+            ServerEvent::DeviceAdded(DeviceKind::Sink, index)
+            | ServerEvent::DeviceChanged(DeviceKind::Sink, index) => {
+                self.query_sink_by_index(index);
+            }
+            ServerEvent::DeviceAdded(DeviceKind::Source, index)
+            | ServerEvent::DeviceChanged(DeviceKind::Source, index) => {
+                self.query_source_by_index(index);
+            }
+            ServerEvent::DeviceRemoved(DeviceKind::Sink, index) => {
+                self.handle_device_removed::<Sink>(index);
+            }
+            ServerEvent::DeviceRemoved(DeviceKind::Source, index) => {
+                self.handle_device_removed::<Source>(index);
+            }
+            ServerEvent::PlaybackStreamAdded(index) => {
+                self.query_sink_input_by_index(index);
+            }
+            ServerEvent::RecordingStreamAdded(index) => {
+                self.query_source_output_by_index(index);
+            }
+            ServerEvent::RecordingStreamRemoved(index) => {
+                self.handle_recording_stopped(index);
+            }
+            ServerEvent::ClientAdded(index) => {
+                self.query_client_by_index(index);
+            }
+            ServerEvent::ClientRemoved(index) => {
+                self.handle_client_removed(index);
+            }
+            ServerEvent::ModuleRemoved(index) => {
+                if self.forget_unloaded_module::<Sink>(index)
+                    | self.forget_unloaded_module::<Source>(index)
+                {
+                    self.check_and_load_all_remaps();
+                }
+            }
+        }
+    }
+
+    /// Run the actions of device configs whose devices have appeared or
+    /// disappeared meanwhile
+    fn run_pending_actions(&mut self) {
+        if self.state.pending_actions.is_empty() {
+            return;
+        }
+        let mut actions = std::mem::take(&mut self.state.pending_actions);
+        for action in actions.drain(..) {
+            if self.state.observing {
+                info!("Observing: would run device action {action:?}");
+                continue;
+            }
+            debug!("Running device action {action:?}");
+            if let Err(e) = self.execute(&action) {
+                warn!("Device action {action:?} failed: {e}");
+            }
+        }
+        // Keep the buffer unless the actions queued others meanwhile
+        if self.state.pending_actions.is_empty() {
+            self.state.pending_actions = actions;
+        }
+    }
+
+    /// Run the timers that are due, each with its own runner so that
+    /// callbacks scheduled by them are not lost
+    pub fn run_due_timers(scope: &Rc<RefCell<State>>) {
+        let callbacks = scope.borrow_mut().timers.take_due(Instant::now());
+        for callback in callbacks {
+            StateRunner::with(scope, callback);
+        }
+    }
+}
+
+impl State {
+    pub fn begin_shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    /// Explain denied requests by the sandbox rather than the server
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+        self.apply_limits();
+    }
+
+    pub fn mark_sandboxed(&mut self) {
+        self.sandboxed = true;
+    }
+
+    /// Observe devices without changing defaults or loading modules
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Read the adjustments made by commands before the last restart;
+    /// defaults and privacy mode are restored once the devices are listed
+    pub fn restore_overrides(&mut self) {
+        let Some(path) = &self.config.overrides_file else {
+            return;
+        };
+        match Overrides::load(path) {
+            Ok(overrides) => {
+                if overrides.paused {
+                    info!("Staying paused as before the restart");
+                    self.paused = true;
+                }
+                self.overrides = overrides;
+            }
+            Err(e) => {
+                warn!("Ignoring the overrides in {}: {e}", path.display());
+            }
+        }
+    }
+
+    fn current_overrides(&self) -> Overrides {
+        Overrides {
+            default_sink: self.all_devices.sinks.default_override.clone(),
+            default_source: self.all_devices.sources.default_override.clone(),
+            paused: self.paused,
+            privacy: self.privacy.as_ref().map(|privacy| PrivacySettings {
+                enabled: true,
+                suspend: privacy.suspend,
+            }),
+        }
+    }
+
+    /// Write the adjustments to the overrides file if they have changed
+    fn save_overrides(&mut self) {
+        // Restored ones are not in effect until the devices are listed
+        if self.observing || !self.is_initial_sync_complete() {
+            return;
+        }
+        let Some(path) = &self.config.overrides_file else {
+            return;
+        };
+        let current = self.current_overrides();
+        if current == self.overrides {
+            return;
+        }
+        if let Err(e) = current.save(path) {
+            warn!("Failed to save the overrides to {}: {e}", path.display());
+        }
+        self.overrides = current;
+    }
+
+    /// Pause for good, logging the defaults that would be set and refusing
+    /// commands that would change anything
+    pub fn observe(&mut self) {
+        self.paused = true;
+        self.observing = true;
+    }
+
+    /// Count the defaults in the statistics file, if configured
+    pub fn enable_statistics(&mut self, state_dir: &Path) {
+        let Some(config) = &self.config.statistics else {
+            return;
+        };
+        let path = config
+            .file
+            .clone()
+            .unwrap_or_else(|| state_dir.join(statistics::FILE_NAME));
+        let now = journal::unix_time(SystemTime::now());
+        self.statistics =
+            Some(statistics::Recorder::open(path, config.clone(), now));
+    }
+
+    /// Count the time of the current defaults in the statistics
+    pub fn close_statistics(&mut self) {
+        if let Some(statistics) = &mut self.statistics {
+            statistics.close(journal::unix_time(SystemTime::now()));
+        }
+    }
+
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+
+    /// Where to write the summary on exit, if anywhere
+    pub fn summary_file(&self) -> Option<&Path> {
+        self.config.summary_file.as_deref()
+    }
+
+    /// Why the connection has failed, if it has
+    pub fn connection_error(&self) -> Option<PAErr> {
+        self.is_connection_failed().then(|| self.context.errno())
+    }
+
+    pub fn is_connection_failed(&self) -> bool {
+        matches!(
+            self.context.get_state(),
+            libpulse_binding::context::State::Failed
+                | libpulse_binding::context::State::Terminated
+        )
+    }
+
+    /// Take the peaks recorded by the monitor of a default device
+    fn read_peak<T: DeviceType>(&mut self) {
+        let scope = T::select_mut(&mut self.all_devices);
+        let Some(monitor) = &mut scope.peak_monitor else {
+            return;
+        };
+
+        loop {
+            let level = match monitor.stream.peek() {
+                Ok(PeekResult::Empty) | Err(_) => return,
+                Ok(PeekResult::Hole(_)) => None,
+                Ok(PeekResult::Data(data)) => last_peak(data),
+            };
+            if let Some(level) = level {
+                monitor.level = level;
+            }
+            if monitor.stream.discard().is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Whether the monitored default device has been silent for too long
+    /// while streams are playing on it
+    fn is_silent_too_long<T: DeviceType>(&mut self) -> Option<u32> {
+        let failover = T::silence_failover(&self.config)?;
+        let AudioDeviceGroup {
+            found_devices,
+            peak_monitor,
+            ..
+        } = T::select_mut(&mut self.all_devices);
+        let monitor = peak_monitor.as_mut()?;
+        let device = found_devices.get(&monitor.device_index)?;
+
+        if device.silent
+            || !device.running
+            || monitor.level > failover.threshold()
+        {
+            monitor.silent_since = None;
+            return None;
+        }
+        let since = *monitor.silent_since.get_or_insert_with(Instant::now);
+        (since.elapsed() >= failover.duration())
+            .then_some(monitor.device_index)
+    }
+
+    /// Current peak level of the default device, if it is monitored
+    fn peak_level<T: DeviceType>(&self) -> Option<f32> {
+        T::select(&self.all_devices)
+            .peak_monitor
+            .as_ref()
+            .map(|monitor| monitor.level)
+    }
+
+    /// Events to subscribe to for the configuration and privacy mode
+    fn wanted_interests(&self) -> InterestMaskSet {
+        let interests = subscription_interests(&self.config);
+        if self.privacy.is_some() {
+            interests | InterestMaskSet::SOURCE
+        } else {
+            interests
+        }
+    }
+
+    fn headset_profile_switching_config(
+        &self,
+    ) -> Option<&HeadsetProfileConfig> {
+        self.config.headset_profile_switching.as_ref()
+    }
+
+    /// Outcome of the test tone, once it has finished playing
+    pub fn test_tone_result(&self) -> Option<Result<(), String>> {
+        self.test_tone.as_ref().and_then(|tone| tone.result.clone())
+    }
+
+    /// Summary of the server and the session managers running on it, once
+    /// both are known
+    pub fn server_diagnostics(&self) -> Option<(String, Vec<String>)> {
+        let server = self.server.as_ref()?;
+        let names = self.session_manager_names.as_ref()?;
+        Some((server.summary(), names.clone()))
+    }
+
+    /// Whether the initial sink and source lists have been received
+    pub fn is_initial_sync_complete(&self) -> bool {
+        self.all_devices.sinks.enumerated
+            && self.all_devices.sources.enumerated
+    }
+
+    /// One-line summary of the state for service managers
+    pub fn status(&self) -> String {
+        let groups = [
+            (Sink::name_lower_case(), &self.all_devices.sinks),
+            (Source::name_lower_case(), &self.all_devices.sources),
+        ];
+
+        for (name, group) in &groups {
+            if group.list_error_count > LIST_RETRY_LIMIT {
+                return format!("Failed to load the list of {name}s");
+            }
+        }
+        if !self.is_initial_sync_complete() {
+            return "Synchronizing with the server".to_string();
+        }
+
+        let [sinks, sources] = groups.map(|(_, group)| {
+            group
+                .found_devices
+                .values()
+                .filter(|device| !device.recognized_as.is_empty())
+                .count()
+        });
+        let mut status =
+            format!("Managing {sinks} sinks and {sources} sources");
+        if self.module_loading_disabled {
+            status.push_str(" (remaps disabled by the server)");
+        }
+        if self.observing {
+            status.push_str(" (observing)");
+        } else if self.paused {
+            status.push_str(" (paused)");
+        }
+        status
+    }
+
+    /// Description of the devices matching no config, if there are any
+    pub fn unmanaged_summary(&self) -> Option<String> {
+        self.all_devices.unmanaged_summary()
+    }
+
+    fn status_report(&self) -> StatusReport {
+        StatusReport {
+            status: self.status(),
+            paused: self.paused,
+            privacy: self.privacy.is_some(),
+            server: self.server.as_ref().map(ServerDetails::summary),
+            default_sink: self.all_devices.sinks.current_default.clone(),
+            default_source: self.all_devices.sources.current_default.clone(),
+            sink_level: self.peak_level::<Sink>(),
+            source_level: self.peak_level::<Source>(),
+            sink_groups: self.best_by_group::<Sink>(),
+            source_groups: self.best_by_group::<Source>(),
+            unmanaged_sinks: self.all_devices.unmanaged::<Sink>(),
+            unmanaged_sources: self.all_devices.unmanaged::<Source>(),
+            events_per_second: self.event_rate.per_window(Instant::now()),
+            drift_repairs: self.drift_repairs,
+            memory: self.memory_report(),
+            skipped_entries: self
+                .config
+                .skipped_entries
+                .keys()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    fn device_report(&self) -> DeviceReport {
+        let entries = |group: &AudioDeviceGroup| {
+            let mut entries: Vec<_> = group
+                .found_devices
+                .iter()
+                .map(|(&index, device)| DeviceEntry {
+                    index,
+                    name: device.original_name.clone(),
+                    recognized_as: group
+                        .config_names(device)
+                        .map(str::to_string)
+                        .collect(),
+                    muted: device.muted,
+                })
+                .collect();
+            entries.sort_by_key(|entry| entry.index);
+            entries
+        };
+
+        DeviceReport {
+            sinks: entries(&self.all_devices.sinks),
+            sources: entries(&self.all_devices.sources),
+        }
+    }
+
+    /// Devices with their properties as last reported, for bug reports
+    pub fn device_snapshots(&self) -> DeviceSnapshots {
+        let snapshots = |group: &AudioDeviceGroup| {
+            let mut snapshots: Vec<_> = group
+                .found_devices
+                .iter()
+                .map(|(&index, device)| DeviceSnapshot {
+                    index,
+                    name: device.original_name.clone(),
+                    owner_module: device.owner_module,
+                    active_port: device.active_port.clone(),
+                    muted: device.muted,
+                    properties: device.properties.clone(),
+                    recognized_as: group
+                        .config_names(device)
+                        .map(str::to_string)
+                        .collect(),
+                })
+                .collect();
+            snapshots.sort_by_key(|snapshot| snapshot.index);
+            snapshots
+        };
+
+        DeviceSnapshots {
+            sinks: snapshots(&self.all_devices.sinks),
+            sources: snapshots(&self.all_devices.sources),
+        }
+    }
+
+    /// Everything the daemon keeps track of, as a block of lines to log
+    /// when a stuck or confused instance is to be looked into
+    pub fn dump(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Status: {} (paused: {}, observing: {}, shutting down: {})",
+            self.status(),
+            self.paused,
+            self.observing,
+            self.shutting_down
+        )];
+        lines.extend(self.dump_group::<Sink>());
+        lines.extend(self.dump_group::<Source>());
+
+        let now = Instant::now();
+        let mut actions: Vec<_> = self
+            .actions
+            .pending()
+            .map(|(submission, in_flight)| {
+                vec![
+                    submission.action.to_string(),
+                    if in_flight { "in flight" } else { "deferred" }
+                        .to_string(),
+                    submission.reason.clone(),
+                ]
+            })
+            .collect();
+        actions.sort();
+        lines.push("Pending actions:".to_string());
+        let header = ["Action", "State", "Reason"];
+        lines.extend(
+            trace::format_table(&header, &actions)
+                .into_iter()
+                .map(|line| format!("  {line}")),
+        );
+
+        let timers: Vec<_> = self
+            .timers
+            .pending()
+            .into_iter()
+            .map(|(name, deadline)| {
+                let remaining = deadline.saturating_duration_since(now);
+                vec![name.to_string(), format!("{remaining:.1?}")]
+            })
+            .collect();
+        lines.push("Timers:".to_string());
+        lines.extend(
+            trace::format_table(&["Name", "Due in"], &timers)
+                .into_iter()
+                .map(|line| format!("  {line}")),
+        );
+        lines
+    }
+
+    /// Lines of the dump about the devices, defaults and modules of a group
+    fn dump_group<T: DeviceType>(&self) -> Vec<String> {
+        let group = T::select(&self.all_devices);
+        let mut indices: Vec<_> =
+            group.found_devices.keys().copied().collect();
+        indices.sort();
+        let devices: Vec<_> = indices
+            .iter()
+            .map(|index| {
+                let device = &group.found_devices[index];
+                vec![
+                    format!("#{index}"),
+                    device.original_name.clone(),
+                    group.config_names(device).collect::<Vec<_>>().join(", "),
+                    device.owner_module.map_or_else(String::new, |module| {
+                        format!("#{module}")
+                    }),
+                    format!("{:?}", device.run_state()).to_lowercase(),
+                    if device.muted { "muted" } else { "" }.to_string(),
+                ]
+            })
+            .collect();
+        let header = ["Index", "Name", "Recognized as", "Owner", "State", ""];
+        let mut lines = vec![format!("{}:", T::name_camel_case())];
+        lines.extend(
+            trace::format_table(&header, &devices)
+                .into_iter()
+                .map(|line| format!("  {line}")),
+        );
+
+        let unset = || "-".to_string();
+        lines.push(format!(
+            "  Default: {} (observed: {}, override: {}, initial: {})",
+            group.current_default.clone().unwrap_or_else(unset),
+            group.observed_default.clone().unwrap_or_else(unset),
+            group.default_override.clone().unwrap_or_else(unset),
+            group.initial_default.clone().unwrap_or_else(unset)
+        ));
+
+        let mut modules: Vec<_> = group
+            .remap_module_indices
+            .iter()
+            .map(|(config, module)| {
+                vec![
+                    group.config_ids.name(*config).to_string(),
+                    format!("#{module}"),
+                    group
+                        .remap_masters
+                        .get(config)
+                        .map_or_else(String::new, |master| {
+                            format!("#{master}")
+                        }),
+                    group
+                        .remap_module_arguments
+                        .get(config)
+                        .cloned()
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect();
+        modules.sort();
+        lines.push("  Modules:".to_string());
+        let header = ["Config", "Module", "Master", "Arguments"];
+        lines.extend(
+            trace::format_table(&header, &modules)
+                .into_iter()
+                .map(|line| format!("    {line}")),
+        );
+        lines
+    }
+
+    pub fn next_timer_deadline(&self) -> Option<Instant> {
+        self.timers.next_deadline()
+    }
+
+    /// Whether defaults are still being restored, streams moved or
+    /// modules unloaded on shutdown
+    pub fn has_pending_shutdown_work(&self) -> bool {
+        !self.shutdown_unloads.is_empty()
+            || self.num_pending_restores > 0
+            || self.num_pending_evictions > 0
+    }
+}
+
+impl StateRunner<'_> {
+    /// Set the defaults the server had when we connected again, if we have
+    /// changed them and the configuration asks for it
+    pub fn restore_initial_defaults(&mut self) {
+        if !self.state.config.restore_defaults_on_exit {
+            return;
+        }
+        self.restore_initial_default::<Sink>();
+        self.restore_initial_default::<Source>();
+    }
+
+    fn restore_initial_default<T: DeviceType>(&mut self) {
+        let Some(name) = T::select(&self.state.all_devices)
+            .default_to_restore()
+            .map(str::to_string)
+        else {
+            return;
+        };
+
+        info!(
+            "Restoring the initial default {} {name}",
+            T::name_lower_case()
+        );
+        self.state.num_pending_restores += 1;
+        let weak_origin = Rc::downgrade(&self.origin);
+        T::set_default(&mut self.state.context, &name, move |success| {
+            if !success {
+                warn!(
+                    "Failed to restore the initial default {}",
+                    T::name_lower_case()
+                );
+            }
+            if let Some(origin) = weak_origin.upgrade() {
+                StateRunner::with(&origin, |runner| {
+                    runner.state.num_pending_restores -= 1;
+                });
+            }
+        });
+    }
+
+    /// Move the streams of other clients off our remap devices, so that
+    /// they keep playing when the modules are unloaded
+    pub fn evict_streams(&mut self) {
+        let devices = &self.state.all_devices;
+        let config = &self.state.config;
+        let sink_targets = devices.eviction_targets::<Sink>(&config.sinks);
+        let source_targets =
+            devices.eviction_targets::<Source>(&config.sources);
+        if !sink_targets.is_empty() {
+            self.evict_sink_inputs(sink_targets);
+        }
+        if !source_targets.is_empty() {
+            self.evict_source_outputs(source_targets);
+        }
+    }
+
+    fn evict_sink_inputs(&mut self, targets: HashMap<u32, u32>) {
+        self.state.num_pending_evictions += 1;
+        let own_client = self.state.context.get_index();
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback = move |list_result: ListResult<&SinkInputInfo<'_>>| {
+            let Some(origin) = weak_origin.upgrade() else {
+                return;
+            };
+            StateRunner::with(&origin, |runner| match list_result {
+                ListResult::Item(info) if info.client != own_client => {
+                    if let Some(&target) = targets.get(&info.sink) {
+                        runner.submit(
+                            Action::MoveStream {
+                                kind: DeviceKind::Sink,
+                                stream: info.index,
+                                device: target,
+                            },
+                            format!("sink #{} is unloaded", info.sink),
+                        );
+                    }
+                }
+                ListResult::Item(_) => {}
+                ListResult::End | ListResult::Error => {
+                    runner.state.num_pending_evictions -= 1;
+                }
+            });
+        };
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_sink_input_info_list(callback);
+    }
+
+    fn move_sink_input(&mut self, index: u32, target: u32) {
+        info!("Moving stream #{index} to sink #{target} before unloading");
+        self.state.num_pending_evictions += 1;
+        let weak_origin = Rc::downgrade(&self.origin);
+        let _op = self.state.context.introspect().move_sink_input_by_index(
+            index,
+            target,
+            Some(Box::new(move |success| {
+                if !success {
+                    warn!("Failed to move stream #{index}");
+                }
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.state.num_pending_evictions -= 1;
+                        runner.finish_action(
+                            &Target::Stream(DeviceKind::Sink, index),
+                            success,
+                        );
+                    });
+                }
+            })),
+        );
+    }
+
+    fn evict_source_outputs(&mut self, targets: HashMap<u32, u32>) {
+        self.state.num_pending_evictions += 1;
+        let own_client = self.state.context.get_index();
+        let weak_origin = Rc::downgrade(&self.origin);
+        let callback =
+            move |list_result: ListResult<&SourceOutputInfo<'_>>| {
+                let Some(origin) = weak_origin.upgrade() else {
+                    return;
+                };
+                StateRunner::with(&origin, |runner| match list_result {
+                    ListResult::Item(info) if info.client != own_client => {
+                        if let Some(&target) = targets.get(&info.source) {
+                            runner.submit(
+                                Action::MoveStream {
+                                    kind: DeviceKind::Source,
+                                    stream: info.index,
+                                    device: target,
+                                },
+                                format!("source #{} is unloaded", info.source),
+                            );
+                        }
+                    }
+                    ListResult::Item(_) => {}
+                    ListResult::End | ListResult::Error => {
+                        runner.state.num_pending_evictions -= 1;
+                    }
+                });
+            };
+        let _op = self
+            .state
+            .context
+            .introspect()
+            .get_source_output_info_list(callback);
+    }
+
+    fn move_source_output(&mut self, index: u32, target: u32) {
+        info!(
+            "Moving recording #{index} to source #{target} before unloading"
+        );
+        self.state.num_pending_evictions += 1;
+        let weak_origin = Rc::downgrade(&self.origin);
+        let _op = self.state.context.introspect().move_source_output_by_index(
+            index,
+            target,
+            Some(Box::new(move |success| {
+                if !success {
+                    warn!("Failed to move recording #{index}");
+                }
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner.state.num_pending_evictions -= 1;
+                        runner.finish_action(
+                            &Target::Stream(DeviceKind::Source, index),
+                            success,
+                        );
+                    });
+                }
+            })),
+        );
+    }
+
+    pub fn cleanup_remap_modules(&mut self) {
+        info!("Cleaning up remap modules on shutdown");
+
+        let mut modules = self.loaded_remaps::<Sink>();
+        modules.extend(self.loaded_remaps::<Source>());
+        let module_count = modules.len();
+        self.state.shutdown_unloads.queued = modules;
+        self.unload_leaf_modules();
+
+        if module_count == 0 {
+            info!("No remap modules to clean up");
+        } else {
+            info!("Waiting for {module_count} remap modules to unload");
+        }
+    }
+
+    fn loaded_remaps<T: DeviceType>(&self) -> Vec<LoadedRemap> {
+        let scope = T::select(&self.state.all_devices);
+        scope
+            .remap_module_indices
+            .iter()
+            .map(|(&config, &module_index)| LoadedRemap {
+                kind: T::name_lower_case(),
+                group: T::group_name(),
+                config_name: scope.config_ids.name(config).to_string(),
+                module_index,
+            })
+            .collect()
+    }
+
+    /// Unload the modules left to unload on shutdown that no other of them
+    /// uses as master, so that chains are torn down from the end
+    fn unload_leaf_modules(&mut self) {
+        let config = &self.state.config;
+        let leaves = self.state.shutdown_unloads.take_leaves(|module| {
+            config.remap_master(module.group, &module.config_name)
+        });
+        for module in leaves {
+            self.unload_on_shutdown(module);
+        }
+    }
+
+    fn unload_on_shutdown(&mut self, module: LoadedRemap) {
+        info!(
+            "Unloading {} remap module #{} for '{}'",
+            module.kind, module.module_index, module.config_name
+        );
+
+        let weak_origin = Rc::downgrade(&self.origin);
+        let _op = self.state.context.introspect().unload_module(
+            module.module_index,
+            move |success| {
+                let LoadedRemap {
+                    kind, config_name, ..
+                } = &module;
+                if success {
+                    debug!(
+                        "Successfully unloaded {kind} remap module for \
+                         '{config_name}'"
+                    );
+                } else {
+                    error!(
+                        "Failed to unload {kind} remap module for \
+                         '{config_name}'"
+                    );
+                }
+                if let Some(origin) = weak_origin.upgrade() {
+                    StateRunner::with(&origin, |runner| {
+                        runner
+                            .state
+                            .shutdown_unloads
+                            .complete(module.module_index);
+                        if success {
+                            runner.state.activity.modules_unloaded += 1;
+                        }
+                        runner.unload_leaf_modules();
+
+                        if !runner.state.has_pending_shutdown_work() {
+                            debug!("All remap modules unloaded");
+                        }
+                    });
+                }
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DetectRule, Priority, RemapConfig, ScoringConfig};
+    use proptest::prelude::*;
+    use std::collections::HashMap;
+
+    fn create_test_proplist(
+        properties: &[(&str, &str)],
+    ) -> BTreeMap<String, String> {
+        properties
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    const NO_CAPABILITIES: Capabilities = Capabilities {
+        channels: 0,
+        rate: 0,
+        hardware_volume: false,
+    };
+
+    fn create_test_match_context<'a>(
+        config: &'a DeviceConfig,
+        proplist: &'a BTreeMap<String, String>,
+        owner_module: Option<u32>,
+        remap_module_indices: &'a HashMap<String, u32>,
+        config_name: &'a str,
+    ) -> DeviceMatchContext<'a> {
+        DeviceMatchContext {
+            device_config: config,
+            config_name,
+            device_name: "",
+            properties: proplist,
+            capabilities: &NO_CAPABILITIES,
+            runtime: (false, DeviceRunState::Idle),
+            owner_module,
+            remap_module: remap_module_indices.get(config_name).copied(),
+            match_remaps_by_name: false,
+        }
+    }
+
+    #[test]
+    fn test_check_device_match_with_matching_properties() {
+        let proplist = create_test_proplist(&[
+            ("device.api", "alsa"),
+            ("device.bus", "usb"),
+        ]);
+
+        // Create matching config
+        let mut detect = HashMap::new();
+        detect.insert(
+            "device.api".to_string(),
+            DetectRule::Exact("alsa".to_string()),
+        );
+        detect.insert(
+            "device.bus".to_string(),
+            DetectRule::Exact("usb".to_string()),
+        );
+
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Detect(detect),
+            ..Default::default()
+        };
+
+        let empty_map = HashMap::new();
+        let context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "test",
+        );
+        assert!(check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_with_non_matching_properties() {
+        let proplist = create_test_proplist(&[
+            ("device.api", "alsa"),
+            ("device.bus", "pci"),
+        ]);
+
+        let mut detect = HashMap::new();
+        detect.insert(
+            "device.api".to_string(),
+            DetectRule::Exact("alsa".to_string()),
+        );
+        detect.insert(
+            "device.bus".to_string(),
+            DetectRule::Exact("usb".to_string()),
+        ); // Different value
+
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Detect(detect),
+            ..Default::default()
+        };
+
+        let empty_map = HashMap::new();
+        let context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "test",
+        );
+        assert!(!check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_with_missing_property() {
+        let proplist = create_test_proplist(&[
+            ("device.api", "alsa"),
+            // device.bus is not set
+        ]);
+
+        let mut detect = HashMap::new();
+        detect.insert(
+            "device.api".to_string(),
+            DetectRule::Exact("alsa".to_string()),
+        );
+        detect.insert(
+            "device.bus".to_string(),
+            DetectRule::Exact("usb".to_string()),
+        );
+
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Detect(detect),
+            ..Default::default()
+        };
+
+        let empty_map = HashMap::new();
+        let context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "test",
+        );
+        assert!(!check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_with_empty_detect() {
+        let proplist = create_test_proplist(&[]);
+
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Detect(HashMap::new()),
+            ..Default::default()
+        };
+
+        // Empty detect matches everything
+        let empty_map = HashMap::new();
+        let context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "test",
+        );
+        assert!(check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_with_remap() {
+        let proplist = create_test_proplist(&[]);
+
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Remap(
+                crate::config::RemapConfig {
+                    master: MasterRef::Name("test".to_string()),
+                    device_name: None,
+                    device_properties: None,
+                    format: None,
+                    rate: None,
+                    channels: None,
+                    channel_map: None,
+                    master_channel_map: None,
+                    resample_method: None,
+                    remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
+                },
+            ),
+            ..Default::default()
+        };
+
+        // Remap configs never match during detection without owner_module
+        let empty_map = HashMap::new();
+        let context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "test",
+        );
+        assert!(!check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_with_remap_and_owner_module() {
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Remap(RemapConfig {
+                master: MasterRef::Name("master_device".to_string()),
+                device_name: Some("remap_device".to_string()),
+                device_properties: None,
+                format: None,
+                rate: None,
+                channels: None,
+                channel_map: None,
+                master_channel_map: None,
+                resample_method: None,
+                remix: None,
+                verify_playback: false,
+                on_name_collision: NameCollisionPolicy::Error,
+                on_external_unload: ExternalUnloadPolicy::Reload,
+            }),
+            ..Default::default()
+        };
+
+        let proplist = create_test_proplist(&[]);
+        let mut remap_module_indices = HashMap::new();
+        remap_module_indices.insert("remap_config".to_string(), 42);
+
+        // Test with matching owner module
+        let context = create_test_match_context(
+            &config,
+            &proplist,
+            Some(42),
+            &remap_module_indices,
+            "remap_config",
+        );
+        assert!(check_device_match(&context));
+
+        // Test with non-matching owner module
+        let context = create_test_match_context(
+            &config,
+            &proplist,
+            Some(43),
+            &remap_module_indices,
+            "remap_config",
+        );
+        assert!(!check_device_match(&context));
+
+        // Test with no owner module
+        let context = create_test_match_context(
+            &config,
+            &proplist,
+            None,
+            &remap_module_indices,
+            "remap_config",
+        );
+        assert!(!check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_with_remap_by_name_quirk() {
+        let config = DeviceConfig {
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Remap(RemapConfig {
+                master: MasterRef::Name("master_device".to_string()),
+                device_name: Some("remap_device".to_string()),
+                device_properties: None,
+                format: None,
+                rate: None,
+                channels: None,
+                channel_map: None,
+                master_channel_map: None,
+                resample_method: None,
+                remix: None,
+                verify_playback: false,
+                on_name_collision: NameCollisionPolicy::Error,
+                on_external_unload: ExternalUnloadPolicy::Reload,
+            }),
+            ..Default::default()
+        };
+
+        let proplist = create_test_proplist(&[]);
+        let mut remap_module_indices = HashMap::new();
+        remap_module_indices.insert("remap_config".to_string(), 42);
+
+        let mut context = create_test_match_context(
+            &config,
+            &proplist,
+            None,
+            &remap_module_indices,
+            "remap_config",
+        );
+        context.device_name = "remap_device";
+        assert!(!check_device_match(&context));
+
+        context.match_remaps_by_name = true;
+        assert!(check_device_match(&context));
+
+        // The module must still be loaded by us
+        context.remap_module = None;
+        assert!(!check_device_match(&context));
+    }
+
+    #[test]
+    fn test_check_device_match_remap_by_tag() {
+        let config: DeviceConfig =
+            serde_yaml::from_str("{remap: {master: speakers}}").unwrap();
+        let tagged =
+            create_test_proplist(&[(REMAP_TAG_PROPERTY, "remap_config")]);
+        let remap_module_indices =
+            HashMap::from([("remap_config".to_string(), 42)]);
+
+        // Without an owner module, as pipewire-pulse reports it
+        let mut context = create_test_match_context(
+            &config,
+            &tagged,
+            None,
+            &remap_module_indices,
+            "remap_config",
+        );
+        assert!(check_device_match(&context));
+
+        // Tagged for another config
+        context.config_name = "other";
+        assert!(!check_device_match(&context));
+
+        // An owner module other than ours is trusted unless quirks say
+        // otherwise, e.g. for a device left by an earlier instance
+        context.config_name = "remap_config";
+        context.owner_module = Some(7);
+        assert!(!check_device_match(&context));
+        context.match_remaps_by_name = true;
+        assert!(check_device_match(&context));
+
+        context.remap_module = None;
+        assert!(!check_device_match(&context));
+    }
+
+    #[test]
+    fn test_remap_name_collision() {
+        let configs: HashMap<String, DeviceConfig> = serde_yaml::from_str(
+            "remap: {remap: {master: hw, device_name: mic}}",
+        )
+        .unwrap();
+        let remap_config = |policy: &str| {
+            let mut config = configs["remap"].clone();
+            let DeviceMatchConfig::Remap(remap) = &mut config.match_config
+            else {
+                unreachable!();
+            };
+            remap.on_name_collision = serde_yaml::from_str(policy).unwrap();
+            config
+        };
+        let device =
+            |name: &str, owner_module, tag: Option<&str>| AudioDevice {
+                original_name: name.to_string(),
+                monitor_name: None,
+                owner_module,
+                active_port: None,
+                muted: false,
+                running: false,
+                suspended: false,
+                silent: false,
+                playback_verified: None,
+                card: None,
+                properties: tag
+                    .map(|tag| {
+                        create_test_proplist(&[(REMAP_TAG_PROPERTY, tag)])
+                    })
+                    .unwrap_or_default(),
+                capabilities: Capabilities::default(),
+                recognized_as: Vec::new(),
+                match_scores: BTreeMap::new(),
+            };
+        let loaded_name = |devices: &AudioDeviceGroup,
+                           config: &DeviceConfig| {
+            let DeviceMatchConfig::Remap(remap) = &config.match_config else {
+                unreachable!();
+            };
+            devices
+                .remap_to_load("remap", remap)
+                .map(|remap| remap.device_name.clone().unwrap())
+        };
+
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs);
+        let error = remap_config("error");
+        let suffix = remap_config("suffix");
+        let adopt = remap_config("adopt");
+        assert_eq!(loaded_name(&devices, &error).as_deref(), Some("mic"));
+
+        // Another device using the name
+        devices.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: device("mic", Some(3), None),
+            },
+            |_| None,
+        );
+        assert_eq!(loaded_name(&devices, &error), None);
+        assert_eq!(loaded_name(&devices, &adopt), None);
+        assert_eq!(loaded_name(&devices, &suffix).as_deref(), Some("mic.2"));
+        devices.insert_device(
+            IndexedDevice {
+                index: 2,
+                device: device("mic.2", None, None),
+            },
+            |_| None,
+        );
+        assert_eq!(loaded_name(&devices, &suffix).as_deref(), Some("mic.3"));
+
+        // Our own devices do not count
+        let config = devices.config_ids.get("remap").unwrap();
+        devices.remap_module_indices.insert(config, 5);
+        devices.insert_device(
+            IndexedDevice {
+                index: 3,
+                device: device("mic.3", Some(5), None),
+            },
+            |_| None,
+        );
+        assert_eq!(loaded_name(&devices, &suffix).as_deref(), Some("mic.3"));
+        devices.remove_device(3, |_| None);
+        devices.insert_device(
+            IndexedDevice {
+                index: 3,
+                device: device("mic.3", None, Some("remap")),
+            },
+            |_| None,
+        );
+        assert_eq!(loaded_name(&devices, &suffix).as_deref(), Some("mic.3"));
+
+        // The existing device is recognized instead of loading one
+        let proplist = create_test_proplist(&[]);
+        let no_modules = HashMap::new();
+        let mut context = create_test_match_context(
+            &adopt,
+            &proplist,
+            Some(3),
+            &no_modules,
+            "remap",
+        );
+        context.device_name = "mic";
+        assert!(check_device_match(&context));
+        context.device_config = &error;
+        assert!(!check_device_match(&context));
+        context.device_config = &adopt;
+        context.remap_module = Some(5);
+        assert!(!check_device_match(&context));
+    }
+
+    /// Unrecognized device with the name and nothing else known
+    fn plain_device(name: &str) -> AudioDevice {
+        AudioDevice {
+            original_name: name.to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: Vec::new(),
+            match_scores: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_enumeration_in_chunks() {
+        let mut devices = AudioDeviceGroup::new();
+        let listed = ENUMERATION_CHUNK as u32 + 36;
+        devices.pending_devices.extend((0..listed).map(|index| {
+            IndexedDevice {
+                index,
+                device: plain_device(&format!("sink{index}")),
             }
+        }));
+
+        let chunk = devices.take_pending_chunk();
+        assert_eq!(chunk.len(), ENUMERATION_CHUNK);
+        assert_eq!(chunk.last().map(|pending| pending.index), Some(63));
+        for pending in chunk {
+            devices.insert_device(pending, |_| None);
         }
 
-        // Load all pending remaps
-        for (config_name, remap, master_index) in remaps_to_load {
-            self.load_remap_module::<T>(RemapModuleParams {
-                config_name: &config_name,
-                remap_config: &remap,
-                master_index,
-            });
+        // Gone before it was matched
+        let gone = ENUMERATION_CHUNK as u32 + 16;
+        assert!(devices.remove_device(gone, |_| None).is_none());
+        let chunk = devices.take_pending_chunk();
+        assert_eq!(chunk.len(), 35);
+        assert!(chunk.iter().all(|pending| pending.index != gone));
+        for pending in chunk {
+            devices.insert_device(pending, |_| None);
         }
+
+        assert!(devices.take_pending_chunk().is_empty());
+        assert_eq!(devices.found_devices.len(), listed as usize - 1);
+        assert!(!devices.found_devices.contains_key(&gone));
     }
 
-    fn check_and_unload_remaps<T: DeviceType>(&mut self) {
-        let configs = T::get_definitions(&self.state.config);
-        let devices = T::select(&self.state.all_devices);
+    #[test]
+    fn test_privacy_mutes_again() {
+        let mut privacy = Privacy::default();
+        let mut mic = plain_device("mic");
+        let mute = PrivacyChange {
+            mute: true,
+            suspend: false,
+        };
+        assert_eq!(privacy.enforce(1, &mut mic), mute);
+        assert!(mic.muted);
+        assert_eq!(privacy.enforce(1, &mut mic), PrivacyChange::default());
+
+        // Unmuted by someone else
+        mic.muted = false;
+        assert_eq!(privacy.enforce(1, &mut mic), mute);
+        assert!(mic.muted);
+
+        // Muted already, and left muted when privacy mode ends
+        let mut line_in = plain_device("line_in");
+        line_in.muted = true;
+        assert_eq!(privacy.enforce(2, &mut line_in), PrivacyChange::default());
+        assert_eq!(privacy.muted, HashSet::from([1]));
+    }
 
-        // Find all remap modules that should be unloaded
-        let mut remaps_to_unload = Vec::new();
+    #[test]
+    fn test_privacy_suspends_once() {
+        let mut privacy = Privacy {
+            suspend: true,
+            ..Privacy::default()
+        };
+        let mut mic = plain_device("mic");
+        assert_eq!(
+            privacy.enforce(1, &mut mic),
+            PrivacyChange {
+                mute: true,
+                suspend: true,
+            }
+        );
+        assert_eq!(privacy.enforce(1, &mut mic), PrivacyChange::default());
+
+        // Resumed along with unmuting, which is not undone twice
+        mic.muted = false;
+        assert_eq!(
+            privacy.enforce(1, &mut mic),
+            PrivacyChange {
+                mute: true,
+                suspend: false,
+            }
+        );
+        assert_eq!(privacy.suspended, HashSet::from([1]));
+    }
 
-        for config_name in devices.remap_module_indices.keys() {
-            let should_unload = if let Some(config) = configs.get(config_name)
-            {
-                if let crate::config::DeviceMatchConfig::Remap(remap) =
-                    &config.match_config
-                {
-                    // Check if the master device still exists
-                    !Self::has_device_with_config_name(devices, &remap.master)
-                } else {
-                    true // Config changed from remap to detect
-                }
-            } else {
-                true // Config removed
+    #[test]
+    fn test_privacy_release() {
+        let sources = || {
+            let mut muted_by_user = plain_device("line_in");
+            muted_by_user.muted = true;
+            HashMap::from([(1, plain_device("mic")), (2, muted_by_user)])
+        };
+        let enforced = |suspend| {
+            let mut privacy = Privacy {
+                suspend,
+                ..Privacy::default()
             };
+            let mut sources = sources();
+            for (&index, device) in &mut sources {
+                privacy.enforce(index, device);
+            }
+            // Gone while privacy mode was on
+            privacy.muted.insert(3);
+            (privacy, sources)
+        };
 
-            if should_unload {
-                remaps_to_unload.push(config_name.clone());
+        let (privacy, mut sources) = enforced(false);
+        assert_eq!(
+            privacy.release(&mut sources),
+            PrivacyRelease {
+                unmute: vec![1],
+                resume: Vec::new(),
             }
-        }
+        );
+        assert!(!sources[&1].muted);
+        assert!(sources[&2].muted);
+
+        let (privacy, mut sources) = enforced(true);
+        assert_eq!(
+            privacy.release(&mut sources),
+            PrivacyRelease {
+                unmute: vec![1],
+                resume: vec![1, 2],
+            }
+        );
+    }
 
-        // Unload all pending remaps
-        for config_name in remaps_to_unload {
-            self.unload_remap_module::<T>(&config_name);
+    fn headset_switch() -> HeadsetSwitch {
+        HeadsetSwitch {
+            card: 4,
+            previous_profile: "a2dp-sink".to_string(),
         }
     }
 
-    pub fn with<Fn, Ret>(scope: &Rc<RefCell<State>>, proc: Fn) -> Ret
-    where
-        Fn: FnOnce(&mut StateRunner<'_>) -> Ret,
-    {
-        let mut runner = StateRunner {
-            origin: Rc::clone(scope),
-            state: &mut scope.borrow_mut(),
-        };
-        proc(&mut runner)
+    #[test]
+    fn test_headset_switch_for_recordings() {
+        let mut recordings = HeadsetRecordings::default();
+        assert!(recordings.start(1, false));
+        assert!(recordings.wants_switch());
+        recordings.switch = Some(headset_switch());
+
+        // Overlapping recordings share the switch
+        assert!(!recordings.start(2, false));
+        assert!(!recordings.wants_switch());
+        assert_eq!(recordings.stop(1), None);
+        // Last one ending
+        assert_eq!(recordings.stop(2), Some(headset_switch()));
+        assert_eq!(recordings.stop(2), None);
+
+        assert!(recordings.start(3, false));
     }
-}
 
-impl State {
-    pub fn begin_shutdown(&mut self) {
-        self.shutting_down = true;
+    #[test]
+    fn test_headset_switch_after_recordings_stopped() {
+        let mut recordings = HeadsetRecordings::default();
+        assert!(recordings.start(1, false));
+        assert!(!recordings.start(2, false));
+        recordings.stop(1);
+        recordings.stop(2);
+        // The card arrives after both have stopped
+        assert!(!recordings.wants_switch());
+        assert_eq!(recordings.switch, None);
+    }
+
+    #[test]
+    fn test_headset_switch_while_paused() {
+        let mut recordings = HeadsetRecordings::default();
+        assert!(!recordings.start(1, true));
+        // Resumed during the recording, which is not the first one then
+        assert!(!recordings.start(2, false));
+        assert_eq!(recordings.stop(1), None);
+        assert_eq!(recordings.stop(2), None);
+        // Unknown streams are ignored
+        assert_eq!(recordings.stop(7), None);
+        assert!(recordings.start(3, false));
     }
 
-    pub fn has_pending_unloads(&self) -> bool {
-        self.num_pending_unloads > 0
+    #[test]
+    fn test_config_ids_round_trip() {
+        let mut ids = ConfigIds::default();
+        let speakers = ids.intern("speakers");
+        let headphones = ids.intern("headphones");
+        assert_ne!(speakers, headphones);
+        assert_eq!(ids.intern("speakers"), speakers);
+        assert_eq!(ids.get("speakers"), Some(speakers));
+        assert_eq!(ids.get("headphones"), Some(headphones));
+        assert_eq!(ids.get("hdmi"), None);
+        assert_eq!(ids.name(speakers), "speakers");
+        assert_eq!(ids.name(headphones), "headphones");
     }
-}
 
-impl StateRunner<'_> {
-    fn cleanup_device_type_modules<T: DeviceType>(&mut self) -> usize {
-        let modules: Vec<_> = T::select(&self.state.all_devices)
-            .remap_module_indices
-            .clone()
-            .into_iter()
+    #[test]
+    fn test_config_ids_stable_across_reload() {
+        let configs = |names: &[&str]| -> HashMap<String, DeviceConfig> {
+            names
+                .iter()
+                .map(|name| (name.to_string(), DeviceConfig::default()))
+                .collect()
+        };
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs(&["speakers", "headphones", "hdmi"]));
+        let before: Vec<_> = ["speakers", "headphones", "hdmi"]
+            .iter()
+            .map(|name| devices.config_ids.get(name).unwrap())
             .collect();
 
-        let mut count = 0;
-        for (config_name, module_index) in modules {
-            info!(
-                "Unloading {} remap module #{} for '{}'",
-                T::name_lower_case(),
-                module_index,
-                config_name
-            );
-            count += 1;
-            self.state.num_pending_unloads += 1;
+        // "hdmi" removed, "headphones" renamed to "headset"
+        devices.intern_configs(&configs(&["speakers", "headset"]));
+        let ids = &devices.config_ids;
+        assert_eq!(ids.get("speakers"), Some(before[0]));
+        // Ids are never reused, so recognitions still held resolve to
+        // the names they were made for
+        assert_eq!(ids.get("headphones"), Some(before[1]));
+        assert_eq!(ids.get("hdmi"), Some(before[2]));
+        assert_eq!(ids.name(before[2]), "hdmi");
+        let headset = ids.get("headset").unwrap();
+        assert!(!before.contains(&headset));
+        assert_eq!(ids.name(headset), "headset");
+    }
 
-            let weak_origin = Rc::downgrade(&self.origin);
-            let config_name_owned = config_name.clone();
-            let device_type_name = T::name_lower_case();
-            let _op = self.state.context.introspect().unload_module(module_index, move |success| {
-                if let Some(origin) = weak_origin.upgrade() {
-                    StateRunner::with(&origin, |runner| {
-                        if success {
-                            debug!("Successfully unloaded {device_type_name} remap module for '{config_name_owned}'");
-                        } else {
-                            error!("Failed to unload {device_type_name} remap module for '{config_name_owned}'");
-                        }
-                        runner.state.num_pending_unloads -= 1;
+    #[test]
+    fn test_denied_module_loading() {
+        assert!(denies_module_loading(PAErr::from(Code::Access)));
+        assert!(denies_module_loading(PAErr(Code::Access as i32)));
+        assert!(!denies_module_loading(PAErr::from(Code::NoEntity)));
+        assert!(!denies_module_loading(PAErr::from(Code::ModInitFailed)));
+    }
 
-                        // Log when all modules are unloaded
-                        if runner.state.num_pending_unloads == 0 && runner.state.shutting_down {
-                            debug!("All remap modules unloaded");
-                        }
-                    });
-                }
-            });
-        }
-        count
+    #[test]
+    fn test_list_retries() {
+        let mut devices = AudioDeviceGroup::new();
+        let delays: Vec<_> = (0..LIST_RETRY_LIMIT)
+            .map(|_| devices.list_failed().unwrap())
+            .collect();
+        assert_eq!(delays, [1, 2, 3].map(Duration::from_secs));
+        assert_eq!(devices.list_failed(), None);
+        assert_eq!(devices.list_failed(), None);
     }
 
-    pub fn cleanup_remap_modules(&mut self) {
-        info!("Cleaning up remap modules on shutdown");
+    #[test]
+    fn test_outdated_remap_modules() {
+        let configs = |remap: &str| -> HashMap<String, DeviceConfig> {
+            serde_yaml::from_str(&format!(
+                "{{hw: {{detect: {{}}}}, remap: {remap}}}"
+            ))
+            .unwrap()
+        };
+        let loaded = configs("{remap: {master: hw, channels: 2}}");
+        let mut devices = AudioDeviceRoot::new();
+        devices.sinks.intern_configs(&loaded);
+        let hw = devices.sinks.config_ids.get("hw").unwrap();
+        let mut master = plain_device("alsa_output.pci");
+        master.recognized_as = vec![hw];
+        devices.sinks.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: master,
+            },
+            |_| None,
+        );
+        let DeviceMatchConfig::Remap(remap) = &loaded["remap"].match_config
+        else {
+            unreachable!();
+        };
+        let arguments = modargs::remap(
+            remap,
+            Sink::name_lower_case(),
+            modargs::RemapNames {
+                master_name: "alsa_output.pci",
+                config_name: "remap",
+            },
+        );
+        let config = devices.sinks.config_ids.get("remap").unwrap();
+        devices.sinks.remap_module_indices.insert(config, 5);
+        devices
+            .sinks
+            .remap_module_arguments
+            .insert(config, arguments);
+        devices.sinks.remap_masters.insert(config, 1);
+
+        let outdated =
+            |devices: &AudioDeviceRoot,
+             configs: &HashMap<String, DeviceConfig>| {
+                devices.outdated_modules::<Sink>(configs, false)
+            };
+        assert!(outdated(&devices, &loaded).is_empty());
+        assert_eq!(
+            outdated(&devices, &configs("{remap: {master: hw, channels: 1}}")),
+            ["remap"]
+        );
+        assert_eq!(outdated(&devices, &configs("{detect: {}}")), ["remap"]);
+        let mut removed = loaded.clone();
+        removed.remove("remap");
+        assert_eq!(outdated(&devices, &removed), ["remap"]);
+
+        // The master was replaced by another device
+        devices.sinks.remap_masters.insert(config, 2);
+        assert_eq!(outdated(&devices, &loaded), ["remap"]);
+    }
 
-        let sink_count = self.cleanup_device_type_modules::<Sink>();
-        let source_count = self.cleanup_device_type_modules::<Source>();
-        let module_count = sink_count + source_count;
+    #[test]
+    fn test_last_peak() {
+        let fragment = |levels: &[f32]| -> Vec<u8> {
+            levels
+                .iter()
+                .flat_map(|level| level.to_le_bytes())
+                .collect()
+        };
+        assert_eq!(last_peak(&fragment(&[0.25, 0.5])), Some(0.5));
+        assert_eq!(last_peak(&fragment(&[1.5])), Some(1.0));
+        assert_eq!(last_peak(&fragment(&[-0.5])), Some(0.0));
+        assert_eq!(last_peak(&[]), None);
+        assert_eq!(last_peak(&[0, 0, 0]), None);
+
+        let mut sink = plain_device("alsa_output.pci");
+        assert_eq!(Sink::peak_source(&sink), None);
+        sink.monitor_name = Some("alsa_output.pci.monitor".to_string());
+        assert_eq!(Sink::peak_source(&sink), Some("alsa_output.pci.monitor"));
+        let source = plain_device("alsa_input.pci");
+        assert_eq!(Source::peak_source(&source), Some("alsa_input.pci"));
+    }
 
-        if module_count == 0 {
-            info!("No remap modules to clean up");
-        } else {
-            info!("Waiting for {module_count} remap modules to unload");
+    #[test]
+    fn test_capturing_streams() {
+        let mut capturing = CapturingStreams::default();
+        let capture = |source, config: &str| Capture {
+            source,
+            config: config.to_string(),
+        };
+        assert!(capturing.start(1, capture(10, "mic")));
+        assert!(!capturing.start(2, capture(11, "headset")));
+
+        assert_eq!(capturing.stop(3), None);
+        assert_eq!(capturing.stop(1), Some(capture(10, "mic")));
+        assert!(!capturing.is_empty());
+        assert_eq!(capturing.stop(1), None);
+        assert_eq!(capturing.stop(2), Some(capture(11, "headset")));
+        assert!(capturing.is_empty());
+
+        // The hook runs again for the next recording
+        assert!(capturing.start(4, capture(10, "mic")));
+    }
+
+    #[test]
+    fn test_unmanaged_summary() {
+        let mut devices = AudioDeviceRoot::new();
+        assert_eq!(devices.unmanaged_summary(), None);
+
+        let speakers = devices.sinks.config_ids.intern("speakers");
+        let mut managed = plain_device("alsa_output.usb");
+        managed.recognized_as = vec![speakers];
+        let mut monitor = plain_device("alsa_output.usb.monitor");
+        monitor.properties =
+            create_test_proplist(&[("device.class", "monitor")]);
+        for (index, device) in [
+            (1, managed),
+            (2, plain_device("alsa_output.pci")),
+            (3, plain_device("alsa_output.hdmi")),
+        ] {
+            devices
+                .sinks
+                .insert_device(IndexedDevice { index, device }, |_| None);
         }
+        devices.sources.insert_device(
+            IndexedDevice {
+                index: 4,
+                device: monitor,
+            },
+            |_| None,
+        );
+        assert_eq!(
+            devices.unmanaged::<Sink>(),
+            ["alsa_output.hdmi", "alsa_output.pci"]
+        );
+        assert!(devices.unmanaged::<Source>().is_empty());
+        assert_eq!(
+            devices.unmanaged_summary().as_deref(),
+            Some(
+                "Devices matching no config: sinks alsa_output.hdmi, \
+                 alsa_output.pci"
+            )
+        );
+
+        devices.sources.insert_device(
+            IndexedDevice {
+                index: 5,
+                device: plain_device("alsa_input.pci"),
+            },
+            |_| None,
+        );
+        assert_eq!(
+            devices.unmanaged_summary().as_deref(),
+            Some(
+                "Devices matching no config: sinks alsa_output.hdmi, \
+                 alsa_output.pci; sources alsa_input.pci"
+            )
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::RemapConfig;
-    use std::collections::HashMap;
+    #[test]
+    fn test_default_to_restore() {
+        let mut devices = AudioDeviceGroup::new();
+        assert_eq!(devices.default_to_restore(), None);
+
+        // Left alone while we have not made any device default
+        devices.initial_default = Some("alsa_output.pci".to_string());
+        assert_eq!(devices.default_to_restore(), None);
+        devices.current_default = Some("usb".to_string());
+        assert_eq!(devices.default_to_restore(), Some("alsa_output.pci"));
+
+        // The server had no default to go back to
+        devices.initial_default = None;
+        assert_eq!(devices.default_to_restore(), None);
+    }
 
-    fn create_test_proplist(
-        properties: &[(&str, &str)],
-    ) -> libpulse_binding::proplist::Proplist {
-        let mut proplist =
-            libpulse_binding::proplist::Proplist::new().unwrap();
-        for (key, value) in properties {
-            proplist.set_str(key, value).unwrap();
+    #[test]
+    fn test_eviction_targets() {
+        let configs: HashMap<String, DeviceConfig> = serde_yaml::from_str(
+            "{hw: {detect: {}}, remap: {remap: {master: hw}}, \
+             orphan: {remap: {master: gone}}, idle: {remap: {master: hw}}}",
+        )
+        .unwrap();
+        let mut devices = AudioDeviceRoot::new();
+        devices.sinks.intern_configs(&configs);
+        let sinks = &mut devices.sinks;
+        let hw = sinks.config_ids.get("hw").unwrap();
+        let mut master = plain_device("alsa_output.pci");
+        master.recognized_as = vec![hw];
+        let remap_device = |name: &str, module| AudioDevice {
+            owner_module: Some(module),
+            ..plain_device(name)
+        };
+        for (index, device) in [
+            (1, master),
+            (2, remap_device("remap", 5)),
+            (3, remap_device("orphan", 6)),
+            (4, plain_device("alsa_output.hdmi")),
+        ] {
+            sinks.insert_device(IndexedDevice { index, device }, |_| None);
         }
-        proplist
+        for (name, module) in [("remap", 5), ("orphan", 6), ("idle", 7)] {
+            let config = sinks.config_ids.get(name).unwrap();
+            sinks.remap_module_indices.insert(config, module);
+        }
+
+        // Without a master, streams go back to the initial default
+        assert_eq!(
+            devices.eviction_targets::<Sink>(&configs),
+            HashMap::from([(2, 1)])
+        );
+        devices.sinks.initial_default = Some("alsa_output.hdmi".to_string());
+        assert_eq!(
+            devices.eviction_targets::<Sink>(&configs),
+            HashMap::from([(2, 1), (3, 4)])
+        );
     }
 
-    fn create_test_match_context<'a>(
-        config: &'a DeviceConfig,
-        proplist: &'a libpulse_binding::proplist::Proplist,
-        owner_module: Option<u32>,
-        remap_module_indices: &'a HashMap<String, u32>,
-        config_name: &'a str,
-    ) -> DeviceMatchContext<'a> {
-        DeviceMatchContext {
-            device_config: config,
-            proplist,
-            owner_module,
-            remap_module_indices,
-            config_name,
+    #[test]
+    fn test_reconciliation_drift() {
+        let mut devices = AudioDeviceGroup::new();
+        for index in 1..=3 {
+            let device = plain_device(&format!("sink{index}"));
+            devices.insert_device(IndexedDevice { index, device }, |_| None);
         }
+        let listed = |index, muted| IndexedDevice {
+            index,
+            device: AudioDevice {
+                muted,
+                ..plain_device(&format!("sink{index}"))
+            },
+        };
+        let fresh = [listed(1, false), listed(2, true), listed(4, false)];
+
+        assert_eq!(devices.missing_from(&fresh), [3]);
+        let outdated: Vec<_> = fresh
+            .iter()
+            .filter(|device| devices.is_outdated(device))
+            .map(|device| device.index)
+            .collect();
+        assert_eq!(outdated, [2, 4]);
+
+        let remap = devices.config_ids.intern("remap");
+        let jack = devices.config_ids.intern("jack");
+        devices.remap_module_indices.insert(remap, 5);
+        devices.remap_module_indices.insert(jack, 6);
+        assert_eq!(devices.unloaded_modules(&HashSet::from([5, 7])), [6]);
+        assert!(devices.unloaded_modules(&HashSet::from([5, 6])).is_empty());
     }
 
     #[test]
-    fn test_check_device_match_with_matching_properties() {
-        let proplist = create_test_proplist(&[
-            ("device.api", "alsa"),
-            ("device.bus", "usb"),
-        ]);
+    fn test_forget_devices_keeps_modules() {
+        let configs: HashMap<String, DeviceConfig> =
+            serde_yaml::from_str("remap: {priority: 1, remap: {master: hw}}")
+                .unwrap();
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs);
+        let config = devices.config_ids.get("remap").unwrap();
+        devices.remap_module_indices.insert(config, 10);
+        let remap = AudioDevice {
+            original_name: "remap".to_string(),
+            monitor_name: None,
+            owner_module: Some(10),
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![config],
+            match_scores: BTreeMap::new(),
+        };
+        devices.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: remap,
+            },
+            |name| fixed_priority(&configs, name),
+        );
 
-        // Create matching config
-        let mut detect = HashMap::new();
-        detect.insert("device.api".to_string(), "alsa".to_string());
-        detect.insert("device.bus".to_string(), "usb".to_string());
+        devices.forget_devices();
+        assert!(devices.found_devices.is_empty());
+        assert_eq!(devices.find_index_by_config_name("remap"), None);
+        assert_eq!(find_default(&devices, &configs), None);
+        assert_eq!(devices.remap_module_index("remap"), Some(10));
+    }
 
-        let config = DeviceConfig {
-            priority: Some(1),
-            match_config: DeviceMatchConfig::Detect(detect),
-        };
+    /// Priority of the config for tests without inheritance
+    fn fixed_priority(
+        configs: &HashMap<String, DeviceConfig>,
+        name: &str,
+    ) -> Option<u32> {
+        match configs.get(name)?.priority {
+            Some(Priority::Fixed(priority)) => Some(priority),
+            Some(Priority::Inherit(_)) | None => None,
+        }
+    }
 
-        let empty_map = HashMap::new();
-        let context = create_test_match_context(
-            &config, &proplist, None, &empty_map, "test",
+    /// The default among the devices of the group by priority
+    fn find_default<'a>(
+        group: &'a AudioDeviceGroup,
+        configs: &'a HashMap<String, DeviceConfig>,
+    ) -> Option<(&'a str, u32)> {
+        group
+            .find_default_device(configs, |_, _| true)
+            .map(|entry| (group.config_ids.name(entry.config), entry.index))
+    }
+
+    #[test]
+    fn test_find_default_device_with_priorities() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            "high_priority".to_string(),
+            DeviceConfig {
+                priority: Some(Priority::Fixed(1)),
+                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
+            },
         );
-        assert!(check_device_match(&context));
+        configs.insert(
+            "medium_priority".to_string(),
+            DeviceConfig {
+                priority: Some(Priority::Fixed(5)),
+                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
+            },
+        );
+        configs.insert(
+            "low_priority".to_string(),
+            DeviceConfig {
+                priority: Some(Priority::Fixed(10)),
+                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
+            },
+        );
+
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs);
+        let id = |name| devices.config_ids.get(name).unwrap();
+        let device1 = AudioDevice {
+            original_name: "device1".to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![id("high_priority"), id("low_priority")],
+            match_scores: BTreeMap::new(),
+        };
+        let device2 = AudioDevice {
+            original_name: "device2".to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![id("medium_priority")],
+            match_scores: BTreeMap::new(),
+        };
+        let priority_of = |name: &str| fixed_priority(&configs, name);
+        devices.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: device1,
+            },
+            priority_of,
+        );
+        devices.insert_device(
+            IndexedDevice {
+                index: 2,
+                device: device2,
+            },
+            priority_of,
+        );
+
+        let result = find_default(&devices, &configs);
+
+        assert!(result.is_some());
+        let (config_name, device_index) = result.unwrap();
+        assert_eq!(config_name, "high_priority");
+        assert_eq!(device_index, 1);
     }
 
     #[test]
-    fn test_check_device_match_with_non_matching_properties() {
-        let proplist = create_test_proplist(&[
-            ("device.api", "alsa"),
-            ("device.bus", "pci"),
-        ]);
+    fn test_find_default_device_skips_silent() {
+        let mut configs = HashMap::new();
+        for (name, priority) in [("high_priority", 1), ("low_priority", 10)] {
+            configs.insert(
+                name.to_string(),
+                DeviceConfig {
+                    priority: Some(Priority::Fixed(priority)),
+                    match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                    ..Default::default()
+                },
+            );
+        }
 
-        let mut detect = HashMap::new();
-        detect.insert("device.api".to_string(), "alsa".to_string());
-        detect.insert("device.bus".to_string(), "usb".to_string()); // Different value
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs);
+        let device = |name: &str, config: &str, silent| AudioDevice {
+            original_name: name.to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: true,
+            suspended: false,
+            silent,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![devices.config_ids.get(config).unwrap()],
+            match_scores: BTreeMap::new(),
+        };
+        let hdmi = device("hdmi", "high_priority", true);
+        let usb = device("usb", "low_priority", false);
+        let priority_of = |name: &str| fixed_priority(&configs, name);
+        devices.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: hdmi,
+            },
+            priority_of,
+        );
+        devices.insert_device(
+            IndexedDevice {
+                index: 2,
+                device: usb,
+            },
+            priority_of,
+        );
 
-        let config = DeviceConfig {
-            priority: Some(1),
-            match_config: DeviceMatchConfig::Detect(detect),
+        let result = find_default(&devices, &configs);
+        assert_eq!(result, Some(("low_priority", 2)));
+    }
+
+    #[test]
+    fn test_find_default_device_requires_playback_check() {
+        let mut configs: HashMap<String, DeviceConfig> = serde_yaml::from_str(
+            "remap: {priority: 1, remap: {master: hw, verify_playback: true}}",
+        )
+        .unwrap();
+
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs);
+        let remap = AudioDevice {
+            original_name: "remap".to_string(),
+            monitor_name: None,
+            owner_module: Some(10),
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![devices.config_ids.get("remap").unwrap()],
+            match_scores: BTreeMap::new(),
         };
+        devices.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: remap,
+            },
+            |name| fixed_priority(&configs, name),
+        );
+        assert_eq!(find_default(&devices, &configs), None);
+
+        let device = devices.found_devices.get_mut(&1).unwrap();
+        device.playback_verified = Some(false);
+        assert_eq!(find_default(&devices, &configs), None);
+
+        let device = devices.found_devices.get_mut(&1).unwrap();
+        device.playback_verified = Some(true);
+        assert_eq!(find_default(&devices, &configs), Some(("remap", 1)));
+
+        // Devices of configs without the option need no check
+        let device = devices.found_devices.get_mut(&1).unwrap();
+        device.playback_verified = None;
+        configs =
+            serde_yaml::from_str("remap: {priority: 1, remap: {master: hw}}")
+                .unwrap();
+        assert!(find_default(&devices, &configs).is_some());
+    }
 
-        let empty_map = HashMap::new();
-        let context = create_test_match_context(
-            &config, &proplist, None, &empty_map, "test",
+    #[test]
+    fn test_find_default_device_with_no_priority() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            "config1".to_string(),
+            DeviceConfig {
+                priority: None,
+                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
+            },
+        );
+
+        let mut devices = AudioDeviceGroup::new();
+        devices.intern_configs(&configs);
+        let device1 = AudioDevice {
+            original_name: "device1".to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![devices.config_ids.get("config1").unwrap()],
+            match_scores: BTreeMap::new(),
+        };
+        devices.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: device1,
+            },
+            |name| fixed_priority(&configs, name),
         );
-        assert!(!check_device_match(&context));
+
+        let result = find_default(&devices, &configs);
+
+        assert!(result.is_none());
     }
 
     #[test]
-    fn test_check_device_match_with_missing_property() {
-        let proplist = create_test_proplist(&[
-            ("device.api", "alsa"),
-            // device.bus is not set
-        ]);
+    fn test_find_default_device_with_empty_devices() {
+        let devices = AudioDeviceGroup::new();
+        let configs = HashMap::new();
 
-        let mut detect = HashMap::new();
-        detect.insert("device.api".to_string(), "alsa".to_string());
-        detect.insert("device.bus".to_string(), "usb".to_string());
+        let result = find_default(&devices, &configs);
 
-        let config = DeviceConfig {
-            priority: Some(1),
-            match_config: DeviceMatchConfig::Detect(detect),
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_capability_requirements() {
+        let stereo = Capabilities {
+            channels: 2,
+            rate: 48000,
+            hardware_volume: true,
+        };
+        let requirements = |yaml: &str| -> CapabilityRequirements {
+            serde_yaml::from_str(yaml).unwrap()
         };
 
+        assert!(stereo.meet(&requirements("{}")));
+        assert!(stereo.meet(&requirements("{min_channels: 2}")));
+        assert!(!stereo.meet(&requirements("{min_channels: 6}")));
+        assert!(stereo.meet(&requirements("{sample_rates: [44100, 48000]}")));
+        assert!(!stereo.meet(&requirements("{sample_rates: [96000]}")));
+        assert!(!stereo.meet(&requirements("{hardware_volume: false}")));
+
+        // Properties alone do not make a stereo device match
+        let config: DeviceConfig = serde_yaml::from_str(
+            "{detect: {device.class: sound}, requires: {min_channels: 6}}",
+        )
+        .unwrap();
+        let proplist = create_test_proplist(&[("device.class", "sound")]);
         let empty_map = HashMap::new();
-        let context = create_test_match_context(
-            &config, &proplist, None, &empty_map, "test",
+        let mut context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "surround",
         );
+        context.capabilities = &stereo;
         assert!(!check_device_match(&context));
+        let surround = Capabilities {
+            channels: 6,
+            ..stereo
+        };
+        context.capabilities = &surround;
+        assert!(check_device_match(&context));
     }
 
     #[test]
-    fn test_check_device_match_with_empty_detect() {
+    fn test_runtime_condition() {
+        let config: DeviceConfig = serde_yaml::from_str(
+            "{detect: {}, when: {state: running, muted: false}}",
+        )
+        .unwrap();
         let proplist = create_test_proplist(&[]);
-
-        let config = DeviceConfig {
-            priority: Some(1),
-            match_config: DeviceMatchConfig::Detect(HashMap::new()),
-        };
-
-        // Empty detect matches everything
         let empty_map = HashMap::new();
-        let context = create_test_match_context(
-            &config, &proplist, None, &empty_map, "test",
+        let mut context = create_test_match_context(
+            &config, &proplist, None, &empty_map, "mic",
         );
+
+        assert!(!check_device_match(&context));
+        context.runtime = (false, DeviceRunState::Running);
         assert!(check_device_match(&context));
+        context.runtime = (true, DeviceRunState::Running);
+        assert!(!check_device_match(&context));
     }
 
     #[test]
-    fn test_check_device_match_with_remap() {
-        let proplist = create_test_proplist(&[]);
-
+    fn test_scoring_match_and_tiebreak() {
         let config = DeviceConfig {
-            priority: Some(1),
-            match_config: DeviceMatchConfig::Remap(
-                crate::config::RemapConfig {
-                    master: "test".to_string(),
-                    device_name: None,
-                    device_properties: None,
-                    format: None,
-                    rate: None,
-                    channels: None,
-                    channel_map: None,
-                    master_channel_map: None,
-                    resample_method: None,
-                    remix: None,
-                },
-            ),
+            priority: Some(Priority::Fixed(1)),
+            match_config: DeviceMatchConfig::Detect(HashMap::from([
+                (
+                    "device.bus".to_string(),
+                    DetectRule::Exact("usb".to_string()),
+                ),
+                (
+                    "device.vendor.id".to_string(),
+                    DetectRule::Exact("1235".to_string()),
+                ),
+                (
+                    "device.serial".to_string(),
+                    DetectRule::Exact("X".to_string()),
+                ),
+            ])),
+            scoring: Some(ScoringConfig {
+                threshold: 2,
+                weights: HashMap::from([("device.serial".to_string(), 3)]),
+            }),
+            ..Default::default()
         };
-
-        // Remap configs never match during detection without owner_module
         let empty_map = HashMap::new();
-        let context = create_test_match_context(
-            &config, &proplist, None, &empty_map, "test",
+        let matches = |pairs: &[(&str, &str)]| {
+            let proplist = create_test_proplist(pairs);
+            check_device_match(&create_test_match_context(
+                &config, &proplist, None, &empty_map, "dac",
+            ))
+        };
+
+        // The serial changed, but the rest still scores enough
+        assert!(matches(&[
+            ("device.bus", "usb"),
+            ("device.vendor.id", "1235")
+        ]));
+        assert!(matches(&[("device.serial", "X")]));
+        assert!(!matches(&[("device.bus", "usb"), ("device.serial", "Y")]));
+
+        // Equal priorities are ranked by score
+        let mut group = AudioDeviceGroup::new();
+        let dac = group.config_ids.intern("dac");
+        let device = |score| AudioDevice {
+            original_name: "alsa_output.usb".to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![dac],
+            match_scores: BTreeMap::from([(dac, score)]),
+        };
+        group.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: device(2),
+            },
+            |_| Some(1),
         );
-        assert!(!check_device_match(&context));
+        group.insert_device(
+            IndexedDevice {
+                index: 2,
+                device: device(5),
+            },
+            |_| Some(1),
+        );
+        let order: Vec<_> =
+            group.ranking.iter().map(|entry| entry.index).collect();
+        assert_eq!(order, vec![2, 1]);
     }
 
     #[test]
-    fn test_check_device_match_with_remap_and_owner_module() {
-        let config = DeviceConfig {
-            priority: Some(1),
-            match_config: DeviceMatchConfig::Remap(RemapConfig {
-                master: "master_device".to_string(),
-                device_name: Some("remap_device".to_string()),
-                device_properties: None,
-                format: None,
-                rate: None,
-                channels: None,
-                channel_map: None,
-                master_channel_map: None,
-                resample_method: None,
-                remix: None,
-            }),
+    fn test_ranking_follows_recognitions() {
+        let mut group = AudioDeviceGroup::new();
+        let usb = group.config_ids.intern("usb");
+        let speakers = group.config_ids.intern("speakers");
+        let unranked = group.config_ids.intern("unranked");
+        let device = |recognized_as| AudioDevice {
+            original_name: "alsa_output.usb".to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: BTreeMap::new(),
+            capabilities: Capabilities::default(),
+            recognized_as,
+            match_scores: BTreeMap::new(),
+        };
+        let priority_of = |name: &str| match name {
+            "usb" => Some(1),
+            "speakers" => Some(5),
+            _ => None,
+        };
+        let ranked = |group: &AudioDeviceGroup| -> Vec<(u32, ConfigId)> {
+            group
+                .ranking
+                .iter()
+                .map(|entry| (entry.index, entry.config))
+                .collect()
         };
 
-        let proplist = create_test_proplist(&[]);
-        let mut remap_module_indices = HashMap::new();
-        remap_module_indices.insert("remap_config".to_string(), 42);
+        group.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: device(vec![speakers, unranked]),
+            },
+            priority_of,
+        );
+        group.insert_device(
+            IndexedDevice {
+                index: 2,
+                device: device(vec![usb]),
+            },
+            priority_of,
+        );
+        assert_eq!(ranked(&group), vec![(2, usb), (1, speakers)]);
+        assert_eq!(group.find_index_by_config_name("unranked"), Some(1));
+
+        // Re-recognition replaces the entries of the device
+        group.insert_device(
+            IndexedDevice {
+                index: 2,
+                device: device(vec![speakers]),
+            },
+            priority_of,
+        );
+        assert_eq!(ranked(&group), vec![(1, speakers), (2, speakers)]);
+        assert_eq!(group.find_index_by_config_name("usb"), None);
 
-        // Test with matching owner module
-        let context = create_test_match_context(
-            &config,
-            &proplist,
-            Some(42),
-            &remap_module_indices,
-            "remap_config",
+        group.remove_device(1, priority_of);
+        assert_eq!(ranked(&group), vec![(2, speakers)]);
+        assert_eq!(group.find_index_by_config_name("speakers"), Some(2));
+
+        // Priorities changed by a reload
+        group.reindex(|_| None);
+        assert!(group.ranking.is_empty());
+        assert_eq!(group.find_index_by_config_name("speakers"), Some(2));
+    }
+
+    #[test]
+    fn test_resolve_master_across_groups() {
+        let mut devices = AudioDeviceRoot::new();
+        let speakers = devices.sinks.config_ids.intern("speakers");
+        devices.sinks.insert_device(
+            IndexedDevice {
+                index: 1,
+                device: AudioDevice {
+                    original_name: "alsa_output.usb".to_string(),
+                    monitor_name: Some("alsa_output.usb.monitor".to_string()),
+                    owner_module: None,
+                    active_port: None,
+                    muted: false,
+                    running: false,
+                    suspended: false,
+                    silent: false,
+                    playback_verified: None,
+                    card: None,
+                    properties: BTreeMap::new(),
+                    capabilities: Capabilities::default(),
+                    recognized_as: vec![speakers],
+                    match_scores: BTreeMap::new(),
+                },
+            },
+            |_| None,
         );
-        assert!(check_device_match(&context));
 
-        // Test with non-matching owner module
-        let context = create_test_match_context(
-            &config,
-            &proplist,
-            Some(43),
-            &remap_module_indices,
-            "remap_config",
+        let monitor_ref = MasterRef::Sink {
+            sink: "speakers".to_string(),
+            monitor: true,
+        };
+        assert_eq!(
+            Source::resolve_master(&devices, &monitor_ref),
+            Some((1, "alsa_output.usb.monitor"))
         );
-        assert!(!check_device_match(&context));
 
-        // Test with no owner module
-        let context = create_test_match_context(
-            &config,
-            &proplist,
-            None,
-            &remap_module_indices,
-            "remap_config",
+        // Plain names stay within the group of the remap
+        let name_ref = MasterRef::Name("speakers".to_string());
+        assert_eq!(Source::resolve_master(&devices, &name_ref), None);
+        assert_eq!(
+            Sink::resolve_master(&devices, &name_ref),
+            Some((1, "alsa_output.usb"))
         );
-        assert!(!check_device_match(&context));
+
+        devices.sinks.remove_device(1, |_| None);
+        assert_eq!(Source::resolve_master(&devices, &monitor_ref), None);
     }
 
     #[test]
-    fn test_find_default_device_with_priorities() {
-        let mut devices = HashMap::new();
-        devices.insert(
-            1,
-            AudioDevice {
-                original_name: "device1".to_string(),
-                recognized_as: vec![
-                    "high_priority".to_string(),
-                    "low_priority".to_string(),
-                ],
-            },
+    fn test_snapshot_proplist() {
+        let mut proplist =
+            libpulse_binding::proplist::Proplist::new().unwrap();
+        proplist.set_str("device.bus", "usb").unwrap();
+        proplist.set_str("device.description", "Headset").unwrap();
+
+        let snapshot = snapshot_proplist(&proplist);
+        assert_eq!(
+            snapshot,
+            create_test_proplist(&[
+                ("device.bus", "usb"),
+                ("device.description", "Headset"),
+            ])
         );
-        devices.insert(
-            2,
+    }
+
+    #[test]
+    fn test_rematch_devices_with_cached_properties() {
+        let mut devices = AudioDeviceRoot::new();
+        let old = devices.sinks.config_ids.intern("old");
+        devices.sinks.found_devices.insert(
+            1,
             AudioDevice {
-                original_name: "device2".to_string(),
-                recognized_as: vec!["medium_priority".to_string()],
+                original_name: "alsa_output.usb".to_string(),
+                monitor_name: None,
+                owner_module: None,
+                active_port: None,
+                muted: false,
+                running: false,
+                suspended: false,
+                silent: false,
+                playback_verified: None,
+                card: None,
+                properties: create_test_proplist(&[("device.bus", "usb")]),
+                capabilities: Capabilities::default(),
+                recognized_as: vec![old],
+                match_scores: BTreeMap::new(),
             },
         );
 
-        let mut configs = HashMap::new();
-        configs.insert(
-            "high_priority".to_string(),
-            DeviceConfig {
-                priority: Some(1),
-                match_config: DeviceMatchConfig::Detect(HashMap::new()),
-            },
+        let mut detect = HashMap::new();
+        detect.insert(
+            "device.bus".to_string(),
+            DetectRule::Exact("usb".to_string()),
         );
-        configs.insert(
-            "medium_priority".to_string(),
+        let mut config = Config::default();
+        config.sinks.insert(
+            "usb".to_string(),
             DeviceConfig {
-                priority: Some(5),
-                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                priority: Some(Priority::Fixed(1)),
+                match_config: DeviceMatchConfig::Detect(detect),
+                ..Default::default()
             },
         );
-        configs.insert(
-            "low_priority".to_string(),
-            DeviceConfig {
-                priority: Some(10),
-                match_config: DeviceMatchConfig::Detect(HashMap::new()),
-            },
+
+        let AudioDeviceRoot { sinks, .. } = &mut devices;
+        let device = &sinks.found_devices[&1];
+        let recognized_as = device.recognize(&MatchEnvironment {
+            configs: &config.sinks,
+            config_ids: &sinks.config_ids,
+            remap_module_indices: &sinks.remap_module_indices,
+            quirks: Quirks::resolve(None, &config.server_quirks),
+            multi_match: config.multi_match,
+        });
+        assert_eq!(recognized_as, vec!["usb"]);
+    }
+
+    #[test]
+    fn test_recognize_with_multiple_matches() {
+        let device = AudioDevice {
+            original_name: "alsa_output.usb".to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: create_test_proplist(&[("device.bus", "usb")]),
+            capabilities: Capabilities::default(),
+            recognized_as: Vec::new(),
+            match_scores: BTreeMap::new(),
+        };
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+sinks:
+  usb: {priority: 2, detect: {device.bus: usb}}
+  any: {priority: 3, detect: {}}
+  pci: {priority: 1, detect: {device.bus: pci}}
+"#,
+        )
+        .unwrap();
+        let config_ids = ConfigIds::default();
+        let remap_module_indices = HashMap::new();
+        let recognize = |config: &Config| {
+            device
+                .recognize(&MatchEnvironment {
+                    configs: &config.sinks,
+                    config_ids: &config_ids,
+                    remap_module_indices: &remap_module_indices,
+                    quirks: Quirks::resolve(None, &config.server_quirks),
+                    multi_match: config.multi_match,
+                })
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(recognize(&config), vec!["any", "usb"]);
+        config.multi_match = MultiMatchPolicy::Warn;
+        assert_eq!(recognize(&config), vec!["any", "usb"]);
+        config.multi_match = MultiMatchPolicy::Error;
+        assert!(recognize(&config).is_empty());
+
+        config.sinks.get_mut("usb").unwrap().exclusive = true;
+        assert_eq!(recognize(&config), vec!["usb"]);
+        config.sinks.get_mut("any").unwrap().exclusive = true;
+        assert!(recognize(&config).is_empty());
+    }
+
+    #[test]
+    fn test_recognize_with_catch_all() {
+        let device = |name: &str, properties: &[(&str, &str)]| AudioDevice {
+            original_name: name.to_string(),
+            monitor_name: None,
+            owner_module: None,
+            active_port: None,
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: create_test_proplist(properties),
+            capabilities: Capabilities::default(),
+            recognized_as: Vec::new(),
+            match_scores: BTreeMap::new(),
+        };
+        let config: Config = serde_yaml::from_str(
+            r#"
+sources:
+  usb: {priority: 1, detect: {device.bus: usb}}
+  _default: {priority: 100, detect: {}}
+"#,
+        )
+        .unwrap();
+        let config_ids = ConfigIds::default();
+        let remap_module_indices = HashMap::new();
+        let env = MatchEnvironment {
+            configs: &config.sources,
+            config_ids: &config_ids,
+            remap_module_indices: &remap_module_indices,
+            quirks: Quirks::resolve(None, &config.server_quirks),
+            multi_match: MultiMatchPolicy::Error,
+        };
+
+        let usb = device("alsa_input.usb", &[("device.bus", "usb")]);
+        assert_eq!(usb.recognize(&env), vec!["usb"]);
+        let pci = device("alsa_input.pci", &[("device.bus", "pci")]);
+        assert_eq!(pci.recognize(&env), vec!["_default"]);
+        let monitor = device(
+            "alsa_output.pci.monitor",
+            &[("device.bus", "pci"), ("device.class", "monitor")],
         );
+        assert!(monitor.recognize(&env).is_empty());
+    }
 
-        let result = State::find_default_device(&devices, &configs);
+    #[test]
+    fn test_trim_properties() {
+        let mut properties: BTreeMap<_, _> = [
+            ("device.description", "Speakers"),
+            ("device.bus", "usb"),
+            ("alsa.long_card_name", "Generic USB Audio at usb-1.2, speed"),
+            ("device.icon_name", "audio-card-usb"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        let kept =
+            HashSet::from(["device.description", "alsa.long_card_name"]);
+
+        let budget = |limit| PropertyBudget { limit, kept: &kept };
+
+        assert_eq!(budget(1000).trim(&mut properties.clone()), 0);
+        // The longest value not kept goes first
+        assert_eq!(budget(95).trim(&mut properties), 1);
+        assert!(!properties.contains_key("device.icon_name"));
+        assert_eq!(budget(0).trim(&mut properties), 1);
+        assert_eq!(
+            properties.keys().collect::<Vec<_>>(),
+            ["alsa.long_card_name", "device.description"]
+        );
+    }
 
-        assert!(result.is_some());
-        let (config_name, device_index) = result.unwrap();
-        assert_eq!(config_name, "high_priority");
-        assert_eq!(device_index, 1);
+    #[test]
+    fn test_event_rate() {
+        let start = Instant::now();
+        let mut rate = EventRate::new(start);
+        rate.record(start);
+        rate.record(start + Duration::from_millis(500));
+        assert_eq!(rate.per_window(start + Duration::from_millis(900)), 0);
+        assert_eq!(rate.per_window(start + Duration::from_millis(1500)), 2);
+
+        rate.record(start + Duration::from_millis(1500));
+        assert_eq!(rate.per_window(start + Duration::from_millis(1600)), 2);
+        assert_eq!(rate.per_window(start + Duration::from_millis(2600)), 1);
+        // Idle since
+        assert_eq!(rate.per_window(start + Duration::from_secs(4)), 0);
     }
 
     #[test]
-    fn test_find_default_device_with_no_priority() {
-        let mut devices = HashMap::new();
-        devices.insert(
-            1,
-            AudioDevice {
-                original_name: "device1".to_string(),
-                recognized_as: vec!["config1".to_string()],
+    fn test_snapshot_comparison_ignores_recognition() {
+        let device = AudioDevice {
+            original_name: "alsa_output.usb".to_string(),
+            monitor_name: Some("alsa_output.usb.monitor".to_string()),
+            owner_module: Some(3),
+            active_port: Some("analog-output".to_string()),
+            muted: false,
+            running: false,
+            suspended: false,
+            silent: false,
+            playback_verified: None,
+            card: None,
+            properties: create_test_proplist(&[("device.bus", "usb")]),
+            capabilities: Capabilities::default(),
+            recognized_as: vec![ConfigId(0)],
+            match_scores: BTreeMap::new(),
+        };
+        let unchanged = AudioDevice {
+            capabilities: Capabilities::default(),
+            recognized_as: Vec::new(),
+            match_scores: BTreeMap::new(),
+            ..device.clone()
+        };
+        assert!(device.has_same_snapshot(&unchanged));
+
+        let port_changed = AudioDevice {
+            active_port: Some("headphones".to_string()),
+            ..device.clone()
+        };
+        assert!(!device.has_same_snapshot(&port_changed));
+
+        let property_changed = AudioDevice {
+            properties: create_test_proplist(&[("device.bus", "pci")]),
+            ..device.clone()
+        };
+        assert!(!device.has_same_snapshot(&property_changed));
+    }
+
+    fn loaded_remap(config_name: &str, module_index: u32) -> LoadedRemap {
+        LoadedRemap {
+            kind: "sink",
+            group: "sinks",
+            config_name: config_name.to_string(),
+            module_index,
+        }
+    }
+
+    /// Names of the leaves taken, with masters given as (remap, master)
+    fn take_leaves(
+        unloads: &mut ShutdownUnloads,
+        masters: &[(&str, &'static str)],
+    ) -> Vec<String> {
+        let mut leaves: Vec<_> = unloads
+            .take_leaves(|module| {
+                masters
+                    .iter()
+                    .find(|(remap, _)| *remap == module.config_name)
+                    .map(|&(_, master)| ("sinks", master))
+            })
+            .into_iter()
+            .map(|module| module.config_name)
+            .collect();
+        leaves.sort();
+        leaves
+    }
+
+    #[test]
+    fn test_shutdown_unloads_chain() {
+        // a <- b <- c, each remapping the one before
+        let masters = [("b", "a"), ("c", "b")];
+        let mut unloads = ShutdownUnloads {
+            queued: vec![
+                loaded_remap("a", 1),
+                loaded_remap("b", 2),
+                loaded_remap("c", 3),
+            ],
+            ..ShutdownUnloads::default()
+        };
+        assert_eq!(take_leaves(&mut unloads, &masters), ["c"]);
+        assert!(take_leaves(&mut unloads, &masters).is_empty());
+        unloads.complete(3);
+        assert_eq!(take_leaves(&mut unloads, &masters), ["b"]);
+        unloads.complete(2);
+        assert_eq!(take_leaves(&mut unloads, &masters), ["a"]);
+        assert!(!unloads.is_empty());
+        unloads.complete(1);
+        assert!(unloads.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_unloads_fan_out() {
+        // c and e both remap b
+        let masters = [("c", "b"), ("e", "b")];
+        let mut unloads = ShutdownUnloads {
+            queued: vec![
+                loaded_remap("b", 1),
+                loaded_remap("c", 2),
+                loaded_remap("e", 3),
+            ],
+            ..ShutdownUnloads::default()
+        };
+        assert_eq!(take_leaves(&mut unloads, &masters), ["c", "e"]);
+
+        // The master waits for every remap still unloading
+        unloads.complete(2);
+        assert!(take_leaves(&mut unloads, &masters).is_empty());
+        unloads.complete(3);
+        assert_eq!(take_leaves(&mut unloads, &masters), ["b"]);
+        unloads.complete(1);
+        assert!(unloads.is_empty());
+    }
+
+    #[test]
+    fn test_shutdown_unloads_cycle() {
+        let masters = [("a", "b"), ("b", "a")];
+        let mut unloads = ShutdownUnloads {
+            queued: vec![loaded_remap("a", 1), loaded_remap("b", 2)],
+            ..ShutdownUnloads::default()
+        };
+        assert_eq!(take_leaves(&mut unloads, &masters), ["a", "b"]);
+        assert!(unloads.queued.is_empty());
+        unloads.complete(1);
+        unloads.complete(2);
+        assert!(unloads.is_empty());
+    }
+
+    #[test]
+    fn test_subscription_interests() {
+        let mut config = Config::default();
+        assert_eq!(subscription_interests(&config), InterestMaskSet::NULL);
+
+        config.sources.insert(
+            "mic".to_string(),
+            DeviceConfig {
+                priority: Some(Priority::Fixed(1)),
+                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                ..Default::default()
             },
         );
+        assert_eq!(subscription_interests(&config), InterestMaskSet::SOURCE);
 
-        let mut configs = HashMap::new();
-        configs.insert(
-            "config1".to_string(),
+        config.sources.insert(
+            "loopback".to_string(),
             DeviceConfig {
                 priority: None,
-                match_config: DeviceMatchConfig::Detect(HashMap::new()),
+                match_config: DeviceMatchConfig::Remap(RemapConfig {
+                    master: MasterRef::Sink {
+                        sink: "speakers".to_string(),
+                        monitor: true,
+                    },
+                    device_name: None,
+                    device_properties: None,
+                    format: None,
+                    rate: None,
+                    channels: None,
+                    channel_map: None,
+                    master_channel_map: None,
+                    resample_method: None,
+                    remix: None,
+                    verify_playback: false,
+                    on_name_collision: NameCollisionPolicy::Error,
+                    on_external_unload: ExternalUnloadPolicy::Reload,
+                }),
+                ..Default::default()
             },
         );
+        assert_eq!(
+            subscription_interests(&config),
+            InterestMaskSet::SINK
+                | InterestMaskSet::SOURCE
+                | InterestMaskSet::MODULE
+        );
 
-        let result = State::find_default_device(&devices, &configs);
+        config.recording_hook = Some("true".to_string());
+        assert_eq!(
+            subscription_interests(&config),
+            InterestMaskSet::SINK
+                | InterestMaskSet::SOURCE
+                | InterestMaskSet::SOURCE_OUTPUT
+                | InterestMaskSet::MODULE
+        );
+    }
 
-        assert!(result.is_none());
+    /// Configs of the generated sequences, interned in this order so that
+    /// their ids break ties the same way
+    const MODEL_CONFIGS: [&str; 4] = ["a", "b", "c", "d"];
+
+    /// Change to a group of devices, as the server and reloads cause them
+    #[derive(Debug, Clone)]
+    enum GroupOp {
+        /// A device appearing, or being matched again, as the configs
+        /// with the scores
+        Insert {
+            index: u32,
+            recognitions: Vec<(usize, u32)>,
+            silent: bool,
+        },
+        Remove(u32),
+        /// A reload giving the configs other priorities
+        Reprioritize(Vec<Option<u32>>),
     }
 
-    #[test]
-    fn test_find_default_device_with_empty_devices() {
-        let devices = HashMap::new();
-        let configs = HashMap::new();
+    fn group_op() -> impl Strategy<Value = GroupOp> {
+        prop_oneof![
+            (
+                0u32..6,
+                prop::collection::vec((0..MODEL_CONFIGS.len(), 0u32..3), 0..3),
+                any::<bool>()
+            )
+                .prop_map(|(index, recognitions, silent)| {
+                    GroupOp::Insert {
+                        index,
+                        recognitions,
+                        silent,
+                    }
+                }),
+            (0u32..6).prop_map(GroupOp::Remove),
+            priorities().prop_map(GroupOp::Reprioritize),
+        ]
+    }
+
+    fn priorities() -> impl Strategy<Value = Vec<Option<u32>>> {
+        prop::collection::vec(prop::option::of(0u32..4), MODEL_CONFIGS.len())
+    }
 
-        let result = State::find_default_device(&devices, &configs);
+    fn model_priority(priorities: &[Option<u32>], name: &str) -> Option<u32> {
+        let position = MODEL_CONFIGS.iter().position(|&n| n == name)?;
+        priorities[position]
+    }
 
-        assert!(result.is_none());
+    proptest! {
+        /// However devices come and go, the incrementally kept ranking
+        /// picks the same default and devices by config as a search over
+        /// the devices present
+        #[test]
+        fn test_default_follows_present_devices(
+            initial in priorities(),
+            ops in prop::collection::vec(group_op(), 1..40)
+        ) {
+            let configs: HashMap<_, _> = MODEL_CONFIGS
+                .iter()
+                .map(|name| {
+                    let config = DeviceConfig {
+                        priority: None,
+                        match_config: DeviceMatchConfig::Detect(
+                            HashMap::new(),
+                        ),
+                        ..Default::default()
+                    };
+                    (name.to_string(), config)
+                })
+                .collect();
+            let mut group = AudioDeviceGroup::new();
+            for name in MODEL_CONFIGS {
+                group.config_ids.intern(name);
+            }
+            let mut priorities = initial;
+            // Recognitions and silence of the devices present
+            let mut model: BTreeMap<u32, (BTreeMap<usize, u32>, bool)> =
+                BTreeMap::new();
+
+            for op in ops {
+                let current = priorities.clone();
+                let priority_of =
+                    |name: &str| model_priority(&current, name);
+                match op.clone() {
+                    GroupOp::Insert {
+                        index,
+                        recognitions,
+                        silent,
+                    } => {
+                        let recognitions: BTreeMap<_, _> =
+                            recognitions.into_iter().collect();
+                        let device = AudioDevice {
+                            original_name: format!("device{index}"),
+                            monitor_name: None,
+                            owner_module: None,
+                            active_port: None,
+                            muted: false,
+                            running: false,
+                            suspended: false,
+                            silent,
+                            playback_verified: None,
+                            card: None,
+                            properties: BTreeMap::new(),
+                            capabilities: Capabilities::default(),
+                            recognized_as: recognitions
+                                .keys()
+                                .map(|&config| ConfigId(config as u32))
+                                .collect(),
+                            match_scores: recognitions
+                                .iter()
+                                .map(|(&config, &score)| {
+                                    (ConfigId(config as u32), score)
+                                })
+                                .collect(),
+                        };
+                        group.insert_device(IndexedDevice { index, device }, priority_of);
+                        model.insert(index, (recognitions, silent));
+                    }
+                    GroupOp::Remove(index) => {
+                        group.remove_device(index, priority_of);
+                        model.remove(&index);
+                    }
+                    GroupOp::Reprioritize(changed) => {
+                        group.reindex(|name| model_priority(&changed, name));
+                        priorities = changed;
+                    }
+                }
+
+                let priorities = &priorities;
+                let expected = model
+                    .iter()
+                    .filter(|(_, (_, silent))| !silent)
+                    .flat_map(|(&index, (recognitions, _))| {
+                        recognitions.iter().filter_map(
+                            move |(&config, &score)| {
+                                Some((
+                                    priorities[config]?,
+                                    Reverse(score),
+                                    config,
+                                    index,
+                                ))
+                            },
+                        )
+                    })
+                    .min()
+                    .map(|(_, _, config, index)| {
+                        (MODEL_CONFIGS[config], index)
+                    });
+                prop_assert_eq!(
+                    find_default(&group, &configs),
+                    expected,
+                    "after {:?}",
+                    op
+                );
+
+                for (position, name) in MODEL_CONFIGS.iter().enumerate() {
+                    let expected = model
+                        .iter()
+                        .find(|(_, (recognitions, _))| {
+                            recognitions.contains_key(&position)
+                        })
+                        .map(|(&index, _)| index);
+                    prop_assert_eq!(
+                        group.find_index_by_config_name(name),
+                        expected,
+                        "after {:?}",
+                        op
+                    );
+                }
+            }
+        }
     }
 }
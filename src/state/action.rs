@@ -0,0 +1,345 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::server_event::DeviceKind;
+use crate::journal::ActionKind;
+
+/// What an action changes on the server; actions on the same target
+/// conflict and are run one after another
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Target {
+    Default(DeviceKind),
+    /// The module of a config
+    Module(DeviceKind, String),
+    /// A sink input or source output
+    Stream(DeviceKind, u32),
+}
+
+/// Change requested from the server, completing asynchronously
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    SetDefault {
+        kind: DeviceKind,
+        device: u32,
+    },
+    LoadModule {
+        kind: DeviceKind,
+        config: String,
+        module: &'static str,
+        argument: String,
+    },
+    MoveStream {
+        kind: DeviceKind,
+        stream: u32,
+        device: u32,
+    },
+}
+
+/// Action with the reason it was taken, for the journal
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub action: Action,
+    pub reason: String,
+}
+
+impl Action {
+    pub fn kind(&self) -> ActionKind {
+        match self {
+            Action::SetDefault { .. } => ActionKind::SetDefault,
+            Action::LoadModule { .. } => ActionKind::LoadModule,
+            Action::MoveStream { .. } => ActionKind::MoveStream,
+        }
+    }
+
+    pub fn target(&self) -> Target {
+        match self {
+            Action::SetDefault { kind, .. } => Target::Default(*kind),
+            Action::LoadModule { kind, config, .. } => {
+                Target::Module(*kind, config.clone())
+            }
+            Action::MoveStream { kind, stream, .. } => {
+                Target::Stream(*kind, *stream)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::SetDefault { kind, device } => {
+                write!(f, "default {kind} to #{device}")
+            }
+            Action::LoadModule { config, module, .. } => {
+                write!(f, "{module} for '{config}'")
+            }
+            Action::MoveStream {
+                kind: DeviceKind::Sink,
+                stream,
+                device,
+            } => write!(f, "sink input #{stream} to sink #{device}"),
+            Action::MoveStream {
+                kind: DeviceKind::Source,
+                stream,
+                device,
+            } => write!(f, "source output #{stream} to source #{device}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Admission {
+    /// Nothing else is in flight for the target, start the action now
+    Start,
+    /// The same action is already in flight
+    Duplicate,
+    /// Another action on the target is in flight; this one replaces any
+    /// action deferred before and starts once that has completed
+    Deferred,
+}
+
+/// Bookkeeping of the actions in flight, so that each target sees one
+/// change at a time and the latest request wins
+#[derive(Default)]
+pub struct ActionExecutor {
+    in_flight: HashMap<Target, Submission>,
+    deferred: HashMap<Target, Submission>,
+}
+
+impl ActionExecutor {
+    pub fn submit(&mut self, submission: Submission) -> Admission {
+        let target = submission.action.target();
+        match self.in_flight.get(&target) {
+            None => {
+                self.in_flight.insert(target, submission);
+                Admission::Start
+            }
+            Some(running) if running.action == submission.action => {
+                // Asking for what is being done anyway supersedes any
+                // other deferred action
+                self.deferred.remove(&target);
+                Admission::Duplicate
+            }
+            Some(_) => {
+                self.deferred.insert(target, submission);
+                Admission::Deferred
+            }
+        }
+    }
+
+    /// Mark the action in flight for the target as completed, returning it
+    /// and the deferred action to start next, if any
+    pub fn complete(
+        &mut self,
+        target: &Target,
+    ) -> (Option<Submission>, Option<Action>) {
+        let finished = self.in_flight.remove(target);
+        let next = self.deferred.remove(target).map(|next| {
+            let action = next.action.clone();
+            self.in_flight.insert(target.clone(), next);
+            action
+        });
+        (finished, next)
+    }
+
+    /// Forget the deferred action for the target, leaving the one in flight
+    pub fn cancel_deferred(&mut self, target: &Target) {
+        self.deferred.remove(target);
+    }
+
+    pub fn is_busy(&self, target: &Target) -> bool {
+        self.in_flight.contains_key(target)
+    }
+
+    /// Actions in flight, then those deferred, each with whether it is in
+    /// flight
+    pub fn pending(&self) -> impl Iterator<Item = (&Submission, bool)> {
+        let in_flight = self.in_flight.values().map(|action| (action, true));
+        let deferred = self.deferred.values().map(|action| (action, false));
+        in_flight.chain(deferred)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn set_default(device: u32) -> Action {
+        Action::SetDefault {
+            kind: DeviceKind::Sink,
+            device,
+        }
+    }
+
+    fn submit(executor: &mut ActionExecutor, action: Action) -> Admission {
+        executor.submit(Submission {
+            action,
+            reason: String::new(),
+        })
+    }
+
+    fn complete(
+        executor: &mut ActionExecutor,
+        target: &Target,
+    ) -> (Option<Action>, Option<Action>) {
+        let (finished, next) = executor.complete(target);
+        (finished.map(|submission| submission.action), next)
+    }
+
+    #[test]
+    fn test_conflicting_actions_are_serialized() {
+        let mut executor = ActionExecutor::default();
+        let target = Target::Default(DeviceKind::Sink);
+        assert_eq!(submit(&mut executor, set_default(1)), Admission::Start);
+        assert_eq!(submit(&mut executor, set_default(2)), Admission::Deferred);
+        assert_eq!(submit(&mut executor, set_default(3)), Admission::Deferred);
+        // Other targets are independent
+        let source = Action::SetDefault {
+            kind: DeviceKind::Source,
+            device: 1,
+        };
+        assert_eq!(submit(&mut executor, source), Admission::Start);
+
+        // The latest deferred action follows
+        assert_eq!(
+            complete(&mut executor, &target),
+            (Some(set_default(1)), Some(set_default(3)))
+        );
+        assert!(executor.is_busy(&target));
+        assert_eq!(
+            complete(&mut executor, &target),
+            (Some(set_default(3)), None)
+        );
+        assert!(!executor.is_busy(&target));
+    }
+
+    #[test]
+    fn test_duplicate_actions() {
+        let mut executor = ActionExecutor::default();
+        let target = Target::Default(DeviceKind::Sink);
+        assert_eq!(submit(&mut executor, set_default(1)), Admission::Start);
+        assert_eq!(submit(&mut executor, set_default(2)), Admission::Deferred);
+        // Going back to the target in flight drops the deferred one
+        assert_eq!(
+            submit(&mut executor, set_default(1)),
+            Admission::Duplicate
+        );
+        assert_eq!(
+            complete(&mut executor, &target),
+            (Some(set_default(1)), None)
+        );
+
+        submit(&mut executor, set_default(1));
+        submit(&mut executor, set_default(2));
+        executor.cancel_deferred(&target);
+        assert_eq!(
+            complete(&mut executor, &target),
+            (Some(set_default(1)), None)
+        );
+    }
+
+    /// Request for an action or completion of the one in flight on the
+    /// default sink or source
+    #[derive(Debug, Clone)]
+    enum ExecutorOp {
+        Submit(Action),
+        Complete(Target),
+    }
+
+    fn kind() -> impl Strategy<Value = DeviceKind> {
+        prop_oneof![Just(DeviceKind::Sink), Just(DeviceKind::Source)]
+    }
+
+    fn executor_op() -> impl Strategy<Value = ExecutorOp> {
+        prop_oneof![
+            (kind(), 0u32..3).prop_map(|(kind, device)| {
+                ExecutorOp::Submit(Action::SetDefault { kind, device })
+            }),
+            kind()
+                .prop_map(|kind| ExecutorOp::Complete(Target::Default(kind))),
+        ]
+    }
+
+    proptest! {
+        /// Each target has at most one action in flight, and once every
+        /// action has completed, the last one started on a target is the
+        /// last one requested for it
+        #[test]
+        fn test_latest_request_wins(
+            ops in prop::collection::vec(executor_op(), 1..40)
+        ) {
+            let mut executor = ActionExecutor::default();
+            let mut in_flight: HashMap<Target, Action> = HashMap::new();
+            let mut last_started: HashMap<Target, Action> = HashMap::new();
+            let mut last_requested: HashMap<Target, Action> = HashMap::new();
+
+            for op in ops.clone() {
+                for kind in [DeviceKind::Sink, DeviceKind::Source] {
+                    let target = Target::Default(kind);
+                    prop_assert_eq!(
+                        executor.is_busy(&target),
+                        in_flight.contains_key(&target)
+                    );
+                }
+
+                let action = match op {
+                    ExecutorOp::Submit(action) => action,
+                    ExecutorOp::Complete(target) => {
+                        let (finished, next) =
+                            complete(&mut executor, &target);
+                        prop_assert_eq!(finished, in_flight.remove(&target));
+                        if let Some(next) = next {
+                            in_flight.insert(target.clone(), next.clone());
+                            last_started.insert(target, next);
+                        }
+                        continue;
+                    }
+                };
+
+                let target = action.target();
+                last_requested.insert(target.clone(), action.clone());
+                match submit(&mut executor, action.clone()) {
+                    Admission::Start => {
+                        prop_assert!(!in_flight.contains_key(&target));
+                        in_flight.insert(target.clone(), action.clone());
+                        last_started.insert(target, action);
+                    }
+                    Admission::Duplicate => {
+                        prop_assert_eq!(in_flight.get(&target), Some(&action));
+                    }
+                    Admission::Deferred => {
+                        prop_assert!(in_flight.contains_key(&target));
+                    }
+                }
+            }
+
+            // Let the server complete everything
+            for kind in [DeviceKind::Sink, DeviceKind::Source] {
+                let target = Target::Default(kind);
+                while executor.is_busy(&target) {
+                    if let (_, Some(next)) = complete(&mut executor, &target) {
+                        last_started.insert(target.clone(), next);
+                    }
+                }
+            }
+            prop_assert_eq!(last_started, last_requested, "after {:?}", ops);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Synthetic workloads for measuring the matching engine with many devices
+
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use super::{
+    AudioDevice, AudioDeviceGroup, Capabilities, IndexedDevice,
+    MatchEnvironment,
+};
+use crate::config::{
+    DetectRule, DeviceConfig, DeviceMatchConfig, MultiMatchPolicy, Priority,
+};
+use crate::quirks::Quirks;
+
+/// How often default selection is repeated over the same devices
+const DEFAULT_SELECTION_ROUNDS: usize = 100;
+
+/// Time taken by a number of iterations of one operation
+pub struct Measurement {
+    pub name: &'static str,
+    pub iterations: usize,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    pub fn per_iteration(&self) -> Duration {
+        self.elapsed / self.iterations.max(1) as u32
+    }
+}
+
+/// Detect configs matching on the bus and vendor, each with its own
+/// priority
+fn synthetic_configs(count: usize) -> HashMap<String, DeviceConfig> {
+    (0..count)
+        .map(|i| {
+            let detect = HashMap::from([
+                (
+                    "device.bus".to_string(),
+                    DetectRule::Exact("usb".to_string()),
+                ),
+                (
+                    "device.vendor.id".to_string(),
+                    DetectRule::Exact(format!("{i:04x}")),
+                ),
+            ]);
+            let config = DeviceConfig {
+                priority: Some(Priority::Fixed(i as u32)),
+                match_config: DeviceMatchConfig::Detect(detect),
+                ..Default::default()
+            };
+            (format!("config_{i}"), config)
+        })
+        .collect()
+}
+
+/// Devices with a typical number of properties, half of which match no
+/// config
+fn synthetic_device(index: usize, config_count: usize) -> AudioDevice {
+    let vendor = index % (config_count * 2).max(1);
+    let properties = [
+        ("device.bus", "usb".to_string()),
+        ("device.vendor.id", format!("{vendor:04x}")),
+        ("device.product.id", format!("{index:04x}")),
+        ("device.description", format!("Synthetic device {index}")),
+        ("device.string", format!("hw:{index}")),
+        ("device.class", "sound".to_string()),
+        ("device.api", "alsa".to_string()),
+        ("alsa.card", index.to_string()),
+        ("media.class", "Audio/Sink".to_string()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value))
+    .collect();
+    AudioDevice {
+        original_name: format!("synthetic_{index}"),
+        monitor_name: None,
+        owner_module: None,
+        active_port: None,
+        muted: false,
+        running: false,
+        suspended: false,
+        silent: false,
+        playback_verified: None,
+        card: None,
+        properties,
+        capabilities: Capabilities::default(),
+        recognized_as: Vec::new(),
+        match_scores: BTreeMap::new(),
+    }
+}
+
+fn measure(
+    name: &'static str,
+    iterations: usize,
+    mut operation: impl FnMut(),
+) -> Measurement {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        operation();
+    }
+    Measurement {
+        name,
+        iterations,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Recognize the given number of synthetic devices among synthetic
+/// configs, then choose the default among them and look each config up
+pub fn run(device_count: usize, config_count: usize) -> Vec<Measurement> {
+    let configs = synthetic_configs(config_count);
+    let quirks = Quirks::resolve(None, &Default::default());
+    let priority_of = |name: &str| match configs.get(name)?.priority {
+        Some(Priority::Fixed(priority)) => Some(priority),
+        Some(Priority::Inherit(_)) | None => None,
+    };
+    let mut group = AudioDeviceGroup::new();
+    group.intern_configs(&configs);
+    let mut devices =
+        (0..device_count).map(|i| synthetic_device(i, config_count));
+
+    let matching = measure("Matching", device_count, || {
+        let Some(mut device) = devices.next() else {
+            return;
+        };
+        let names = device.recognize(&MatchEnvironment {
+            configs: &configs,
+            config_ids: &group.config_ids,
+            remap_module_indices: &group.remap_module_indices,
+            quirks,
+            multi_match: MultiMatchPolicy::Allow,
+        });
+        device.recognized_as = names
+            .iter()
+            .filter_map(|name| group.config_ids.get(name))
+            .collect();
+        let index = group.found_devices.len() as u32;
+        group.insert_device(IndexedDevice { index, device }, priority_of);
+    });
+
+    let selection =
+        measure("Default selection", DEFAULT_SELECTION_ROUNDS, || {
+            black_box(group.find_default_device(&configs, |_, _| true));
+        });
+
+    let names: Vec<_> = configs.keys().collect();
+    let mut names_iter = names.iter();
+    let lookup = measure("Reverse index lookup", names.len(), || {
+        if let Some(name) = names_iter.next() {
+            black_box(group.find_index_by_config_name(name));
+        }
+    });
+
+    vec![matching, selection, lookup]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let measurements = run(20, 5);
+        let runs: Vec<_> = measurements
+            .iter()
+            .map(|measurement| (measurement.name, measurement.iterations))
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                ("Matching", 20),
+                ("Default selection", DEFAULT_SELECTION_ROUNDS),
+                ("Reverse index lookup", 5),
+            ]
+        );
+
+        // Nothing is divided by zero without devices or configs
+        let empty = run(0, 0);
+        assert_eq!(empty[0].iterations, 0);
+        assert_eq!(empty[0].per_iteration(), empty[0].elapsed);
+    }
+
+    #[test]
+    fn test_half_of_the_devices_match() {
+        let configs = synthetic_configs(5);
+        let mut group = AudioDeviceGroup::new();
+        group.intern_configs(&configs);
+        let environment = MatchEnvironment {
+            configs: &configs,
+            config_ids: &group.config_ids,
+            remap_module_indices: &group.remap_module_indices,
+            quirks: Quirks::resolve(None, &Default::default()),
+            multi_match: MultiMatchPolicy::Allow,
+        };
+
+        let recognized: Vec<_> = (0..10)
+            .map(|index| synthetic_device(index, 5).recognize(&environment))
+            .collect();
+        assert_eq!(recognized[3], ["config_3"]);
+        assert!(recognized[7].is_empty());
+        assert_eq!(
+            recognized.iter().filter(|names| !names.is_empty()).count(),
+            5
+        );
+    }
+}
@@ -0,0 +1,232 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::action::Action;
+use super::server_event::DeviceKind;
+
+const BOTH_KINDS: [DeviceKind; 2] = [DeviceKind::Sink, DeviceKind::Source];
+
+/// Situation that policies may react to, after the runner has brought the
+/// known devices up to date
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyEvent {
+    /// Devices were added, changed or re-matched within the batching window
+    DevicesChanged {
+        sinks: bool,
+        sources: bool,
+    },
+    DeviceRemoved(DeviceKind),
+    /// A session manager appeared or went, changing the effective policy
+    SessionManagerChanged,
+    ConfigReloaded,
+    Resumed,
+    /// A JACK server started or stopped
+    JackServerChanged,
+    /// The server has completed an action
+    ActionCompleted {
+        action: Action,
+        success: bool,
+    },
+}
+
+/// What a policy wants the runner to do
+///
+/// Identical actions wanted by several policies are performed once, in
+/// the order of the policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    StartPlaybackChecks(DeviceKind),
+    UpdateDefault(DeviceKind),
+    UnloadRemaps,
+    LoadRemaps,
+}
+
+/// Facts about the configuration that policies decide upon
+pub struct PolicyContext {
+    pub has_bundles: bool,
+}
+
+/// Behavior reacting to events with actions, keeping the decisions apart
+/// from the mechanics of carrying them out
+pub trait Policy {
+    fn on_event(
+        &mut self,
+        event: &PolicyEvent,
+        context: &PolicyContext,
+    ) -> Vec<PolicyAction>;
+}
+
+/// Policies of every daemon, in the order their actions are performed
+pub fn built_in() -> Vec<Box<dyn Policy>> {
+    vec![Box::new(DefaultSelection), Box::new(RemapLifecycle)]
+}
+
+/// Keeps the default sink and source the best recognized devices
+pub struct DefaultSelection;
+
+impl Policy for DefaultSelection {
+    fn on_event(
+        &mut self,
+        event: &PolicyEvent,
+        context: &PolicyContext,
+    ) -> Vec<PolicyAction> {
+        let (kinds, check_playback) = match *event {
+            PolicyEvent::DevicesChanged { sinks, sources } => {
+                // Bundles make the defaults depend on both types
+                let any = sinks || sources;
+                let kinds = if context.has_bundles {
+                    [any, any]
+                } else {
+                    [sinks, sources]
+                };
+                (kinds, true)
+            }
+            PolicyEvent::DeviceRemoved(kind) if !context.has_bundles => (
+                [kind == DeviceKind::Sink, kind == DeviceKind::Source],
+                false,
+            ),
+            PolicyEvent::ConfigReloaded => ([true, true], true),
+            PolicyEvent::DeviceRemoved(_)
+            | PolicyEvent::SessionManagerChanged
+            | PolicyEvent::Resumed => ([true, true], false),
+            PolicyEvent::JackServerChanged
+            | PolicyEvent::ActionCompleted { .. } => ([false, false], false),
+        };
+
+        let mut actions = Vec::new();
+        for (kind, _) in BOTH_KINDS.into_iter().zip(kinds).filter(|k| k.1) {
+            if check_playback {
+                actions.push(PolicyAction::StartPlaybackChecks(kind));
+            }
+            actions.push(PolicyAction::UpdateDefault(kind));
+        }
+        actions
+    }
+}
+
+/// Loads remap modules once their masters are present and unloads them
+/// when they are gone
+pub struct RemapLifecycle;
+
+impl Policy for RemapLifecycle {
+    fn on_event(
+        &mut self,
+        event: &PolicyEvent,
+        _context: &PolicyContext,
+    ) -> Vec<PolicyAction> {
+        match event {
+            PolicyEvent::ActionCompleted { .. } => Vec::new(),
+            PolicyEvent::DeviceRemoved(_) => vec![PolicyAction::UnloadRemaps],
+            PolicyEvent::SessionManagerChanged => {
+                vec![PolicyAction::LoadRemaps]
+            }
+            PolicyEvent::DevicesChanged { .. }
+            | PolicyEvent::ConfigReloaded
+            | PolicyEvent::Resumed
+            | PolicyEvent::JackServerChanged => {
+                vec![PolicyAction::UnloadRemaps, PolicyAction::LoadRemaps]
+            }
+        }
+    }
+}
+
+/// Actions of all policies for the event, without repetitions
+pub fn decide(
+    policies: &mut [Box<dyn Policy>],
+    event: &PolicyEvent,
+    context: &PolicyContext,
+) -> Vec<PolicyAction> {
+    let mut actions = Vec::new();
+    for policy in policies {
+        for action in policy.on_event(event, context) {
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+    }
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decide_built_in(
+        event: &PolicyEvent,
+        has_bundles: bool,
+    ) -> Vec<PolicyAction> {
+        let context = PolicyContext { has_bundles };
+        decide(&mut built_in(), event, &context)
+    }
+
+    #[test]
+    fn test_devices_changed() {
+        let event = PolicyEvent::DevicesChanged {
+            sinks: true,
+            sources: false,
+        };
+        assert_eq!(
+            decide_built_in(&event, false),
+            [
+                PolicyAction::StartPlaybackChecks(DeviceKind::Sink),
+                PolicyAction::UpdateDefault(DeviceKind::Sink),
+                PolicyAction::UnloadRemaps,
+                PolicyAction::LoadRemaps,
+            ]
+        );
+        assert!(
+            decide_built_in(&event, true)
+                .contains(&PolicyAction::UpdateDefault(DeviceKind::Source))
+        );
+    }
+
+    #[test]
+    fn test_device_removed() {
+        let event = PolicyEvent::DeviceRemoved(DeviceKind::Source);
+        assert_eq!(
+            decide_built_in(&event, false),
+            [
+                PolicyAction::UpdateDefault(DeviceKind::Source),
+                PolicyAction::UnloadRemaps,
+            ]
+        );
+        assert_eq!(decide_built_in(&event, true).len(), 3);
+    }
+
+    #[test]
+    fn test_actions_are_deduplicated() {
+        struct Reload;
+        impl Policy for Reload {
+            fn on_event(
+                &mut self,
+                _event: &PolicyEvent,
+                _context: &PolicyContext,
+            ) -> Vec<PolicyAction> {
+                vec![PolicyAction::LoadRemaps, PolicyAction::LoadRemaps]
+            }
+        }
+
+        let mut policies = built_in();
+        policies.push(Box::new(Reload));
+        let context = PolicyContext { has_bundles: false };
+        let actions =
+            decide(&mut policies, &PolicyEvent::JackServerChanged, &context);
+        assert_eq!(
+            actions,
+            [PolicyAction::UnloadRemaps, PolicyAction::LoadRemaps]
+        );
+    }
+}
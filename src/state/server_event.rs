@@ -0,0 +1,169 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+use libpulse_binding::context::subscribe::{Facility, Operation};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    Sink,
+    Source,
+}
+
+/// Notification of the server as the policies see it, decoupled from the
+/// callbacks of libpulse
+///
+/// Subscription callbacks only translate what they get into these and
+/// queue them; the runner handles them once the callback has returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerEvent {
+    DeviceAdded(DeviceKind, u32),
+    DeviceChanged(DeviceKind, u32),
+    DeviceRemoved(DeviceKind, u32),
+    PlaybackStreamAdded(u32),
+    RecordingStreamAdded(u32),
+    RecordingStreamRemoved(u32),
+    ClientAdded(u32),
+    ClientRemoved(u32),
+    ModuleRemoved(u32),
+}
+
+impl ServerEvent {
+    /// The event of a subscription notification, unless it is one that no
+    /// policy acts upon
+    pub fn from_subscription(
+        facility: Option<Facility>,
+        operation: Option<Operation>,
+        index: u32,
+    ) -> Option<Self> {
+        let device = |kind| match operation? {
+            Operation::New => Some(ServerEvent::DeviceAdded(kind, index)),
+            Operation::Changed => {
+                Some(ServerEvent::DeviceChanged(kind, index))
+            }
+            Operation::Removed => {
+                Some(ServerEvent::DeviceRemoved(kind, index))
+            }
+        };
+        match (facility?, operation?) {
+            (Facility::Sink, _) => device(DeviceKind::Sink),
+            (Facility::Source, _) => device(DeviceKind::Source),
+            (Facility::SinkInput, Operation::New) => {
+                Some(ServerEvent::PlaybackStreamAdded(index))
+            }
+            (Facility::SourceOutput, Operation::New) => {
+                Some(ServerEvent::RecordingStreamAdded(index))
+            }
+            (Facility::SourceOutput, Operation::Removed) => {
+                Some(ServerEvent::RecordingStreamRemoved(index))
+            }
+            (Facility::Client, Operation::New) => {
+                Some(ServerEvent::ClientAdded(index))
+            }
+            (Facility::Client, Operation::Removed) => {
+                Some(ServerEvent::ClientRemoved(index))
+            }
+            (Facility::Module, Operation::Removed) => {
+                Some(ServerEvent::ModuleRemoved(index))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceKind::Sink => write!(f, "sink"),
+            DeviceKind::Source => write!(f, "source"),
+        }
+    }
+}
+
+impl fmt::Display for ServerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerEvent::DeviceAdded(kind, index) => {
+                write!(f, "new {kind} #{index}")
+            }
+            ServerEvent::DeviceChanged(kind, index) => {
+                write!(f, "changed {kind} #{index}")
+            }
+            ServerEvent::DeviceRemoved(kind, index) => {
+                write!(f, "removed {kind} #{index}")
+            }
+            ServerEvent::PlaybackStreamAdded(index) => {
+                write!(f, "new sink input #{index}")
+            }
+            ServerEvent::RecordingStreamAdded(index) => {
+                write!(f, "new source output #{index}")
+            }
+            ServerEvent::RecordingStreamRemoved(index) => {
+                write!(f, "removed source output #{index}")
+            }
+            ServerEvent::ClientAdded(index) => {
+                write!(f, "new client #{index}")
+            }
+            ServerEvent::ClientRemoved(index) => {
+                write!(f, "removed client #{index}")
+            }
+            ServerEvent::ModuleRemoved(index) => {
+                write!(f, "removed module #{index}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_subscription() {
+        let translate = |facility, operation| {
+            ServerEvent::from_subscription(Some(facility), Some(operation), 7)
+        };
+        assert_eq!(
+            translate(Facility::Sink, Operation::New),
+            Some(ServerEvent::DeviceAdded(DeviceKind::Sink, 7))
+        );
+        assert_eq!(
+            translate(Facility::Source, Operation::Changed),
+            Some(ServerEvent::DeviceChanged(DeviceKind::Source, 7))
+        );
+        assert_eq!(
+            translate(Facility::SourceOutput, Operation::Removed),
+            Some(ServerEvent::RecordingStreamRemoved(7))
+        );
+        // Nothing acts upon streams that go or change
+        assert_eq!(translate(Facility::SinkInput, Operation::Removed), None);
+        assert_eq!(translate(Facility::Client, Operation::Changed), None);
+        assert_eq!(translate(Facility::Module, Operation::New), None);
+        assert_eq!(
+            translate(Facility::Module, Operation::Removed),
+            Some(ServerEvent::ModuleRemoved(7))
+        );
+        assert_eq!(
+            ServerEvent::from_subscription(None, Some(Operation::New), 7),
+            None
+        );
+        assert_eq!(
+            ServerEvent::DeviceRemoved(DeviceKind::Sink, 7).to_string(),
+            "removed sink #7"
+        );
+    }
+}
@@ -0,0 +1,400 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Local statistics of the defaults, for tuning priorities
+//!
+//! Nothing leaves the machine: the counts are only written to a file,
+//! which `autopulsed stats` prints. Counting starts anew after a number of
+//! days, keeping a few previous files next to the current one.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use autopulsed::config::StatisticsConfig;
+
+use crate::journal;
+use crate::state::trace;
+
+pub const FILE_NAME: &str = "statistics.json";
+
+/// A default replaced sooner than this has flapped
+const FLAP_SECONDS: u64 = 60;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Statistics {
+    /// Unix time counting started at
+    pub since: u64,
+    #[serde(default)]
+    pub sink: KindStatistics,
+    #[serde(default)]
+    pub source: KindStatistics,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KindStatistics {
+    /// Changes of the default from one config to another
+    pub switches: u64,
+    /// Defaults replaced within a minute
+    pub flaps: u64,
+    pub configs: BTreeMap<String, ConfigStatistics>,
+}
+
+/// The default of a kind being set to a config, or to no config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NewDefault<'a> {
+    pub kind: &'static str,
+    pub config_name: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigStatistics {
+    /// Times the config has been made the default
+    pub chosen: u64,
+    /// Seconds the config has been the default, up to the last switch
+    pub seconds_default: u64,
+    pub flaps: u64,
+}
+
+impl Statistics {
+    fn kind_mut(&mut self, kind: &str) -> &mut KindStatistics {
+        match kind {
+            "sink" => &mut self.sink,
+            _ => &mut self.source,
+        }
+    }
+
+    /// Tables of the configs, those that have been the default longest
+    /// first
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines =
+            vec![format!("Since {}", journal::format_time(self.since))];
+        for (kind, statistics) in
+            [("Sinks", &self.sink), ("Sources", &self.source)]
+        {
+            lines.push(String::new());
+            lines.push(format!(
+                "{kind}: {} switch(es), {} flap(s)",
+                statistics.switches, statistics.flaps
+            ));
+            let mut configs: Vec<_> = statistics.configs.iter().collect();
+            configs.sort_by(|a, b| {
+                b.1.seconds_default
+                    .cmp(&a.1.seconds_default)
+                    .then(a.0.cmp(b.0))
+            });
+            let rows: Vec<_> = configs
+                .into_iter()
+                .map(|(name, config)| {
+                    vec![
+                        name.clone(),
+                        config.chosen.to_string(),
+                        format_seconds(config.seconds_default),
+                        config.flaps.to_string(),
+                    ]
+                })
+                .collect();
+            if !rows.is_empty() {
+                lines.extend(trace::format_table(
+                    &["CONFIG", "CHOSEN", "DEFAULT FOR", "FLAPS"],
+                    &rows,
+                ));
+            }
+        }
+        lines
+    }
+}
+
+/// Keeps the statistics of the running daemon in the file
+pub struct Recorder {
+    path: PathBuf,
+    config: StatisticsConfig,
+    statistics: Statistics,
+    /// Config of the current default by kind, with the time it was set
+    current: BTreeMap<&'static str, (String, u64)>,
+}
+
+impl Recorder {
+    pub fn open(path: PathBuf, config: StatisticsConfig, now: u64) -> Self {
+        let statistics = match read(&path) {
+            Ok(Some(statistics)) => statistics,
+            Ok(None) => Statistics {
+                since: now,
+                ..Statistics::default()
+            },
+            Err(e) => {
+                warn!(
+                    "Starting the statistics anew, {} is unreadable: {e}",
+                    path.display()
+                );
+                Statistics {
+                    since: now,
+                    ..Statistics::default()
+                }
+            }
+        };
+        Self {
+            path,
+            config,
+            statistics,
+            current: BTreeMap::new(),
+        }
+    }
+
+    /// Count the default being set
+    pub fn record_default(&mut self, default: NewDefault<'_>, now: u64) {
+        let NewDefault { kind, config_name } = default;
+        if self.current.get(kind).map(|(name, _)| name.as_str()) == config_name
+        {
+            return;
+        }
+        self.rotate_if_due(now);
+        let had_default = self.end_default(kind, now);
+        let statistics = self.statistics.kind_mut(kind);
+        if had_default && config_name.is_some() {
+            statistics.switches += 1;
+        }
+        if let Some(name) = config_name {
+            statistics
+                .configs
+                .entry(name.to_string())
+                .or_default()
+                .chosen += 1;
+            self.current.insert(kind, (name.to_string(), now));
+        }
+        self.save();
+    }
+
+    /// Count the time of the current defaults, when exiting
+    pub fn close(&mut self, now: u64) {
+        self.count_current_time(now);
+        self.current.clear();
+        self.save();
+    }
+
+    /// Count the time the current default of the kind has been the
+    /// default, returning whether there was one
+    fn end_default(&mut self, kind: &'static str, now: u64) -> bool {
+        let Some((name, started)) = self.current.remove(kind) else {
+            return false;
+        };
+        let since = self.statistics.since;
+        let statistics = self.statistics.kind_mut(kind);
+        let config = statistics.configs.entry(name).or_default();
+        // Only the time within this period counts
+        config.seconds_default += now.saturating_sub(started.max(since));
+        if now.saturating_sub(started) < FLAP_SECONDS {
+            config.flaps += 1;
+            statistics.flaps += 1;
+        }
+        true
+    }
+
+    fn count_current_time(&mut self, now: u64) {
+        let since = self.statistics.since;
+        for (kind, (name, started)) in &self.current {
+            let config = self
+                .statistics
+                .kind_mut(kind)
+                .configs
+                .entry(name.clone())
+                .or_default();
+            config.seconds_default +=
+                now.saturating_sub((*started).max(since));
+        }
+    }
+
+    fn rotate_if_due(&mut self, now: u64) {
+        let due = self.statistics.since + self.config.rotate_days() * 86400;
+        if now < due {
+            return;
+        }
+        self.count_current_time(now);
+        self.save();
+        if let Err(e) = rotate(&self.path, self.config.keep()) {
+            warn!("Failed to rotate {}: {e}", self.path.display());
+        }
+        self.statistics = Statistics {
+            since: now,
+            ..Statistics::default()
+        };
+    }
+
+    fn save(&self) {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let written = serde_json::to_string_pretty(&self.statistics)
+            .map_err(io::Error::from)
+            .and_then(|json| std::fs::write(&temporary, json))
+            .and_then(|()| std::fs::rename(&temporary, &self.path));
+        if let Err(e) = written {
+            warn!("Failed to write {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// The statistics in the file, or None if there is no file yet
+pub fn read(path: &Path) -> io::Result<Option<Statistics>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map(Some)
+            .map_err(io::Error::from),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Shift file.1 to file.2 and so on, dropping the oldest beyond `keep`,
+/// and move the file to file.1
+fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.to_path_buf().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    for n in (1..keep).rev() {
+        match std::fs::rename(numbered(n), numbered(n + 1)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+/// Like "3d 4h", "2h 5m", "5m" or "42s"
+fn format_seconds(seconds: u64) -> String {
+    let (days, hours, minutes) =
+        (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, _) => format!("{minutes}m"),
+        (0, _, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sink(config_name: Option<&str>) -> NewDefault<'_> {
+        NewDefault {
+            kind: "sink",
+            config_name,
+        }
+    }
+
+    #[test]
+    fn test_record_default() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let mut recorder =
+            Recorder::open(path.clone(), StatisticsConfig::default(), 1000);
+        recorder.record_default(sink(Some("speakers")), 1000);
+        // Re-applied, not a switch
+        recorder.record_default(sink(Some("speakers")), 1100);
+        recorder.record_default(sink(Some("headphones")), 4600);
+        recorder.record_default(sink(Some("speakers")), 4630);
+        recorder.close(5000);
+
+        let statistics = read(&path).unwrap().unwrap();
+        assert_eq!(statistics.since, 1000);
+        assert_eq!(statistics.sink.switches, 2);
+        assert_eq!(statistics.sink.flaps, 1);
+        assert_eq!(
+            statistics.sink.configs["speakers"],
+            ConfigStatistics {
+                chosen: 2,
+                seconds_default: 3600 + 370,
+                flaps: 0,
+            }
+        );
+        assert_eq!(
+            statistics.sink.configs["headphones"],
+            ConfigStatistics {
+                chosen: 1,
+                seconds_default: 30,
+                flaps: 1,
+            }
+        );
+        assert_eq!(statistics.source, KindStatistics::default());
+
+        // Carried on by the next run
+        let recorder =
+            Recorder::open(path.clone(), StatisticsConfig::default(), 9000);
+        assert_eq!(recorder.statistics, statistics);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let config = StatisticsConfig {
+            file: None,
+            rotate_days: Some(1),
+            keep: Some(2),
+        };
+        let mut recorder = Recorder::open(path.clone(), config, 0);
+        recorder.record_default(sink(Some("speakers")), 0);
+        for day in 1..=3 {
+            let name = if day % 2 == 0 {
+                "speakers"
+            } else {
+                "headphones"
+            };
+            recorder.record_default(sink(Some(name)), day * 86400);
+        }
+
+        let current = read(&path).unwrap().unwrap();
+        assert_eq!(current.since, 3 * 86400);
+        let previous = read(&dir.path().join("statistics.json.1"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(previous.since, 2 * 86400);
+        assert_eq!(previous.sink.configs["speakers"].seconds_default, 86400);
+        assert!(dir.path().join("statistics.json.2").exists());
+        assert!(!dir.path().join("statistics.json.3").exists());
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut statistics = Statistics::default();
+        statistics.sink.switches = 3;
+        for (name, seconds) in [("speakers", 120), ("headphones", 7500)] {
+            statistics.sink.configs.insert(
+                name.to_string(),
+                ConfigStatistics {
+                    chosen: 1,
+                    seconds_default: seconds,
+                    flaps: 0,
+                },
+            );
+        }
+        let lines = statistics.lines();
+        assert_eq!(lines[0], "Since 1970-01-01 00:00:00Z");
+        assert_eq!(lines[2], "Sinks: 3 switch(es), 0 flap(s)");
+        assert!(lines[4].starts_with("headphones  1       2h 5m"));
+        assert!(lines[5].starts_with("speakers    1       2m"));
+        assert_eq!(lines[7], "Sources: 0 switch(es), 0 flap(s)");
+        assert_eq!(lines.len(), 8);
+    }
+}
@@ -0,0 +1,162 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// What the daemon has done while running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Activity {
+    pub default_switches: u64,
+    /// Remap and JACK bridge modules loaded and unloaded by the daemon
+    pub modules_loaded: u64,
+    pub modules_unloaded: u64,
+}
+
+/// Why the daemon stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    Signal(&'static str),
+    /// Stopped on purpose because of a failure, such as unmatched devices
+    Failure(String),
+    MainloopQuit,
+    MainloopError,
+}
+
+impl ShutdownReason {
+    fn kind(&self) -> &'static str {
+        match self {
+            ShutdownReason::Signal(_) => "signal",
+            ShutdownReason::Failure(_) => "failure",
+            ShutdownReason::MainloopQuit => "mainloop_quit",
+            ShutdownReason::MainloopError => "mainloop_error",
+        }
+    }
+
+    fn detail(&self) -> Option<&str> {
+        match self {
+            ShutdownReason::Signal(signal) => Some(signal),
+            ShutdownReason::Failure(failure) => Some(failure),
+            ShutdownReason::MainloopQuit | ShutdownReason::MainloopError => {
+                None
+            }
+        }
+    }
+}
+
+/// How long the daemon ran, and what it did meanwhile
+#[derive(Debug, Clone, Default)]
+pub struct Run {
+    pub uptime: Duration,
+    pub activity: Activity,
+}
+
+/// Account of a run, logged on exit and optionally written to a file, so
+/// that clean stops can be told from crashes, which leave none
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShutdownSummary {
+    pub reason: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uptime_secs: u64,
+    #[serde(flatten)]
+    pub activity: Activity,
+    /// Whether the modules were unloaded and defaults restored in time
+    pub cleanup_completed: bool,
+}
+
+impl ShutdownSummary {
+    pub fn new(
+        reason: &ShutdownReason,
+        run: Run,
+        cleanup_completed: bool,
+    ) -> Self {
+        Self {
+            reason: reason.kind(),
+            detail: reason.detail().map(str::to_string),
+            uptime_secs: run.uptime.as_secs(),
+            activity: run.activity,
+            cleanup_completed,
+        }
+    }
+
+    /// Write the summary as JSON, replacing the file of an earlier run
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n")
+    }
+}
+
+impl fmt::Display for ShutdownSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "reason={}", self.reason)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " detail={detail:?}")?;
+        }
+        write!(
+            f,
+            " uptime={}s default_switches={} modules_loaded={} \
+             modules_unloaded={} cleanup_completed={}",
+            self.uptime_secs,
+            self.activity.default_switches,
+            self.activity.modules_loaded,
+            self.activity.modules_unloaded,
+            self.cleanup_completed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown_summary() {
+        let activity = Activity {
+            default_switches: 3,
+            modules_loaded: 2,
+            modules_unloaded: 2,
+        };
+        let summary = ShutdownSummary::new(
+            &ShutdownReason::Signal("SIGTERM"),
+            Run {
+                uptime: Duration::from_millis(61_500),
+                activity,
+            },
+            true,
+        );
+        assert_eq!(
+            summary.to_string(),
+            "reason=signal detail=\"SIGTERM\" uptime=61s default_switches=3 \
+             modules_loaded=2 modules_unloaded=2 cleanup_completed=true"
+        );
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["reason"], "signal");
+        assert_eq!(json["default_switches"], 3);
+
+        let summary = ShutdownSummary::new(
+            &ShutdownReason::MainloopQuit,
+            Run::default(),
+            false,
+        );
+        let json = serde_json::to_value(&summary).unwrap();
+        assert!(json.get("detail").is_none());
+    }
+}
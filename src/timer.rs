@@ -0,0 +1,172 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::time::{Duration, Instant};
+
+pub struct Timer<C> {
+    pub delay: Duration,
+    pub callback: C,
+}
+
+/// Named one-shot timers driven by the main loop
+///
+/// Scheduling a timer under a name that is already pending replaces it.
+pub struct TimerQueue<C> {
+    timers: HashMap<String, (Instant, C)>,
+}
+
+impl<C> TimerQueue<C> {
+    pub fn new() -> Self {
+        Self {
+            timers: HashMap::new(),
+        }
+    }
+
+    pub fn schedule(&mut self, name: &str, timer: Timer<C>) {
+        let deadline = Instant::now() + timer.delay;
+        self.timers
+            .insert(name.to_string(), (deadline, timer.callback));
+    }
+
+    pub fn cancel(&mut self, name: &str) -> bool {
+        self.timers.remove(name).is_some()
+    }
+
+    pub fn is_scheduled(&self, name: &str) -> bool {
+        self.timers.contains_key(name)
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.values().map(|(deadline, _)| *deadline).min()
+    }
+
+    /// Names of the pending timers with their deadlines, soonest first
+    pub fn pending(&self) -> Vec<(&str, Instant)> {
+        let mut pending: Vec<_> = self
+            .timers
+            .iter()
+            .map(|(name, (deadline, _))| (name.as_str(), *deadline))
+            .collect();
+        pending.sort_by_key(|&(name, deadline)| (deadline, name));
+        pending
+    }
+
+    /// Removes the timers due at `now` and returns their callbacks in the
+    /// order of their deadlines
+    pub fn take_due(&mut self, now: Instant) -> Vec<C> {
+        let mut due_names: Vec<(Instant, String)> = self
+            .timers
+            .iter()
+            .filter(|(_, (deadline, _))| *deadline <= now)
+            .map(|(name, (deadline, _))| (*deadline, name.clone()))
+            .collect();
+        due_names.sort();
+
+        due_names
+            .into_iter()
+            .filter_map(|(_, name)| self.timers.remove(&name))
+            .map(|(_, callback)| callback)
+            .collect()
+    }
+}
+
+/// Delay before the retry of the given attempt, counting from 1: the base
+/// delay doubled for every attempt before, randomly shortened or lengthened
+/// by up to half so that retries do not line up with whatever they wait for
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    // Every RandomState is keyed differently
+    let random = RandomState::new().hash_one(attempt);
+    jitter(
+        base * 2u32.saturating_pow(attempt.saturating_sub(1)),
+        random,
+    )
+}
+
+fn jitter(delay: Duration, random: u64) -> Duration {
+    delay.mul_f64(0.5 + (random % 1001) as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn after<C>(delay: Duration, callback: C) -> Timer<C> {
+        Timer { delay, callback }
+    }
+
+    #[test]
+    fn test_take_due_in_deadline_order() {
+        let mut timers = TimerQueue::new();
+        timers.schedule("late", after(Duration::from_millis(20), "late"));
+        timers.schedule("early", after(Duration::from_millis(10), "early"));
+        timers.schedule("never", after(Duration::from_secs(3600), "never"));
+
+        assert!(timers.take_due(Instant::now()).is_empty());
+
+        let now = Instant::now() + Duration::from_millis(30);
+        assert_eq!(timers.take_due(now), vec!["early", "late"]);
+        assert!(timers.is_scheduled("never"));
+        assert!(!timers.is_scheduled("early"));
+    }
+
+    #[test]
+    fn test_schedule_replaces_pending_timer() {
+        let mut timers = TimerQueue::new();
+        timers.schedule("batch", after(Duration::from_secs(3600), 1));
+        let first_deadline = timers.next_deadline().unwrap();
+        timers.schedule("batch", after(Duration::ZERO, 2));
+
+        assert!(timers.next_deadline().unwrap() < first_deadline);
+        assert_eq!(timers.take_due(Instant::now()), vec![2]);
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(100);
+        assert_eq!(jitter(base, 0), Duration::from_millis(50));
+        assert_eq!(jitter(base, 1000), Duration::from_millis(150));
+
+        for attempt in 1..=4 {
+            let full = base * 2u32.pow(attempt - 1);
+            let delay = backoff(base, attempt);
+            assert!(delay >= full / 2 && delay <= full * 3 / 2);
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut timers = TimerQueue::new();
+        timers.schedule("batch", after(Duration::ZERO, 1));
+
+        assert!(timers.cancel("batch"));
+        assert!(!timers.cancel("batch"));
+        assert!(timers.take_due(Instant::now()).is_empty());
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut timers = TimerQueue::new();
+        timers.schedule("late", after(Duration::from_secs(20), ()));
+        timers.schedule("early", after(Duration::from_secs(10), ()));
+
+        let names: Vec<_> =
+            timers.pending().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["early", "late"]);
+    }
+}
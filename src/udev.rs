@@ -0,0 +1,101 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Properties of the sound card from the udev database
+//!
+//! PulseAudio copies some of them into the proplist, but not every server
+//! does, and without them two identical USB devices cannot be told apart.
+
+use std::collections::BTreeMap;
+
+/// Properties copied from the database, as `udev.<NAME>`
+const PROPERTIES: &[&str] = &["ID_SERIAL", "ID_PATH", "ID_USB_INTERFACE_NUM"];
+
+/// Add the udev properties of the ALSA card of a device, if it has one and
+/// udev knows it
+pub fn enrich(
+    mut properties: BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    enrich_from(&mut properties, |card| {
+        std::fs::read_to_string(format!("/run/udev/data/+sound:card{card}"))
+            .ok()
+    });
+    properties
+}
+
+fn enrich_from(
+    properties: &mut BTreeMap<String, String>,
+    read_database: impl Fn(u32) -> Option<String>,
+) {
+    let Some(card) = properties
+        .get("alsa.card")
+        .and_then(|card| card.parse().ok())
+    else {
+        return;
+    };
+    let Some(database) = read_database(card) else {
+        return;
+    };
+    for (name, value) in parse_database(&database) {
+        if PROPERTIES.contains(&name) {
+            properties
+                .entry(format!("udev.{name}"))
+                .or_insert_with(|| value.to_string());
+        }
+    }
+}
+
+/// Environment entries of a udev database file, written as `E:NAME=VALUE`
+fn parse_database(database: &str) -> impl Iterator<Item = (&str, &str)> {
+    database
+        .lines()
+        .filter_map(|line| line.strip_prefix("E:")?.split_once('='))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enrich() {
+        let database = "I:1234\n\
+                        E:ID_SERIAL=Vendor_USB_Headset-00\n\
+                        E:ID_PATH=pci-0000:00:14.0-usb-0:2:1.0\n\
+                        E:ID_USB_INTERFACE_NUM=00\n\
+                        E:ID_MODEL=USB_Headset\n\
+                        G:sound\n";
+        let mut properties =
+            BTreeMap::from([("alsa.card".to_string(), "1".to_string())]);
+        enrich_from(&mut properties, |card| {
+            (card == 1).then(|| database.to_string())
+        });
+        assert_eq!(
+            properties.get("udev.ID_PATH").map(String::as_str),
+            Some("pci-0000:00:14.0-usb-0:2:1.0")
+        );
+        assert_eq!(
+            properties.get("udev.ID_SERIAL").map(String::as_str),
+            Some("Vendor_USB_Headset-00")
+        );
+        assert!(properties.contains_key("udev.ID_USB_INTERFACE_NUM"));
+        assert!(!properties.contains_key("udev.ID_MODEL"));
+
+        // Devices of no card are left alone
+        let mut properties = BTreeMap::new();
+        enrich_from(&mut properties, |_| Some(database.to_string()));
+        assert!(properties.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Posting events to HTTP endpoints, for monitoring systems and chat alerts
+//!
+//! Each webhook has a thread of its own, so that a slow endpoint only holds
+//! up its own events. The body is the JSON of the event, as on the event
+//! stream of the REST API.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use sha2::Sha256;
+
+use autopulsed::config::WebhookConfig;
+
+use crate::events::{Event, EventFeed};
+
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before the first retry, doubled for each further one
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+struct Endpoint {
+    /// Host and port, as in the URL
+    authority: String,
+    path: String,
+}
+
+/// Why a delivery failed, and whether trying again may help
+#[derive(Debug, PartialEq, Eq)]
+struct Failure {
+    message: String,
+    retry: bool,
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Self {
+            message: e.to_string(),
+            retry: true,
+        }
+    }
+}
+
+/// One event on its way to an endpoint
+struct Delivery<'a> {
+    endpoint: &'a Endpoint,
+    name: &'a str,
+    body: &'a str,
+    /// Value of `X-Autopulsed-Signature`, if the webhook has a secret
+    signature: Option<String>,
+}
+
+/// Post the events from the feed to every webhook on threads of their own
+pub fn spawn(
+    webhooks: &[WebhookConfig],
+    events: &EventFeed,
+) -> io::Result<()> {
+    for webhook in webhooks {
+        let endpoint = parse_url(&webhook.url).ok_or_else(|| {
+            io::Error::other(format!("Invalid URL {}", webhook.url))
+        })?;
+        let receiver = events.subscribe();
+        let webhook = webhook.clone();
+        info!("Posting events to {}", webhook.url);
+        thread::Builder::new().name("webhook".to_string()).spawn(
+            move || {
+                for event in receiver {
+                    deliver(&webhook, &endpoint, &event);
+                }
+            },
+        )?;
+    }
+    Ok(())
+}
+
+fn deliver(webhook: &WebhookConfig, endpoint: &Endpoint, event: &Event) {
+    let body = match serde_json::to_value(event) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to serialize event: {e}");
+            return;
+        }
+    };
+    let name = body["event"].as_str().unwrap_or_default().to_string();
+    if !webhook.wants(&name) {
+        return;
+    }
+    let body = body.to_string();
+    let delivery = Delivery {
+        endpoint,
+        name: &name,
+        body: &body,
+        signature: webhook.secret.as_deref().map(|secret| sign(secret, &body)),
+    };
+
+    let mut delay = RETRY_DELAY;
+    for attempt in 0..=webhook.retries() {
+        if attempt > 0 {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        match delivery.post() {
+            Ok(()) => {
+                debug!("Posted {name} to {}", webhook.url);
+                return;
+            }
+            Err(failure) if failure.retry && attempt < webhook.retries() => {
+                debug!(
+                    "Failed to post {name} to {}, trying again: {}",
+                    webhook.url, failure.message
+                );
+            }
+            Err(failure) => {
+                warn!(
+                    "Failed to post {name} to {}: {}",
+                    webhook.url, failure.message
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// Host and path of a plain HTTP URL
+fn parse_url(url: &str) -> Option<Endpoint> {
+    let rest = url.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    (!authority.is_empty()).then(|| Endpoint {
+        authority: authority.to_string(),
+        path: path.to_string(),
+    })
+}
+
+/// Value of the signature header, the HMAC-SHA256 of the body in hex
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+impl Delivery<'_> {
+    fn request(&self) -> String {
+        let signature = self
+            .signature
+            .as_ref()
+            .map(|signature| {
+                format!("X-Autopulsed-Signature: {signature}\r\n")
+            })
+            .unwrap_or_default();
+        format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nX-Autopulsed-Event: {}\r\n{signature}\
+             Connection: close\r\n\r\n{}",
+            self.endpoint.path,
+            self.endpoint.authority,
+            self.body.len(),
+            self.name,
+            self.body
+        )
+    }
+
+    fn post(&self) -> Result<(), Failure> {
+        let authority = &self.endpoint.authority;
+        let address = if authority.contains(':') {
+            authority.clone()
+        } else {
+            format!("{authority}:80")
+        };
+        let mut stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        stream.write_all(self.request().as_bytes())?;
+        // The status line is all that matters
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        check_status(&status_line)
+    }
+}
+
+/// Success for 2xx replies; client errors other than 429 are not retried,
+/// as they would fail again
+fn check_status(reply: &str) -> Result<(), Failure> {
+    let status = reply
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(status) => Err(Failure {
+            message: format!("HTTP status {status}"),
+            retry: !(400..500).contains(&status) || status == 429,
+        }),
+        None => Err(Failure {
+            message: "Malformed reply".to_string(),
+            retry: true,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    use autopulsed::config::EVENT_NAMES;
+
+    #[test]
+    fn test_sign() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=\
+             5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let endpoint =
+            parse_url("http://localhost:8123/api/webhook/audio").unwrap();
+        assert_eq!(endpoint.authority, "localhost:8123");
+        assert_eq!(endpoint.path, "/api/webhook/audio");
+        assert_eq!(parse_url("http://example.com").unwrap().path, "/");
+        assert!(parse_url("https://example.com/").is_none());
+        assert!(parse_url("http:///path").is_none());
+    }
+
+    #[test]
+    fn test_check_status() {
+        assert_eq!(check_status("HTTP/1.1 204 No Content\r\n"), Ok(()));
+        let retried = |reply| check_status(reply).unwrap_err().retry;
+        assert!(retried("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(retried("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(!retried("HTTP/1.1 404 Not Found\r\n"));
+        assert!(retried(""));
+    }
+
+    #[test]
+    fn test_event_names() {
+        let event = Event::DefaultChanged {
+            kind: "sink",
+            index: 1,
+            name: "alsa_output.usb".to_string(),
+            config: None,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert!(EVENT_NAMES.contains(&json["event"].as_str().unwrap()));
+    }
+
+    /// Head and body of a request, read as far as its Content-Length
+    fn read_request(stream: &mut TcpStream) -> String {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+        request + &String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn test_deliver() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let webhook = WebhookConfig {
+            url: format!("http://{}/hook", listener.local_addr().unwrap()),
+            events: None,
+            secret: Some("key".to_string()),
+            secret_file: None,
+            retries: Some(1),
+        };
+        let endpoint = parse_url(&webhook.url).unwrap();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            // Fail the first attempt, accept the retry
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                requests.push(read_request(&mut stream));
+                let reply =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+            requests
+        });
+
+        let event = Event::ModuleFailed {
+            kind: "sink",
+            config: "remap".to_string(),
+            error: "No such entity".to_string(),
+        };
+        deliver(&webhook, &endpoint, &event);
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"), "{request}");
+        assert!(request.contains("X-Autopulsed-Event: module_failed\r\n"));
+        let body = request.split_once("\r\n\r\n").unwrap().1;
+        assert!(request.contains(&format!(
+            "X-Autopulsed-Signature: {}\r\n",
+            sign("key", body)
+        )));
+    }
+}
@@ -0,0 +1,94 @@
+// autopulsed - A daemon for configuring PulseAudio automatically
+// Copyright (C) 2025  Flokart World, Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Just enough of RFC 6455 to push text messages to clients
+
+use std::io::{self, Write};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_PING: u8 = 0x9;
+
+/// Value of `Sec-WebSocket-Accept` answering the key of a handshake
+pub fn accept_key(key: &str) -> String {
+    STANDARD.encode(Sha1::digest(format!("{key}{GUID}")))
+}
+
+pub fn write_text(stream: &mut impl Write, text: &str) -> io::Result<()> {
+    write_frame(stream, OPCODE_TEXT, text.as_bytes())
+}
+
+pub fn write_ping(stream: &mut impl Write) -> io::Result<()> {
+    write_frame(stream, OPCODE_PING, &[])
+}
+
+fn write_frame(
+    stream: &mut impl Write,
+    opcode: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    // Frames from servers are final and unmasked
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_frame_lengths() {
+        let mut frame = Vec::new();
+        write_text(&mut frame, "hi").unwrap();
+        assert_eq!(frame, [0x81, 2, b'h', b'i']);
+
+        let mut frame = Vec::new();
+        write_text(&mut frame, &"x".repeat(300)).unwrap();
+        assert_eq!(frame[..4], [0x81, 126, 0x01, 0x2c]);
+        assert_eq!(frame.len(), 4 + 300);
+
+        let mut frame = Vec::new();
+        write_ping(&mut frame).unwrap();
+        assert_eq!(frame, [0x89, 0]);
+    }
+}
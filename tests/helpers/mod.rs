@@ -302,6 +302,19 @@ impl OutputCapturer {
         self.child.kill()
     }
 
+    /// Ask the process to exit with SIGTERM, as systemd stops the daemon,
+    /// and wait for it to clean up
+    pub fn terminate(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<ExitStatus, String> {
+        std::process::Command::new("kill")
+            .args(["-TERM", &self.child.id().to_string()])
+            .status()
+            .map_err(|e| format!("Failed to run kill: {e}"))?;
+        self.wait_for_exit(timeout)
+    }
+
     /// Wait for process to exit and return exit status
     pub fn wait_for_exit(
         &mut self,
@@ -396,6 +409,10 @@ impl OutputCapturer {
 
 impl Drop for OutputCapturer {
     fn drop(&mut self) {
+        // Let the daemon unload its modules when a test fails midway
+        if self.is_running() {
+            let _ = self.terminate(Duration::from_secs(5));
+        }
         let _ = self.child.kill();
 
         if let Some(thread) = self.reader_thread.take() {
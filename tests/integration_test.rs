@@ -180,6 +180,28 @@ load-module module-native-protocol-unix socket={socket_str} auth-anonymous=1
         eprintln!("TEST: Using socket path: {path}");
         path
     }
+
+    /// Run pactl against the server, returning its output
+    fn pactl(&self, args: &[&str]) -> String {
+        let output = Command::new("pactl")
+            .arg("--server")
+            .arg(self.socket_path())
+            .args(args)
+            .output()
+            .expect("Failed to run pactl");
+        assert!(output.status.success(), "pactl {args:?} failed");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// Index of the module loaded with the given argument
+    fn module_index(&self, argument: &str) -> String {
+        self.pactl(&["list", "short", "modules"])
+            .lines()
+            .find(|line| line.split_whitespace().any(|word| word == argument))
+            .and_then(|line| line.split_whitespace().next())
+            .expect("Module not found")
+            .to_string()
+    }
 }
 
 impl Drop for IsolatedPulseServer {
@@ -526,6 +548,157 @@ sinks:
     eprintln!("TEST: Module parameter test completed successfully");
 }
 
+#[test]
+fn test_remap_follows_replaced_master() {
+    use helpers::OutputCapturer;
+
+    let pulse_config = r#"
+load-module module-null-sink sink_name=first_master sink_properties=device.description=SwapMaster
+"#;
+
+    let server = IsolatedPulseServer::start_with_config(pulse_config)
+        .expect("Failed to start isolated PulseAudio server");
+
+    let config_content = r#"
+sinks:
+  master_sink:
+    priority: 2
+    detect:
+      device.description: "SwapMaster"
+  remapped_sink:
+    priority: 1
+    remap:
+      master: "master_sink"
+      device_name: "swap_remap"
+"#;
+
+    let config_path = server.temp_dir.path().join("test_swap_config.yml");
+    std::fs::write(&config_path, config_content)
+        .expect("Failed to write test config");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--server",
+        &server.socket_path(),
+        "--verbose",
+    ])
+    .env("RUST_LOG", "debug");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.expect_string("Connected to PulseAudio server");
+    autopulsed.expect_string(
+        "Loading sink remap module for 'remapped_sink' with master \
+         first_master",
+    );
+    autopulsed.expect_regex(
+        r"Successfully loaded sink remap module #\d+ for 'remapped_sink'",
+    );
+    autopulsed.expect_regex(r"Sink #\d+ is recognized as 'remapped_sink'");
+
+    // Another device matching the master config takes over, and the first
+    // one goes away along with the remap the server unloads with it
+    server.pactl(&[
+        "load-module",
+        "module-null-sink",
+        "sink_name=second_master",
+        "sink_properties=device.description=SwapMaster",
+    ]);
+    let first_master = server.module_index("sink_name=first_master");
+    server.pactl(&["unload-module", &first_master]);
+
+    autopulsed.expect_string(
+        "Loading sink remap module for 'remapped_sink' with master \
+         second_master",
+    );
+    autopulsed.expect_regex(
+        r"Successfully loaded sink remap module #\d+ for 'remapped_sink'",
+    );
+    let modules = server.pactl(&["list", "short", "modules"]);
+    assert!(
+        modules.contains("master=second_master"),
+        "Remap is not bound to the new master: {modules}"
+    );
+    assert!(!modules.contains("master=first_master"));
+
+    autopulsed.kill().ok();
+}
+
+#[test]
+fn test_externally_unloaded_remap() {
+    use helpers::OutputCapturer;
+
+    let server = IsolatedPulseServer::start()
+        .expect("Failed to start isolated PulseAudio server");
+
+    let config_content = r#"
+sinks:
+  master_sink:
+    priority: 2
+    detect:
+      device.description: "TestSink1"
+  reloaded_sink:
+    priority: 1
+    remap:
+      master: "master_sink"
+      device_name: "reloaded_remap"
+  accepted_sink:
+    priority: 3
+    remap:
+      master: "master_sink"
+      device_name: "accepted_remap"
+      on_external_unload: accept
+"#;
+
+    let config_path = server.temp_dir.path().join("test_unload_config.yml");
+    std::fs::write(&config_path, config_content)
+        .expect("Failed to write test config");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--server",
+        &server.socket_path(),
+        "--verbose",
+    ])
+    .env("RUST_LOG", "debug");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.expect_string("Connected to PulseAudio server");
+    autopulsed.expect_regex(r"Sink #\d+ is recognized as 'reloaded_sink'");
+    autopulsed.expect_regex(r"Sink #\d+ is recognized as 'accepted_sink'");
+
+    let reloaded = server.module_index("sink_name=reloaded_remap");
+    server.pactl(&["unload-module", &reloaded]);
+    autopulsed.expect_string(&format!(
+        "Module #{reloaded} for 'reloaded_sink' has been unloaded without \
+         notice"
+    ));
+    autopulsed.expect_regex(
+        r"Successfully loaded sink remap module #\d+ for 'reloaded_sink'",
+    );
+
+    let accepted = server.module_index("sink_name=accepted_remap");
+    server.pactl(&["unload-module", &accepted]);
+    autopulsed.expect_string("Leaving 'accepted_sink' unloaded as configured");
+    autopulsed.expect_no_regex(
+        r"Loading sink remap module for 'accepted_sink'",
+        Duration::from_secs(2),
+    );
+
+    autopulsed.kill().ok();
+}
+
 #[test]
 fn test_circular_reference_detection() {
     use helpers::OutputCapturer;
@@ -747,3 +920,303 @@ sinks:
     autopulsed.kill().ok();
     eprintln!("TEST: Deferring test completed");
 }
+
+#[test]
+fn test_strict_refs_rejects_undefined_master() {
+    use helpers::OutputCapturer;
+
+    let config_content = r#"
+sinks:
+  remapped_sink:
+    priority: 1
+    remap:
+      master: "nonexistent_master"
+      device_name: "orphan_remap"
+"#;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_path = temp_dir.path().join("strict_refs_config.yml");
+    std::fs::write(&config_path, config_content)
+        .expect("Failed to write test config");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--strict-refs",
+    ])
+    .env("RUST_LOG", "info");
+
+    eprintln!("TEST: Running autopulsed with --strict-refs");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.assert_exit_failure(Duration::from_secs(2));
+    autopulsed.expect_string(
+        "Remap 'remapped_sink' in sinks refers to undefined master \
+         'nonexistent_master' in sinks",
+    );
+
+    eprintln!("TEST: Strict reference test completed successfully");
+}
+
+#[test]
+fn test_test_tone_subcommand() {
+    use helpers::OutputCapturer;
+
+    let server = IsolatedPulseServer::start()
+        .expect("Failed to start isolated PulseAudio server");
+
+    let config_content = r#"
+sinks:
+  test_device_1:
+    priority: 1
+    detect:
+      device.description: "TestSink1"
+"#;
+
+    let config_path = server.temp_dir.path().join("test_tone_config.yml");
+    std::fs::write(&config_path, config_content)
+        .expect("Failed to write test config");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--server",
+        &server.socket_path(),
+        "test",
+        "--device",
+        "test_device_1",
+    ])
+    .env("RUST_LOG", "info");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.expect_string("Playing a test tone on");
+    autopulsed.assert_exit_success(Duration::from_secs(5));
+    // Nothing is managed while testing
+    autopulsed.expect_no_string(
+        "Successfully set default sink",
+        Duration::from_millis(100),
+    );
+}
+
+#[test]
+fn test_self_test() {
+    use helpers::OutputCapturer;
+
+    let server = IsolatedPulseServer::start()
+        .expect("Failed to start isolated PulseAudio server");
+    server.pactl(&["set-default-sink", "test_sink_2"]);
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--server",
+        &server.socket_path(),
+        "--self-test",
+    ])
+    .env("LC_ALL", "C");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.expect_string("PASS Load a null sink");
+    autopulsed.expect_string("PASS Set the remap sink as default");
+    autopulsed.expect_string("PASS Restore the previous default");
+    autopulsed.expect_string("PASS Unload the null sink");
+    autopulsed.expect_string("6 of 6 steps passed");
+    autopulsed.assert_exit_success(Duration::from_secs(5));
+
+    // Everything the self-test changed is undone
+    assert!(
+        server
+            .pactl(&["info"])
+            .contains("Default Sink: test_sink_2")
+    );
+    let sinks = server.pactl(&["list", "short", "sinks"]);
+    assert!(
+        !sinks.contains("autopulsed_self_test"),
+        "A self-test sink was left behind: {sinks}"
+    );
+}
+
+#[test]
+fn test_observe_changes_nothing() {
+    use helpers::OutputCapturer;
+
+    let server = IsolatedPulseServer::start()
+        .expect("Failed to start isolated PulseAudio server");
+    let default_sink = |server: &IsolatedPulseServer| {
+        server
+            .pactl(&["info"])
+            .lines()
+            .find_map(|line| line.strip_prefix("Default Sink: "))
+            .map(str::to_string)
+    };
+    let initial_default = default_sink(&server);
+
+    // Acting on this would change the default, load a remap module, set
+    // passthrough formats and upload the test sound
+    let config_content = r#"
+play_test_sound: true
+sinks:
+  preferred_sink:
+    priority: 1
+    passthrough: [ac3]
+    detect:
+      device.description: "TestSink2"
+  remapped_sink:
+    priority: 2
+    remap:
+      master: "preferred_sink"
+      device_name: "observed_remap_sink"
+"#;
+
+    let config_path = server.temp_dir.path().join("test_observe_config.yml");
+    std::fs::write(&config_path, config_content)
+        .expect("Failed to write test config");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--server",
+        &server.socket_path(),
+        "--observe",
+        "--verbose",
+    ])
+    .env("RUST_LOG", "debug");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.expect_string("Connected to PulseAudio server");
+    autopulsed.expect_string("Observing: would use sink 'preferred_sink'");
+    autopulsed.expect_string(
+        "Observing: would set passthrough formats of test_sink_2",
+    );
+    autopulsed.expect_no_regex(
+        "Setting default sink|Loading sink remap module|\
+          Setting passthrough formats|Uploaded the test sound",
+        Duration::from_secs(2),
+    );
+    autopulsed
+        .terminate(Duration::from_secs(5))
+        .expect("autopulsed did not exit");
+
+    assert_eq!(default_sink(&server), initial_default);
+    let modules = server.pactl(&["list", "short", "modules"]);
+    assert!(
+        !modules.contains("module-remap-sink"),
+        "A remap module was loaded: {modules}"
+    );
+    let samples = server.pactl(&["list", "short", "samples"]);
+    assert!(
+        samples.trim().is_empty(),
+        "A sample was uploaded: {samples}"
+    );
+}
+
+/// Remap devices under pipewire-pulse, which reports no owner module for
+/// them. Runs only against the server in AUTOPULSED_PIPEWIRE_SERVER, since
+/// pipewire cannot be started as easily as an isolated PulseAudio server.
+#[test]
+fn test_remap_identification_under_pipewire() {
+    use helpers::OutputCapturer;
+
+    let Ok(server) = std::env::var("AUTOPULSED_PIPEWIRE_SERVER") else {
+        eprintln!("TEST: AUTOPULSED_PIPEWIRE_SERVER is not set, skipping");
+        return;
+    };
+
+    let output = Command::new("pactl")
+        .args([
+            "--server",
+            &server,
+            "load-module",
+            "module-null-sink",
+            "sink_name=autopulsed_pipewire_master",
+        ])
+        .output()
+        .expect("Failed to run pactl");
+    assert!(output.status.success(), "Failed to load the master sink");
+    // Declared before the daemon, so that it is dropped after the daemon
+    // has stopped and unloaded its remap
+    let _master_module = ModuleGuard {
+        server: &server,
+        index: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    };
+
+    // No device_name, so only the ownership tag can identify the remap
+    let config_content = r#"
+sinks:
+  master_sink:
+    priority: 2
+    detect:
+      device.name: "autopulsed_pipewire_master"
+  remapped_sink:
+    priority: 1
+    remap:
+      master: "master_sink"
+      device_properties:
+        device.description: "Remapped Pipewire Sink"
+"#;
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_path = temp_dir.path().join("test_pipewire_config.yml");
+    std::fs::write(&config_path, config_content)
+        .expect("Failed to write test config");
+
+    let mut cmd = Command::new("cargo");
+    cmd.args([
+        "run",
+        "--",
+        "--config",
+        config_path.to_str().unwrap(),
+        "--server",
+        &server,
+        "--verbose",
+    ])
+    .env("RUST_LOG", "debug");
+
+    let mut autopulsed =
+        OutputCapturer::spawn(cmd).expect("Failed to spawn autopulsed");
+
+    autopulsed.expect_string("Connected to PulseAudio server");
+    autopulsed.expect_regex(r"Sink #\d+ is recognized as 'master_sink'");
+    autopulsed.expect_regex(
+        r"Successfully loaded sink remap module #\d+ for 'remapped_sink'",
+    );
+    autopulsed.expect_regex(r"Sink #\d+ is recognized as 'remapped_sink'");
+
+    let status = autopulsed
+        .terminate(Duration::from_secs(10))
+        .expect("autopulsed did not stop");
+    assert!(status.success(), "autopulsed failed to stop: {status:?}");
+}
+
+/// Module loaded on a shared server, unloaded again when dropped
+struct ModuleGuard<'a> {
+    server: &'a str,
+    index: String,
+}
+
+impl Drop for ModuleGuard<'_> {
+    fn drop(&mut self) {
+        Command::new("pactl")
+            .args(["--server", self.server, "unload-module", &self.index])
+            .status()
+            .ok();
+    }
+}